@@ -2,16 +2,20 @@ use crate::{
     db::LocalDb,
     info, msg, success,
     types::{
-        config::{Config, Mirror, Opts},
+        config::{BenchConfig, Config, IpFamily, Mirror, NetworkConfig, Opts},
         Checksum, ChecksumValidator,
     },
-    utils::{downloader::Downloader, pager::Pager},
+    utils::{
+        downloader::{client_builder, is_connect_error, Downloader},
+        pager::{count_lines, Pager},
+    },
+    warn,
 };
 
 use anyhow::{bail, Result};
 use console::style;
 use indicatif::HumanBytes;
-use reqwest::{Client, ClientBuilder};
+use reqwest::Client;
 use std::{
     fs,
     io::Write,
@@ -26,16 +30,30 @@ pub async fn bench(
     config: &Config,
     db: LocalDb,
     downloader: &Downloader,
+    network_config: &NetworkConfig,
+    bench_config: &BenchConfig,
 ) -> Result<()> {
     // First, update local db
     db.update(downloader).await?;
 
     info!("Starting benchmarks...");
-    // Set reqwest parameters
-    let clientbuilder = ClientBuilder::new()
-        .connect_timeout(Duration::from_secs(5))
-        .timeout(Duration::from_secs(30));
-    let client = clientbuilder.build()?;
+    // Benchmark over each IP family separately if asked, otherwise let the system choose
+    let families = if bench_config.ip_families {
+        vec![Some(IpFamily::Ipv4), Some(IpFamily::Ipv6)]
+    } else {
+        vec![None]
+    };
+    let mut clients = Vec::new();
+    for family in families {
+        // Set reqwest parameters
+        let mut clientbuilder = client_builder(network_config)?
+            .connect_timeout(Duration::from_secs(5))
+            .timeout(Duration::from_secs(30));
+        if let Some(family) = family {
+            clientbuilder = clientbuilder.local_address(family.local_address());
+        }
+        clients.push((family, clientbuilder.build()?));
+    }
 
     let mut config = config.clone();
     let mut results = Vec::new();
@@ -48,18 +66,23 @@ pub async fn bench(
                 );
                 continue;
             }
-            Mirror::MirrorList {
-                preferred: _,
-                mirrorlist: _,
-            } => repo.get_mirrors()?,
+            Mirror::MirrorList { .. } => repo.get_mirrors()?,
         };
+        if repo.flat {
+            msg!(
+                "Skipping repository {} because flat repositories don't provide contents metadata to benchmark with.",
+                style(name).bold()
+            );
+            continue;
+        }
 
         msg!("Running benchmark for repository {}...", style(name).bold());
         let mut res = Vec::new();
+        let arch = repo.arch.as_deref().unwrap_or(&config.arch);
         // Fetch Contents-all.gz for specified repo
         let contents_filename = format!(
             "Contents_{}_{}_{}.gz",
-            repo.distribution, repo.components[0], config.arch
+            repo.distribution, repo.components[0], arch
         );
         // Get ChecksumValidator for this file
         let local = db.get_contents_db(name)?;
@@ -81,30 +104,49 @@ pub async fn bench(
 
         for (name, mirror) in urls {
             let contents_url = format!(
-                "{}/dists/{}/{}/Contents-{}.gz",
-                mirror.url, repo.distribution, repo.components[0], config.arch
+                "{}/{}/Contents-{}.gz",
+                repo.release_dir_url(&mirror.url),
+                repo.components[0],
+                arch
             );
-            // Start counting
-            let start = Instant::now();
-            match try_download(&contents_url, &client, validator.clone()).await {
-                Ok(_) => {
-                    let time = start.elapsed();
-                    res.push((name.clone(), mirror.url.clone(), Some(time)));
-                }
-                Err(e) => {
-                    msg!("Mirror {name} failed to complete benchmark: {e}");
-                    res.push((name.clone(), mirror.url.clone(), None));
-                }
+            for (family, client) in &clients {
+                // Start counting
+                let start = Instant::now();
+                let time = match try_download(&contents_url, client, validator.clone()).await {
+                    Ok(_) => Some(start.elapsed()),
+                    Err(e) => {
+                        match family {
+                            Some(family) if is_connect_error(&e) => {
+                                msg!("Mirror {name} is not reachable over {family}: {e}");
+                            }
+                            Some(family) => {
+                                msg!(
+                                    "Mirror {name} failed to complete benchmark over {family}: {e}"
+                                );
+                            }
+                            None => {
+                                msg!("Mirror {name} failed to complete benchmark: {e}");
+                            }
+                        }
+                        None
+                    }
+                };
+                res.push(MirrorResult {
+                    name: name.clone(),
+                    url: mirror.url.clone(),
+                    family: *family,
+                    time,
+                });
             }
         }
         // Sort result based on time
-        res.sort_by_key(|(_, _, time)| time.unwrap_or(Duration::MAX));
+        res.sort_by_key(|r| r.time.unwrap_or(Duration::MAX));
         // Push result of this repo to results
         results.push((name.as_str(), size, res));
     }
 
     // Show results
-    show_bench_results(&results, opts.no_pager)?;
+    show_bench_results(&results, opts, config.pager.as_deref())?;
 
     // Ask if to write back results
     if crate::cli::ask_confirm(opts, "Apply optimal mirrors based on benchmark result?")? {
@@ -116,10 +158,7 @@ pub async fn bench(
             .join("config.toml");
         let original_toml = fs::read_to_string(&config_path)?;
         let mut new_config = original_toml.parse::<Document>()?;
-        for (repo_name, _, result) in &results {
-            let new_preferred = &result[0].0;
-            new_config["repo"][repo_name]["source"]["preferred"] = value(new_preferred);
-        }
+        apply_results(&mut new_config, &results);
         let new_config = new_config.to_string();
         std::fs::write(config_path, new_config)?;
         success!(
@@ -131,6 +170,40 @@ pub async fn bench(
     Ok(())
 }
 
+/// Benchmark result of a mirror, over one IP family if `family` is set
+struct MirrorResult {
+    name: String,
+    url: String,
+    family: Option<IpFamily>,
+    /// None if it failed
+    time: Option<Duration>,
+}
+
+/// Set the best mirror of each repository as the preferred one in config.toml
+fn apply_results(config: &mut Document, results: &[(&str, u64, Vec<MirrorResult>)]) {
+    for (repo_name, _, result) in results {
+        let best = &result[0];
+        if best.time.is_none() {
+            warn!(
+                "No mirror of repository {} completed benchmark, keeping the current one.",
+                style(repo_name).bold()
+            );
+            continue;
+        }
+        let source = &mut config["repo"][repo_name]["source"];
+        source["preferred"] = value(&best.name);
+        match best.family {
+            Some(family) => source["ip_family"] = value(family.as_config_str()),
+            // Measured over any family, so don't restrict it
+            None => {
+                if let Some(source) = source.as_table_like_mut() {
+                    source.remove("ip_family");
+                }
+            }
+        }
+    }
+}
+
 #[inline]
 async fn try_download(url: &str, client: &Client, mut validator: ChecksumValidator) -> Result<()> {
     let mut resp = client.get(url).send().await?;
@@ -159,28 +232,19 @@ struct BenchResultRow {
 
 #[inline]
 fn show_bench_results(
-    results: &[(&str, u64, Vec<(String, String, Option<Duration>)>)],
-    no_pager: bool,
+    results: &[(&str, u64, Vec<MirrorResult>)],
+    opts: &Opts,
+    pager_cmd: Option<&str>,
 ) -> Result<()> {
     info!("Benchmark result:");
 
-    let mut pager = Pager::new(no_pager)?;
-    let pager_name = pager.pager_name().to_owned();
-    let mut writer = pager.get_writer()?;
-
-    if pager_name == Some("less") {
-        writeln!(
-            writer,
-            "Press {} to finish reviewing benchmark result.",
-            style("q").bold()
-        )?;
-        writeln!(writer)?;
-    }
+    // Render tables first, so we can decide whether to use a pager
+    let mut out: Vec<u8> = Vec::new();
 
     for (name, size, repo_results) in results {
         let mut rows = Vec::new();
-        for (i, (name, url, time)) in repo_results.iter().enumerate() {
-            let speed = if let Some(duration) = time {
+        for (i, result) in repo_results.iter().enumerate() {
+            let speed = if let Some(duration) = result.time {
                 let ms = duration.as_millis();
                 // *1024 because ms to s
                 let bytes_per_sec: u128 = *size as u128 / ms * 1024;
@@ -193,10 +257,14 @@ fn show_bench_results(
             } else {
                 String::new()
             };
+            let name = match result.family {
+                Some(family) => format!("{} ({})", result.name, family),
+                None => result.name.clone(),
+            };
             let row = BenchResultRow {
                 best,
-                name: name.clone(),
-                url: url.clone(),
+                name,
+                url: result.url.clone(),
                 speed,
             };
             rows.push(row);
@@ -212,8 +280,22 @@ fn show_bench_results(
             .with(Modify::new(Column(0..1)).with(Alignment::center_horizontal()))
             .with(Modify::new(Column(1..)).with(|s: &str| format!(" {} ", s)))
             .with(Style::PSEUDO_CLEAN);
-        writeln!(writer, "{table}\n")?;
+        writeln!(out, "{table}\n")?;
+    }
+
+    let lines = count_lines(&out) + 2;
+    let mut pager = Pager::with_threshold(opts, pager_cmd, lines)?;
+    let pager_name = pager.pager_name().to_owned();
+    let mut writer = pager.get_writer()?;
+    if pager_name == Some("less") {
+        writeln!(
+            writer,
+            "Press {} to finish reviewing benchmark result.",
+            style("q").bold()
+        )?;
+        writeln!(writer)?;
     }
+    writer.write_all(&out)?;
 
     drop(writer);
     pager.wait_for_exit()?;
@@ -221,3 +303,59 @@ fn show_bench_results(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn result(name: &str, family: Option<IpFamily>, secs: Option<u64>) -> MirrorResult {
+        MirrorResult {
+            name: name.to_string(),
+            url: format!("https://{name}.example.org"),
+            family,
+            time: secs.map(Duration::from_secs),
+        }
+    }
+
+    #[test]
+    fn test_apply_results() {
+        let mut config = r#"
+[repo.main]
+source = { mirrorlist = "/mirrors.toml", preferred = "origin" }
+[repo.extra]
+source = { mirrorlist = "/mirrors.toml", preferred = "origin", ip_family = "ipv6" }
+[repo.broken]
+source = { mirrorlist = "/mirrors.toml", preferred = "origin" }
+"#
+        .parse::<Document>()
+        .unwrap();
+        let results = vec![
+            (
+                "main",
+                0,
+                vec![
+                    result("b", Some(IpFamily::Ipv6), Some(1)),
+                    result("b", Some(IpFamily::Ipv4), Some(2)),
+                    result("a", Some(IpFamily::Ipv6), None),
+                ],
+            ),
+            ("extra", 0, vec![result("a", None, Some(1))]),
+            ("broken", 0, vec![result("a", Some(IpFamily::Ipv4), None)]),
+        ];
+        apply_results(&mut config, &results);
+        let source = |repo: &str, key: &str| {
+            config["repo"][repo]["source"]
+                .get(key)
+                .and_then(|v| v.as_str())
+                .map(|s| s.to_owned())
+        };
+        assert_eq!(source("main", "preferred").as_deref(), Some("b"));
+        assert_eq!(source("main", "ip_family").as_deref(), Some("ipv6"));
+        // Benchmarked over any family, the old restriction is dropped
+        assert_eq!(source("extra", "preferred").as_deref(), Some("a"));
+        assert_eq!(source("extra", "ip_family"), None);
+        // Nothing reachable, left untouched
+        assert_eq!(source("broken", "preferred").as_deref(), Some("origin"));
+        assert_eq!(source("broken", "ip_family"), None);
+    }
+}
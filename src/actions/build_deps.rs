@@ -0,0 +1,124 @@
+use crate::{types::VersionRequirement, utils::debcontrol::arch_matches};
+
+use anyhow::{bail, format_err, Context, Result};
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::path::Path;
+
+const BUILD_DEPS_FIELDS: &[&str] = &["Build-Depends", "Build-Depends-Arch", "Build-Depends-Indep"];
+
+/// Read build dependencies from a debian/control or dsc file
+pub fn read_build_deps(path: &Path, arch: &str) -> Result<Vec<(String, VersionRequirement)>> {
+    let content = std::fs::read_to_string(path)
+        .context(format!("Failed to read control file {} .", path.display()))?;
+    parse_build_deps(&content, arch).context(format!(
+        "Failed to parse build dependencies from {} .",
+        path.display()
+    ))
+}
+
+fn parse_build_deps(content: &str, arch: &str) -> Result<Vec<(String, VersionRequirement)>> {
+    // dsc files may be signed, only keep the signed message
+    let content = match content.strip_prefix("-----BEGIN PGP SIGNED MESSAGE-----") {
+        Some(rest) => {
+            let start = rest.find("\n\n").map(|i| i + 2).unwrap_or(0);
+            let end = rest
+                .find("-----BEGIN PGP SIGNATURE-----")
+                .unwrap_or(rest.len());
+            &rest[start..end]
+        }
+        None => content,
+    };
+    // Strip comments
+    let content: String = content
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .map(|line| format!("{line}\n"))
+        .collect();
+    let paragraphs = match debcontrol::parse_str(&content) {
+        Ok(p) => p,
+        Err(e) => bail!("Failed to parse control file: {} .", e),
+    };
+
+    let mut res = Vec::new();
+    for p in paragraphs {
+        for field in p.fields {
+            if !BUILD_DEPS_FIELDS.contains(&field.name) {
+                continue;
+            }
+            for entry in field.value.split(',') {
+                let entry = entry.split_whitespace().collect::<Vec<&str>>().join(" ");
+                if entry.is_empty() {
+                    continue;
+                }
+                if let Some(dep) = parse_build_dep(&entry, arch)? {
+                    res.push(dep);
+                }
+            }
+        }
+    }
+
+    Ok(res)
+}
+
+/// Parse a single build dependency. Returns None if it doesn't apply to this build.
+fn parse_build_dep(entry: &str, arch: &str) -> Result<Option<(String, VersionRequirement)>> {
+    lazy_static! {
+        static ref BUILD_DEP: Regex = Regex::new(
+            r"^(?P<name>[a-z0-9][a-z0-9+.-]*)(?::[a-z0-9-]+)? ?(?:\((?P<ver>[^)]*)\))? ?(?:\[(?P<arch>[^\]]*)\])? ?(?P<profile><.*>)?$"
+        )
+        .unwrap();
+    }
+
+    // Only use the first alternative
+    let entry = entry.split('|').next().unwrap().trim();
+    let captures = BUILD_DEP
+        .captures(entry)
+        .ok_or_else(|| format_err!("Malformed build dependency: {entry}"))?;
+    // Ignore profile-restricted entries
+    if captures.name("profile").is_some() {
+        return Ok(None);
+    }
+    if let Some(archs) = captures.name("arch") {
+        if !arch_matches(archs.as_str(), arch) {
+            return Ok(None);
+        }
+    }
+    let ver_req = match captures.name("ver") {
+        Some(ver) => VersionRequirement::try_from(ver.as_str().trim())?,
+        None => VersionRequirement::new(),
+    };
+
+    Ok(Some((captures["name"].to_string(), ver_req)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_build_deps() {
+        let control = include_str!("../../tests/fixtures/control");
+        let deps = parse_build_deps(control, "amd64").unwrap();
+        let deps: Vec<String> = deps
+            .into_iter()
+            .map(|(name, ver_req)| format!("{name} {ver_req}"))
+            .collect();
+        assert_eq!(
+            deps,
+            vec![
+                "debhelper-compat =13",
+                "autoconf >=2.69",
+                "libfoo-dev ",
+                "libamd64-only ",
+                "gettext ",
+                "texinfo ",
+            ]
+        );
+
+        let deps = parse_build_deps(control, "arm64").unwrap();
+        let names: Vec<&str> = deps.iter().map(|(name, _)| name.as_str()).collect();
+        assert!(names.contains(&"libnot-amd64"));
+        assert!(!names.contains(&"libamd64-only"));
+    }
+}
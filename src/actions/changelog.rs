@@ -0,0 +1,250 @@
+use crate::{
+    db::LocalDb,
+    debug,
+    pool::{self, source::local::read_file_from_deb, PkgFields},
+    types::{
+        config::{Config, Opts},
+        PkgMeta, PkgSource,
+    },
+    utils::{
+        downloader::{Compression, DownloadJob, Downloader},
+        pager::Pager,
+    },
+    warn,
+};
+
+use anyhow::{bail, Context, Result};
+use console::style;
+use flate2::read::GzDecoder;
+use std::{
+    fs,
+    io::{Read, Write},
+    path::Path,
+};
+
+/// Show the changelog of the newest available version of `pkgname`
+pub async fn show_changelog(
+    pkgname: &str,
+    local_db: &LocalDb,
+    downloader: &Downloader,
+    cache: &Path,
+    opts: &Opts,
+    config: &Config,
+) -> Result<()> {
+    let dbs = local_db
+        .get_all_package_db()
+        .context("Invalid local package database")?;
+    let pool = pool::source::create_pool(&dbs, &[], PkgFields::All, &Default::default())?;
+    let pkg = match pool.get_pkgs_by_name(pkgname) {
+        Some(ids) => pool.get_pkg_by_id(ids[0]).unwrap(),
+        None => bail!("Package {} not found", style(pkgname).bold()),
+    };
+
+    page_changelog(pkg, downloader, cache, opts, config).await
+}
+
+/// Fetch the changelog of `pkg` and show it through the pager, or warn if there is none
+pub(super) async fn page_changelog(
+    pkg: &PkgMeta,
+    downloader: &Downloader,
+    cache: &Path,
+    opts: &Opts,
+    config: &Config,
+) -> Result<()> {
+    let changelog = match fetch_changelog(pkg, downloader, cache).await? {
+        Some(changelog) => changelog,
+        None => {
+            warn!(
+                "No changelog is available for {} {}.",
+                style(&pkg.name).bold(),
+                pkg.version
+            );
+            return Ok(());
+        }
+    };
+
+    let lines: Vec<&str> = changelog.lines().collect();
+    let mut pager = Pager::with_threshold(opts, config.pager.as_deref(), lines.len())?;
+    let mut writer = pager.get_writer()?;
+    for line in lines {
+        writeln!(writer, "{line}")?;
+    }
+    drop(writer);
+    pager.wait_for_exit()?;
+
+    Ok(())
+}
+
+/// Changelog of `pkg`, None if the repository doesn't provide one
+///
+/// A `.changelog` file next to the deb is preferred. Otherwise the deb is fetched into `cache`,
+/// where it is reused when the package is installed, and the changelog is read from
+/// `/usr/share/doc`.
+async fn fetch_changelog(
+    pkg: &PkgMeta,
+    downloader: &Downloader,
+    cache: &Path,
+) -> Result<Option<String>> {
+    let (url, size, checksum) = match &pkg.source {
+        PkgSource::Http((url, size, checksum)) => (url, size, checksum),
+        PkgSource::Local(path) => return changelog_from_deb(path, &pkg.name),
+        PkgSource::Installed => return Ok(None),
+    };
+    if let Some(changelog) = fetch_standalone(pkg, url, downloader).await {
+        return Ok(Some(changelog));
+    }
+
+    let job = DownloadJob {
+        url: url.clone(),
+        mirrors: pkg.mirror_urls(),
+        repos: pkg.repos.clone(),
+        description: None,
+        filename: None,
+        size: Some(*size),
+        compression: Compression::None(Some(checksum.clone())),
+    };
+    let res = downloader
+        .fetch(vec![job], cache, false)
+        .await
+        .context(format!(
+            "Failed to fetch {} from repository.",
+            style(&pkg.name).bold()
+        ))?;
+    changelog_from_deb(&res[url], &pkg.name)
+}
+
+/// Fetch the `.changelog` file published next to the deb at `url`, if any
+async fn fetch_standalone(pkg: &PkgMeta, url: &str, downloader: &Downloader) -> Option<String> {
+    let url = changelog_url(url)?;
+    let job = DownloadJob {
+        url: url.clone(),
+        mirrors: pkg
+            .mirror_urls()
+            .iter()
+            .filter_map(|m| changelog_url(m))
+            .collect(),
+        repos: pkg.repos.clone(),
+        description: Some(format!("Changelog of {}", pkg.name)),
+        filename: None,
+        size: None,
+        compression: Compression::None(None),
+    };
+    let dir = std::env::temp_dir().join(format!("omakase-changelog-{}", std::process::id()));
+    let res = match downloader.fetch(vec![job], &dir, false).await {
+        Ok(files) => files
+            .get(&url)
+            .and_then(|path| fs::read_to_string(path).ok()),
+        Err(e) => {
+            // Most repositories don't publish these
+            debug!("No standalone changelog for {}: {:#}", pkg.name, e);
+            None
+        }
+    };
+    fs::remove_dir_all(&dir).ok();
+    res
+}
+
+/// `foo_1.0_amd64.deb` -> `foo_1.0_amd64.changelog`
+fn changelog_url(url: &str) -> Option<String> {
+    url.strip_suffix(".deb")
+        .map(|base| format!("{base}.changelog"))
+}
+
+/// Read the Debian changelog, or the upstream one, shipped in the deb at `path`
+fn changelog_from_deb(path: &Path, pkgname: &str) -> Result<Option<String>> {
+    let doc = format!("./usr/share/doc/{pkgname}");
+    let content = read_file_from_deb(
+        path,
+        &[
+            &format!("{doc}/changelog.Debian.gz"),
+            &format!("{doc}/changelog.gz"),
+        ],
+    )?;
+    let content = match content {
+        Some(content) => content,
+        None => return Ok(None),
+    };
+    let mut changelog = String::new();
+    GzDecoder::new(content.as_slice())
+        .read_to_string(&mut changelog)
+        .context(format!(
+            "Failed to decompress changelog in {} .",
+            path.display()
+        ))?;
+    Ok(Some(changelog))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        types::{test_pkg, Checksum},
+        utils::{downloader::test_server, temp_dir::TempDir},
+    };
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_fetch_changelog() {
+        let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/debs");
+        let deb_path = fixtures.join("hello-changelog_1.1-1_all.deb");
+        let plain_path = fixtures.join("hello-gz_1.0-1_all.deb");
+        let deb = fs::read(&deb_path).unwrap();
+        let plain_deb = fs::read(&plain_path).unwrap();
+        let http_pkg = |name: &str, url: String, path: &Path| {
+            let mut pkg = test_pkg(name, "1.0");
+            let checksum = Checksum::from_file_sha256(path).unwrap();
+            pkg.source = PkgSource::Http((url, fs::metadata(path).unwrap().len(), checksum));
+            pkg
+        };
+
+        let mut files = HashMap::new();
+        files.insert("/debs/hello-changelog_1.1-1_all.deb".to_string(), deb);
+        files.insert(
+            "/debs/hello-gz_1.0-1_all.deb".to_string(),
+            plain_deb.clone(),
+        );
+        files.insert("/debs/foo_1.0_all.deb".to_string(), plain_deb);
+        files.insert(
+            "/debs/foo_1.0_all.changelog".to_string(),
+            b"foo (1.0) stable; urgency=low\n".to_vec(),
+        );
+        let base = test_server(files);
+        let cache = TempDir::new("changelog-test");
+        let downloader = Downloader::new(&Default::default(), &HashMap::new()).unwrap();
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+
+        // Published next to the deb
+        let foo = http_pkg("foo", format!("{base}/debs/foo_1.0_all.deb"), &plain_path);
+        let changelog = rt
+            .block_on(fetch_changelog(&foo, &downloader, &cache))
+            .unwrap();
+        assert_eq!(changelog.unwrap(), "foo (1.0) stable; urgency=low\n");
+
+        // Read from the deb, which is kept in cache
+        let hello = http_pkg(
+            "hello-changelog",
+            format!("{base}/debs/hello-changelog_1.1-1_all.deb"),
+            &deb_path,
+        );
+        let changelog = rt
+            .block_on(fetch_changelog(&hello, &downloader, &cache))
+            .unwrap()
+            .unwrap();
+        assert!(changelog.starts_with("hello-changelog (1.1-1) stable; urgency=medium"));
+        assert!(cache.join("hello-changelog_1.1-1_all.deb").is_file());
+
+        // Not available anywhere
+        let plain = http_pkg(
+            "hello-gz",
+            format!("{base}/debs/hello-gz_1.0-1_all.deb"),
+            &plain_path,
+        );
+        let changelog = rt
+            .block_on(fetch_changelog(&plain, &downloader, &cache))
+            .unwrap();
+        assert!(changelog.is_none());
+    }
+}
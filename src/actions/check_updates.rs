@@ -0,0 +1,120 @@
+use crate::{
+    db::LocalDb,
+    executor::{MachineStatus, PkgState},
+    pool::{self, PkgFields, PkgPool},
+    types::PkgVersion,
+};
+
+use anyhow::{Context, Result};
+use serde_json::{json, Value};
+use std::io::Write;
+
+/// Installed packages that have a newer version in the pool
+// -> Vec<(PkgName, Installed, Candidate)>, sorted by name
+fn find_upgradable(
+    pool: &dyn PkgPool,
+    machine_status: &MachineStatus,
+) -> Vec<(String, PkgVersion, PkgVersion)> {
+    let mut res = Vec::new();
+    for (name, status) in &machine_status.pkgs {
+        if status.state != PkgState::Installed {
+            continue;
+        }
+        // Versions are sorted in descending order
+        let newest = pool
+            .get_pkgs_by_name(name)
+            .and_then(|ids| ids.first().copied())
+            .and_then(|id| pool.get_pkg_by_id(id));
+        if let Some(pkg) = newest {
+            if pkg.version > status.version {
+                res.push((name.clone(), status.version.clone(), pkg.version.clone()));
+            }
+        }
+    }
+    res.sort();
+    res
+}
+
+/// Print the number of packages that can be upgraded, according to local package metadata
+///
+/// Returns how many packages can be upgraded.
+pub fn check_updates(
+    local_db: &LocalDb,
+    machine_status: &MachineStatus,
+    json: bool,
+) -> Result<usize> {
+    let dbs = local_db
+        .get_all_package_db()
+        .context("Invalid local package database")?;
+    let pool = pool::source::create_pool(&dbs, &[], PkgFields::All, &Default::default())?;
+    let upgradable = find_upgradable(pool.as_ref(), machine_status);
+
+    let mut writer = crate::WRITER.get_writer();
+    if json {
+        let pkgs: Vec<Value> = upgradable
+            .iter()
+            .map(|(name, installed, candidate)| {
+                json!({
+                    "name": name,
+                    "installed": installed.to_string(),
+                    "candidate": candidate.to_string(),
+                })
+            })
+            .collect();
+        writeln!(
+            writer,
+            "{}",
+            json!({"count": upgradable.len(), "packages": pkgs})
+        )?;
+    } else {
+        writeln!(writer, "{}", upgradable.len())?;
+    }
+
+    Ok(upgradable.len())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        executor::PkgStatus,
+        pool::{BasicPkgPool, InMemoryPool},
+        types::test_pkg as pkg,
+    };
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_find_upgradable() {
+        let ver = |v| PkgVersion::try_from(v).unwrap();
+        let status = |name: &str, v, state| PkgStatus {
+            name: name.to_string(),
+            version: ver(v),
+            state,
+            install_size: 0,
+            essential: false,
+            depends: Vec::new(),
+            conflicts: Vec::new(),
+        };
+        let mut pool = InMemoryPool::new();
+        for (name, v) in [("a", "1.0"), ("a", "2.0"), ("b", "1.0"), ("c", "2.0")] {
+            pool.add(pkg(name, v));
+        }
+        pool.finalize();
+        let machine_status = MachineStatus {
+            pkgs: HashMap::from([
+                ("a".to_string(), status("a", "1.0", PkgState::Installed)),
+                // Newer than the repository
+                ("b".to_string(), status("b", "2.0", PkgState::Installed)),
+                // Only config files are left
+                ("c".to_string(), status("c", "1.0", PkgState::ConfigFiles)),
+                // Not in the repository
+                ("d".to_string(), status("d", "1.0", PkgState::Installed)),
+            ]),
+        };
+
+        assert_eq!(
+            find_upgradable(&pool, &machine_status),
+            vec![("a".to_string(), ver("1.0"), ver("2.0"))]
+        );
+    }
+}
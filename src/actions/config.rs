@@ -0,0 +1,124 @@
+use crate::{
+    msg, success,
+    types::config::{Config, ConfigCmd, ConfigKey, ConfigOpts, Opts},
+    utils::arch,
+    warn, WRITER,
+};
+
+use anyhow::{bail, Context, Result};
+use console::style;
+use std::{fs, io::Write, path::Path};
+use toml_edit::{value, Document};
+
+pub fn config_command(
+    opts: &Opts,
+    config_root: &Path,
+    config: &Config,
+    cmd: &ConfigOpts,
+) -> Result<()> {
+    let native = arch::detect(&opts.root, config.dpkg_admindir.as_deref());
+    match &cmd.cmd {
+        ConfigCmd::Get(get) => match get.key {
+            ConfigKey::Arch => {
+                writeln!(WRITER.get_writer(), "{}", config.arch)?;
+                match native {
+                    Some(native) if native != config.arch => {
+                        warn!(
+                            "This system is {}, run {} to use it.",
+                            style(&native).bold(),
+                            style(format!("oma config set arch {native}")).bold()
+                        );
+                    }
+                    Some(_) => (),
+                    None => warn!("Failed to detect the architecture of this system."),
+                }
+            }
+        },
+        ConfigCmd::Set(set) => {
+            let new = match (&set.value, set.key) {
+                (Some(new), _) => new.clone(),
+                (None, ConfigKey::Arch) => native.clone().context(
+                    "Failed to detect the architecture of this system, please specify it.",
+                )?,
+            };
+            if new == config.arch {
+                msg!(
+                    "{} is already {}.",
+                    style("arch").bold(),
+                    style(&new).bold()
+                );
+                return Ok(());
+            }
+            if let Some(native) = native.filter(|native| *native != new) {
+                warn!(
+                    "This system is {}, packages of {} may not be installable.",
+                    style(native).bold(),
+                    style(&new).bold()
+                );
+            }
+            let config_path = config_root.join("config.toml");
+            let mut doc = fs::read_to_string(&config_path)
+                .context(format!(
+                    "Failed to read configuration file {}.",
+                    config_path.display()
+                ))?
+                .parse::<Document>()
+                .context("Failed to parse configuration file.")?;
+            set_value(&mut doc, set.key, &new)?;
+            fs::write(&config_path, doc.to_string()).context(format!(
+                "Failed to write configuration file {}.",
+                config_path.display()
+            ))?;
+            success!(
+                "{} is set to {} in {}.",
+                style("arch").bold(),
+                style(&new).bold(),
+                style("config.toml").bold()
+            );
+            msg!(
+                "Run {} to fetch package metadata for it.",
+                style("oma refresh").bold()
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Set `key` to `new` in config.toml, keeping the rest of it as is
+fn set_value(doc: &mut Document, key: ConfigKey, new: &str) -> Result<()> {
+    match key {
+        ConfigKey::Arch => {
+            // Names used by dpkg, e.g. amd64, arm64 or musl-linux-amd64
+            let valid = !new.is_empty()
+                && new
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+            if !valid {
+                bail!("Invalid architecture {}.", style(new).bold());
+            }
+            doc["arch"] = value(new);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_set_arch() {
+        let mut doc = "# Set by the installer\narch = \"i386\"\n\n[repo.main]\nsource = \"https://repo.example.org\"\n"
+            .parse::<Document>()
+            .unwrap();
+        set_value(&mut doc, ConfigKey::Arch, "amd64").unwrap();
+        assert_eq!(
+            doc.to_string(),
+            "# Set by the installer\narch = \"amd64\"\n\n[repo.main]\nsource = \"https://repo.example.org\"\n"
+        );
+        assert!(set_value(&mut doc, ConfigKey::Arch, "AMD64").is_err());
+        assert!(set_value(&mut doc, ConfigKey::Arch, "").is_err());
+        assert_eq!(doc["arch"].as_str(), Some("amd64"));
+    }
+}
@@ -0,0 +1,134 @@
+use crate::{
+    cli::ask_confirm,
+    executor::{dpkg, journal::Journal, MachineStatus, PkgState},
+    info, success,
+    types::{
+        config::{Config, Opts},
+        PkgActions, PkgVersion,
+    },
+};
+
+use anyhow::{bail, Result};
+use console::style;
+use std::collections::{HashMap, HashSet};
+
+/// Configure packages that are unpacked but not configured, without fetching or resolving anything
+// -> Result<UserCancelled?>
+pub fn only_configure(opts: &Opts, config: &Config) -> Result<bool> {
+    if opts.root != std::path::Path::new("/") {
+        bail!("Packages are never configured in external system root mode, run this on the system itself.");
+    }
+    if Journal::load(&opts.root)?.is_some() {
+        bail!(
+            "The last transaction was interrupted, run {} to resume it first.",
+            style("oma upgrade").bold()
+        );
+    }
+
+    let machine_status = MachineStatus::new(&opts.root, config.dpkg_admindir.as_deref())?;
+    let actions = PkgActions {
+        configure: pending_configure(&machine_status),
+        ..Default::default()
+    };
+    if actions.is_empty() {
+        success!("No package is waiting to be configured.");
+        return Ok(false);
+    }
+    info!("Omakase will configure the following packages:");
+    actions.show();
+    if opts.no_act {
+        info!("Stopping before running dpkg as requested.");
+        return Ok(false);
+    }
+    if !ask_confirm(opts, "Proceed?")? {
+        return Ok(true);
+    }
+
+    let count = actions.configure.len();
+    let unsafe_config = config.r#unsafe.clone().unwrap_or_default();
+    dpkg::execute_pkg_actions(
+        actions,
+        &HashMap::new(),
+        &opts.root,
+        config.dpkg_admindir.as_deref(),
+        &config.dpkg_force.args(unsafe_config.unsafe_io),
+        config.dpkg_order,
+    )?;
+    success!("{} package(s) have been configured.", count);
+    Ok(false)
+}
+
+/// Packages left unpacked, half-configured or waiting for triggers, dependencies first
+fn pending_configure(machine_status: &MachineStatus) -> Vec<(String, PkgVersion)> {
+    let mut pending: Vec<&str> = machine_status
+        .pkgs
+        .values()
+        .filter(|pkg| {
+            matches!(
+                pkg.state,
+                PkgState::Unpacked
+                    | PkgState::HalfConfigured
+                    | PkgState::TriggerAwaited
+                    | PkgState::TriggerPending
+            )
+        })
+        .map(|pkg| pkg.name.as_str())
+        .collect();
+    // Keep the order stable between runs
+    pending.sort_unstable();
+
+    let mut res = Vec::new();
+    let mut visited = HashSet::new();
+    for name in &pending {
+        visit(machine_status, &pending, name, &mut visited, &mut res);
+    }
+    res
+}
+
+/// Add dependencies of `name` that are pending as well, then `name` itself. Cycles are broken
+/// where they are found.
+fn visit<'a>(
+    machine_status: &'a MachineStatus,
+    pending: &[&str],
+    name: &'a str,
+    visited: &mut HashSet<&'a str>,
+    res: &mut Vec<(String, PkgVersion)>,
+) {
+    if !visited.insert(name) {
+        return;
+    }
+    let pkg = &machine_status.pkgs[name];
+    for dep in &pkg.depends {
+        if pending.contains(&dep.as_str()) {
+            visit(machine_status, pending, dep, visited, res);
+        }
+    }
+    res.push((pkg.name.clone(), pkg.version.clone()));
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn test_pending_configure() {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+        let ms =
+            MachineStatus::new(root, Some(Path::new("tests/fixtures/admindir-pending"))).unwrap();
+        assert_eq!(
+            ms.pkgs["curl"].depends,
+            vec!["openssl", "zlib", "ca-certs", "ca-certificates"]
+        );
+        let names: Vec<String> = pending_configure(&ms)
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        // Installed and removed packages are left alone
+        assert_eq!(names, vec!["zlib", "openssl", "ca-certs", "curl"]);
+
+        // Nothing pending
+        let ms = MachineStatus::new(root, Some(Path::new("tests/fixtures/admindir"))).unwrap();
+        assert!(pending_configure(&ms).is_empty());
+    }
+}
@@ -0,0 +1,287 @@
+use crate::{
+    db::LocalDb,
+    pool::{self, PkgFields, PkgPool},
+    types::config::{Config, DependsPkg, GraphFormat, Opts},
+    utils::pager::Pager,
+};
+
+use anyhow::{bail, Context, Result};
+use console::style;
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    io::Write,
+};
+
+/// Marker for packages whose dependencies have been shown earlier in the tree
+const SHOWN_MARKER: &str = "(*)";
+
+/// Dependency relations between the newest versions of packages in a pool
+struct DepGraph<'a> {
+    // PkgName -> Vec<(PkgName, VersionRequirement, IsRecommends)>
+    edges: HashMap<&'a str, Vec<(&'a str, String, bool)>>,
+    // PkgName -> Version
+    versions: HashMap<&'a str, String>,
+}
+
+impl<'a> DepGraph<'a> {
+    fn new(pool: &'a dyn PkgPool, recommends: bool, reverse: bool) -> Self {
+        let mut edges: HashMap<&str, Vec<(&str, String, bool)>> = HashMap::new();
+        let mut pkg_versions = HashMap::new();
+        for (_, versions) in pool.pkgname_iter() {
+            let pkg = match versions.first() {
+                Some((id, _)) => pool.get_pkg_by_id(*id).unwrap(),
+                None => continue,
+            };
+            pkg_versions.insert(pkg.name.as_str(), pkg.version.to_string());
+            let deps = pkg
+                .pre_depends
+                .iter()
+                .chain(pkg.depends.iter())
+                .map(|dep| (dep, false));
+            let recomms = pkg
+                .recommends
+                .iter()
+                .flatten()
+                .filter(|_| recommends)
+                .map(|dep| (dep, true));
+            for ((name, ver_req), is_recomm) in deps.chain(recomms) {
+                let ver_req = if ver_req.is_arbitary() {
+                    String::new()
+                } else {
+                    ver_req.to_string()
+                };
+                let (from, to) = if reverse {
+                    (name.as_str(), pkg.name.as_str())
+                } else {
+                    (pkg.name.as_str(), name.as_str())
+                };
+                let children = edges.entry(from).or_default();
+                // Pre-Depends and Depends may list the same package
+                if !children.iter().any(|(n, _, _)| *n == to) {
+                    children.push((to, ver_req, is_recomm));
+                }
+            }
+        }
+        for children in edges.values_mut() {
+            children.sort();
+        }
+        DepGraph {
+            edges,
+            versions: pkg_versions,
+        }
+    }
+
+    /// Format everything reachable from `name` as a Graphviz DOT graph
+    ///
+    /// Nodes are labeled with versions, recommended packages are linked with dashed edges.
+    fn dot(&self, name: &'a str) -> Vec<String> {
+        let mut nodes = Vec::new();
+        let mut edges = Vec::new();
+        let mut visited = HashSet::from([name]);
+        let mut queue = VecDeque::from([name]);
+        while let Some(pkg) = queue.pop_front() {
+            let label = match self.versions.get(pkg) {
+                Some(version) => format!("{pkg}\\n{version}"),
+                // Virtual or missing packages
+                None => pkg.to_string(),
+            };
+            nodes.push(format!("    {} [label={}];", dot_str(pkg), dot_str(&label)));
+            for (child, _, is_recomm) in self.edges.get(pkg).into_iter().flatten() {
+                let style = if *is_recomm { " [style=dashed]" } else { "" };
+                edges.push(format!(
+                    "    {} -> {}{style};",
+                    dot_str(pkg),
+                    dot_str(child)
+                ));
+                // Cycles end here, every package is only visited once
+                if visited.insert(*child) {
+                    queue.push_back(*child);
+                }
+            }
+        }
+
+        let mut lines = vec![format!("digraph {} {{", dot_str(name))];
+        lines.extend(nodes);
+        lines.extend(edges);
+        lines.push("}".to_string());
+        lines
+    }
+
+    /// Format the dependency tree of `name`, only direct dependencies are shown if not `recursive`
+    fn tree(&self, name: &'a str, recursive: bool) -> Vec<String> {
+        let mut lines = vec![name.to_string()];
+        let mut shown = HashSet::from([name]);
+        self.walk(name, "", recursive, &mut shown, &mut lines);
+        lines
+    }
+
+    fn walk(
+        &self,
+        name: &'a str,
+        prefix: &str,
+        recursive: bool,
+        shown: &mut HashSet<&'a str>,
+        lines: &mut Vec<String>,
+    ) {
+        let children = match self.edges.get(name) {
+            Some(children) => children,
+            None => return,
+        };
+        for (i, (child, ver_req, is_recomm)) in children.iter().enumerate() {
+            let last = i == children.len() - 1;
+            let mut line = format!("{prefix}{}{child}", if last { "└── " } else { "├── " });
+            if !ver_req.is_empty() {
+                line.push_str(&format!(" ({ver_req})"));
+            }
+            if *is_recomm {
+                line.push_str(" [recommends]");
+            }
+            let has_children = recursive && self.edges.contains_key(child);
+            // Cycles end here as well, since packages on the current path are already shown
+            let expand = has_children && shown.insert(*child);
+            if has_children && !expand {
+                line.push(' ');
+                line.push_str(SHOWN_MARKER);
+            }
+            lines.push(line);
+            if expand {
+                let prefix = format!("{prefix}{}", if last { "    " } else { "│   " });
+                self.walk(child, &prefix, recursive, shown, lines);
+            }
+        }
+    }
+}
+
+/// Quote `s` as a DOT identifier
+fn dot_str(s: &str) -> String {
+    format!("\"{}\"", s.replace('"', "\\\""))
+}
+
+/// Show what a package depends on, or what depends on it with `--reverse`
+///
+/// With `--graph`, the whole graph is written to stdout in the given format instead.
+pub fn show_depends(
+    local_db: &LocalDb,
+    depends: &DependsPkg,
+    opts: &Opts,
+    config: &Config,
+) -> Result<()> {
+    let pkgname = depends.name.as_str();
+    let dbs = local_db
+        .get_all_package_db()
+        .context("Invalid local package database")?;
+    let pool = pool::source::create_pool(&dbs, &[], PkgFields::All, &Default::default())?;
+    if pool.get_pkgs_by_name(pkgname).is_none() {
+        bail!("Package {} not found", style(pkgname).bold());
+    }
+
+    let dep_graph = DepGraph::new(pool.as_ref(), depends.recommends, depends.reverse);
+    if depends.graph {
+        // Not through a pager, so that it can be piped into `dot`
+        let mut writer = crate::WRITER.get_writer();
+        let lines = match depends.format {
+            GraphFormat::Dot => dep_graph.dot(pkgname),
+        };
+        for line in lines {
+            writeln!(writer, "{line}")?;
+        }
+        return Ok(());
+    }
+    let lines = dep_graph.tree(pkgname, depends.tree);
+    let mut pager = Pager::with_threshold(opts, config.pager.as_deref(), lines.len())?;
+    let mut writer = pager.get_writer()?;
+    for line in lines {
+        writeln!(writer, "{line}")?;
+    }
+    drop(writer);
+    pager.wait_for_exit()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        pool::{BasicPkgPool, InMemoryPool},
+        types::{test_pkg as pkg, VersionRequirement},
+    };
+
+    #[test]
+    fn test_dep_tree() {
+        let any = VersionRequirement::default;
+        // a -> b -> c -> a (cycle), a -> c, a recommends d
+        let mut a = pkg("a", "1.0");
+        a.depends = vec![("b".to_string(), any()), ("c".to_string(), any())];
+        a.recommends = Some(vec![("d".to_string(), any())]);
+        let mut b = pkg("b", "1.0");
+        b.pre_depends = vec![("c".to_string(), any())];
+        let mut c = pkg("c", "1.0");
+        c.depends = vec![("a".to_string(), any())];
+        let mut pool = InMemoryPool::new();
+        for meta in [a, b, c, pkg("d", "1.0")] {
+            pool.add(meta);
+        }
+        pool.finalize();
+
+        let graph = DepGraph::new(&pool, false, false);
+        assert_eq!(
+            graph.tree("a", true),
+            vec!["a", "├── b", "│   └── c", "│       └── a (*)", "└── c (*)"]
+        );
+        assert_eq!(graph.tree("a", false), vec!["a", "├── b", "└── c"]);
+
+        let graph = DepGraph::new(&pool, true, false);
+        assert_eq!(
+            graph.tree("a", false),
+            vec!["a", "├── b", "├── c", "└── d [recommends]"]
+        );
+
+        let graph = DepGraph::new(&pool, false, true);
+        assert_eq!(
+            graph.tree("c", true),
+            vec!["c", "├── a", "│   └── c (*)", "└── b", "    └── a (*)"]
+        );
+    }
+
+    #[test]
+    fn test_dep_dot() {
+        let any = VersionRequirement::default;
+        // a -> b -> c -> a (cycle), a -> c, a recommends d, d -> virtual e
+        let mut a = pkg("a", "1.0");
+        a.depends = vec![("b".to_string(), any()), ("c".to_string(), any())];
+        a.recommends = Some(vec![("d".to_string(), any())]);
+        let mut b = pkg("b", "2.0");
+        b.pre_depends = vec![("c".to_string(), any())];
+        b.depends = vec![("c".to_string(), any())];
+        let mut c = pkg("c", "3.0");
+        c.depends = vec![("a".to_string(), any())];
+        let mut d = pkg("d", "4.0");
+        d.depends = vec![("e".to_string(), any())];
+        let mut pool = InMemoryPool::new();
+        for meta in [a, b, c, d] {
+            pool.add(meta);
+        }
+        pool.finalize();
+
+        let graph = DepGraph::new(&pool, true, false);
+        assert_eq!(
+            graph.dot("a"),
+            vec![
+                r#"digraph "a" {"#,
+                r#"    "a" [label="a\n1.0"];"#,
+                r#"    "b" [label="b\n2.0"];"#,
+                r#"    "c" [label="c\n3.0"];"#,
+                r#"    "d" [label="d\n4.0"];"#,
+                r#"    "e" [label="e"];"#,
+                r#"    "a" -> "b";"#,
+                r#"    "a" -> "c";"#,
+                r#"    "a" -> "d" [style=dashed];"#,
+                r#"    "b" -> "c";"#,
+                r#"    "c" -> "a";"#,
+                r#"    "d" -> "e";"#,
+                "}",
+            ]
+        );
+    }
+}
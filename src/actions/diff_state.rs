@@ -0,0 +1,116 @@
+use crate::types::{config::DiffStateOpts, LockDiff, Lockfile};
+
+use anyhow::Result;
+use console::style;
+use serde_json::{json, Value};
+use std::{io::Write, path::Path};
+
+/// Show how the package set recorded in one lockfile differs from another
+pub fn diff_state(cmd: &DiffStateOpts) -> Result<()> {
+    let diff = compare_files(&cmd.old, &cmd.new)?;
+    let lines = if cmd.json {
+        vec![to_json(&diff)]
+    } else {
+        to_lines(&diff)
+    };
+
+    let mut writer = crate::WRITER.get_writer();
+    for line in lines {
+        writeln!(writer, "{line}")?;
+    }
+
+    Ok(())
+}
+
+fn compare_files(old: &Path, new: &Path) -> Result<LockDiff> {
+    let old = Lockfile::from_file(old)?;
+    let new = Lockfile::from_file(new)?;
+    Ok(old.compare(&new))
+}
+
+fn to_lines(diff: &LockDiff) -> Vec<String> {
+    let mut res = Vec::new();
+    if diff.is_empty() {
+        res.push(format!("{}", style("No differences.").green()));
+    }
+    if !diff.added.is_empty() {
+        res.push(format!("{}", style("Added packages:").bold()));
+        for (name, ver) in &diff.added {
+            res.push(format!("  {} {}", style(name).green(), ver));
+        }
+    }
+    if !diff.removed.is_empty() {
+        res.push(format!("{}", style("Removed packages:").bold()));
+        for (name, ver) in &diff.removed {
+            res.push(format!("  {} {}", style(name).red(), ver));
+        }
+    }
+    if !diff.changed.is_empty() {
+        res.push(format!("{}", style("Changed packages:").bold()));
+        for (name, old, new) in &diff.changed {
+            res.push(format!("  {}: {} -> {}", style(name).yellow(), old, new));
+        }
+    }
+    res
+}
+
+fn to_json(diff: &LockDiff) -> String {
+    let pkgs = |pkgs: &[(String, String)]| -> Vec<Value> {
+        pkgs.iter()
+            .map(|(name, ver)| json!({"name": name, "version": ver}))
+            .collect()
+    };
+    let changed: Vec<Value> = diff
+        .changed
+        .iter()
+        .map(|(name, old, new)| json!({"name": name, "old": old, "new": new}))
+        .collect();
+    json!({
+        "added": pkgs(&diff.added),
+        "removed": pkgs(&diff.removed),
+        "changed": changed,
+    })
+    .to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::utils::temp_dir::TempDir;
+    use std::fs;
+
+    #[test]
+    fn test_diff_state() {
+        let dir = TempDir::new("diff-state");
+        let old = dir.join("old.lock");
+        let new = dir.join("new.lock");
+        fs::write(
+            &old,
+            "[[package]]\nname = \"bash\"\nversion = \"5.1\"\nchecksum = \"sha256::00\"\n\n\
+             [[package]]\nname = \"vim\"\nversion = \"9.0\"\n\n\
+             [[package]]\nname = \"zlib\"\nversion = \"1:1.2.12\"\n",
+        )
+        .unwrap();
+        fs::write(
+            &new,
+            "[[package]]\nname = \"zlib\"\nversion = \"1:1.2.12\"\n\n\
+             [[package]]\nname = \"bash\"\nversion = \"5.2\"\nchecksum = \"sha256::01\"\n\n\
+             [[package]]\nname = \"curl\"\nversion = \"8.0\"\n",
+        )
+        .unwrap();
+
+        let diff = compare_files(&old, &new).unwrap();
+        assert_eq!(
+            to_json(&diff),
+            r#"{"added":[{"name":"curl","version":"8.0"}],"removed":[{"name":"vim","version":"9.0"}],"changed":[{"name":"bash","old":"5.1","new":"5.2"}]}"#
+        );
+        let lines = to_lines(&diff);
+        assert_eq!(lines.len(), 6);
+        assert!(console::strip_ansi_codes(&lines[5]).ends_with("bash: 5.1 -> 5.2"));
+
+        let same = compare_files(&new, &new).unwrap();
+        assert!(same.is_empty());
+        assert_eq!(to_json(&same), r#"{"added":[],"removed":[],"changed":[]}"#);
+        assert!(compare_files(&old, &dir.join("missing.lock")).is_err());
+    }
+}
@@ -0,0 +1,410 @@
+use crate::{
+    msg,
+    types::config::{Config, Opts},
+    utils::{alt_root::join_root, lock},
+    WRITER,
+};
+
+use anyhow::{bail, Result};
+use console::style;
+use nix::unistd::Uid;
+use std::{fs, io::Write, path::Path, process::Command};
+use tabled::{Alignment, Full, Modify, Style, Table, Tabled};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Status {
+    Pass,
+    Warn,
+    Fail,
+}
+
+/// Result of a single check
+#[derive(Debug)]
+struct Check {
+    name: String,
+    status: Status,
+    detail: String,
+    /// How to fix it, if it doesn't pass
+    hint: Option<String>,
+}
+
+impl Check {
+    fn pass(name: impl Into<String>, detail: impl Into<String>) -> Self {
+        Check {
+            name: name.into(),
+            status: Status::Pass,
+            detail: detail.into(),
+            hint: None,
+        }
+    }
+
+    fn problem(
+        status: Status,
+        name: impl Into<String>,
+        detail: impl Into<String>,
+        hint: impl Into<String>,
+    ) -> Self {
+        Check {
+            name: name.into(),
+            status,
+            detail: detail.into(),
+            hint: Some(hint.into()),
+        }
+    }
+}
+
+#[derive(Tabled)]
+struct CheckRow {
+    #[header("Check")]
+    name: String,
+    #[header("Result")]
+    status: String,
+    #[header("Details")]
+    detail: String,
+}
+
+/// Check the configuration and the environment Omakase works in, fails if any hard check fails
+///
+/// This doesn't rely on the configuration being valid, so it's handled before loading it.
+pub fn doctor(opts: &Opts) -> Result<()> {
+    let checks = run_checks(opts);
+    let rows: Vec<CheckRow> = checks
+        .iter()
+        .map(|check| CheckRow {
+            name: check.name.clone(),
+            status: match check.status {
+                Status::Pass => style("PASS").green().to_string(),
+                Status::Warn => style("WARN").yellow().to_string(),
+                Status::Fail => style("FAIL").red().bold().to_string(),
+            },
+            detail: check.detail.clone(),
+        })
+        .collect();
+    let table = Table::new(&rows)
+        .with(Modify::new(Full).with(Alignment::left()))
+        .with(Modify::new(Full).with(|s: &str| format!(" {s} ")))
+        .with(Style::PSEUDO_CLEAN);
+    writeln!(WRITER.get_writer(), "\n{table}")?;
+
+    for check in &checks {
+        if let Some(hint) = &check.hint {
+            msg!("{}: {}", style(&check.name).bold(), hint);
+        }
+    }
+    let failed = checks.iter().filter(|c| c.status == Status::Fail).count();
+    if failed > 0 {
+        bail!("{} check(s) failed.", style(failed).bold());
+    }
+
+    Ok(())
+}
+
+fn run_checks(opts: &Opts) -> Vec<Check> {
+    let mut checks = Vec::new();
+    let is_root = Uid::effective().is_root();
+
+    // Configuration, nothing else can be checked without it
+    let config = match load_config(opts, &mut checks) {
+        Some(config) => config,
+        None => return checks,
+    };
+
+    // Public keys of repositories
+    let key_root = opts.root.join(crate::DB_KEY_PATH);
+    checks.append(&mut check_keys(&config, &key_root));
+
+    // Directories Omakase writes to
+    for (name, path) in [
+        ("Metadata cache", crate::DB_CACHE_PATH),
+        ("Package cache", crate::PKG_CACHE_PATH),
+    ] {
+        checks.push(check_writable(name, &opts.root.join(path), is_root));
+    }
+
+    // dpkg, and the architectures it accepts
+    checks.append(&mut check_dpkg(opts, &config));
+
+    // Instance lock
+    checks.push(check_lock(&opts.root, is_root));
+
+    checks
+}
+
+/// Check that the config can be found, parsed and passes sanity checks
+fn load_config(opts: &Opts, checks: &mut Vec<Check>) -> Option<Config> {
+    let name = "Configuration";
+    let config_root = opts.root.join(&opts.config_root);
+    if !config_root.is_dir() {
+        checks.push(Check::problem(
+            Status::Fail,
+            name,
+            format!("{} is not a directory", config_root.display()),
+            "Pass the right directory with --config-root, relative to --root.",
+        ));
+        return None;
+    }
+    let config_path = config_root.join("config.toml");
+    let config: Config = match fs::read_to_string(&config_path)
+        .map_err(anyhow::Error::from)
+        .and_then(|data| Ok(toml::from_str(&data)?))
+    {
+        Ok(config) => config,
+        Err(e) => {
+            checks.push(Check::problem(
+                Status::Fail,
+                name,
+                format!("Failed to load {}: {}", config_path.display(), e),
+                "Fix the configuration file, see doc/config.md for its format.",
+            ));
+            return None;
+        }
+    };
+    if let Err(e) = config.check_sanity() {
+        checks.push(Check::problem(
+            Status::Fail,
+            name,
+            format!("{e:#}"),
+            "Fix the configuration file, see doc/config.md for its format.",
+        ));
+        return None;
+    }
+    checks.push(Check::pass(
+        name,
+        format!(
+            "{}, {} repository(s)",
+            config_path.display(),
+            config.repo.len()
+        ),
+    ));
+    Some(config)
+}
+
+/// Check that key files of each repository are in `key_root`
+fn check_keys(config: &Config, key_root: &Path) -> Vec<Check> {
+    let mut names: Vec<&String> = config.repo.keys().collect();
+    names.sort();
+    let mut checks = Vec::new();
+    for name in names {
+        let repo = &config.repo[name];
+        let check_name = format!("Keys of {name}");
+        let files: Vec<&str> = repo.keys.iter().map(|key| key.file()).collect();
+        let missing: Vec<&str> = files
+            .iter()
+            .filter(|file| !key_root.join(file).is_file())
+            .copied()
+            .collect();
+        let check = if repo.trusted {
+            Check::problem(
+                Status::Warn,
+                check_name,
+                "Signature is not verified (trusted = true)",
+                "Sign the repository, then add its public key and remove `trusted`.",
+            )
+        } else if repo.keys.is_empty() {
+            Check::problem(
+                Status::Fail,
+                check_name,
+                "No key configured",
+                "Add the public key with `oma key add`, then list it in `keys` of the repository.",
+            )
+        } else if !missing.is_empty() {
+            Check::problem(
+                Status::Fail,
+                check_name,
+                format!("Missing in {}: {}", key_root.display(), missing.join(", ")),
+                "Add the public key with `oma key add`, or fix its name in `keys` of the repository.",
+            )
+        } else {
+            Check::pass(check_name, files.join(", "))
+        };
+        checks.push(check);
+    }
+    checks
+}
+
+/// Check that files can be created in `dir`, only a warning if not running as root
+fn check_writable(name: &str, dir: &Path, is_root: bool) -> Check {
+    let probe = dir.join(".omakase-doctor");
+    let res = fs::create_dir_all(dir)
+        .and_then(|_| fs::write(&probe, b""))
+        .and_then(|_| fs::remove_file(&probe));
+    match res {
+        Ok(()) => Check::pass(name, dir.display().to_string()),
+        Err(e) => Check::problem(
+            if is_root { Status::Fail } else { Status::Warn },
+            name,
+            format!("{} is not writable: {}", dir.display(), e),
+            if is_root {
+                "Check permissions and free space of the file system."
+            } else {
+                "Run Omakase as root."
+            },
+        ),
+    }
+}
+
+/// Check that dpkg can be run, and accepts the configured architectures
+fn check_dpkg(opts: &Opts, config: &Config) -> Vec<Check> {
+    let dpkg = |arg: &str| -> Result<String> {
+        let mut cmd = Command::new("dpkg");
+        cmd.arg("--root").arg(&opts.root);
+        if let Some(admindir) = &config.dpkg_admindir {
+            cmd.arg("--admindir").arg(join_root(&opts.root, admindir));
+        }
+        let output = cmd.arg(arg).output()?;
+        if !output.status.success() {
+            bail!("{}", String::from_utf8_lossy(&output.stderr).trim());
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    };
+
+    let native = match dpkg("--print-architecture") {
+        Ok(arch) => arch,
+        Err(e) => {
+            return vec![Check::problem(
+                Status::Fail,
+                "dpkg",
+                format!("Failed to run dpkg: {e:#}"),
+                "Make sure dpkg is installed and in PATH.",
+            )]
+        }
+    };
+    let mut checks = vec![Check::pass("dpkg", format!("Native architecture {native}"))];
+
+    let foreign = dpkg("--print-foreign-architectures").unwrap_or_default();
+    let accepted: Vec<&str> = std::iter::once(native.as_str())
+        .chain(foreign.split_whitespace())
+        .chain(std::iter::once("all"))
+        .collect();
+    // Repository name, None for `arch` of config.toml
+    let mut archs: Vec<(&str, Option<&String>)> = vec![(config.arch.as_str(), None)];
+    for (name, repo) in &config.repo {
+        if let Some(arch) = &repo.arch {
+            archs.push((arch, Some(name)));
+        }
+    }
+    archs.sort();
+    for (arch, repo) in archs {
+        let name = format!("Architecture {arch}");
+        let source = match repo {
+            Some(repo) => format!("arch of repository {repo}"),
+            None => "arch".to_string(),
+        };
+        if repo.is_none() && arch != native {
+            // Most packages would be missing
+            checks.push(Check::problem(
+                Status::Warn,
+                name,
+                format!("Set by {source}, but the native architecture is {native}"),
+                format!("Run `oma config set arch {native}`, unless this is on purpose."),
+            ));
+        } else if accepted.contains(&arch) {
+            checks.push(Check::pass(name, format!("Set by {source}")));
+        } else {
+            checks.push(Check::problem(
+                Status::Warn,
+                name,
+                format!("Set by {source}, but dpkg doesn't accept it"),
+                format!("Fix {source} in config.toml, or run `dpkg --add-architecture {arch}`."),
+            ));
+        }
+    }
+    checks
+}
+
+/// Check that no other instance is running, and the lock can be taken
+fn check_lock(root: &Path, is_root: bool) -> Check {
+    let name = "Instance lock";
+    let lock_path = root.join(crate::LOCK_PATH);
+    match lock::check(root) {
+        Ok(Some(pid)) => {
+            return Check::problem(
+                Status::Fail,
+                name,
+                format!("Held by PID {pid}"),
+                format!(
+                "Wait for the other instance to finish. If PID {pid} is not running, remove {}.",
+                lock_path.display()
+            ),
+            )
+        }
+        Ok(None) => (),
+        Err(e) => {
+            return Check::problem(
+                Status::Fail,
+                name,
+                format!("{e:#}"),
+                format!(
+                    "Remove {} if no other instance is running.",
+                    lock_path.display()
+                ),
+            )
+        }
+    }
+    if !is_root {
+        return Check::problem(
+            Status::Warn,
+            name,
+            "Not running as root",
+            "Run Omakase as root to change packages.",
+        );
+    }
+    match lock::lock(root).and_then(|_| lock::unlock(root)) {
+        Ok(()) => Check::pass(name, "Available"),
+        Err(e) => Check::problem(
+            Status::Fail,
+            name,
+            format!("{e:#}"),
+            format!("Check permissions of {}.", lock_path.display()),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::utils::temp_dir::TempDir;
+
+    #[test]
+    fn test_doctor_checks() {
+        let root = TempDir::new("doctor");
+        let key_root = root.join("keys");
+        fs::create_dir_all(&key_root).unwrap();
+        fs::write(key_root.join("main.asc"), "").unwrap();
+        let config: Config = toml::from_str(
+            r#"
+            arch = "amd64"
+            [repo.main]
+            source = "https://repo.example.org"
+            distribution = "stable"
+            components = ["main"]
+            keys = ["main.asc"]
+            [repo.extra]
+            source = "https://extra.example.org"
+            distribution = "stable"
+            components = ["main"]
+            keys = ["main.asc", "extra.asc"]
+            "#,
+        )
+        .unwrap();
+        let checks = check_keys(&config, &key_root);
+        let res: Vec<(&str, Status)> = checks.iter().map(|c| (c.name.as_str(), c.status)).collect();
+        assert_eq!(
+            res,
+            vec![
+                ("Keys of extra", Status::Fail),
+                ("Keys of main", Status::Pass)
+            ]
+        );
+        assert!(checks[0].detail.ends_with("extra.asc"));
+
+        let check = check_writable("Cache", &root.join("cache"), true);
+        assert_eq!(check.status, Status::Pass);
+        assert!(root.join("cache").is_dir());
+        // A file is in the way
+        fs::write(root.join("file"), "").unwrap();
+        let check = check_writable("Cache", &root.join("file/cache"), false);
+        assert_eq!(check.status, Status::Warn);
+        assert!(check.hint.is_some());
+    }
+}
@@ -1,6 +1,7 @@
 use crate::{
     db::LocalDb,
-    info, pool,
+    info,
+    pool::{self, PkgFields},
     types::{Checksum, PkgSource},
     utils::downloader::{Compression, DownloadJob, Downloader},
 };
@@ -17,11 +18,12 @@ pub async fn download(
     let dbs = local_db
         .get_all_package_db()
         .context("Invalid local package database")?;
-    let pool = pool::source::create_pool(&dbs, &[])?;
+    let pool = pool::source::create_pool(&dbs, &[], PkgFields::All, &Default::default())?;
 
     // Get all versions
-    // Choices: Vec<(DisplayString, URL)>
-    let mut choices: Vec<(String, String, u64, Checksum)> = Vec::new();
+    // Choices: Vec<(DisplayString, URL, Size, Checksum, Mirrors, Repos)>
+    #[allow(clippy::type_complexity)]
+    let mut choices: Vec<(String, String, u64, Checksum, Vec<String>, Vec<String>)> = Vec::new();
     if let Some(ids) = pool.get_pkgs_by_name(pkgname) {
         let mut first = true;
         for id in ids {
@@ -29,7 +31,9 @@ pub async fn download(
             let (url, size, checksum) = match &meta.source {
                 PkgSource::Http((url, size, checksum)) => (url, size, checksum),
                 // This should never happen
-                PkgSource::Local(_) => panic!("Local source from http repo"),
+                PkgSource::Local(_) | PkgSource::Installed => {
+                    panic!("Local source from http repo")
+                }
             };
             // Form version str for display
             let mut version_str = meta.version.to_string();
@@ -40,7 +44,14 @@ pub async fn download(
             if !info_segments.is_empty() {
                 version_str.push_str(&format!(" ({})", info_segments.join(", ")));
             }
-            choices.push((version_str, url.to_owned(), *size, checksum.to_owned()));
+            choices.push((
+                version_str,
+                url.to_owned(),
+                *size,
+                checksum.to_owned(),
+                meta.mirror_urls(),
+                meta.repos.clone(),
+            ));
             // Not the first anymore
             first = false;
         }
@@ -60,9 +71,11 @@ pub async fn download(
             .interact()?
     };
 
-    let (_, url, size, checksum) = &choices[i];
+    let (_, url, size, checksum, mirrors, repos) = &choices[i];
     let job = DownloadJob {
         url: url.to_owned(),
+        mirrors: mirrors.clone(),
+        repos: repos.clone(),
         description: None,
         filename: None,
         size: Some(*size),
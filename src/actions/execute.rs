@@ -1,23 +1,34 @@
-use super::UserRequest;
+use super::{manifest, UserRequest};
 use crate::{
     cli::{self, ask_confirm},
     db::LocalDb,
-    debug,
-    executor::{dpkg, modifier, MachineStatus, PkgState},
-    info,
+    debug, error,
+    executor::{
+        self, conffiles, dpkg, journal::Journal, modifier, script, ExpectedState, MachineStatus,
+        PkgState, PkgStatus,
+    },
+    info, msg,
     pool::{self, PkgPool},
     solver::Solver,
     success,
     types::{
-        config::{Blueprints, Config, Opts},
-        PkgActionModifier,
+        config::{Blueprints, Config, Opts, SubCmd, UpgradePolicy},
+        Lockfile, PkgActionModifier, PkgActions, PkgMeta, PkgSource, PkgVersion,
+        VersionRequirement,
     },
-    utils::downloader::Downloader,
+    utils::{alt_root, disk, downloader::Downloader},
     warn,
 };
 
 use anyhow::{anyhow, bail, Context, Result};
 use console::style;
+use indicatif::HumanBytes;
+use std::{
+    collections::{HashMap, HashSet},
+    io::Write,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
 
 // -> Result<UserCancelled?>
 pub async fn execute(
@@ -35,12 +46,22 @@ pub async fn execute(
             "Operating in external system root mode, Omakase will only unpack packages without configuration!"
         );
         alt_root = true;
+        check_alt_root(opts, config)?;
     }
 
     // Load unsafe configs
     let unsafe_config = config.r#unsafe.clone().unwrap_or_default();
 
+    // Finish what an interrupted transaction left behind, before looking at the system
+    if let Some(journal) = Journal::load(&opts.root)? {
+        resume_transaction(journal, opts, config)?;
+    }
+
+    let root = &opts.root;
+    let machine_status = MachineStatus::new(root, config.dpkg_admindir.as_deref())?;
+
     debug!("Parsing dpkg database...");
+    let start = Instant::now();
     let dbs = local_db
         .get_all_package_db()
         .context("Invalid local package database!")?;
@@ -48,72 +69,814 @@ pub async fn execute(
     if !local_repo.is_dir() {
         std::fs::create_dir_all(&local_repo)?;
     }
-    let pool = pool::source::create_pool(&dbs, &[local_repo])?;
+    let installed: HashMap<String, PkgVersion> = machine_status
+        .pkgs
+        .iter()
+        .map(|(name, pkg)| (name.clone(), pkg.version.clone()))
+        .collect();
+    let version_limit = config
+        .max_versions_per_package
+        .map(|max| pool::VersionLimit {
+            max,
+            installed: installed.clone(),
+        });
+    // Packages the user asked to remove are not protected by never_autoremove
+    let never_autoremove = modifier::NeverAutoRemove {
+        patterns: config.never_autoremove.clone(),
+        explicit: match &request {
+            UserRequest::Remove(list) => list.iter().map(|(name, _)| name.clone()).collect(),
+            _ => HashSet::new(),
+        },
+    };
+    // Packages named by user are not affected by section holds
+    let explicit: HashSet<String> = match &request {
+        UserRequest::Install((list, _)) => list.iter().map(|i| i.pkgname.clone()).collect(),
+        _ => HashSet::new(),
+    };
+    let target_release = match &opts.subcmd {
+        SubCmd::Install(install) => {
+            install
+                .target_release
+                .as_ref()
+                .map(|distribution| pool::TargetRelease {
+                    distribution: distribution.clone(),
+                    names: explicit.iter().cloned().collect(),
+                })
+        }
+        _ => None,
+    };
+    let repo_filter = match &opts.subcmd {
+        SubCmd::Install(install) if !install.repo.is_empty() => {
+            local_db.select_repos(&install.repo)?;
+            Some(pool::RepoFilter {
+                repos: install.repo.clone(),
+                names: explicit.iter().cloned().collect(),
+                strict: install.strict_repo,
+            })
+        }
+        _ => None,
+    };
+    let version_preference = match (&request, config.upgrade_policy) {
+        // Upgrading is an explicit request to move all packages to their newest versions
+        (UserRequest::Upgrade { .. }, UpgradePolicy::Conservative) => None,
+        (_, policy) => Some(pool::VersionPreference {
+            policy,
+            installed,
+            explicit: explicit.iter().cloned().collect(),
+        }),
+    };
+    // Candidates of installed packages are constrained before solving
+    let pins = version_pins(
+        &machine_status,
+        &request,
+        blueprint,
+        &explicit,
+        opts,
+        config,
+    )?;
+    let (pool, pinned) = pool::source::create_pool_pinned(
+        &dbs,
+        &[local_repo],
+        config.resolve_fields(),
+        &pool::PoolOptions {
+            version_pins: Some(&pins),
+            repo_filter: repo_filter.as_ref(),
+            version_limit: version_limit.as_ref(),
+            target_release: target_release.as_ref(),
+            version_preference: version_preference.as_ref(),
+        },
+    )?;
+    crate::TIMINGS.record("Pool import", start);
 
     debug!("Processing user request...");
-    let root = &opts.root;
-    let machine_status = MachineStatus::new(root)?;
-    process_user_request(request, pool.as_ref(), blueprint, &machine_status)?;
+    let strict_recomms = process_user_request(request, pool.as_ref(), blueprint, &machine_status)?;
 
     debug!("Applying replaces according to package catalog...");
     apply_replaces(opts, pool.as_ref(), blueprint)?;
 
     info!("Resolving dependencies...");
-    let solver = Solver::from(pool);
-    let res = solver.install(blueprint)?;
+    let start = Instant::now();
+    let mut solver = Solver::from(pool);
+    if opts.solver_timeout > 0 {
+        solver.timeout = Some(Duration::from_secs(opts.solver_timeout));
+    }
+    solver.exclude = config.exclude.clone();
+    solver.dump = opts.dump_solver.clone();
+    // Protected packages are kept along with their dependencies
+    let mut keep: Vec<String> = machine_status
+        .pkgs
+        .values()
+        .filter(|pkg| {
+            pkg.state == PkgState::Installed
+                && never_autoremove.is_protected(&pkg.name)
+                && solver.pool.get_pkgs_by_name(&pkg.name).is_some()
+        })
+        .map(|pkg| pkg.name.clone())
+        .collect();
+    keep.sort();
+    let res = match solver.install_keeping(blueprint, &keep) {
+        Ok(res) => res,
+        Err(e) => {
+            check_strict_recommends(&solver, &strict_recomms)?;
+            if !pinned.is_empty() {
+                warn!("Only some versions of the following installed packages are considered:");
+                let pinned: Vec<String> = pinned.iter().map(pin_reason).collect();
+                crate::WRITER.write_chunks("", &pinned)?;
+            }
+            if let Some(max) = config.max_versions_per_package {
+                warn!(
+                "Only the newest {max} versions of each package are considered (max_versions_per_package), some version requirements may not be satisfiable."
+            );
+            }
+            return Err(e.into());
+        }
+    };
+    crate::TIMINGS.record("Dependency resolution", start);
+    report_pinned(&pinned, &pins, &res);
+    if !keep.is_empty() {
+        let requested: HashSet<String> = blueprint
+            .get_pkg_requests()
+            .into_iter()
+            .map(|req| req.name)
+            .collect();
+        let orphans = never_autoremove.orphans(&res, &requested);
+        if !orphans.is_empty() {
+            info!(
+                "Keeping {} package(s) that match never_autoremove, although nothing requires them:",
+                orphans.len()
+            );
+            crate::WRITER.write_chunks("", &orphans)?;
+        }
+    }
+    // Check resolved packages against lockfile
+    let lockfile_path = opts.root.join(&opts.config_root).join(crate::LOCKFILE_NAME);
+    let lockfile = Lockfile::from_pkgs(&res);
+    if opts.locked {
+        if !lockfile_path.is_file() {
+            bail!(
+                "Lockfile {} does not exist, cannot operate in locked mode.",
+                lockfile_path.display()
+            );
+        }
+        Lockfile::from_file(&lockfile_path)?.check(&lockfile)?;
+    }
     // Translating result to list of actions
     let mut actions = machine_status.gen_actions(res.as_slice(), unsafe_config.purge_on_remove);
+    if !config.never_autoremove.is_empty() {
+        never_autoremove.apply(&mut actions);
+    }
     if alt_root {
         let modifier = modifier::UnpackOnly::default();
         modifier.apply(&mut actions);
     }
 
+    // Show how the user blueprint is going to change, it's written back after the transaction
+    let blueprint_changed = show_blueprint_diff(blueprint)?;
     if actions.is_empty() {
+        // Nothing else to confirm, so ask about the blueprint alone
+        if blueprint_changed
+            && !opts.no_act
+            && !ask_confirm(opts, "Write these changes to the blueprint?")?
+        {
+            return Ok(true);
+        }
+        if !opts.no_act && !opts.assume_no {
+            lockfile.write(&lockfile_path)?;
+        }
         success!("There is nothing to do.");
         return Ok(false);
     }
 
     // There is something to do. Show it.
     info!("Omakase will perform the following actions:");
-    if opts.yes && opts.no_pager {
-        actions.show();
+    let display = actions.sorted(opts.sort);
+    if (opts.yes || opts.assume_no) && opts.no_pager {
+        display.show();
     } else {
-        actions.show_tables(opts.no_pager)?;
+        display.show_tables(opts, config.pager.as_deref())?;
     }
     crate::WRITER.writeln("", "")?;
     actions.show_size_change();
+    enforce_free_space(&actions, opts, config)?;
 
     // Additional confirmation if removing essential packages
     if actions.remove_essential() {
-        if unsafe_config.allow_remove_essential {
-            let prefix = style("DANGER").red().to_string();
-            crate::WRITER.writeln(
-                &prefix,
-                "Some ESSENTIAL packages will be removed/purged. Are you REALLY sure?",
-            )?;
-            if cli::ask_confirm(opts, "Is this supposed to happen?")? {
-                bail!("User cancelled operation.");
-            }
-        } else {
+        let policy = essential_removal_policy(
+            opts.allow_remove_essential,
+            unsafe_config.allow_remove_essential,
+            opts.yes,
+        );
+        if policy == EssentialRemoval::Refuse {
             bail!("Some ESSENTIAL packages will be removed. Aborting...")
         }
+        let prefix = style("DANGER").red().to_string();
+        crate::WRITER.writeln(
+            &prefix,
+            "Some ESSENTIAL packages will be removed/purged. Are you REALLY sure?",
+        )?;
+        if policy == EssentialRemoval::RefuseYes {
+            bail!(
+                "Refusing to remove ESSENTIAL packages with {} alone, also pass {} if this is intended.",
+                style("--yes").bold(),
+                style("--allow-remove-essential").bold()
+            );
+        }
+        if policy == EssentialRemoval::Confirm
+            && !cli::ask_confirm(opts, "Is this supposed to happen?")?
+        {
+            return Ok(true);
+        }
+    }
+
+    // Essential packages are upgraded before anything else, see dpkg::install_stages
+    let essential_upgrades = actions.essential_upgrades();
+    if !essential_upgrades.is_empty() {
+        warn!(
+            "The following ESSENTIAL packages will be upgraded first, do not interrupt Omakase or power off the system until it finishes:"
+        );
+        crate::WRITER.write_chunks("", &essential_upgrades)?;
+    }
+
+    // Let the user know about local changes to config files before dpkg asks about them
+    let upgrading: HashSet<&str> = actions
+        .install
+        .iter()
+        .chain(actions.unpack.iter())
+        .filter(|(_, old)| old.is_some())
+        .map(|(pkg, _)| pkg.name.as_str())
+        .chain(actions.downgrade.iter().map(|(pkg, _)| pkg.name.as_str()))
+        .collect();
+    if !upgrading.is_empty() {
+        let modified =
+            conffiles::modified_conffiles(root, config.dpkg_admindir.as_deref(), &upgrading)?;
+        if !modified.is_empty() {
+            warn!("The following configuration files have local modifications, you may be asked whether to keep them:");
+            let list: Vec<String> = modified
+                .iter()
+                .map(|(name, path)| format!("{path} ({name})"))
+                .collect();
+            crate::WRITER.write_chunks("", &list)?;
+        }
+    }
+
+    if matches!(&opts.subcmd, SubCmd::Execute(upgrade) if upgrade.show_changelog) {
+        offer_changelogs(&actions, downloader, opts, config).await?;
+    }
+
+    if let Some(path) = &opts.script {
+        script::write_script(
+            path,
+            actions,
+            &download_dir(opts),
+            &opts.root,
+            config.dpkg_admindir.as_deref(),
+            &config.dpkg_force.args(unsafe_config.unsafe_io),
+            config.dpkg_order,
+        )?;
+        success!(
+            "Commands to apply these changes have been written to {}.",
+            style(path.display()).bold()
+        );
+        return Ok(false);
     }
 
-    if ask_confirm(opts, "Proceed?")? {
+    if opts.no_act || opts.download_only {
+        let (download_res, _) = fetch_pkgs(
+            &mut actions,
+            opts,
+            downloader,
+            &explicit,
+            &machine_status.pkgs,
+        )
+        .await?;
+        let count = dpkg::verify_pkg_actions(&actions, &download_res)?;
+        if opts.download_only {
+            let manifest = manifest::write_manifest(
+                &actions.install_actions(),
+                &download_res,
+                &download_dir(opts),
+                opts.manifest_format,
+            )?;
+            success!(
+                "All {} package(s) have been fetched and validated, checksums are written to {}.",
+                count,
+                style(manifest.display()).bold()
+            );
+            return Ok(false);
+        }
+        success!(
+            "All {} package(s) have been fetched and validated. Stopping before running dpkg as requested.",
+            count
+        );
+        return Ok(false);
+    }
+
+    let prompt = format!("Proceed? [{}]", actions.gen_size_summary());
+    let proceed = if large_confirm_required(&actions, opts, config) {
+        warn!(
+            "This is a large transaction, exceeding {} in config.",
+            style("confirm_threshold").bold()
+        );
+        cli::ask_phrase(opts, &prompt, LARGE_CONFIRM_PHRASE)?
+    } else {
+        ask_confirm(opts, &prompt)?
+    };
+    if proceed {
         // Run it!
-        dpkg::execute_pkg_actions(actions, &opts.root, downloader, unsafe_config.unsafe_io).await?;
+        let start = Instant::now();
+        let (download_res, skipped) = fetch_pkgs(
+            &mut actions,
+            opts,
+            downloader,
+            &explicit,
+            &machine_status.pkgs,
+        )
+        .await?;
+        let expected = executor::expected_states(&actions);
+        let mut summary = actions.summary();
+        let res = dpkg::execute_pkg_actions(
+            actions,
+            &download_res,
+            &opts.root,
+            config.dpkg_admindir.as_deref(),
+            &config.dpkg_force.args(unsafe_config.unsafe_io),
+            config.dpkg_order,
+        );
+        if let Err(e) = res {
+            report_dpkg_failure(&e, &opts.root, config, &machine_status);
+            // Keep requested packages in blueprint, so that a re-run resumes the operation
+            blueprint.export()?;
+            return Err(e);
+        }
+        if opts.verify_after || config.verify_after {
+            verify_after(&expected, &opts.root, config)?;
+        }
+        if skipped {
+            warn!("Some packages were skipped, the lockfile is not updated.");
+        } else if lockfile.write(&lockfile_path)? {
+            debug!("Lockfile updated at {}.", lockfile_path.display());
+        }
+        summary.elapsed = start.elapsed();
+        if opts.json {
+            writeln!(crate::WRITER.get_writer(), "{}", summary.to_json())?;
+        } else {
+            for line in summary.to_lines() {
+                success!("{}", line);
+            }
+        }
         Ok(false)
     } else {
         Ok(true)
     }
 }
 
+/// Refuse to apply `actions` if the root file system would run out of space
+///
+/// With --no-act, --download-only, --script and --assume-no nothing is applied, so a shortage is
+/// only reported as a warning.
+fn enforce_free_space(actions: &PkgActions, opts: &Opts, config: &Config) -> Result<()> {
+    if let Err(e) = check_free_space(actions, opts, config) {
+        if opts.no_act || opts.download_only || opts.script.is_some() || opts.assume_no {
+            warn!("{:#}", e);
+        } else {
+            return Err(e);
+        }
+    }
+    Ok(())
+}
+
+/// Check whether the root file system keeps `min_free_space` free after applying `actions`
+///
+/// Downloaded packages count too, if they are stored on the same file system. Whether a shortage
+/// stops the transaction is up to [`enforce_free_space`].
+fn check_free_space(actions: &PkgActions, opts: &Opts, config: &Config) -> Result<()> {
+    let root_space = disk::fs_space(&opts.root)?;
+    let summary = actions.summary();
+    let mut used = summary.size_change;
+    if disk::fs_space(&download_dir(opts))?.id == root_space.id {
+        used += i128::from(summary.download_size);
+    }
+    let reserve = match &config.min_free_space {
+        Some(reserve) => reserve.bytes(root_space.total)?,
+        None => 0,
+    };
+    let projected = disk::check_reserve(&root_space, used, reserve).context(format!(
+        "Refusing to apply the transaction on {}.",
+        style(opts.root.display()).bold()
+    ))?;
+    crate::WRITER.writeln(
+        "",
+        &format!(
+            "{} {}",
+            style("Estimated free space afterwards:").bold(),
+            HumanBytes(projected)
+        ),
+    )?;
+
+    Ok(())
+}
+
+/// What to type to proceed with a large transaction
+const LARGE_CONFIRM_PHRASE: &str = "Yes, do as I say!";
+
+/// Whether `actions` exceed `confirm_threshold`, so that the confirmation phrase is needed
+///
+/// `--yes` skips it, unless `require_explicit_large` is set.
+fn large_confirm_required(actions: &PkgActions, opts: &Opts, config: &Config) -> bool {
+    if opts.yes && !config.require_explicit_large {
+        return false;
+    }
+    let summary = actions.summary();
+    let packages = summary.installed + summary.upgraded + summary.downgraded + summary.removed;
+    config
+        .confirm_threshold
+        .exceeded(packages, summary.download_size)
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum EssentialRemoval {
+    /// Removing essential packages is not allowed
+    Refuse,
+    /// Allowed in config, but `--yes` must not automate it
+    RefuseYes,
+    /// Ask the user to confirm once more
+    Confirm,
+    /// Explicitly allowed on command line with `--yes`
+    Proceed,
+}
+
+/// How installed packages may change in this transaction, see [`pool::VersionPins`]
+///
+/// Packages named by the user in `explicit` are not affected by section holds.
+fn version_pins(
+    machine_status: &MachineStatus,
+    request: &UserRequest,
+    blueprint: &Blueprints,
+    explicit: &HashSet<String>,
+    opts: &Opts,
+    config: &Config,
+) -> Result<pool::VersionPins> {
+    let security_only = matches!(
+        request,
+        UserRequest::Upgrade {
+            security_only: true
+        }
+    );
+    // Only downgrade packages if explicitly requested by version requirements
+    let mut requirements: Vec<(String, VersionRequirement)> = blueprint
+        .get_pkg_requests()
+        .into_iter()
+        .map(|req| (req.name, req.version))
+        .collect();
+    if let UserRequest::Install((list, _)) = request {
+        for install in list {
+            if let Some(ver_req) = &install.ver_req {
+                requirements.push((install.pkgname.clone(), ver_req.clone()));
+            }
+        }
+    }
+    let downgrade_requested: HashSet<&str> = requirements
+        .iter()
+        .filter(|(name, ver_req)| {
+            machine_status
+                .pkgs
+                .get(name)
+                .is_some_and(|pkg| !ver_req.contains(&pkg.version))
+        })
+        .map(|(name, _)| name.as_str())
+        .collect();
+    if !security_only && config.hold_sections.is_empty() && opts.allow_downgrades {
+        return Ok(pool::VersionPins::default());
+    }
+    // Sections are only known from the full status entries
+    let names: HashSet<&str> = machine_status
+        .pkgs
+        .values()
+        .filter(|pkg| {
+            !matches!(
+                pkg.state,
+                PkgState::NotInstalled | PkgState::ConfigFiles | PkgState::HalfInstalled
+            )
+        })
+        .map(|pkg| pkg.name.as_str())
+        .collect();
+    let metas = MachineStatus::installed_meta(
+        &opts.root,
+        config.dpkg_admindir.as_deref(),
+        &names,
+        &config.arch,
+    )?;
+
+    let mut pkgs = HashMap::new();
+    for meta in metas {
+        let pin = pool::Pin {
+            hold: config.hold_sections.contains(&meta.section) && !explicit.contains(&meta.name),
+            security_only,
+            no_downgrade: !opts.allow_downgrades
+                && !downgrade_requested.contains(meta.name.as_str()),
+        };
+        if !pin.is_empty() {
+            pkgs.insert(meta.name.clone(), (meta, pin));
+        }
+    }
+    Ok(pool::VersionPins { pkgs })
+}
+
+/// What kept `pinned` from moving to some versions
+fn pin_reason(pinned: &pool::Pinned) -> String {
+    let reason = if pinned.pin.hold {
+        "held section"
+    } else if pinned.newer && pinned.pin.security_only {
+        "non-security upgrades"
+    } else {
+        "downgrades"
+    };
+    format!("{} ({})", pinned.name, reason)
+}
+
+/// Tell the user about installed packages that stayed at their version because of pins
+fn report_pinned(pinned: &[pool::Pinned], pins: &pool::VersionPins, res: &[&PkgMeta]) {
+    let res: HashMap<&str, &PkgMeta> = res.iter().map(|pkg| (pkg.name.as_str(), *pkg)).collect();
+    let mut skipped = 0;
+    let mut kept_newer = false;
+    for pinned in pinned {
+        let pkg = match res.get(pinned.name.as_str()) {
+            Some(pkg) if pkg.version == pinned.version => pkg,
+            // Removed, or still moving to an allowed version
+            _ => continue,
+        };
+        if pinned.pin.hold {
+            warn!(
+                "Keeping {} at installed version {} because section {} is held.",
+                style(&pinned.name).bold(),
+                pinned.version,
+                style(&pins.pkgs[&pinned.name].0.section).bold()
+            );
+        } else if pinned.newer && pinned.pin.security_only {
+            skipped += 1;
+        } else if pinned.older && pinned.pin.no_downgrade {
+            // Only worth mentioning if no repository has the installed version
+            if matches!(pkg.source, PkgSource::Installed) {
+                warn!(
+                    "Keeping {} at installed version {}, which is newer than the available versions.",
+                    style(&pinned.name).bold(),
+                    pinned.version
+                );
+                kept_newer = true;
+            }
+        }
+    }
+    if skipped > 0 {
+        info!("Skipped {} non-security upgrade(s).", skipped);
+    }
+    if kept_newer {
+        msg!(
+            "Use {} to downgrade these packages.",
+            style("--allow-downgrades").bold()
+        );
+    }
+}
+
+/// Fetch packages needed by `actions`, returns a map of URL -> local path
+///
+/// With `--ignore-missing`, packages that fail to download are left out of `actions`, along with
+/// packages that depend on them, see [`modifier::SkipMissing`]. Returns whether any package was
+/// left out.
+async fn fetch_pkgs(
+    actions: &mut PkgActions<'_>,
+    opts: &Opts,
+    downloader: &Downloader,
+    explicit: &HashSet<String>,
+    installed: &HashMap<String, PkgStatus>,
+) -> Result<(HashMap<String, PathBuf>, bool)> {
+    let download_res = dpkg::fetch_pkgs(
+        actions,
+        &download_dir(opts),
+        downloader,
+        opts.reinstall_from_cache,
+        opts.ignore_missing,
+    )
+    .await?;
+    let missing = dpkg::missing_pkgs(actions, &download_res);
+    if missing.is_empty() {
+        return Ok((download_res, false));
+    }
+
+    let incoming = |actions: &PkgActions| -> HashSet<String> {
+        actions
+            .install
+            .iter()
+            .map(|(pkg, _)| pkg.name.clone())
+            .chain(actions.downgrade.iter().map(|(pkg, _)| pkg.name.clone()))
+            .chain(actions.unpack.iter().map(|(pkg, _)| pkg.name.clone()))
+            .collect()
+    };
+    let before = incoming(actions);
+    let modifier = modifier::SkipMissing { missing, installed };
+    modifier.apply(actions);
+    let after = incoming(actions);
+    // Packages requested by the user can't be skipped
+    let mut required: Vec<&String> = before
+        .difference(&after)
+        .filter(|name| explicit.contains(*name))
+        .collect();
+    if !required.is_empty() {
+        required.sort();
+        crate::WRITER.write_chunks("", &required)?;
+        bail!(
+            "{} requested package(s) cannot be installed, as they or their dependencies failed to download.",
+            style(required.len()).bold()
+        );
+    }
+
+    Ok((download_res, true))
+}
+
+/// Ask whether to show the changelog of each upgraded package, for `--show-changelog`
+async fn offer_changelogs(
+    actions: &PkgActions<'_>,
+    downloader: &Downloader,
+    opts: &Opts,
+    config: &Config,
+) -> Result<()> {
+    let upgrades = actions
+        .install
+        .iter()
+        .chain(actions.unpack.iter())
+        .filter(|(_, old)| old.is_some())
+        .map(|(pkg, _)| *pkg);
+    for pkg in upgrades {
+        let prompt = format!("Show the changelog of {} {}?", pkg.name, pkg.version);
+        if ask_confirm(opts, &prompt)? {
+            super::changelog::page_changelog(pkg, downloader, &download_dir(opts), opts, config)
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+/// Where packages are downloaded to, `--download-dir` or the package cache
+pub(super) fn download_dir(opts: &Opts) -> PathBuf {
+    match &opts.download_dir {
+        Some(dir) => dir.clone(),
+        None => opts.root.join(crate::PKG_CACHE_PATH),
+    }
+}
+
+/// Check that packages ended up in the `expected` state after running dpkg
+fn verify_after(expected: &[(String, ExpectedState)], root: &Path, config: &Config) -> Result<()> {
+    info!("Verifying package states...");
+    let machine_status = MachineStatus::new(root, config.dpkg_admindir.as_deref())?;
+    let diverged = machine_status.diverged_from(expected);
+    if diverged.is_empty() {
+        return Ok(());
+    }
+    for (name, expected, found) in &diverged {
+        error!(
+            "{}: expected {}, but found {}.",
+            style(name).bold(),
+            expected,
+            found
+        );
+    }
+    bail!(
+        "{} package(s) did not end up in the expected state after running dpkg.",
+        style(diverged.len()).bold()
+    );
+}
+
+/// Offer to run the remaining dpkg steps of an interrupted transaction
+fn resume_transaction(journal: Journal, opts: &Opts, config: &Config) -> Result<()> {
+    warn!(
+        "The last transaction was interrupted after {} of {} dpkg step(s), the system may be in an inconsistent state.",
+        journal.completed,
+        journal.steps.len()
+    );
+    let missing: Vec<&str> = journal
+        .remaining()
+        .iter()
+        .filter(|step| matches!(step[0].as_str(), "--install" | "--unpack"))
+        .flat_map(|step| step[1..].iter())
+        .filter(|path| !Path::new(path).is_file())
+        .map(|path| path.as_str())
+        .collect();
+    if !missing.is_empty() {
+        warn!("It cannot be resumed, since some packages are no longer in the cache:");
+        crate::WRITER.write_chunks("", &missing)?;
+        return Ok(());
+    }
+    if opts.no_act || !ask_confirm(opts, "Resume it?")? {
+        info!("Not resuming, the interrupted transaction will be discarded by the next one.");
+        return Ok(());
+    }
+
+    let unsafe_config = config.r#unsafe.clone().unwrap_or_default();
+    dpkg::resume_pkg_actions(
+        journal,
+        &opts.root,
+        config.dpkg_admindir.as_deref(),
+        &config.dpkg_force.args(unsafe_config.unsafe_io),
+    )?;
+    success!("The interrupted transaction has been completed.");
+    Ok(())
+}
+
+/// Make sure dpkg operations in the external system root don't leak into this system
+fn check_alt_root(opts: &Opts, config: &Config) -> Result<()> {
+    let admindir = config
+        .dpkg_admindir
+        .as_deref()
+        .unwrap_or_else(|| Path::new(crate::DPKG_ADMINDIR));
+    let paths = [
+        admindir,
+        Path::new(crate::PKG_CACHE_PATH),
+        Path::new(crate::LOCAL_REPO_PATH),
+    ];
+    let problems = alt_root::check_alt_root(&opts.root, &paths)?;
+    if problems.is_empty() {
+        return Ok(());
+    }
+    for problem in &problems {
+        warn!("{}", problem);
+    }
+    if !opts.force_alt_root {
+        bail!(
+            "System root {} is not a self-contained directory, dpkg operations may affect this system. Use {} to proceed anyway.",
+            style(opts.root.display()).bold(),
+            style("--force-alt-root").bold()
+        );
+    }
+    warn!("Proceeding anyway as requested.");
+
+    Ok(())
+}
+
+/// Decide how to handle removal of essential packages
+///
+/// `--yes` only applies to essential packages if `--allow-remove-essential` is also given,
+/// so that a blanket `--yes` never removes them silently.
+fn essential_removal_policy(allow_flag: bool, allow_config: bool, yes: bool) -> EssentialRemoval {
+    if !allow_flag && !allow_config {
+        EssentialRemoval::Refuse
+    } else if !yes {
+        EssentialRemoval::Confirm
+    } else if allow_flag {
+        EssentialRemoval::Proceed
+    } else {
+        EssentialRemoval::RefuseYes
+    }
+}
+
+/// Show which packages failed, and what has been changed before dpkg failed
+fn report_dpkg_failure(
+    e: &anyhow::Error,
+    root: &std::path::Path,
+    config: &Config,
+    before: &MachineStatus,
+) {
+    if let Some(dpkg_err) = e.downcast_ref::<dpkg::DpkgError>() {
+        if !dpkg_err.failed.is_empty() {
+            error!("dpkg failed to process the following packages:");
+            crate::WRITER.write_chunks("", &dpkg_err.failed).ok();
+        }
+    }
+
+    let after = match MachineStatus::new(root, config.dpkg_admindir.as_deref()) {
+        Ok(ms) => ms,
+        Err(e) => {
+            warn!("Failed to read dpkg status after failure: {e}");
+            return;
+        }
+    };
+    let changes = after.changes_since(before);
+    if changes.is_empty() {
+        info!("No package has been changed.");
+        return;
+    }
+    info!("The following changes have been applied before dpkg failed:");
+    for (name, old, new) in changes {
+        let change = match (old, new) {
+            (Some(old), Some(new)) => format!("{old} -> {new}"),
+            (None, Some(new)) => format!("installed {new}"),
+            (Some(old), None) => format!("removed {old}"),
+            (None, None) => continue,
+        };
+        msg!("{} ({})", style(name).bold(), change);
+    }
+    msg!(
+        "Run {} again to continue after fixing the problem.",
+        style("oma execute").bold()
+    );
+}
+
+/// Returns the recommended packages added in strict mode, as (PkgName, VersionRequirement, Requester)
 fn process_user_request(
     req: UserRequest,
     pool: &dyn PkgPool,
     blueprint: &mut Blueprints,
     ms: &MachineStatus,
-) -> Result<()> {
+) -> Result<Vec<(String, VersionRequirement, String)>> {
+    let mut strict_recomms = Vec::new();
     match req {
         UserRequest::Install((list, init_mode)) => {
             for install in list {
@@ -132,6 +895,12 @@ fn process_user_request(
                     }
                 }
 
+                // Installing with --no-recommends marks the entry, and marked entries stay so,
+                // e.g. when picking a version
+                let no_recommends = blueprint
+                    .user_request(&install.pkgname)
+                    .is_some_and(|req| req.no_recommends)
+                    || (!install.modify && !install.install_recomm);
                 // Add pkg to blueprint
                 let add_res = blueprint.add(
                     &install.pkgname,
@@ -139,11 +908,12 @@ fn process_user_request(
                     None,
                     install.ver_req,
                     install.local,
+                    no_recommends && !install.local,
                 );
                 if let Err(e) = add_res {
                     warn!("Cannot add package {}: {e}", style(&install.pkgname).bold());
                 }
-                if !install.local && install.install_recomm {
+                if !install.local && install.install_recomm && !no_recommends {
                     let choices = match pool.get_pkgs_by_name(&install.pkgname) {
                         Some(pkgs) => pkgs,
                         None => bail!(
@@ -165,18 +935,40 @@ fn process_user_request(
                                 }
                             }
 
+                            if install.strict_recomm {
+                                // Recommended packages must be installable in strict mode
+                                pool.pick_best_pkg(&recommend.0, &recommend.1, false)
+                                    .context(format!(
+                                        "Recommended package {} of {} cannot be installed.",
+                                        style(&recommend.0).bold(),
+                                        style(&install.pkgname).bold()
+                                    ))?;
+                            }
+
                             let add_res = blueprint.add(
                                 &recommend.0,
                                 false,
                                 Some(&install.pkgname),
                                 Some(recommend.1.clone()),
                                 false,
+                                false,
                             );
-                            if let Err(e) = add_res {
+                            if install.strict_recomm {
+                                add_res.context(format!(
+                                    "Cannot add {} recommended by {}.",
+                                    style(&recommend.0).bold(),
+                                    style(&install.pkgname).bold()
+                                ))?;
+                                strict_recomms.push((
+                                    recommend.0.clone(),
+                                    recommend.1.clone(),
+                                    install.pkgname.clone(),
+                                ));
+                            } else if let Err(e) = add_res {
                                 warn!(
                                     "Cannot add {} recommended by {}: {e}",
-                                    style(&install.pkgname).bold(),
-                                    recommend.0
+                                    style(&recommend.0).bold(),
+                                    install.pkgname
                                 );
                             }
                         }
@@ -189,12 +981,48 @@ fn process_user_request(
                 blueprint.remove(&name, remove_recomm)?;
             }
         }
-        UserRequest::Upgrade => (),
+        UserRequest::Upgrade { .. } => (),
     };
 
+    Ok(strict_recomms)
+}
+
+/// Find out which recommended package added in strict mode makes the request unsatisfiable
+fn check_strict_recommends(
+    solver: &Solver,
+    recomms: &[(String, VersionRequirement, String)],
+) -> Result<()> {
+    for (name, ver_req, requester) in recomms {
+        if let Err(e) = solver.resolve(&[(name.clone(), ver_req.clone())]) {
+            return Err(anyhow::Error::from(e).context(format!(
+                "Recommended package {} of {} cannot be installed.",
+                style(name).bold(),
+                style(requester).bold()
+            )));
+        }
+    }
     Ok(())
 }
 
+/// Print changes to the user blueprint, returns whether there are any
+fn show_blueprint_diff(blueprint: &Blueprints) -> Result<bool> {
+    let diff = blueprint.diff();
+    if diff.is_empty() {
+        return Ok(false);
+    }
+    info!("The user blueprint will be changed as follows:");
+    for line in &diff {
+        let line = if line.starts_with('+') {
+            style(line).green()
+        } else {
+            style(line).red()
+        };
+        crate::WRITER.writeln("", &line.to_string())?;
+    }
+    crate::WRITER.writeln("", "")?;
+    Ok(true)
+}
+
 fn apply_replaces(opts: &Opts, pool: &dyn PkgPool, blueprint: &mut Blueprints) -> Result<()> {
     // For every package in blueprint, check if they are replaced
     for pkg in blueprint.get_pkg_requests() {
@@ -204,15 +1032,117 @@ fn apply_replaces(opts: &Opts, pool: &dyn PkgPool, blueprint: &mut Blueprints) -
             if blueprint.user_list_contains(&pkg.name) {
                 if cli::ask_confirm(opts, &format!("Replace {} with {}?", pkg.name, replacement))? {
                     blueprint.remove(&pkg.name, true)?;
-                    blueprint.add(&replacement, false, None, None, false)?;
+                    blueprint.add(&replacement, false, None, None, false, pkg.no_recommends)?;
                 } else {
                     warn!("Package {} has been replaced by {}. Please update or edit vendor blueprint to use the new package.",
                           style(&pkg.name).bold(),
                           style(&replacement).bold());
                 }
             }
+        } else if let Some(successor) = pool.find_successor(&pkg.name) {
+            // Installing a transitional package only leaves cruft behind, install what it leads to
+            if blueprint.user_list_contains(&pkg.name) {
+                info!(
+                    "{} is a transitional package for {}.",
+                    style(&pkg.name).bold(),
+                    style(&successor).bold()
+                );
+                if cli::ask_confirm(opts, &format!("Install {successor} instead?"))? {
+                    blueprint.remove(&pkg.name, true)?;
+                    blueprint.add(&successor, false, None, None, false, pkg.no_recommends)?;
+                }
+            }
         }
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        pool::{BasicPkgPool, InMemoryPool},
+        types::{config::FreeSpaceReserve, Checksum},
+        utils::temp_dir::TempDir,
+    };
+    use clap::Parser;
+
+    #[test]
+    fn test_essential_removal_policy() {
+        use EssentialRemoval::*;
+        // (--allow-remove-essential, allow_remove_essential in config, --yes)
+        let cases = [
+            ((false, false, false), Refuse),
+            ((false, false, true), Refuse),
+            ((false, true, false), Confirm),
+            ((false, true, true), RefuseYes),
+            ((true, false, false), Confirm),
+            ((true, false, true), Proceed),
+            ((true, true, false), Confirm),
+            ((true, true, true), Proceed),
+        ];
+        for ((flag, config, yes), expected) in cases {
+            assert_eq!(
+                essential_removal_policy(flag, config, yes),
+                expected,
+                "flag: {flag}, config: {config}, yes: {yes}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_check_strict_recommends() {
+        // Requests only pick packages from repositories
+        let pkg = |name: &str| {
+            let url = format!("https://repo.example.org/debs/{name}_1_amd64.deb");
+            let source = PkgSource::Http((url, 0, Checksum::Sha256(Vec::new())));
+            PkgMeta::new(name, PkgVersion::try_from("1").unwrap(), source)
+        };
+        let mut pool = InMemoryPool::new();
+        let mut broken = pkg("broken");
+        broken.depends = vec![("missing".to_string(), VersionRequirement::default())];
+        pool.add(broken);
+        pool.add(pkg("fine"));
+        pool.finalize();
+        let solver = Solver::from(Box::new(pool) as Box<dyn PkgPool>);
+
+        let recomm = |name: &str| {
+            (
+                name.to_string(),
+                VersionRequirement::default(),
+                "app".to_string(),
+            )
+        };
+        assert!(check_strict_recommends(&solver, &[recomm("fine")]).is_ok());
+        let e = check_strict_recommends(&solver, &[recomm("fine"), recomm("broken")]).unwrap_err();
+        let msg = console::strip_ansi_codes(&e.to_string()).to_string();
+        assert_eq!(
+            msg,
+            "Recommended package broken of app cannot be installed."
+        );
+    }
+
+    #[test]
+    fn test_enforce_free_space() {
+        let root = TempDir::new("free-space");
+        let mut config: Config = toml::from_str("arch = \"amd64\"\n[repo]").unwrap();
+        config.min_free_space = Some(FreeSpaceReserve::Bytes(u64::MAX));
+        let actions = PkgActions::default();
+        let enforce = |args: &[&str]| {
+            let mut argv = vec!["oma", "--root", root.to_str().unwrap()];
+            argv.extend_from_slice(args);
+            argv.push("upgrade");
+            let opts = Opts::try_parse_from(argv).unwrap();
+            enforce_free_space(&actions, &opts, &config)
+        };
+
+        assert!(enforce(&[]).is_err());
+        assert!(enforce(&["--yes"]).is_err());
+        // Nothing is applied in these modes
+        assert!(enforce(&["--no-act"]).is_ok());
+        assert!(enforce(&["--download-only"]).is_ok());
+        assert!(enforce(&["--script", "apply.sh"]).is_ok());
+        assert!(enforce(&["--assume-no"]).is_ok());
+    }
+}
@@ -0,0 +1,256 @@
+use crate::{
+    cli,
+    db::{parse_certs, read_certs},
+    info, msg, success,
+    types::config::{key_filename_char, Config, Opts},
+    utils::downloader::{Compression, DownloadJob, Downloader},
+    warn,
+};
+
+use anyhow::{bail, Context, Result};
+use console::style;
+use sequoia_openpgp::Cert;
+use std::{fs, path::Path};
+
+/// Import a public key from a URL or a local file into `key_root`
+///
+/// Returns true if the user cancelled the operation.
+pub async fn add(
+    source: &str,
+    key_root: &Path,
+    opts: &Opts,
+    config: &Config,
+    downloader: &Downloader,
+) -> Result<bool> {
+    let filename = key_filename(source)?;
+    let data = if source.starts_with("https://") || source.starts_with("http://") {
+        let download_path = opts.root.join(crate::DB_CACHE_PATH);
+        let job = DownloadJob {
+            url: source.to_owned(),
+            mirrors: Vec::new(),
+            repos: Vec::new(),
+            description: Some(format!("Public key {}", style(&filename).bold())),
+            filename: Some(format!("key_{filename}")),
+            size: None,
+            compression: Compression::None(None),
+        };
+        let res = downloader
+            .fetch(vec![job], &download_path, false)
+            .await
+            .context(format!("Failed to download public key from {source}."))?;
+        let path = res
+            .into_values()
+            .next()
+            .context("Failed to download public key.")?;
+        let data = fs::read(&path)?;
+        fs::remove_file(&path)?;
+        data
+    } else {
+        fs::read(source).context(format!(
+            "Failed to read public key file {}.",
+            style(source).bold()
+        ))?
+    };
+    let certs = parse_certs(&data).context("Failed to load public key.")?;
+
+    info!("Importing public key to {}:", style(&filename).bold());
+    for cert in &certs {
+        show_cert(cert);
+    }
+    let path = key_root.join(&filename);
+    if path.exists() {
+        if fs::read(&path)? == data {
+            success!("This public key is already imported.");
+            return Ok(false);
+        }
+        bail!(
+            "A different public key file named {} already exists.",
+            style(&filename).bold()
+        );
+    }
+    warn!("Packages from repositories signed by this key will be trusted.");
+    if !cli::ask_confirm(opts, "Trust this key?")? {
+        return Ok(true);
+    }
+
+    fs::create_dir_all(key_root).context("Failed to create public key directory.")?;
+    fs::write(&path, &data).context(format!(
+        "Failed to write public key file {}.",
+        style(path.display()).bold()
+    ))?;
+    success!("Public key saved as {}.", style(&filename).bold());
+    if !config
+        .repo
+        .values()
+        .any(|repo| repo.keys.iter().any(|key| key.file() == filename))
+    {
+        info!(
+            "Add {} to {} of a repository in config.toml to use it.",
+            style(format!("\"{filename}\"")).bold(),
+            style("keys").bold()
+        );
+    }
+
+    Ok(false)
+}
+
+/// List public keys in `key_root`, and the repositories that use them
+pub fn list(key_root: &Path, config: &Config) -> Result<()> {
+    let mut filenames = Vec::new();
+    if key_root.is_dir() {
+        for entry in fs::read_dir(key_root).context("Failed to read public key directory.")? {
+            let entry = entry?;
+            if entry.path().is_file() {
+                filenames.push(entry.file_name().to_string_lossy().to_string());
+            }
+        }
+    }
+    if filenames.is_empty() {
+        info!("No public key is imported.");
+        return Ok(());
+    }
+    filenames.sort();
+
+    for filename in filenames {
+        let mut repos: Vec<&str> = config
+            .repo
+            .iter()
+            .filter(|(_, repo)| repo.keys.iter().any(|key| key.file() == filename))
+            .map(|(name, _)| name.as_str())
+            .collect();
+        repos.sort_unstable();
+        let used_by = if repos.is_empty() {
+            style("(not used by any repository)".to_string()).dim()
+        } else {
+            style(format!("(used by {})", repos.join(", "))).dim()
+        };
+        msg!("{} {}", style(&filename).bold(), used_by);
+        match read_certs(&key_root.join(&filename)) {
+            Ok(certs) => certs.iter().for_each(show_cert),
+            Err(e) => {
+                warn!("Failed to load {}: {:#}", style(&filename).bold(), e);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Remove the key file containing the key with this fingerprint
+///
+/// Returns true if the user cancelled the operation.
+pub fn remove(fingerprint: &str, key_root: &Path, opts: &Opts, config: &Config) -> Result<bool> {
+    let fingerprint: String = fingerprint
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect::<String>()
+        .to_ascii_uppercase();
+    let mut found = None;
+    if key_root.is_dir() {
+        for entry in fs::read_dir(key_root).context("Failed to read public key directory.")? {
+            let path = entry?.path();
+            // Ignore broken key files, they are reported by list
+            let certs = match read_certs(&path) {
+                Ok(certs) => certs,
+                Err(_) => continue,
+            };
+            if certs
+                .iter()
+                .any(|cert| cert.fingerprint().to_hex() == fingerprint)
+            {
+                found = Some((path, certs));
+                break;
+            }
+        }
+    }
+    let (path, certs) = match found {
+        Some(found) => found,
+        None => bail!(
+            "Public key with fingerprint {} is not found.",
+            style(&fingerprint).bold()
+        ),
+    };
+    let filename = path
+        .file_name()
+        .map(|f| f.to_string_lossy().to_string())
+        .unwrap_or_default();
+    if certs.len() > 1 {
+        bail!(
+            "Public key file {} contains other keys, please remove it manually.",
+            style(path.display()).bold()
+        );
+    }
+
+    info!("Removing public key file {}:", style(&filename).bold());
+    certs.iter().for_each(show_cert);
+    for (name, repo) in &config.repo {
+        if repo.keys.iter().any(|key| key.file() == filename) {
+            warn!(
+                "Repository {} uses this key, and will fail to refresh until it is removed from config.",
+                style(name).bold()
+            );
+        }
+    }
+    if !cli::ask_confirm(opts, "Remove this key?")? {
+        return Ok(true);
+    }
+    fs::remove_file(&path).context(format!(
+        "Failed to remove public key file {}.",
+        style(path.display()).bold()
+    ))?;
+    success!("Public key removed.");
+
+    Ok(false)
+}
+
+fn show_cert(cert: &Cert) {
+    msg!("  {}", style(cert.fingerprint().to_spaced_hex()).green());
+    for uid in cert.userids() {
+        msg!("    {}", uid.userid());
+    }
+}
+
+/// Generate a filename in `key_root` for the key from `source`
+fn key_filename(source: &str) -> Result<String> {
+    // Remove query string of URLs
+    let source = source.split(['?', '#']).next().unwrap_or(source);
+    // Only use the path of URLs
+    let path = match source.split_once("://") {
+        Some((_, rest)) => rest.split_once('/').map(|(_, path)| path).unwrap_or(""),
+        None => source,
+    };
+    let name = path.trim_end_matches('/').rsplit('/').next().unwrap_or("");
+    let name: String = name
+        .chars()
+        .map(|c| if key_filename_char(c) { c } else { '_' })
+        .collect();
+    let name = name.trim_start_matches('.');
+    if name.is_empty() {
+        bail!(
+            "Cannot decide a filename for public key from {}.",
+            style(source).bold()
+        );
+    }
+
+    Ok(name.to_owned())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_key_filename() {
+        assert_eq!(
+            key_filename("https://example.com/keys/repo-key.asc?raw=1").unwrap(),
+            "repo-key.asc"
+        );
+        assert_eq!(
+            key_filename("/tmp/my key (1).gpg").unwrap(),
+            "my_key__1_.gpg"
+        );
+        assert_eq!(key_filename("../.hidden.asc").unwrap(), "hidden.asc");
+        assert!(key_filename("https://example.com/").is_err());
+        assert!(key_filename("..").is_err());
+    }
+}
@@ -26,7 +26,7 @@ pub fn add(opts: &Opts, paths: &[PathBuf]) -> Result<Vec<String>> {
             style(&pkgmeta.name).bold(),
             pkgmeta.version
         );
-        if cli::ask_confirm(opts, "Confirm?")? {
+        if !cli::ask_confirm(opts, "Confirm?")? {
             bail!("User cancelled operation.");
         }
 
@@ -84,3 +84,31 @@ pub fn clean(ms: &MachineStatus, root: &Path) -> Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::utils::temp_dir::TempDir;
+    use clap::Parser;
+
+    #[test]
+    fn test_add() {
+        let root = TempDir::new("local-add");
+        let deb = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures/debs/hello-gz_1.0-1_all.deb");
+        let opts = |answer: &str| {
+            Opts::try_parse_from(["oma", "--root", root.to_str().unwrap(), answer, "upgrade"])
+                .unwrap()
+        };
+
+        // Declined, nothing is copied
+        assert!(add(&opts("--assume-no"), &[deb.clone()]).is_err());
+        assert!(!root.join(crate::LOCAL_REPO_PATH).exists());
+        // Confirmed, this used to cancel the operation too
+        assert_eq!(add(&opts("--yes"), &[deb]).unwrap(), vec!["hello-gz"]);
+        assert!(root
+            .join(crate::LOCAL_REPO_PATH)
+            .join("hello-gz_1.0-1_all.deb")
+            .is_file());
+    }
+}
@@ -0,0 +1,151 @@
+use crate::types::{config::ManifestFormat, Checksum, PkgInstallAction};
+
+use anyhow::{format_err, Context, Result};
+use console::style;
+use serde_json::{json, Value};
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// A downloaded package, as listed in the manifest
+struct ManifestEntry<'a> {
+    action: &'a PkgInstallAction,
+    filename: String,
+    sha256: String,
+}
+
+/// Match `actions` with files in `download_res`, using SHA256 checksums from the repository
+///
+/// Packages that only come with other checksums are hashed again from the downloaded files.
+fn manifest_entries<'a>(
+    actions: &'a [PkgInstallAction],
+    download_res: &HashMap<String, PathBuf>,
+) -> Result<Vec<ManifestEntry<'a>>> {
+    let mut res = Vec::new();
+    for action in actions {
+        let path = download_res.get(&action.url).ok_or_else(|| {
+            format_err!(
+                "Package {} has not been downloaded.",
+                style(&action.name).bold()
+            )
+        })?;
+        let filename = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| format_err!("Invalid package path {}.", path.display()))?
+            .to_string();
+        let sha256 = match &action.checksum {
+            Checksum::Sha256(hash) => hex::encode(hash),
+            Checksum::Sha512(_) => match Checksum::from_file_sha256(path)? {
+                Checksum::Sha256(hash) => hex::encode(hash),
+                Checksum::Sha512(_) => unreachable!(),
+            },
+        };
+        res.push(ManifestEntry {
+            action,
+            filename,
+            sha256,
+        });
+    }
+    res.sort_by(|a, b| a.filename.cmp(&b.filename));
+    Ok(res)
+}
+
+/// Write a checksum manifest of packages in `actions` to `dir`, returns the path of the manifest
+pub fn write_manifest(
+    actions: &[PkgInstallAction],
+    download_res: &HashMap<String, PathBuf>,
+    dir: &Path,
+    format: ManifestFormat,
+) -> Result<PathBuf> {
+    let entries = manifest_entries(actions, download_res)?;
+    let (path, content) = match format {
+        ManifestFormat::Sha256 => {
+            // The same format as sha256sum, so that it can be checked with `sha256sum -c`
+            let content: String = entries
+                .iter()
+                .map(|entry| format!("{}  {}\n", entry.sha256, entry.filename))
+                .collect();
+            (dir.join("SHA256SUMS"), content)
+        }
+        ManifestFormat::Json => {
+            let pkgs: Vec<Value> = entries
+                .iter()
+                .map(|entry| {
+                    json!({
+                        "name": entry.action.name,
+                        "version": entry.action.version.to_string(),
+                        "filename": entry.filename,
+                        "size": entry.action.download_size,
+                        "sha256": entry.sha256,
+                    })
+                })
+                .collect();
+            (
+                dir.join("manifest.json"),
+                format!("{}\n", json!({ "packages": pkgs })),
+            )
+        }
+    };
+    fs::write(&path, content).context(format!(
+        "Failed to write checksum manifest to {}.",
+        path.display()
+    ))?;
+
+    Ok(path)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::types::PkgVersion;
+    use crate::utils::temp_dir::TempDir;
+
+    #[test]
+    fn test_write_manifest() {
+        let dir = TempDir::new("manifest");
+        let mut actions = Vec::new();
+        let mut download_res = HashMap::new();
+        for (name, content) in [("b", "bbb"), ("a", "aaa")] {
+            let path = dir.join(format!("{name}_1.0_all.deb"));
+            fs::write(&path, content).unwrap();
+            let url = format!("https://repo.example.com/debs/{name}_1.0_all.deb");
+            // Without a SHA256 checksum from the repository, the file is hashed instead
+            let checksum = if name == "a" {
+                Checksum::from_file_sha256(&path).unwrap()
+            } else {
+                Checksum::Sha512(vec![0; 64])
+            };
+            actions.push(PkgInstallAction {
+                name: name.to_string(),
+                url: url.clone(),
+                download_size: content.len() as u64,
+                install_size: 0,
+                checksum,
+                version: PkgVersion::try_from("1.0").unwrap(),
+            });
+            download_res.insert(url, path);
+        }
+
+        let manifest =
+            write_manifest(&actions, &download_res, &dir, ManifestFormat::Sha256).unwrap();
+        assert_eq!(manifest, dir.join("SHA256SUMS"));
+        let content = fs::read_to_string(&manifest).unwrap();
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(lines.len(), 2);
+        for (line, name) in lines.iter().zip(["a_1.0_all.deb", "b_1.0_all.deb"]) {
+            let (hash, filename) = line.split_once("  ").unwrap();
+            assert_eq!(filename, name);
+            assert_eq!(
+                Checksum::from_sha256_str(hash).unwrap(),
+                Checksum::from_file_sha256(&dir.join(filename)).unwrap()
+            );
+        }
+
+        let manifest = write_manifest(&actions, &download_res, &dir, ManifestFormat::Json).unwrap();
+        let content = fs::read_to_string(manifest).unwrap();
+        assert!(content.starts_with("{\"packages\":[{\"name\":\"a\",\"version\":\"1.0\",\"filename\":\"a_1.0_all.deb\",\"size\":3,\"sha256\":\""));
+    }
+}
@@ -1,24 +1,45 @@
 mod bench;
+mod build_deps;
+mod changelog;
+mod check_updates;
+pub mod config;
+mod configure;
+mod depends;
+pub mod diff_state;
+pub mod doctor;
 mod download;
 mod execute;
+mod key;
 mod local;
+mod manifest;
 mod pick;
+mod pkg_list;
+mod policy;
+mod priority;
+pub mod profile;
+mod refresh;
+mod repo;
 mod search;
+mod snapshot;
+mod status;
 use execute::execute;
 
 use crate::{
-    db::LocalDb,
+    cli,
+    db::{self, LocalDb, Snapshot},
     executor::MachineStatus,
-    info, success,
+    info, msg, success,
     types::{
-        config::{Blueprints, Config, Opts, SubCmd},
+        config::{Blueprints, CheckUpdatesOpts, Config, KeyCmd, Opts, RepoCmd, SubCmd},
         VersionRequirement,
     },
-    utils::lock,
+    utils::{downloader::Downloader, lock},
+    warn,
 };
 
-use anyhow::{Context, Result};
-use std::path::PathBuf;
+use anyhow::{bail, Context, Result};
+use console::style;
+use std::{path::PathBuf, sync::atomic::Ordering, time::Duration};
 
 #[derive(Debug)]
 pub enum UserRequest {
@@ -27,34 +48,121 @@ pub enum UserRequest {
     Install((Vec<InstallRequest>, bool)),
     // Vec<(PkgName, remove_recomm)>
     Remove(Vec<(String, bool)>),
-    Upgrade,
+    Upgrade { security_only: bool },
 }
 
 #[derive(Debug)]
 pub struct InstallRequest {
     pkgname: String,
     install_recomm: bool,
+    /// Fail if any recommended package cannot be installed
+    strict_recomm: bool,
     ver_req: Option<VersionRequirement>,
     local: bool,
     /// Whether modify existing entry
     modify: bool,
 }
 
+/// Local package database of the system
+fn local_db(config: &Config, opts: &Opts) -> LocalDb {
+    LocalDb::new(
+        opts.root.join(crate::DB_CACHE_PATH),
+        opts.root.join(crate::DB_KEY_PATH),
+        config.repo.clone(),
+        &config.arch,
+        config.preferred_checksum,
+        config.reject_expired_keys,
+        config.fallback_on_bad_signature,
+    )
+}
+
+/// Pin `localdb` to `--snapshot`, or the snapshot in use if any
+fn pin_snapshot(localdb: &mut LocalDb, opts: &Opts) -> Result<()> {
+    let dir = opts.root.join(crate::SNAPSHOT_PATH);
+    let name = match &opts.snapshot {
+        Some(name) => Some(name.clone()),
+        None => db::snapshot::active(&dir)?,
+    };
+    if let Some(name) = name {
+        localdb.pin(Snapshot::load(&dir, &name)?);
+    }
+    Ok(())
+}
+
+/// Print the number of upgradable packages, returns the exit code
+pub fn check_updates(config: &Config, opts: &Opts, check: &CheckUpdatesOpts) -> Result<i32> {
+    let localdb = local_db(config, opts);
+    let machine_status = MachineStatus::new(&opts.root, config.dpkg_admindir.as_deref())?;
+    let count = check_updates::check_updates(&localdb, &machine_status, check.json)?;
+    match check.exit_code {
+        Some(code) if count > 0 => Ok(code),
+        _ => Ok(0),
+    }
+}
+
+/// Warn if local metadata is older than `max_metadata_age`, and offer to refresh it
+///
+/// With `auto_refresh`, it's refreshed without asking. It can't be refreshed with
+/// `--reinstall-from-cache`, so only the warning is shown then.
+async fn check_metadata_age(
+    localdb: &LocalDb,
+    downloader: &Downloader,
+    opts: &Opts,
+    config: &Config,
+) -> Result<()> {
+    let max_age = match config.max_metadata_age {
+        Some(days) => Duration::from_secs(days * 86400),
+        None => return Ok(()),
+    };
+    let age = match localdb.stale_for(max_age) {
+        Some(age) => age,
+        None => return Ok(()),
+    };
+    warn!(
+        "Local package metadata was last refreshed {} day(s) ago, exceeding {} in config.",
+        age.as_secs() / 86400,
+        style("max_metadata_age").bold()
+    );
+    if opts.reinstall_from_cache {
+        msg!(
+            "Metadata is not refreshed with {}.",
+            style("--reinstall-from-cache").bold()
+        );
+        return Ok(());
+    }
+    if !config.auto_refresh && !cli::ask_confirm(opts, "Refresh it now?")? {
+        return Ok(());
+    }
+    let refresh = async {
+        // Queries may be run by regular users, who can't lock or refresh
+        if !crate::LOCKED.load(Ordering::Relaxed) {
+            lock::ensure_unlocked(&opts.root)?;
+            lock::lock(&opts.root)?;
+        }
+        localdb.update(downloader).await
+    };
+    // Stale metadata is still usable, so carry on
+    if let Err(e) = refresh.await {
+        warn!("Failed to refresh local package metadata: {:#}", e);
+    }
+    Ok(())
+}
+
 /// bool in return type indicated whether user cancelled operation
 pub async fn fullfill_command(
     config: &Config,
     opts: &Opts,
     blueprints: &mut Blueprints,
 ) -> Result<bool> {
-    let downloader = crate::utils::downloader::Downloader::new();
+    let network_config = config.network.clone().unwrap_or_default();
+    let downloader = crate::utils::downloader::Downloader::new(&network_config, &config.repo)?;
     // Directory that stores trusted public keys for repos
     let key_root = opts.root.join(crate::DB_KEY_PATH);
-    let localdb = LocalDb::new(
-        opts.root.join(crate::DB_CACHE_PATH),
-        key_root,
-        config.repo.clone(),
-        &config.arch,
-    );
+    let mut localdb = local_db(config, opts);
+    // Snapshots can be managed even if the one in use is gone
+    if !matches!(opts.subcmd, SubCmd::Snapshot(_)) {
+        pin_snapshot(&mut localdb, opts)?;
+    }
 
     match &opts.subcmd {
         SubCmd::Install(add) => {
@@ -62,25 +170,67 @@ pub async fn fullfill_command(
             lock::ensure_unlocked(&opts.root)?;
             lock::lock(&opts.root)?;
 
+            let names = pkg_list::merge_names(&add.names, add.from_file.as_deref(), !add.local)?;
             let names = if add.local {
-                let paths: Vec<PathBuf> = add.names.iter().map(PathBuf::from).collect();
+                let paths: Vec<PathBuf> = names.iter().map(PathBuf::from).collect();
                 local::add(opts, &paths)?
             } else {
-                add.names.clone()
+                names
             };
-            let req = names
-                .iter()
-                .map(|pkgname| InstallRequest {
-                    pkgname: pkgname.to_owned(),
+            let mut names: Vec<(String, Option<VersionRequirement>)> =
+                names.into_iter().map(|name| (name, None)).collect();
+            if let Some(control) = &add.build_deps {
+                let build_deps = build_deps::read_build_deps(control, &config.arch)?;
+                info!(
+                    "Installing {} build dependencies from {}...",
+                    build_deps.len(),
+                    control.display()
+                );
+                names.extend(
+                    build_deps
+                        .into_iter()
+                        .map(|(name, ver_req)| (name, Some(ver_req))),
+                );
+            }
+            let strict_recomm = add.recommends_as_depends || config.strict_recommends;
+            if strict_recomm && add.no_recommends {
+                bail!("Cannot skip recommended packages in strict recommends mode.");
+            }
+            let mut req: Vec<InstallRequest> = names
+                .into_iter()
+                .map(|(pkgname, ver_req)| InstallRequest {
+                    pkgname,
                     install_recomm: !add.no_recommends,
-                    ver_req: None,
+                    strict_recomm,
+                    ver_req,
                     local: add.local,
                     modify: false,
                 })
                 .collect();
+            // Update local db, unless installing from cache only
+            if !opts.reinstall_from_cache {
+                localdb.update(&downloader).await?;
+            } else {
+                check_metadata_age(&localdb, &downloader, opts, config).await?;
+            }
+            // Packages are picked by priority from the refreshed metadata
+            if let Some(level) = add.install_priority {
+                let names = priority::read_priority_pkgs(&localdb, level)?;
+                info!(
+                    "Installing {} package(s) of priority {} or higher...",
+                    names.len(),
+                    style(level).bold()
+                );
+                req.extend(names.into_iter().map(|pkgname| InstallRequest {
+                    pkgname,
+                    install_recomm: !add.no_recommends,
+                    strict_recomm,
+                    ver_req: None,
+                    local: false,
+                    modify: false,
+                }));
+            }
             let req = UserRequest::Install((req, add.init));
-            // Update local db
-            localdb.update(&downloader).await?;
             // Execute blueprint
             let cancelled = execute(&localdb, &downloader, blueprints, opts, config, req).await?;
 
@@ -91,15 +241,35 @@ pub async fn fullfill_command(
             lock::ensure_unlocked(&opts.root)?;
             lock::lock(&opts.root)?;
 
+            let names = pkg_list::merge_names(&rm.names, rm.from_file.as_deref(), !rm.by_file)?;
+            let names = if rm.by_file {
+                let ms = MachineStatus::new(&opts.root, config.dpkg_admindir.as_deref())?;
+                let mut res = Vec::with_capacity(names.len());
+                for path in &names {
+                    let pkgname = search::find_file_owner(&ms, path, opts, config)?;
+                    info!(
+                        "File {} belongs to package {}.",
+                        style(path).bold(),
+                        style(&pkgname).bold()
+                    );
+                    res.push(pkgname);
+                }
+                res
+            } else {
+                names
+            };
             // Prepare request
-            let req: Vec<(String, bool)> = rm
-                .names
+            let req: Vec<(String, bool)> = names
                 .iter()
                 .map(|name| (name.clone(), rm.remove_recommends))
                 .collect();
             let req = UserRequest::Remove(req);
-            // Update local db
-            localdb.update(&downloader).await?;
+            // Update local db, unless installing from cache only
+            if !opts.reinstall_from_cache {
+                localdb.update(&downloader).await?;
+            } else {
+                check_metadata_age(&localdb, &downloader, opts, config).await?;
+            }
             // Apply stuff
             let cancelled = execute(&localdb, &downloader, blueprints, opts, config, req).await?;
 
@@ -110,46 +280,142 @@ pub async fn fullfill_command(
             lock::ensure_unlocked(&opts.root)?;
             lock::lock(&opts.root)?;
 
-            let req = pick::pick(&pick.name, blueprints, opts, &localdb)?;
-            // Update local db
-            localdb.update(&downloader).await?;
+            let req = pick::pick(&pick.name, blueprints, opts, config, &localdb)?;
+            // Update local db, unless installing from cache only
+            if !opts.reinstall_from_cache {
+                localdb.update(&downloader).await?;
+            } else {
+                check_metadata_age(&localdb, &downloader, opts, config).await?;
+            }
             // Apply stuff
             let cancelled = execute(&localdb, &downloader, blueprints, opts, config, req).await?;
 
             Ok(cancelled)
         }
-        SubCmd::Refresh => {
+        // Handled before loading blueprints, as the active profile may be broken
+        SubCmd::Profile(_)
+        | SubCmd::Config(_)
+        | SubCmd::CheckUpdates(_)
+        | SubCmd::Doctor
+        | SubCmd::DiffState(_) => {
+            unreachable!()
+        }
+        SubCmd::Key(key) => match &key.cmd {
+            KeyCmd::Add(add) => {
+                // This operation has side effects
+                lock::ensure_unlocked(&opts.root)?;
+                lock::lock(&opts.root)?;
+                key::add(&add.source, &key_root, opts, config, &downloader).await
+            }
+            KeyCmd::List => {
+                key::list(&key_root, config)?;
+                Ok(false)
+            }
+            KeyCmd::Remove(rm) => {
+                // This operation has side effects
+                lock::ensure_unlocked(&opts.root)?;
+                lock::lock(&opts.root)?;
+                key::remove(&rm.fingerprint, &key_root, opts, config)
+            }
+        },
+        SubCmd::Repo(repo) => match &repo.cmd {
+            RepoCmd::Check(check) => {
+                // Metadata is downloaded to a temporary directory next to local metadata
+                lock::ensure_unlocked(&opts.root)?;
+                lock::lock(&opts.root)?;
+                repo::check(&localdb, &downloader, &check.names).await?;
+                Ok(false)
+            }
+        },
+        SubCmd::Refresh(refresh) => {
             // This operation has side effects
             lock::ensure_unlocked(&opts.root)?;
             lock::lock(&opts.root)?;
 
-            localdb.update(&downloader).await?;
+            if refresh.check {
+                refresh::check(&localdb, &downloader, &refresh.names).await?;
+                return Ok(false);
+            }
+            localdb.update_repos(&downloader, &refresh.names).await?;
             success!("Omakase has successfully refreshed local package metadata.");
             Ok(false)
         }
-        SubCmd::Execute => {
+        SubCmd::Execute(upgrade) => {
             // This operation has side effects
             lock::ensure_unlocked(&opts.root)?;
             lock::lock(&opts.root)?;
 
-            let req = UserRequest::Upgrade;
-            localdb
-                .update(&downloader)
-                .await
-                .context("Failed to refresh local package metadata!")?;
+            if upgrade.only_configure {
+                return configure::only_configure(opts, config);
+            }
+            let req = UserRequest::Upgrade {
+                security_only: upgrade.security_only,
+            };
+            if !opts.reinstall_from_cache {
+                localdb
+                    .update(&downloader)
+                    .await
+                    .context("Failed to refresh local package metadata!")?;
+            } else {
+                check_metadata_age(&localdb, &downloader, opts, config).await?;
+            }
 
             let exit = execute(&localdb, &downloader, blueprints, opts, config, req).await?;
 
             Ok(exit)
         }
         SubCmd::Search(search) => {
-            let machine_status = MachineStatus::new(&opts.root)?;
-            search::search_deb_db(&localdb, &search.keyword, &machine_status)?;
+            check_metadata_age(&localdb, &downloader, opts, config).await?;
+            let machine_status = MachineStatus::new(&opts.root, config.dpkg_admindir.as_deref())?;
+            search::search_deb_db(&localdb, &search.keyword, &search.repo, &machine_status)?;
             Ok(false)
         }
         SubCmd::Provide(provide) => {
-            let machine_status = MachineStatus::new(&opts.root)?;
-            search::show_provide_file(&localdb, &machine_status, &provide.file, provide.bin)?;
+            check_metadata_age(&localdb, &downloader, opts, config).await?;
+            let machine_status = MachineStatus::new(&opts.root, config.dpkg_admindir.as_deref())?;
+            search::show_provide_file(
+                &localdb,
+                &machine_status,
+                &provide.file,
+                provide.bin,
+                &opts.root.join(crate::CONTENTS_INDEX_PATH),
+                config.resolve_fields(),
+            )?;
+            Ok(false)
+        }
+        SubCmd::Files(files) => {
+            check_metadata_age(&localdb, &downloader, opts, config).await?;
+            let machine_status = MachineStatus::new(&opts.root, config.dpkg_admindir.as_deref())?;
+            search::show_files(&localdb, &machine_status, &files.name, opts, config)?;
+            Ok(false)
+        }
+        SubCmd::Depends(depends) => {
+            check_metadata_age(&localdb, &downloader, opts, config).await?;
+            depends::show_depends(&localdb, depends, opts, config)?;
+            Ok(false)
+        }
+        SubCmd::Changelog(changelog) => {
+            check_metadata_age(&localdb, &downloader, opts, config).await?;
+            changelog::show_changelog(
+                &changelog.name,
+                &localdb,
+                &downloader,
+                &execute::download_dir(opts),
+                opts,
+                config,
+            )
+            .await?;
+            Ok(false)
+        }
+        SubCmd::Policy(policy) => {
+            check_metadata_age(&localdb, &downloader, opts, config).await?;
+            let machine_status = MachineStatus::new(&opts.root, config.dpkg_admindir.as_deref())?;
+            policy::show_policy(&localdb, &machine_status, &policy.names)?;
+            Ok(false)
+        }
+        SubCmd::Status(status) => {
+            let machine_status = MachineStatus::new(&opts.root, config.dpkg_admindir.as_deref())?;
+            status::show_status(blueprints, &machine_status, status.json, opts, config)?;
             Ok(false)
         }
         SubCmd::Clean(cleanconfig) => {
@@ -165,7 +431,7 @@ pub async fn fullfill_command(
             }
 
             info!("Purging local package cache...");
-            let ms = MachineStatus::new(&opts.root)?;
+            let ms = MachineStatus::new(&opts.root, config.dpkg_admindir.as_deref())?;
             local::clean(&ms, &opts.root)?;
 
             if cleanconfig.all {
@@ -175,19 +441,45 @@ pub async fn fullfill_command(
                     std::fs::remove_dir_all(&db_cache_path)?;
                     std::fs::create_dir_all(&db_cache_path)?;
                 }
+                let index_path = opts.root.join(crate::CONTENTS_INDEX_PATH);
+                if index_path.is_dir() {
+                    std::fs::remove_dir_all(&index_path)?;
+                }
+            } else if cleanconfig.metadata {
+                info!("Purging metadata of removed repositories...");
+                let removed = localdb.clean_orphans()?;
+                for path in &removed {
+                    msg!("Removed {}", path.display());
+                }
+                if removed.is_empty() {
+                    msg!("Nothing to remove.");
+                }
             }
 
             Ok(false)
         }
-        SubCmd::Bench => {
+        SubCmd::Bench(bench_config) => {
             // This operation has side effects (refresh)
             lock::ensure_unlocked(&opts.root)?;
             lock::lock(&opts.root)?;
 
-            bench::bench(opts, config, localdb, &downloader).await?;
+            bench::bench(
+                opts,
+                config,
+                localdb,
+                &downloader,
+                &network_config,
+                bench_config,
+            )
+            .await?;
+            Ok(false)
+        }
+        SubCmd::Snapshot(cmd) => {
+            snapshot::snapshot_command(&localdb, opts, cmd)?;
             Ok(false)
         }
         SubCmd::Download(download) => {
+            check_metadata_age(&localdb, &downloader, opts, config).await?;
             let mut latest = download.latest;
             if opts.yes {
                 // If yesman, then use latest version automatically
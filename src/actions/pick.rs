@@ -3,8 +3,11 @@ use crate::{
     db::LocalDb,
     error,
     executor::MachineStatus,
-    info, msg, pool,
-    types::{config::Blueprints, config::Opts, PkgSource, PkgVersion, VersionRequirement},
+    info, msg,
+    pool::{self, PkgFields},
+    types::{
+        config::Blueprints, config::Config, config::Opts, PkgSource, PkgVersion, VersionRequirement,
+    },
 };
 
 use anyhow::{bail, Context, Result};
@@ -14,6 +17,7 @@ pub fn pick(
     pkgname: &str,
     blueprints: &Blueprints,
     opts: &Opts,
+    config: &Config,
     local_db: &LocalDb,
 ) -> Result<UserRequest> {
     // Don't allow picking if the target is in the vendor blueprint
@@ -34,10 +38,10 @@ pub fn pick(
     if !local_repo.is_dir() {
         std::fs::create_dir_all(&local_repo)?;
     }
-    let pool = pool::source::create_pool(&dbs, &[local_repo])?;
+    let pool = pool::source::create_pool(&dbs, &[local_repo], PkgFields::All, &Default::default())?;
 
     // To check if this package is installed already
-    let ms = MachineStatus::new(&opts.root)?;
+    let ms = MachineStatus::new(&opts.root, config.dpkg_admindir.as_deref())?;
     let currrent_version = ms.pkgs.get(pkgname).map(|state| &state.version);
     // Get all versions
     let mut choices: Vec<(String, PkgVersion, bool, bool)> = Vec::new();
@@ -90,6 +94,7 @@ pub fn pick(
         pkgname: pkgname.to_owned(),
         modify: true,
         install_recomm: false,
+        strict_recomm: false,
         ver_req,
         local: *local,
     }];
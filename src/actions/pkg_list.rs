@@ -0,0 +1,140 @@
+use anyhow::{bail, Context, Result};
+use console::style;
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::{
+    fs::File,
+    io::{BufRead, BufReader},
+    path::Path,
+};
+
+/// Path that means reading from stdin
+const STDIN_PATH: &str = "-";
+
+/// Read package names from a file, or stdin if path is "-"
+pub fn read_pkg_list(path: &Path, validate: bool) -> Result<Vec<String>> {
+    let display = if path.as_os_str() == STDIN_PATH {
+        "stdin".to_string()
+    } else {
+        path.display().to_string()
+    };
+    let names = if path.as_os_str() == STDIN_PATH {
+        parse_pkg_list(std::io::stdin().lock(), validate)
+    } else {
+        let f = File::open(path).context(format!(
+            "Failed to open package list {}.",
+            style(&display).bold()
+        ))?;
+        parse_pkg_list(BufReader::new(f), validate)
+    };
+
+    names.context(format!(
+        "Failed to read package list from {}.",
+        style(&display).bold()
+    ))
+}
+
+/// Merge names from the command line with names from package lists, keeping the order
+pub fn merge_names(
+    names: &[String],
+    from_file: Option<&Path>,
+    validate: bool,
+) -> Result<Vec<String>> {
+    let mut res: Vec<String> = Vec::with_capacity(names.len());
+    let mut read_stdin = false;
+    fn push(res: &mut Vec<String>, name: String) {
+        if !res.contains(&name) {
+            res.push(name);
+        }
+    }
+    let mut sources = Vec::new();
+    if let Some(path) = from_file {
+        sources.push(path);
+    }
+    for name in names {
+        if name == STDIN_PATH {
+            sources.push(Path::new(STDIN_PATH));
+        } else {
+            push(&mut res, name.clone());
+        }
+    }
+    for path in sources {
+        if path.as_os_str() == STDIN_PATH {
+            // Only read stdin once
+            if read_stdin {
+                continue;
+            }
+            read_stdin = true;
+        }
+        for name in read_pkg_list(path, validate)? {
+            push(&mut res, name);
+        }
+    }
+
+    Ok(res)
+}
+
+/// Parse a newline-separated list of names. Blank lines and `#` comments are ignored.
+fn parse_pkg_list(reader: impl BufRead, validate: bool) -> Result<Vec<String>> {
+    lazy_static! {
+        static ref PKGNAME: Regex = Regex::new(r"^[a-z0-9][a-z0-9+.-]*$").unwrap();
+    }
+
+    let mut res = Vec::new();
+    for (no, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = match line.split_once('#') {
+            Some((content, _)) => content,
+            None => &line,
+        }
+        .trim();
+        if line.is_empty() {
+            continue;
+        }
+        if validate && !PKGNAME.is_match(line) {
+            bail!(
+                "Malformed package name {} at line {}.",
+                style(line).bold(),
+                no + 1
+            );
+        }
+        res.push(line.to_owned());
+    }
+
+    Ok(res)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::utils::temp_dir::TempDir;
+
+    #[test]
+    fn test_read_pkg_list() {
+        let dir = TempDir::new("pkg-list");
+        let path = dir.join("pkgs.list");
+        std::fs::write(
+            &path,
+            "# Base system\nbash\n\n  coreutils  # comment\nlibstdc++\nvim\n",
+        )
+        .unwrap();
+        assert_eq!(
+            read_pkg_list(&path, true).unwrap(),
+            vec!["bash", "coreutils", "libstdc++", "vim"]
+        );
+        // Names on the command line come first, duplicates are removed
+        assert_eq!(
+            merge_names(&["vim".to_string(), "zsh".to_string()], Some(&path), true).unwrap(),
+            vec!["vim", "zsh", "bash", "coreutils", "libstdc++"]
+        );
+
+        std::fs::write(&path, "bash\nfoo bar\n").unwrap();
+        let err = read_pkg_list(&path, true).unwrap_err();
+        assert!(format!("{:#}", err).contains("line 2"));
+        // Entries are not package names, e.g. paths of local debs
+        assert_eq!(
+            read_pkg_list(&path, false).unwrap(),
+            vec!["bash", "foo bar"]
+        );
+    }
+}
@@ -0,0 +1,237 @@
+use crate::{
+    db::{LocalDb, PkgDb},
+    executor::{MachineStatus, PkgState},
+    pool::{self, PkgFields, PkgPool},
+    types::{PkgSource, PkgVersion},
+    warn,
+};
+
+use anyhow::{bail, Context, Result};
+use console::style;
+use std::io::Write;
+
+/// Installed and candidate versions of a package, along with all available versions
+#[derive(Debug, PartialEq, Eq)]
+struct Policy {
+    name: String,
+    installed: Option<PkgVersion>,
+    /// The newest version, which is what installing the package gets
+    candidate: Option<PkgVersion>,
+    // Vec<(Version, Origins)>, from the newest
+    versions: Vec<(PkgVersion, Vec<String>)>,
+}
+
+impl Policy {
+    fn new(
+        pool: &dyn PkgPool,
+        dbs: &[PkgDb],
+        machine_status: &MachineStatus,
+        name: &str,
+    ) -> Option<Self> {
+        let installed = machine_status
+            .pkgs
+            .get(name)
+            .filter(|status| {
+                status.state != PkgState::NotInstalled && status.state != PkgState::ConfigFiles
+            })
+            .map(|status| status.version.clone());
+        let mut versions = Vec::new();
+        for id in pool.get_pkgs_by_name(name).unwrap_or_default() {
+            let pkg = pool.get_pkg_by_id(id).unwrap();
+            let origins = match &pkg.source {
+                PkgSource::Http((url, _, _)) => {
+                    // The same package may be in several repositories
+                    let mut repos = Vec::new();
+                    for url in std::iter::once(url.clone()).chain(pkg.mirror_urls()) {
+                        if let Some(db) = dbs.iter().find(|db| url.starts_with(&db.baseurl)) {
+                            // Flat repositories may have no distribution
+                            let origin = if db.distribution.is_empty() {
+                                db.repo.clone()
+                            } else {
+                                format!("{} ({})", db.repo, db.distribution)
+                            };
+                            if !repos.contains(&origin) {
+                                repos.push(origin);
+                            }
+                        }
+                    }
+                    repos
+                }
+                PkgSource::Local(_) => vec!["local".to_string()],
+                PkgSource::Installed => vec!["dpkg status".to_string()],
+            };
+            versions.push((pkg.version.clone(), origins));
+        }
+        if let Some(installed) = &installed {
+            // Not available from anywhere anymore
+            if !versions.iter().any(|(version, _)| version == installed) {
+                let pos = versions
+                    .iter()
+                    .position(|(version, _)| version < installed)
+                    .unwrap_or(versions.len());
+                versions.insert(pos, (installed.clone(), vec!["dpkg status".to_string()]));
+            }
+        }
+        if versions.is_empty() {
+            return None;
+        }
+
+        Some(Policy {
+            name: name.to_owned(),
+            candidate: pool
+                .get_pkgs_by_name(name)
+                .and_then(|ids| ids.first().copied())
+                .and_then(|id| pool.get_pkg_by_id(id))
+                .map(|pkg| pkg.version.clone()),
+            installed,
+            versions,
+        })
+    }
+
+    fn to_lines(&self) -> Vec<String> {
+        let none = || style("(none)").dim().to_string();
+        let mut res = vec![
+            format!("{}:", style(&self.name).bold()),
+            format!(
+                "  Installed: {}",
+                self.installed.as_ref().map_or_else(none, |v| v.to_string())
+            ),
+            format!(
+                "  Candidate: {}",
+                self.candidate.as_ref().map_or_else(none, |v| v.to_string())
+            ),
+            "  Versions:".to_string(),
+        ];
+        for (version, origins) in &self.versions {
+            let marker = if Some(version) == self.installed.as_ref() {
+                style("***").green().bold().to_string()
+            } else {
+                "   ".to_string()
+            };
+            res.push(format!(" {} {} {}", marker, version, origins.join(", ")));
+        }
+        res
+    }
+}
+
+/// Show installed, candidate and available versions of packages
+pub fn show_policy(
+    local_db: &LocalDb,
+    machine_status: &MachineStatus,
+    names: &[String],
+) -> Result<()> {
+    let dbs = local_db
+        .get_all_package_db()
+        .context("Invalid local package database")?;
+    let pool = pool::source::create_pool(&dbs, &[], PkgFields::All, &Default::default())?;
+
+    let mut writer = crate::WRITER.get_writer();
+    let mut missing = 0;
+    for name in names {
+        match Policy::new(pool.as_ref(), &dbs, machine_status, name) {
+            Some(policy) => {
+                for line in policy.to_lines() {
+                    writeln!(writer, "{line}")?;
+                }
+            }
+            None => {
+                warn!("Package {} not found.", style(name).bold());
+                missing += 1;
+            }
+        }
+    }
+    if missing > 0 {
+        bail!("{} package(s) not found.", style(missing).bold());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        executor::PkgStatus,
+        pool::{BasicPkgPool, InMemoryPool},
+        types::{test_pkg, Checksum, PkgMirrors},
+    };
+    use std::{collections::HashMap, path::PathBuf, sync::Arc};
+
+    #[test]
+    fn test_policy() {
+        let ver = |v| PkgVersion::try_from(v).unwrap();
+        let db = |repo: &str, distribution: &str| PkgDb {
+            baseurl: format!("https://{repo}.example.org/debs"),
+            path: PathBuf::new(),
+            security: false,
+            distribution: distribution.to_string(),
+            arch: "amd64".to_string(),
+            repo: repo.to_string(),
+            bases: Arc::from(Vec::new()),
+        };
+        let dbs = vec![db("main", "stable"), db("extra", "testing")];
+        let pkg = |version: &str, repo: &str| {
+            let mut pkg = test_pkg("bash", version);
+            pkg.source = PkgSource::Http((
+                format!("https://{repo}.example.org/debs/pool/bash_{version}_amd64.deb"),
+                0,
+                Checksum::Sha256(Vec::new()),
+            ));
+            pkg
+        };
+        let mut pool = InMemoryPool::new();
+        pool.add(pkg("5.1", "main"));
+        let mut newest = pkg("5.2", "extra");
+        newest.mirrors.push(PkgMirrors {
+            bases: Arc::from(vec!["https://main.example.org/debs".to_string()]),
+            path: "pool/bash_5.2_amd64.deb".to_string(),
+        });
+        pool.add(newest);
+        pool.add(test_pkg("dash", "0.5"));
+        pool.finalize();
+        let machine_status = MachineStatus {
+            pkgs: HashMap::from([(
+                "bash".to_string(),
+                PkgStatus {
+                    name: "bash".to_string(),
+                    version: ver("5.1"),
+                    install_size: 0,
+                    essential: false,
+                    state: PkgState::Installed,
+                    depends: Vec::new(),
+                    conflicts: Vec::new(),
+                },
+            )]),
+        };
+
+        // Installed, and can be upgraded
+        let policy = Policy::new(&pool, &dbs, &machine_status, "bash").unwrap();
+        assert_eq!(
+            policy,
+            Policy {
+                name: "bash".to_string(),
+                installed: Some(ver("5.1")),
+                candidate: Some(ver("5.2")),
+                versions: vec![
+                    (
+                        ver("5.2"),
+                        vec!["extra (testing)".to_string(), "main (stable)".to_string()]
+                    ),
+                    (ver("5.1"), vec!["main (stable)".to_string()]),
+                ],
+            }
+        );
+        let lines = policy.to_lines();
+        assert!(lines[1].ends_with("5.1"));
+        assert!(lines[2].ends_with("5.2"));
+        assert!(lines[5].contains("***"));
+
+        let policy = Policy::new(&pool, &dbs, &machine_status, "dash").unwrap();
+        assert_eq!(policy.installed, None);
+        assert_eq!(
+            policy.versions,
+            vec![(ver("0.5"), vec!["local".to_string()])]
+        );
+        assert!(Policy::new(&pool, &dbs, &machine_status, "zsh").is_none());
+    }
+}
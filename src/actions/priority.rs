@@ -0,0 +1,83 @@
+use crate::{
+    db::LocalDb,
+    pool::{self, PkgFields, PkgPool},
+    types::Priority,
+};
+
+use anyhow::{bail, Context, Result};
+use console::style;
+
+/// Names of packages in the repositories with priority `level` or higher, for building base systems
+pub fn read_priority_pkgs(local_db: &LocalDb, level: Priority) -> Result<Vec<String>> {
+    let dbs = local_db
+        .get_all_package_db()
+        .context("Invalid local package database")?;
+    let pool = pool::source::create_pool(&dbs, &[], PkgFields::Resolve, &Default::default())?;
+    let names = select_by_priority(pool.as_ref(), level);
+    if names.is_empty() {
+        bail!(
+            "No package of priority {} or higher is found, the repositories may not set the Priority field.",
+            style(level).bold()
+        );
+    }
+    Ok(names)
+}
+
+/// Packages whose newest version is of priority `level` or higher, sorted by name
+fn select_by_priority(pool: &dyn PkgPool, level: Priority) -> Vec<String> {
+    let mut res: Vec<String> = pool
+        .pkgname_iter()
+        .filter(|(_, versions)| {
+            pool.get_pkg_by_id(versions[0].0)
+                .and_then(|pkg| pkg.priority)
+                .is_some_and(|priority| priority.at_least(level))
+        })
+        .map(|(name, _)| name.to_owned())
+        .collect();
+    res.sort();
+    res
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        pool::{BasicPkgPool, InMemoryPool},
+        types::test_pkg,
+    };
+
+    #[test]
+    fn test_select_by_priority() {
+        let mut pool = InMemoryPool::new();
+        for (name, version, priority) in [
+            ("dpkg", "1.21", Some(Priority::Required)),
+            ("bash", "5.2", Some(Priority::Required)),
+            ("less", "590", Some(Priority::Important)),
+            ("file", "5.44", Some(Priority::Standard)),
+            ("vim", "9.0", Some(Priority::Optional)),
+            ("legacy", "1.0", Some(Priority::Extra)),
+            ("custom", "1.0", None),
+            // Only the newest version counts
+            ("nano", "6.0", Some(Priority::Standard)),
+            ("nano", "7.0", Some(Priority::Optional)),
+        ] {
+            let mut pkg = test_pkg(name, version);
+            pkg.priority = priority;
+            pool.add(pkg);
+        }
+        pool.finalize();
+
+        assert_eq!(
+            select_by_priority(&pool, Priority::Required),
+            vec!["bash", "dpkg"]
+        );
+        assert_eq!(
+            select_by_priority(&pool, Priority::Standard),
+            vec!["bash", "dpkg", "file", "less"]
+        );
+        assert_eq!(
+            select_by_priority(&pool, Priority::Extra),
+            vec!["bash", "dpkg", "file", "legacy", "less", "nano", "vim"]
+        );
+    }
+}
@@ -0,0 +1,171 @@
+use crate::{
+    info, msg, success,
+    types::config::{ProfileCmd, ProfileOpts},
+    utils::name::check_name,
+};
+
+use anyhow::{bail, Context, Result};
+use console::style;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+// The default profile uses the user blueprint
+const DEFAULT_PROFILE: &str = "default";
+const DEFAULT_BLUEPRINT: &str = "user.blueprint";
+// Relative to config_root
+const PROFILES_DIR: &str = "blueprints";
+const ACTIVE_PROFILE_FILE: &str = "active_profile";
+
+/// Path of the user blueprint of a profile
+fn profile_path(config_root: &Path, name: &str) -> PathBuf {
+    if name == DEFAULT_PROFILE {
+        config_root.join(DEFAULT_BLUEPRINT)
+    } else {
+        config_root.join(PROFILES_DIR).join(name)
+    }
+}
+
+/// Get the name of the active profile
+pub fn active_profile(config_root: &Path) -> Result<String> {
+    let path = config_root.join(ACTIVE_PROFILE_FILE);
+    if !path.is_file() {
+        return Ok(DEFAULT_PROFILE.to_string());
+    }
+    let name = fs::read_to_string(&path)
+        .context(format!(
+            "Failed to read active profile from {}.",
+            style(path.display()).bold()
+        ))?
+        .trim()
+        .to_owned();
+    check_name("profile", &name)?;
+    Ok(name)
+}
+
+/// Get the user blueprint to use, `profile` overrides the active profile
+pub fn user_blueprint_path(config_root: &Path, profile: Option<&str>) -> Result<PathBuf> {
+    let name = match profile {
+        Some(name) => {
+            check_name("profile", name)?;
+            name.to_owned()
+        }
+        None => active_profile(config_root)?,
+    };
+    let path = profile_path(config_root, &name);
+    if !path.is_file() {
+        bail!(
+            "Profile {} does not exist, use {} to create it.",
+            style(&name).bold(),
+            style(format!("oma profile switch {name}")).bold()
+        );
+    }
+    Ok(path)
+}
+
+/// List all profiles, sorted by name
+fn get_profiles(config_root: &Path) -> Result<Vec<String>> {
+    let mut res = vec![DEFAULT_PROFILE.to_string()];
+    let dir = config_root.join(PROFILES_DIR);
+    if dir.is_dir() {
+        for entry in fs::read_dir(&dir).context("Failed to read profiles directory.")? {
+            let entry = entry?;
+            if let Some(name) = entry.file_name().to_str() {
+                if entry.path().is_file()
+                    && check_name("profile", name).is_ok()
+                    && name != DEFAULT_PROFILE
+                {
+                    res.push(name.to_owned());
+                }
+            }
+        }
+    }
+    res.sort();
+    Ok(res)
+}
+
+fn list(config_root: &Path) -> Result<()> {
+    let active = active_profile(config_root)?;
+    for name in get_profiles(config_root)? {
+        if name == active {
+            msg!("{} {}", style(&name).bold(), style("(active)").green());
+        } else {
+            msg!("{}", name);
+        }
+    }
+    Ok(())
+}
+
+pub fn profile_command(config_root: &Path, opts: &ProfileOpts) -> Result<()> {
+    match &opts.cmd {
+        ProfileCmd::List => list(config_root),
+        ProfileCmd::Switch(switch) => self::switch(config_root, &switch.name),
+    }
+}
+
+/// Switch the active profile, create an empty one if it doesn't exist
+fn switch(config_root: &Path, name: &str) -> Result<()> {
+    check_name("profile", name)?;
+    let path = profile_path(config_root, name);
+    if !path.is_file() {
+        fs::create_dir_all(config_root.join(PROFILES_DIR))
+            .context("Failed to create profiles directory.")?;
+        fs::write(&path, "").context(format!(
+            "Failed to create blueprint for profile {}.",
+            style(name).bold()
+        ))?;
+        info!(
+            "Created empty profile {} at {}.",
+            style(name).bold(),
+            path.display()
+        );
+    }
+    fs::write(config_root.join(ACTIVE_PROFILE_FILE), format!("{name}\n"))
+        .context("Failed to save active profile.")?;
+    success!("Switched to profile {}.", style(name).bold());
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::types::config::Blueprints;
+    use crate::utils::temp_dir::TempDir;
+
+    #[test]
+    fn test_profiles() {
+        let config_root = TempDir::new("profile");
+        fs::write(config_root.join(DEFAULT_BLUEPRINT), "").unwrap();
+        assert_eq!(active_profile(&config_root).unwrap(), DEFAULT_PROFILE);
+
+        // Add a package to the active profile
+        let install = |pkgname: &str, profile: Option<&str>| {
+            let path = user_blueprint_path(&config_root, profile).unwrap();
+            let mut blueprint = Blueprints::from_files(path, &[]).unwrap();
+            blueprint
+                .add(pkgname, false, None, None, false, false)
+                .unwrap();
+            blueprint.export().unwrap();
+        };
+        let read = |name: &str| fs::read_to_string(profile_path(&config_root, name)).unwrap();
+
+        switch(&config_root, "server").unwrap();
+        install("nginx", None);
+        switch(&config_root, "desktop").unwrap();
+        assert_eq!(active_profile(&config_root).unwrap(), "desktop");
+        install("firefox", None);
+        // Explicitly selected profile overrides the active one
+        install("openssh", Some("server"));
+
+        assert_eq!(read("server"), "nginx\nopenssh\n");
+        assert_eq!(read("desktop"), "firefox\n");
+        assert_eq!(read(DEFAULT_PROFILE), "");
+        assert_eq!(
+            get_profiles(&config_root).unwrap(),
+            vec!["default", "desktop", "server"]
+        );
+        assert!(user_blueprint_path(&config_root, Some("nonexistent")).is_err());
+        assert!(switch(&config_root, "../evil").is_err());
+    }
+}
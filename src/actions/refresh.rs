@@ -0,0 +1,42 @@
+use crate::{db::LocalDb, utils::downloader::Downloader, WRITER};
+
+use anyhow::Result;
+use console::style;
+use indicatif::HumanBytes;
+use std::io::Write;
+use tabled::{Alignment, Column, Full, Modify, Style, Table, Tabled};
+
+#[derive(Tabled)]
+struct RepoStatusRow {
+    #[header("Repository")]
+    name: String,
+    #[header("Status")]
+    status: String,
+    #[header("Download Size")]
+    size: String,
+}
+
+/// Report which repositories in `names` (or all if empty) have updated metadata, without refreshing local databases
+pub async fn check(localdb: &LocalDb, downloader: &Downloader, names: &[String]) -> Result<()> {
+    let statuses = localdb.check_update(downloader, names).await?;
+    let rows: Vec<RepoStatusRow> = statuses
+        .into_iter()
+        .map(|status| RepoStatusRow {
+            name: style(status.name).bold().to_string(),
+            status: if status.updated {
+                style("Updated").green().to_string()
+            } else {
+                "Up to date".to_string()
+            },
+            size: HumanBytes(status.download_size).to_string(),
+        })
+        .collect();
+    let table = Table::new(&rows)
+        .with(Modify::new(Full).with(Alignment::left()))
+        .with(Modify::new(Column(2..3)).with(Alignment::right()))
+        .with(Modify::new(Full).with(|s: &str| format!(" {s} ")))
+        .with(Style::PSEUDO_CLEAN);
+    writeln!(WRITER.get_writer(), "\n{table}")?;
+
+    Ok(())
+}
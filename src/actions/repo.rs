@@ -0,0 +1,69 @@
+use crate::{db::LocalDb, msg, utils::downloader::Downloader, warn, WRITER};
+
+use anyhow::{bail, Result};
+use console::style;
+use std::io::Write;
+use tabled::{Alignment, Full, Modify, Style, Table, Tabled};
+
+#[derive(Tabled)]
+struct RepoHealthRow {
+    #[header("Repository")]
+    name: String,
+    #[header("Status")]
+    status: String,
+    #[header("Mirror")]
+    mirror: String,
+    #[header("Components")]
+    components: String,
+    #[header("Architectures")]
+    archs: String,
+}
+
+/// Check that repositories in `names` (or all if empty) are reachable and provide configured components
+///
+/// Fails if any problem is found.
+pub async fn check(localdb: &LocalDb, downloader: &Downloader, names: &[String]) -> Result<()> {
+    let results = localdb.check_health(downloader, names).await?;
+    let rows: Vec<RepoHealthRow> = results
+        .iter()
+        .map(|health| RepoHealthRow {
+            name: style(&health.name).bold().to_string(),
+            status: match (&health.mirror, health.problems.is_empty()) {
+                (None, _) => style("Unreachable").red().bold().to_string(),
+                (Some(_), false) => style("Problems").yellow().to_string(),
+                (Some(_), true) => style("OK").green().to_string(),
+            },
+            mirror: health.mirror.clone().unwrap_or_default(),
+            components: health.components.join(" "),
+            archs: health.archs.join(" "),
+        })
+        .collect();
+    let table = Table::new(&rows)
+        .with(Modify::new(Full).with(Alignment::left()))
+        .with(Modify::new(Full).with(|s: &str| format!(" {s} ")))
+        .with(Style::PSEUDO_CLEAN);
+    writeln!(WRITER.get_writer(), "\n{table}")?;
+
+    let mut failed = 0;
+    for health in &results {
+        if health.problems.is_empty() {
+            continue;
+        }
+        failed += 1;
+        warn!(
+            "Problems found in repository {}:",
+            style(&health.name).bold()
+        );
+        for problem in &health.problems {
+            msg!("{problem}");
+        }
+    }
+    if failed > 0 {
+        bail!(
+            "{} repository(s) have problems, please check the configuration.",
+            style(failed).bold()
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,195 @@
+use super::provide::package_files;
+use crate::{
+    db::LocalDb,
+    debug, error,
+    executor::{MachineStatus, PkgState, PkgStatus},
+    info, msg,
+    types::config::{Config, Opts},
+    utils::pager::Pager,
+    warn,
+};
+
+use anyhow::{bail, Context, Result};
+use console::style;
+use std::{
+    collections::HashMap,
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+/// List files in a package. Installed packages use dpkg's file list, others use Contents metadata
+pub fn show_files(
+    local_db: &LocalDb,
+    machine_status: &MachineStatus,
+    pkgname: &str,
+    opts: &Opts,
+    config: &Config,
+) -> Result<()> {
+    let installed = matches!(
+        machine_status.pkgs.get(pkgname).map(|pkg| pkg.state),
+        Some(state) if state != PkgState::NotInstalled && state != PkgState::ConfigFiles
+    );
+    let mut files = if installed {
+        read_dpkg_list(&admindir(opts, config), pkgname, &config.arch)?
+    } else {
+        let files = package_files(local_db, pkgname)?;
+        if files.is_empty() {
+            bail!(
+                "Package {} is not installed, and no file of it is found in Contents metadata.",
+                style(pkgname).bold()
+            );
+        }
+        info!(
+            "Package {} is not installed, showing files from Contents metadata.",
+            style(pkgname).bold()
+        );
+        warn!("Contents metadata only lists regular files and symlinks of the latest version, directories are not shown.");
+        files
+    };
+    files.sort();
+    files.dedup();
+
+    let mut pager = Pager::with_threshold(opts, config.pager.as_deref(), files.len())?;
+    let mut writer = pager.get_writer()?;
+    for file in files {
+        writeln!(writer, "{file}")?;
+    }
+    drop(writer);
+    pager.wait_for_exit()?;
+
+    Ok(())
+}
+
+/// Find the installed package that owns the file at the given absolute path, from dpkg's file lists
+pub fn find_file_owner(
+    machine_status: &MachineStatus,
+    path: &str,
+    opts: &Opts,
+    config: &Config,
+) -> Result<String> {
+    if !path.starts_with('/') {
+        bail!("Path {} is not absolute.", style(path).bold());
+    }
+    let mut owners = file_owners(
+        &machine_status.pkgs,
+        &admindir(opts, config),
+        path,
+        &config.arch,
+    );
+    match owners.len() {
+        0 => bail!(
+            "File {} does not belong to any installed package.",
+            style(path).bold()
+        ),
+        1 => Ok(owners.pop().unwrap()),
+        _ => {
+            error!(
+                "File {} belongs to multiple installed packages: {}",
+                style(path).bold(),
+                owners.join(", ")
+            );
+            msg!("Please specify which package to remove by its name.");
+            bail!("Cannot determine which package owns {}.", path)
+        }
+    }
+}
+
+/// Names of installed packages whose file list contains `path`, sorted
+fn file_owners(
+    installed: &HashMap<String, PkgStatus>,
+    admindir: &Path,
+    path: &str,
+    arch: &str,
+) -> Vec<String> {
+    let mut owners: Vec<String> = installed
+        .values()
+        .filter(|pkg| pkg.state == PkgState::Installed)
+        .filter(|pkg| match read_dpkg_list(admindir, &pkg.name, arch) {
+            Ok(files) => files.iter().any(|file| file == path),
+            Err(e) => {
+                debug!("{:#}", e);
+                false
+            }
+        })
+        .map(|pkg| pkg.name.clone())
+        .collect();
+    owners.sort();
+    owners
+}
+
+/// dpkg database directory of the target system
+fn admindir(opts: &Opts, config: &Config) -> PathBuf {
+    opts.root.join(
+        config
+            .dpkg_admindir
+            .as_deref()
+            .unwrap_or_else(|| Path::new(crate::DPKG_ADMINDIR)),
+    )
+}
+
+/// Read the list of files installed by a package from dpkg database
+fn read_dpkg_list(admindir: &Path, pkgname: &str, arch: &str) -> Result<Vec<String>> {
+    let info_dir = admindir.join("info");
+    // Multi-arch packages have their architecture in the name of the list
+    let candidates: [PathBuf; 2] = [
+        info_dir.join(format!("{pkgname}.list")),
+        info_dir.join(format!("{pkgname}:{arch}.list")),
+    ];
+    let path = match candidates.iter().find(|path| path.is_file()) {
+        Some(path) => path,
+        None => bail!(
+            "File list of package {} does not exist in dpkg database.",
+            style(pkgname).bold()
+        ),
+    };
+    let content = fs::read_to_string(path).context(format!(
+        "Failed to read file list {}.",
+        style(path.display()).bold()
+    ))?;
+    let files = content
+        .lines()
+        // The root directory is recorded as `/.`
+        .filter(|line| !line.is_empty() && *line != "/.")
+        .map(|line| line.to_owned())
+        .collect();
+
+    Ok(files)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_read_dpkg_list() {
+        let admindir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/admindir");
+        let files = read_dpkg_list(&admindir, "bash", "amd64").unwrap();
+        assert_eq!(
+            files,
+            vec![
+                "/usr",
+                "/usr/bin",
+                "/usr/bin/bash",
+                "/usr/share/man/man1/bash.1.gz"
+            ]
+        );
+        // Multi-arch file list
+        let files = read_dpkg_list(&admindir, "zlib", "amd64").unwrap();
+        assert_eq!(files, vec!["/usr/lib/libz.so.1"]);
+        assert!(read_dpkg_list(&admindir, "nonexistent", "amd64").is_err());
+    }
+
+    #[test]
+    fn test_file_owners() {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+        let admindir = root.join("tests/fixtures/admindir");
+        let installed = MachineStatus::new(root, Some(Path::new("tests/fixtures/admindir")))
+            .unwrap()
+            .pkgs;
+        let owners = |path| file_owners(&installed, &admindir, path, "amd64");
+        assert_eq!(owners("/usr/bin/bash"), vec!["bash"]);
+        assert_eq!(owners("/usr/lib/libz.so.1"), vec!["zlib"]);
+        assert!(owners("/usr/bin/zsh").is_empty());
+    }
+}
@@ -1,5 +1,7 @@
+mod files;
 mod pkg;
 mod provide;
+pub use files::{find_file_owner, show_files};
 pub use pkg::search_deb_db;
 pub use provide::show_provide_file;
 
@@ -43,6 +45,11 @@ impl<'a> PkgInfo<'a> {
         // Write package description
         crate::WRITER.writeln("", &self.pkg.description)?;
 
+        if let Some(priority) = self.pkg.priority {
+            let prefix = style("Priority:").dim().to_string();
+            crate::WRITER.write_chunks("", &[prefix, priority.to_string()])?;
+        }
+
         // Write provided packages
         if let Some(provides) = &self.pkg.provides {
             let prefix = style("Provides:").dim().to_string();
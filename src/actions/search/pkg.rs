@@ -1,5 +1,9 @@
 use super::PkgInfo;
-use crate::{db::LocalDb, executor::MachineStatus, pool, pool::PkgPool};
+use crate::{
+    db::LocalDb,
+    executor::MachineStatus,
+    pool::{self, PkgFields, PkgPool},
+};
 
 use anyhow::{Context, Result};
 use std::{cmp::Reverse, collections::HashMap};
@@ -7,12 +11,17 @@ use std::{cmp::Reverse, collections::HashMap};
 pub fn search_deb_db(
     local_db: &LocalDb,
     keyword: &str,
+    repos: &[String],
     machine_status: &MachineStatus,
 ) -> Result<()> {
-    let dbs = local_db
+    let mut dbs = local_db
         .get_all_package_db()
         .context("Failed to initialize local database for searching!")?;
-    let pool = pool::source::create_pool(&dbs, &[])?;
+    if !repos.is_empty() {
+        local_db.select_repos(repos)?;
+        dbs.retain(|db| repos.contains(&db.repo));
+    }
+    let pool = pool::source::create_pool(&dbs, &[], PkgFields::All, &Default::default())?;
 
     let mut pkgs = search_pkg_helper(pool.as_ref(), keyword);
 
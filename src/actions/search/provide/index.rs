@@ -0,0 +1,248 @@
+/// On-disk index of Contents files, for fast lookups of paths by suffix
+///
+/// The index is a text file. The first line is a header with the size and modification time of
+/// the Contents file it is built from, so that it is rebuilt whenever the Contents file is
+/// downloaded again, without reading the whole file on every search.
+/// Each following line is `REVERSED_PATH\tPKG1,PKG2`, sorted, so that paths ending with
+/// a certain string are next to each other and can be found with binary search.
+use super::parse;
+use crate::{debug, info};
+
+use anyhow::{Context, Result};
+use flate2::read::GzDecoder;
+use std::{
+    fs::{self, File},
+    io::{BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+const INDEX_VERSION: &str = "omakase-contents-index-v2";
+
+pub struct ContentsIndex {
+    reader: BufReader<File>,
+    /// Where the first entry starts
+    data_start: u64,
+    len: u64,
+    /// Whether the index has just been (re)built
+    pub rebuilt: bool,
+}
+
+impl ContentsIndex {
+    /// Open the index of `contents` in `index_root`, building it first if it's missing or outdated
+    ///
+    /// `compressed` tells whether `contents` is Gzip compressed.
+    pub fn open(contents: &Path, index_root: &Path, compressed: bool) -> Result<Self> {
+        let index_path = index_path(contents, index_root);
+        let header = format!("{INDEX_VERSION} {}", file_stamp(contents)?);
+        let mut rebuilt = false;
+        if read_header(&index_path).as_deref() != Some(header.as_str()) {
+            info!(
+                "Indexing {}, later searches will be faster...",
+                contents.display()
+            );
+            build_index(contents, &index_path, &header, compressed)?;
+            rebuilt = true;
+        }
+
+        let file =
+            File::open(&index_path).context(format!("Failed to open {}.", index_path.display()))?;
+        let len = file.metadata()?.len();
+        Ok(ContentsIndex {
+            reader: BufReader::new(file),
+            data_start: header.len() as u64 + 1,
+            len,
+            rebuilt,
+        })
+    }
+
+    /// Find paths ending with `suffix`, along with packages providing them
+    // -> Vec<(Path, Vec<PkgName>)>
+    pub fn lookup(&mut self, suffix: &str) -> Result<Vec<(String, Vec<String>)>> {
+        let target: String = suffix.chars().rev().collect();
+        // Find the first entry that is not less than `target`
+        let (mut lo, mut hi) = (self.data_start, self.len);
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            let (start, line) = self.line_at_or_after(mid)?;
+            match line {
+                Some(line) if key(&line) < target.as_str() => lo = start + line.len() as u64 + 1,
+                _ => hi = mid,
+            }
+        }
+
+        let mut res = Vec::new();
+        let (_, mut line) = self.line_at_or_after(lo)?;
+        while let Some(entry) = line {
+            if !key(&entry).starts_with(&target) {
+                break;
+            }
+            let (rev_path, pkgs) = entry.split_once('\t').unwrap_or((&entry, ""));
+            let path = rev_path.chars().rev().collect();
+            let pkgs = pkgs.split(',').map(|s| s.to_owned()).collect();
+            res.push((path, pkgs));
+            line = self.next_line()?;
+        }
+
+        Ok(res)
+    }
+
+    /// The first line that starts at or after `pos`, and where it starts
+    fn line_at_or_after(&mut self, pos: u64) -> Result<(u64, Option<String>)> {
+        // Read the rest of the line before `pos`, the header line ends right before `data_start`
+        self.reader.seek(SeekFrom::Start(pos - 1))?;
+        let mut skipped = Vec::new();
+        let n = self.reader.read_until(b'\n', &mut skipped)?;
+        let start = pos - 1 + n as u64;
+        Ok((start, self.next_line()?))
+    }
+
+    fn next_line(&mut self) -> Result<Option<String>> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        if line.ends_with('\n') {
+            line.pop();
+        }
+        Ok(Some(line))
+    }
+}
+
+fn key(line: &str) -> &str {
+    line.split_once('\t').map_or(line, |(key, _)| key)
+}
+
+/// Where the index of `contents` is stored, named after the repository and the Contents file
+fn index_path(contents: &Path, index_root: &Path) -> PathBuf {
+    let file_name = contents.file_name().unwrap_or_default().to_string_lossy();
+    let repo = contents
+        .parent()
+        .and_then(|p| p.file_name())
+        .unwrap_or_default()
+        .to_string_lossy();
+    index_root.join(format!("{repo}_{file_name}.idx"))
+}
+
+/// Size and modification time of `path`, which change whenever it is written
+fn file_stamp(path: &Path) -> Result<String> {
+    let metadata = fs::metadata(path).context(format!("Failed to read {}.", path.display()))?;
+    let mtime = metadata
+        .modified()?
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default();
+    Ok(format!("{} {}", metadata.len(), mtime.as_nanos()))
+}
+
+fn read_header(index_path: &Path) -> Option<String> {
+    let mut header = String::new();
+    BufReader::new(File::open(index_path).ok()?)
+        .read_line(&mut header)
+        .ok()?;
+    Some(header.trim_end().to_owned())
+}
+
+fn build_index(contents: &Path, index_path: &Path, header: &str, compressed: bool) -> Result<()> {
+    let f = File::open(contents).context(format!("Failed to open {}.", contents.display()))?;
+    let reader: Box<dyn Read> = if compressed {
+        Box::new(GzDecoder::new(f))
+    } else {
+        Box::new(f)
+    };
+    let mut entries = Vec::new();
+    for line in BufReader::new(reader).lines() {
+        let line = line?;
+        match parse::parse_contents_line(&line) {
+            Ok((path, packages)) => {
+                let rev_path: String = path.chars().rev().collect();
+                let pkgs: Vec<&str> = packages.into_iter().map(|(_, name)| name).collect();
+                entries.push(format!("{rev_path}\t{}", pkgs.join(",")));
+            }
+            Err(e) => {
+                debug!("{}", e);
+            }
+        }
+    }
+    entries.sort_unstable();
+
+    let index_root = index_path.parent().unwrap();
+    fs::create_dir_all(index_root).context(format!(
+        "Failed to create index directory {}.",
+        index_root.display()
+    ))?;
+    // Write to a temporary file first, so that a half-written index is never used
+    let tmp_path = index_path.with_extension("tmp");
+    let mut writer = BufWriter::new(File::create(&tmp_path)?);
+    writeln!(writer, "{header}")?;
+    for entry in entries {
+        writeln!(writer, "{entry}")?;
+    }
+    writer.flush()?;
+    drop(writer);
+    fs::rename(&tmp_path, index_path)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::utils::temp_dir::TempDir;
+    use flate2::{write::GzEncoder, Compression};
+
+    fn write_contents(path: &Path, content: &str) {
+        let mut encoder = GzEncoder::new(File::create(path).unwrap(), Compression::default());
+        encoder.write_all(content.as_bytes()).unwrap();
+        encoder.finish().unwrap();
+    }
+
+    #[test]
+    fn test_contents_index() {
+        let base = TempDir::new("contents");
+        let index_root = base.join("contents-index");
+        fs::create_dir_all(base.join("main")).unwrap();
+        let contents = base.join("main/Contents_stable_main_amd64.gz");
+        write_contents(
+            &contents,
+            "usr/bin/bash shells/bash\nusr/bin/rbash shells/bash\nusr/bin/dash shells/dash\nusr/share/doc/bash/README doc/bash-doc,shells/bash\n",
+        );
+
+        let mut index = ContentsIndex::open(&contents, &index_root, true).unwrap();
+        assert!(index.rebuilt);
+        assert_eq!(
+            index.lookup("bin/bash").unwrap(),
+            vec![("usr/bin/bash".to_string(), vec!["bash".to_string()])]
+        );
+        // Like searching Contents directly, any path ending with the name matches
+        let mut res = index.lookup("bash").unwrap();
+        res.sort();
+        assert_eq!(
+            res,
+            vec![
+                ("usr/bin/bash".to_string(), vec!["bash".to_string()]),
+                ("usr/bin/rbash".to_string(), vec!["bash".to_string()])
+            ]
+        );
+        assert_eq!(
+            index.lookup("README").unwrap(),
+            vec![(
+                "usr/share/doc/bash/README".to_string(),
+                vec!["bash-doc".to_string(), "bash".to_string()]
+            )]
+        );
+        assert!(index.lookup("zsh").unwrap().is_empty());
+
+        // Unchanged Contents, the index is reused
+        let index = ContentsIndex::open(&contents, &index_root, true).unwrap();
+        assert!(!index.rebuilt);
+
+        // Contents has been refreshed, the index must be rebuilt
+        write_contents(&contents, "usr/bin/zsh shells/zsh\n");
+        let mut index = ContentsIndex::open(&contents, &index_root, true).unwrap();
+        assert!(index.rebuilt);
+        assert!(index.lookup("bin/bash").unwrap().is_empty());
+        assert_eq!(
+            index.lookup("zsh").unwrap(),
+            vec![("usr/bin/zsh".to_string(), vec!["zsh".to_string()])]
+        );
+    }
+}
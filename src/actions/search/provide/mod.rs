@@ -1,7 +1,13 @@
+mod index;
 mod parse;
 
 use super::PkgInfo;
-use crate::{db::LocalDb, debug, executor::MachineStatus, pool};
+use crate::{
+    db::LocalDb,
+    debug,
+    executor::MachineStatus,
+    pool::{self, source::debrepo, PkgFields},
+};
 
 use anyhow::{Context, Result};
 use console::style;
@@ -11,7 +17,7 @@ use std::{
     collections::{HashMap, HashSet},
     fs::File,
     io::{BufRead, BufReader, Read},
-    path::PathBuf,
+    path::{Path, PathBuf},
 };
 
 const READ_BUFFER_SIZE: usize = 8000;
@@ -21,54 +27,37 @@ pub fn show_provide_file(
     machine_status: &MachineStatus,
     filename: &str,
     bin: bool,
+    index_root: &Path,
+    fields: PkgFields,
 ) -> Result<()> {
-    let content_paths: Vec<PathBuf> = if bin {
-        local_db.get_all_bincontents_db()
-    } else {
-        local_db.get_all_contents_db()
-    }
-    .context("Failed to initialize local database for searching!")?
-    .into_iter()
-    .map(|(_, path)| path)
-    .collect();
-
-    let mut content_dbs: Vec<Box<dyn Read>> = Vec::with_capacity(content_paths.len());
-    for path in &content_paths {
-        let f = File::open(path)?;
-        if bin {
-            // BinContents are not compressed
-            content_dbs.push(Box::new(f));
-        } else {
-            // Contents are Gzip compressed
-            let f = GzDecoder::new(f);
-            content_dbs.push(Box::new(f));
-        }
-    }
-
     // Find a list of package names that provide the designated file
     debug!("Searching Contents metadata...");
-    let mut pkgnames = Vec::from_iter(package_name_provide_file(content_dbs, filename)?);
+    let mut pkgnames = Vec::from_iter(provide_file_indexed(local_db, filename, bin, index_root)?);
     // Sort based on number of matched paths
     pkgnames.sort_by_key(|(_, paths)| Reverse(paths.len()));
 
     // Create a Solver so we can get more info    let mut solver = Solver::new();
     debug!("Constructing package pool...");
     let dbs = local_db.get_all_package_db()?;
-    let pool = pool::source::create_pool(&dbs, &[])?;
+    let pool = pool::source::create_pool(&dbs, &[], fields, &Default::default())?;
 
     debug!("Generating detailed package information...");
     for (pkgname, paths) in pkgnames {
         if let Some(pkgs) = pool.get_pkgs_by_name(&pkgname) {
             // This is safe unless the pool is broken
             let latest_pkg_id = pkgs.get(0).unwrap();
-            let latest_pkg = pool.get_pkg_by_id(*latest_pkg_id).unwrap();
+            let mut latest_pkg = pool.get_pkg_by_id(*latest_pkg_id).unwrap().clone();
+            if fields == PkgFields::Resolve && !debrepo::load_display_fields(&dbs, &mut latest_pkg)?
+            {
+                debug!("Failed to load description of {pkgname}.");
+            }
             let provide_paths = paths
                 .into_iter()
                 .map(|path| format!("Provides: {}", style(path).bold()))
                 .collect();
             // Prepare a PkgInfo
             let pkginfo = PkgInfo {
-                pkg: latest_pkg,
+                pkg: &latest_pkg,
                 has_dbg_pkg: pool.has_dbg_pkg(*latest_pkg_id)?,
                 additional_info: provide_paths,
             };
@@ -79,6 +68,110 @@ pub fn show_provide_file(
     Ok(())
 }
 
+/// List files shipped by a package according to Contents metadata
+pub fn package_files(local_db: &LocalDb, pkgname: &str) -> Result<Vec<String>> {
+    let mut res = Vec::new();
+    for db in open_contents_dbs(local_db, false)? {
+        for line in BufReader::new(db).lines() {
+            let line = line?;
+            // Cheap check before parsing
+            if !line.contains(pkgname) {
+                continue;
+            }
+            match parse::parse_contents_line(&line) {
+                Ok((path, packages)) => {
+                    if packages.iter().any(|(_, name)| *name == pkgname) {
+                        res.push(format!("/{path}"));
+                    }
+                }
+                Err(e) => {
+                    debug!("{}", e);
+                }
+            }
+        }
+    }
+
+    Ok(res)
+}
+
+fn contents_paths(local_db: &LocalDb, bin: bool) -> Result<Vec<PathBuf>> {
+    let content_paths = if bin {
+        local_db.get_all_bincontents_db()
+    } else {
+        local_db.get_all_contents_db()
+    }
+    .context("Failed to initialize local database for searching!")?
+    .into_iter()
+    .map(|(_, path)| path)
+    .collect();
+
+    Ok(content_paths)
+}
+
+fn open_contents_db(path: &Path, bin: bool) -> Result<Box<dyn Read>> {
+    let f = File::open(path)?;
+    if bin {
+        // BinContents are not compressed
+        Ok(Box::new(f))
+    } else {
+        // Contents are Gzip compressed
+        Ok(Box::new(GzDecoder::new(f)))
+    }
+}
+
+fn open_contents_dbs(local_db: &LocalDb, bin: bool) -> Result<Vec<Box<dyn Read>>> {
+    contents_paths(local_db, bin)?
+        .iter()
+        .map(|path| open_contents_db(path, bin))
+        .collect()
+}
+
+/// Like `package_name_provide_file`, but look up indices of Contents files in `index_root`
+///
+/// Indices are built on first use, or when Contents files change. If an index can't be
+/// built (e.g. `index_root` is not writable), its Contents file is searched directly.
+fn provide_file_indexed(
+    local_db: &LocalDb,
+    filename: &str,
+    bin: bool,
+    index_root: &Path,
+) -> Result<HashMap<String, HashSet<String>>> {
+    let mut res: HashMap<String, HashSet<String>> = HashMap::new();
+    for path in contents_paths(local_db, bin)? {
+        let start = std::time::Instant::now();
+        let mut index = match index::ContentsIndex::open(&path, index_root, !bin) {
+            Ok(index) => {
+                if index.rebuilt {
+                    debug!(
+                        "Indexed {} in {:.1}s.",
+                        path.display(),
+                        start.elapsed().as_secs_f64()
+                    );
+                }
+                index
+            }
+            Err(e) => {
+                debug!("Searching {} without index: {e:#}", path.display());
+                let found =
+                    package_name_provide_file(vec![open_contents_db(&path, bin)?], filename)?;
+                for (pkgname, paths) in found {
+                    res.entry(pkgname).or_default().extend(paths);
+                }
+                continue;
+            }
+        };
+        for (path, pkgnames) in index.lookup(filename)? {
+            // Add `/` to the front of path, because Contents file uses relative path
+            let path = format!("/{path}");
+            for pkgname in pkgnames {
+                res.entry(pkgname).or_default().insert(path.clone());
+            }
+        }
+    }
+
+    Ok(res)
+}
+
 // Given a filename or path, find package names that provide such file
 pub fn package_name_provide_file(
     dbs: Vec<Box<dyn Read>>,
@@ -0,0 +1,50 @@
+use crate::{
+    db::{snapshot, LocalDb},
+    msg, success,
+    types::config::{Opts, SnapshotCmd, SnapshotOpts},
+};
+
+use anyhow::Result;
+use console::style;
+
+pub fn snapshot_command(localdb: &LocalDb, opts: &Opts, cmd: &SnapshotOpts) -> Result<()> {
+    let dir = opts.root.join(crate::SNAPSHOT_PATH);
+    match &cmd.cmd {
+        SnapshotCmd::Create(create) => {
+            let snapshot = localdb.take_snapshot(&create.name)?;
+            snapshot.save(&dir)?;
+            success!(
+                "Recorded metadata of {} repository(s) as snapshot {}.",
+                snapshot.repo.len(),
+                style(&create.name).bold()
+            );
+        }
+        SnapshotCmd::List => {
+            let active = snapshot::active(&dir)?;
+            let names = snapshot::list(&dir)?;
+            if names.is_empty() {
+                msg!("No snapshot.");
+            }
+            for name in names {
+                if Some(&name) == active.as_ref() {
+                    msg!("{} {}", style(&name).bold(), style("(in use)").green());
+                } else {
+                    msg!("{}", name);
+                }
+            }
+        }
+        SnapshotCmd::Use(using) => {
+            snapshot::set_active(&dir, Some(&using.name))?;
+            success!(
+                "Using snapshot {}, refreshes will only accept metadata recorded in it.",
+                style(&using.name).bold()
+            );
+        }
+        SnapshotCmd::Clear => {
+            snapshot::set_active(&dir, None)?;
+            success!("No longer using a snapshot.");
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,204 @@
+use crate::{
+    executor::{MachineStatus, PkgState},
+    types::{
+        config::{Blueprints, Config, Opts, PkgRequest},
+        PkgVersion,
+    },
+    utils::pager::Pager,
+};
+
+use anyhow::Result;
+use console::style;
+use serde_json::{json, Value};
+use std::io::Write;
+
+/// Difference between blueprints and packages installed on the system
+#[derive(Debug, Default, PartialEq, Eq)]
+struct StatusReport {
+    /// Requested in blueprints, but not installed
+    missing: Vec<PkgRequest>,
+    /// Installed, but the version doesn't satisfy the blueprint
+    mismatch: Vec<(PkgRequest, PkgVersion)>,
+    /// Installed, but not requested in blueprints (e.g. dependencies)
+    unrequested: Vec<(String, PkgVersion)>,
+}
+
+/// Show how installed packages differ from blueprints
+pub fn show_status(
+    blueprints: &Blueprints,
+    machine_status: &MachineStatus,
+    json: bool,
+    opts: &Opts,
+    config: &Config,
+) -> Result<()> {
+    let report = gen_report(&blueprints.get_pkg_requests(), machine_status);
+    let lines = if json {
+        vec![report.to_json()]
+    } else {
+        report.to_lines()
+    };
+
+    // JSON output is meant for other programs
+    let mut pager = if json {
+        Pager::new(true, None)?
+    } else {
+        Pager::with_threshold(opts, config.pager.as_deref(), lines.len())?
+    };
+    let mut writer = pager.get_writer()?;
+    for line in lines {
+        writeln!(writer, "{line}")?;
+    }
+    drop(writer);
+    pager.wait_for_exit()?;
+
+    Ok(())
+}
+
+fn gen_report(requests: &[PkgRequest], machine_status: &MachineStatus) -> StatusReport {
+    let mut res = StatusReport::default();
+    for req in requests {
+        // A package may appear in multiple blueprints
+        if res.missing.iter().any(|r| r.name == req.name)
+            || res.mismatch.iter().any(|(r, _)| r.name == req.name)
+        {
+            continue;
+        }
+        match machine_status.pkgs.get(&req.name) {
+            Some(pkg) if is_installed(pkg.state) => {
+                if !req.version.contains(&pkg.version) {
+                    res.mismatch.push((req.clone(), pkg.version.clone()));
+                }
+            }
+            _ => res.missing.push(req.clone()),
+        }
+    }
+
+    for (name, pkg) in &machine_status.pkgs {
+        if is_installed(pkg.state) && !requests.iter().any(|req| &req.name == name) {
+            res.unrequested.push((name.clone(), pkg.version.clone()));
+        }
+    }
+
+    res.missing.sort_by(|a, b| a.name.cmp(&b.name));
+    res.mismatch.sort_by(|a, b| a.0.name.cmp(&b.0.name));
+    res.unrequested.sort();
+    res
+}
+
+fn is_installed(state: PkgState) -> bool {
+    state != PkgState::NotInstalled && state != PkgState::ConfigFiles
+}
+
+impl StatusReport {
+    fn to_lines(&self) -> Vec<String> {
+        let mut res = Vec::new();
+        if self.missing.is_empty() && self.mismatch.is_empty() {
+            res.push(format!(
+                "{}",
+                style("All packages in blueprints are installed.").green()
+            ));
+        }
+        if !self.missing.is_empty() {
+            res.push(format!(
+                "{}",
+                style("Packages in blueprints but not installed:").bold()
+            ));
+            for req in &self.missing {
+                res.push(format!("  {}", style(req).red()));
+            }
+        }
+        if !self.mismatch.is_empty() {
+            res.push(format!(
+                "{}",
+                style("Installed packages with mismatched versions:").bold()
+            ));
+            for (req, ver) in &self.mismatch {
+                res.push(format!(
+                    "  {}: installed {}, requires {}",
+                    style(&req.name).yellow(),
+                    ver,
+                    req.version
+                ));
+            }
+        }
+        if !self.unrequested.is_empty() {
+            res.push(format!(
+                "{}",
+                style("Installed packages not in blueprints:").bold()
+            ));
+            for (name, ver) in &self.unrequested {
+                res.push(format!("  {} {}", name, style(ver).dim()));
+            }
+        }
+        res
+    }
+
+    fn to_json(&self) -> String {
+        let missing: Vec<Value> = self
+            .missing
+            .iter()
+            .map(|req| json!({"name": req.name, "requirement": req.version.to_string()}))
+            .collect();
+        let mismatch: Vec<Value> = self
+            .mismatch
+            .iter()
+            .map(|(req, ver)| {
+                json!({
+                    "name": req.name,
+                    "requirement": req.version.to_string(),
+                    "installed": ver.to_string(),
+                })
+            })
+            .collect();
+        let unrequested: Vec<Value> = self
+            .unrequested
+            .iter()
+            .map(|(name, ver)| json!({"name": name, "installed": ver.to_string()}))
+            .collect();
+        json!({"missing": missing, "mismatch": mismatch, "unrequested": unrequested}).to_string()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::types::VersionRequirement;
+    use std::path::Path;
+
+    #[test]
+    fn test_gen_report() {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+        let ms = MachineStatus::new(root, Some(Path::new("tests/fixtures/admindir"))).unwrap();
+        let req = |name: &str, ver: &str| PkgRequest {
+            name: name.to_string(),
+            version: if ver.is_empty() {
+                VersionRequirement::default()
+            } else {
+                VersionRequirement::try_from(ver).unwrap()
+            },
+            ..Default::default()
+        };
+        let requests = vec![
+            req("bash", ">= 5.1"),
+            req("vim", ""),
+            req("zlib", "<< 1:1.2.12"),
+            req("vim", ""),
+        ];
+        let report = gen_report(&requests, &ms);
+        assert_eq!(report.missing, vec![req("vim", "")]);
+        assert_eq!(
+            report.mismatch,
+            vec![(
+                req("zlib", "<< 1:1.2.12"),
+                PkgVersion::try_from("1:1.2.12").unwrap()
+            )]
+        );
+        assert!(report.unrequested.is_empty());
+
+        let report = gen_report(&[req("bash", "")], &ms);
+        assert_eq!(
+            report.to_json(),
+            r#"{"missing":[],"mismatch":[],"unrequested":[{"name":"zlib","installed":"1:1.2.12"}]}"#
+        );
+    }
+}
@@ -0,0 +1,255 @@
+use crate::{
+    actions, cli, due_to, error,
+    types::config::{Blueprints, Config, Opts, SubCmd},
+    utils, warn, LOCKED, LOG, QUIET, SUBPROCESS, TIMINGS, VERBOSE, WRITER,
+};
+
+use anyhow::{bail, Context, Result};
+use clap::Parser;
+use console::style;
+use nix::sys::signal;
+use std::{
+    fs::{read_dir, File},
+    io::Read,
+    path::Path,
+    sync::atomic::Ordering,
+};
+
+/// Run the oma command line
+///
+/// Exit codes:
+/// 1 => program screwed up
+/// 2 => user cancelled operation
+/// `oma check-updates --exit-code` may exit with a custom code if updates are available
+pub async fn main() -> i32 {
+    // Initial setup
+    let mut opts: Opts = Opts::parse();
+    // Set-up debug globally
+    VERBOSE.store(opts.verbose, Ordering::Relaxed);
+    QUIET.store(opts.quiet, Ordering::Relaxed);
+    // If yes or no mode is enabled, pager will be disabled
+    if opts.yes || opts.assume_no {
+        opts.no_pager = true;
+    }
+
+    // Set up SIGINT handler
+    {
+        let root = opts.root.to_owned();
+        ctrlc::set_handler(move || sigint_handler(&root)).expect("Error setting SIGINT handler.");
+    }
+
+    // Run main logic
+    let exit_code = match try_main(&opts).await {
+        Ok(exit_code) => exit_code,
+        Err(err) => {
+            // Create a new line first, for visual distinction
+            WRITER.writeln("", "").ok();
+            error!("{}", err.to_string());
+            err.chain().skip(1).for_each(|cause| {
+                due_to!("{}", cause);
+            });
+            1
+        }
+    };
+
+    if opts.timings {
+        if let Err(e) = TIMINGS.show() {
+            error!("{}", e);
+        }
+    }
+
+    // Unlock if current process locked
+    if LOCKED.load(Ordering::Relaxed) {
+        if let Err(e) = utils::lock::unlock(&opts.root) {
+            error!("{}", e);
+        }
+    }
+
+    // Always show cursor, just in case
+    let _ = WRITER.show_cursor();
+    LOG.write(
+        cli::log::Level::Info,
+        &format!("Finished with exit code {exit_code}."),
+    );
+
+    exit_code
+}
+
+/// Start logging to `--log-file`, or the file in the `log` section of config
+fn open_log(opts: &Opts, config: &Config) -> Result<()> {
+    let path = match (&opts.log_file, &config.log) {
+        (Some(path), _) => path.clone(),
+        (None, Some(log)) => utils::alt_root::join_root(&opts.root, &log.path),
+        (None, None) => return Ok(()),
+    };
+    let log = config.log.as_ref();
+    LOG.open(
+        &path,
+        log.and_then(|log| log.max_size)
+            .unwrap_or(cli::log::DEFAULT_MAX_SIZE),
+        log.and_then(|log| log.keep)
+            .unwrap_or(cli::log::DEFAULT_KEEP),
+    )?;
+    let args: Vec<String> = std::env::args().collect();
+    LOG.write(
+        cli::log::Level::Info,
+        &format!(
+            "Omakase {} started: {}",
+            env!("CARGO_PKG_VERSION"),
+            args.join(" ")
+        ),
+    );
+    Ok(())
+}
+
+/// Warn if `arch` in config doesn't match this system, as repositories would seem to lack packages
+fn check_arch(opts: &Opts, config: &Config) {
+    let native = match utils::arch::detect(&opts.root, config.dpkg_admindir.as_deref()) {
+        Some(native) => native,
+        None => return,
+    };
+    if native != config.arch {
+        warn!(
+            "{} is {} in config.toml, but this system is {}. Packages for this system won't be found, run {} to fix it.",
+            style("arch").bold(),
+            style(&config.arch).bold(),
+            style(&native).bold(),
+            style(format!("oma config set arch {native}")).bold()
+        );
+    }
+}
+
+async fn try_main(opts: &Opts) -> Result<i32> {
+    // Diagnoses problems with the configuration itself, so it can't rely on it
+    if let SubCmd::Doctor = opts.subcmd {
+        actions::doctor::doctor(opts)?;
+        return Ok(0);
+    }
+    // Only compares two files, nothing else on the system is involved
+    if let SubCmd::DiffState(cmd) = &opts.subcmd {
+        actions::diff_state::diff_state(cmd)?;
+        return Ok(0);
+    }
+    // Start reading configs
+    let config_root = opts
+        .root
+        .join(&opts.config_root)
+        .canonicalize()
+        .context(format!(
+            "Failed to find config_root in Omakase configuration file {} .",
+            opts.config_root.display()
+        ))?;
+    if !config_root.is_dir() {
+        bail!(
+            "Configuration root (config_root) does not exist or is not a directory at {} .",
+            config_root.display()
+        );
+    }
+
+    let config_path = config_root.join("config.toml");
+    // Set-up main config file
+    let mut config_file = File::open(&config_path).context(format!(
+        "Failed to open configuration file {} .",
+        config_path.display()
+    ))?;
+    let mut data = String::new();
+    config_file
+        .read_to_string(&mut data)
+        .context("Failed to read configuration file.")?;
+    let mut config: Config =
+        toml::from_str(&data).context("Failed to parse configuration file.")?;
+    config
+        .read_repo_order(&data)
+        .context("Failed to parse configuration file.")?;
+    config.check_sanity()?;
+    open_log(opts, &config)?;
+    let mut trusted: Vec<&String> = config
+        .repo
+        .iter()
+        .filter(|(_, repo)| repo.trusted)
+        .map(|(name, _)| name)
+        .collect();
+    trusted.sort();
+    for name in trusted {
+        warn!(
+            "Repository {} is unauthenticated (trusted = true), its metadata is used without verifying the signature!",
+            style(name).bold()
+        );
+    }
+    if let SubCmd::Config(cmd) = &opts.subcmd {
+        actions::config::config_command(opts, &config_root, &config, cmd)?;
+        return Ok(0);
+    }
+    check_arch(opts, &config);
+
+    // Set-up blueprints
+    let mut vendor_blueprint_paths = Vec::new();
+    let blueprint_d_path = config_root.join("blueprint.d");
+    if blueprint_d_path.is_dir() {
+        let paths = read_dir(blueprint_d_path).context("Failed to load Blueprint directory.")?;
+        for path in paths {
+            let path = path?;
+            let filename = path
+                .file_name()
+                .to_str()
+                .context(format!(
+                    "Bad filename in configuration folder: {} .",
+                    path.path().display()
+                ))?
+                .to_owned();
+            if filename.ends_with(".blueprint") {
+                vendor_blueprint_paths.push(path.path());
+            }
+        }
+    }
+    if let SubCmd::Profile(profile) = &opts.subcmd {
+        actions::profile::profile_command(&config_root, profile)?;
+        return Ok(0);
+    }
+    let user_blueprint_path =
+        actions::profile::user_blueprint_path(&config_root, opts.profile.as_deref())?;
+    let mut blueprint = Blueprints::from_files(user_blueprint_path, &vendor_blueprint_paths)?;
+
+    // Do stuff
+    warn!("Omakase is currently under construction and active testing. Proceed with caution on production systems!");
+    if let SubCmd::CheckUpdates(check) = &opts.subcmd {
+        return actions::check_updates(&config, opts, check);
+    }
+    let cancelled = actions::fullfill_command(&config, opts, &mut blueprint).await?;
+    if !cancelled {
+        // Write back blueprint, unless nothing is supposed to be changed
+        // They will determine if it really need to write back user blueprint
+        if !opts.no_act && !opts.assume_no && opts.script.is_none() {
+            blueprint.export()?;
+        }
+        Ok(0)
+    } else {
+        // User cancelled operation. Don't write back blueprint
+        Ok(2)
+    }
+}
+
+fn sigint_handler(root: &Path) {
+    if crate::DPKG_RUNNING.load(Ordering::Relaxed) {
+        warn!("You may not interrupt Omakase when dpkg is running.");
+        // Don't exit. Important things are happening
+        return;
+    }
+
+    // Kill subprocess
+    let subprocess_pid = SUBPROCESS.load(Ordering::Relaxed);
+    if subprocess_pid > 0 {
+        let pid = nix::unistd::Pid::from_raw(subprocess_pid);
+        signal::kill(pid, signal::SIGTERM).expect("Failed to kill child process.");
+    }
+
+    // Dealing with lock
+    if LOCKED.load(Ordering::Relaxed) {
+        utils::lock::unlock(root).expect("Failed to unlock instance.");
+    }
+
+    // Show cursor before exiting.
+    // This is not a big deal so we won't panic on this.
+    let _ = WRITER.show_cursor();
+    std::process::exit(2);
+}
@@ -0,0 +1,157 @@
+use crate::utils::time::format_time;
+
+use anyhow::{Context, Result};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::SystemTime,
+};
+
+/// Rotate the log once it's larger than this, in bytes
+pub const DEFAULT_MAX_SIZE: u64 = 1024 * 1024;
+/// How many old logs are kept
+pub const DEFAULT_KEEP: usize = 1;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Level {
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl Level {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Level::Debug => "DEBUG",
+            Level::Info => "INFO",
+            Level::Warn => "WARN",
+            Level::Error => "ERROR",
+        }
+    }
+}
+
+/// Plain text log with a timestamp and level on each line, kept apart from terminal output
+///
+/// Nothing is logged until a file is opened. Write errors are ignored, like those of `WRITER`.
+#[derive(Default)]
+pub struct FileLog {
+    file: Mutex<Option<File>>,
+}
+
+impl FileLog {
+    /// Start logging to `path`
+    ///
+    /// If the log is already larger than `max_size`, it's moved to `path.1` first, `path.1` to
+    /// `path.2`, and so on, keeping `keep` old logs. With `keep` being 0, the log is truncated.
+    pub fn open(&self, path: &Path, max_size: u64, keep: usize) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let full = fs::metadata(path).is_ok_and(|meta| meta.len() > max_size);
+        if full && keep > 0 {
+            rotate(path, keep).context(format!("Failed to rotate log {}.", path.display()))?;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .append(!full || keep > 0)
+            .write(true)
+            .truncate(full && keep == 0)
+            .open(path)
+            .context(format!("Failed to open log {}.", path.display()))?;
+        *self.file.lock().unwrap() = Some(file);
+        Ok(())
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.file.lock().unwrap().is_some()
+    }
+
+    /// Log `msg`, without ANSI styling. Each line of it gets its own timestamp and level.
+    pub fn write(&self, level: Level, msg: &str) {
+        let mut file = self.file.lock().unwrap();
+        let file = match file.as_mut() {
+            Some(file) => file,
+            None => return,
+        };
+        let time = format_time(SystemTime::now());
+        let mut buf = String::new();
+        for line in console::strip_ansi_codes(msg).lines() {
+            buf.push_str(&format!("{} {:<5} {}\n", time, level.as_str(), line));
+        }
+        // Write at once, so that lines from dpkg threads don't interleave
+        file.write_all(buf.as_bytes()).ok();
+    }
+}
+
+/// Shift `path.N` to `path.N+1`, dropping the ones beyond `keep`, then move `path` to `path.1`
+fn rotate(path: &Path, keep: usize) -> Result<()> {
+    let numbered = |n: usize| {
+        let mut name = path.as_os_str().to_owned();
+        name.push(format!(".{n}"));
+        PathBuf::from(name)
+    };
+    for n in (1..keep).rev() {
+        let old = numbered(n);
+        if old.exists() {
+            fs::rename(&old, numbered(n + 1))?;
+        }
+    }
+    fs::rename(path, numbered(1))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::utils::temp_dir::TempDir;
+
+    #[test]
+    fn test_file_log() {
+        let root = TempDir::new("log");
+        let path = root.join("log/oma.log");
+        let log = FileLog::default();
+        // Not opened yet
+        log.write(Level::Info, "dropped");
+        assert!(!log.enabled());
+
+        log.open(&path, 100, 2).unwrap();
+        log.write(
+            Level::Warn,
+            &format!("{} is\nbroken", console::style("bash").bold()),
+        );
+        let content = fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].ends_with(" WARN  bash is"));
+        assert!(lines[1].ends_with(" WARN  broken"));
+        // YYYY-MM-DD HH:MM:SS
+        assert_eq!(lines[0].find(" WARN"), Some(19));
+
+        // Appended while small enough
+        log.open(&path, 100, 2).unwrap();
+        log.write(Level::Error, &"x".repeat(100));
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 3);
+        // Rotated once too large
+        log.open(&path, 100, 2).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().len(), 0);
+        assert!(fs::read_to_string(root.join("log/oma.log.1"))
+            .unwrap()
+            .starts_with(&content));
+        log.write(Level::Debug, &"y".repeat(100));
+        log.open(&path, 100, 2).unwrap();
+        assert!(fs::read_to_string(root.join("log/oma.log.1"))
+            .unwrap()
+            .contains("DEBUG"));
+        assert!(fs::read_to_string(root.join("log/oma.log.2"))
+            .unwrap()
+            .contains("ERROR"));
+        // Truncated without old logs to keep
+        log.write(Level::Info, &"z".repeat(100));
+        log.open(&path, 100, 0).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().len(), 0);
+        assert!(!root.join("log/oma.log.3").exists());
+    }
+}
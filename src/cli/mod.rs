@@ -1,8 +1,10 @@
+pub mod log;
 mod prompt;
-pub use prompt::{ask_confirm, OmaTheme};
+pub use prompt::{ask_confirm, ask_phrase, OmaTheme};
 
 use anyhow::{Context, Result};
 use console::Term;
+use indicatif::{ProgressBar, ProgressStyle};
 use std::io::Write;
 
 const PREFIX_LEN: u16 = 10;
@@ -20,6 +22,36 @@ pub fn gen_prefix(prefix: &str) -> String {
     real_prefix
 }
 
+/// Create a progress bar with `len` steps, or a spinner if `len` is None
+///
+/// The progress bar is hidden in quiet mode, or if stderr is not a terminal.
+pub fn progress_bar(msg: &'static str, len: Option<u64>) -> ProgressBar {
+    if crate::quiet() || !console::user_attended_stderr() {
+        return ProgressBar::hidden();
+    }
+
+    let prefix = gen_prefix("");
+    match len {
+        Some(len) => {
+            let style = ProgressStyle::default_bar()
+                .template(" {prefix}{msg} [{wide_bar:.white/black}] {pos}/{len} {percent:>3}%");
+            ProgressBar::new(len)
+                .with_style(style)
+                .with_prefix(prefix)
+                .with_message(msg)
+        }
+        None => {
+            let style = ProgressStyle::default_spinner().template(" {prefix}{spinner} {msg}");
+            let bar = ProgressBar::new_spinner()
+                .with_style(style)
+                .with_prefix(prefix)
+                .with_message(msg);
+            bar.enable_steady_tick(100);
+            bar
+        }
+    }
+}
+
 pub struct Writer {
     term: Term,
 }
@@ -110,53 +142,71 @@ impl Writer {
 }
 
 // We will ignore write errors in the following macros, since cannot print messages is not an emergency
+// Messages are also written to the log file, if enabled, see `log::FileLog`
 #[macro_export]
 macro_rules! msg {
-    ($($arg:tt)+) => {
-        $crate::WRITER.writeln("", &format!($($arg)+)).ok();
-    };
+    ($($arg:tt)+) => {{
+        let msg = format!($($arg)+);
+        $crate::WRITER.writeln("", &msg).ok();
+        $crate::LOG.write($crate::cli::log::Level::Info, &msg);
+    }};
 }
 
 #[macro_export]
 macro_rules! debug {
     ($($arg:tt)+) => {
-        if crate::verbose() {
-            $crate::WRITER.writeln(&console::style("DEBUG").dim().to_string(), &format!($($arg)+)).ok();
+        // Always logged, even if not shown
+        if crate::verbose() || $crate::LOG.enabled() {
+            let msg = format!($($arg)+);
+            if crate::verbose() {
+                $crate::WRITER.writeln(&console::style("DEBUG").dim().to_string(), &msg).ok();
+            }
+            $crate::LOG.write($crate::cli::log::Level::Debug, &msg);
         }
     };
 }
 
 #[macro_export]
 macro_rules! success {
-    ($($arg:tt)+) => {
-        $crate::WRITER.writeln(&console::style("SUCCESS").green().bold().to_string(), &format!($($arg)+)).ok();
-    };
+    ($($arg:tt)+) => {{
+        let msg = format!($($arg)+);
+        $crate::WRITER.writeln(&console::style("SUCCESS").green().bold().to_string(), &msg).ok();
+        $crate::LOG.write($crate::cli::log::Level::Info, &msg);
+    }};
 }
 
 #[macro_export]
 macro_rules! info {
-    ($($arg:tt)+) => {
-        $crate::WRITER.writeln(&console::style("INFO").blue().bold().to_string(), &format!($($arg)+)).ok();
-    };
+    ($($arg:tt)+) => {{
+        let msg = format!($($arg)+);
+        $crate::WRITER.writeln(&console::style("INFO").blue().bold().to_string(), &msg).ok();
+        $crate::LOG.write($crate::cli::log::Level::Info, &msg);
+    }};
 }
 
 #[macro_export]
 macro_rules! warn {
-    ($($arg:tt)+) => {
-        $crate::WRITER.writeln(&console::style("WARNING").yellow().bold().to_string(), &format!($($arg)+)).ok();
-    };
+    ($($arg:tt)+) => {{
+        let msg = format!($($arg)+);
+        $crate::WRITER.writeln(&console::style("WARNING").yellow().bold().to_string(), &msg).ok();
+        $crate::LOG.write($crate::cli::log::Level::Warn, &msg);
+    }};
 }
 
 #[macro_export]
 macro_rules! error {
-    ($($arg:tt)+) => {
-        $crate::WRITER.writeln(&console::style("ERROR").red().bold().to_string(), &format!($($arg)+)).ok();
-    };
+    ($($arg:tt)+) => {{
+        let msg = format!($($arg)+);
+        $crate::WRITER.writeln(&console::style("ERROR").red().bold().to_string(), &msg).ok();
+        $crate::LOG.write($crate::cli::log::Level::Error, &msg);
+    }};
 }
 
 #[macro_export]
 macro_rules! due_to {
-    ($($arg:tt)+) => {
-        $crate::WRITER.writeln(&console::style("DUE TO").yellow().bold().to_string(), &format!($($arg)+)).ok();
-    };
+    ($($arg:tt)+) => {{
+        let msg = format!($($arg)+);
+        $crate::WRITER.writeln(&console::style("DUE TO").yellow().bold().to_string(), &msg).ok();
+        $crate::LOG.write($crate::cli::log::Level::Error, &msg);
+    }};
 }
@@ -2,7 +2,7 @@ use crate::types::config::Opts;
 
 use anyhow::Result;
 use console::style;
-use dialoguer::{theme::Theme, Confirm};
+use dialoguer::{theme::Theme, Confirm, Input};
 use std::fmt;
 
 pub fn ask_confirm(opts: &Opts, msg: &str) -> Result<bool> {
@@ -12,10 +12,35 @@ pub fn ask_confirm(opts: &Opts, msg: &str) -> Result<bool> {
 
     let prefix = super::gen_prefix("");
     let msg = format!("{prefix}{msg}");
+    if opts.assume_no {
+        return assume_no(&msg);
+    }
     let res = Confirm::new().with_prompt(msg).interact()?;
     Ok(res)
 }
 
+/// Ask the user to type `phrase` exactly, for operations where y/N is too easy to answer
+///
+/// Unlike `ask_confirm`, `--yes` doesn't answer this, but `--assume-no` still does.
+pub fn ask_phrase(opts: &Opts, msg: &str, phrase: &str) -> Result<bool> {
+    let prefix = super::gen_prefix("");
+    let msg = format!("{prefix}{msg} Type \"{phrase}\" to continue");
+    if opts.assume_no {
+        return assume_no(&msg);
+    }
+    let input: String = Input::new()
+        .with_prompt(msg)
+        .allow_empty(true)
+        .interact_text()?;
+    Ok(input == phrase)
+}
+
+/// Show the prompt with its answer, as if the user had declined it
+fn assume_no(msg: &str) -> Result<bool> {
+    crate::WRITER.writeln("", &format!("{msg} {}", style("no").bold()))?;
+    Ok(false)
+}
+
 /// Theme for dialoguer
 #[derive(Default)]
 pub struct OmaTheme;
@@ -35,3 +60,17 @@ impl Theme for OmaTheme {
         write!(f, "{prefix}{text}")
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use clap::Parser;
+
+    #[test]
+    fn test_assume_no() {
+        let opts = Opts::try_parse_from(["oma", "-n", "upgrade"]).unwrap();
+        assert!(!ask_confirm(&opts, "Proceed?").unwrap());
+        assert!(!ask_phrase(&opts, "Proceed?", "Yes, do as I say!").unwrap());
+        assert!(Opts::try_parse_from(["oma", "--yes", "--assume-no", "upgrade"]).is_err());
+    }
+}
@@ -1,8 +1,11 @@
+pub mod snapshot;
 mod verify;
+pub use snapshot::Snapshot;
+pub use verify::{parse_certs, read_certs};
 
 use crate::{
-    info,
-    types::{config::RepoConfig, Checksum},
+    debug, info,
+    types::{config::RepoConfig, Checksum, ChecksumType},
     utils::downloader::{Compression, DownloadJob, Downloader},
     warn,
 };
@@ -10,7 +13,14 @@ use anyhow::{anyhow, bail, Context, Result};
 use console::style;
 use lazy_static::lazy_static;
 use regex::Regex;
-use std::{collections::HashMap, path::PathBuf};
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
+use thiserror::Error;
 
 #[derive(Debug)]
 pub struct LocalDb {
@@ -20,6 +30,64 @@ pub struct LocalDb {
     key_root: PathBuf,
     arch: String,
     repos: HashMap<String, RepoConfig>,
+    // checksum used for metadata if InRelease provides several
+    preferred_checksum: ChecksumType,
+    // refuse InRelease signed by expired keys
+    reject_expired_keys: bool,
+    // try other mirrors if InRelease from one fails verification
+    fallback_on_bad_signature: bool,
+    // only accept InRelease recorded in this snapshot
+    snapshot: Option<Snapshot>,
+}
+
+/// Why InRelease from a mirror can't be used
+#[derive(Debug, Error)]
+enum InReleaseFailure {
+    /// Network issues, safe to try other mirrors
+    #[error(transparent)]
+    Fetch(anyhow::Error),
+    /// Bad signature, may be a security issue
+    #[error(transparent)]
+    Verify(anyhow::Error),
+}
+
+/// A package database of a configured repository
+pub struct PkgDb {
+    pub baseurl: String,
+    pub path: PathBuf,
+    /// Whether the repository provides security updates
+    pub security: bool,
+    /// Distribution (suite) of the repository
+    pub distribution: String,
+    /// Architecture of packages in this database, see `RepoConfig::arch`
+    pub arch: String,
+    /// Name of the repository
+    pub repo: String,
+    /// Base URLs of all mirrors of the repository, starting with `baseurl`. Shared by all
+    /// databases of the repository.
+    pub bases: Arc<[String]>,
+}
+
+/// Result of checking a repository for updates
+pub struct RepoUpdateStatus {
+    pub name: String,
+    /// Whether InRelease differs from the local copy
+    pub updated: bool,
+    /// Size of package databases that need to be downloaded
+    pub download_size: u64,
+}
+
+/// Result of checking whether a repository is reachable and provides what is configured
+pub struct RepoHealth {
+    pub name: String,
+    /// Mirror that provides valid metadata, None if all mirrors failed
+    pub mirror: Option<String>,
+    /// Components provided by the repository
+    pub components: Vec<String>,
+    /// Architectures provided by the repository
+    pub archs: Vec<String>,
+    /// Problems found, e.g. unreachable mirrors or configured components that are not provided
+    pub problems: Vec<String>,
 }
 
 impl LocalDb {
@@ -28,13 +96,59 @@ impl LocalDb {
         key_root: PathBuf,
         repos: HashMap<String, RepoConfig>,
         arch: &str,
+        preferred_checksum: ChecksumType,
+        reject_expired_keys: bool,
+        fallback_on_bad_signature: bool,
     ) -> Self {
         LocalDb {
             root,
             key_root,
             arch: arch.to_owned(),
             repos,
+            preferred_checksum,
+            reject_expired_keys,
+            fallback_on_bad_signature,
+            snapshot: None,
+        }
+    }
+
+    /// Refuse metadata other than what's recorded in `snapshot` when refreshing
+    pub fn pin(&mut self, snapshot: Snapshot) {
+        self.snapshot = Some(snapshot);
+    }
+
+    /// Record InRelease of all repositories from the last refresh as snapshot `name`
+    pub fn take_snapshot(&self, name: &str) -> Result<Snapshot> {
+        let mut repo = BTreeMap::new();
+        for repo_name in self.repos.keys() {
+            let path = self.root.join(format!("InRelease_{repo_name}"));
+            let inrelease = std::fs::read(&path).context(format!(
+                "No local metadata for repository {}, please refresh first.",
+                style(repo_name).bold()
+            ))?;
+            repo.insert(repo_name.clone(), snapshot::InReleaseId::new(&inrelease));
         }
+        Ok(Snapshot {
+            name: name.to_owned(),
+            repo,
+        })
+    }
+
+    /// Time since the least recently refreshed repository was refreshed, if longer than `max_age`
+    ///
+    /// InRelease is downloaded on every refresh, so its mtime tells when that happened. The `Date`
+    /// field is not used, as metadata of a repository that rarely changes is not stale.
+    pub fn stale_for(&self, max_age: Duration) -> Option<Duration> {
+        let now = SystemTime::now();
+        self.repos
+            .keys()
+            .filter_map(|name| {
+                let path = self.root.join(format!("InRelease_{name}"));
+                let modified = std::fs::metadata(path).and_then(|meta| meta.modified());
+                now.duration_since(modified.ok()?).ok()
+            })
+            .max()
+            .filter(|age| *age > max_age)
     }
 
     pub fn get_package_db(&self, name: &str) -> Result<Vec<(String, PathBuf)>> {
@@ -45,8 +159,13 @@ impl LocalDb {
 
         let mut files: Vec<(String, PathBuf)> = Vec::new();
         let distribution = &repo.distribution;
-        let arch = &self.arch;
+        let arch = self.repo_arch(repo);
         let repo_url = repo.get_url()?;
+        // Flat repositories have a single index for all architectures
+        let flat = self.root.join(format!("{name}/Packages_flat"));
+        if repo.flat && flat.is_file() {
+            files.push((repo_url.clone(), flat));
+        }
         for component in &repo.components {
             // First prepare arch-specific repo
             let arch = self
@@ -74,11 +193,25 @@ impl LocalDb {
         Ok(files)
     }
 
-    // Get (BaseURL, FilePath) of all configured repos
-    pub fn get_all_package_db(&self) -> Result<Vec<(String, PathBuf)>> {
+    // Get package databases of all configured repos
+    pub fn get_all_package_db(&self) -> Result<Vec<PkgDb>> {
         let mut res = Vec::new();
-        for repo in &self.repos {
-            res.append(&mut self.get_package_db(repo.0)?);
+        // Preferred repositories first, duplicated packages are taken from the first one
+        let mut repos: Vec<(&String, &RepoConfig)> = self.repos.iter().collect();
+        repos.sort_unstable_by_key(|(name, repo)| (Reverse(repo.priority), repo.order, *name));
+        for (name, repo) in repos {
+            let bases: Arc<[String]> = repo.get_urls()?.into();
+            for (baseurl, path) in self.get_package_db(name)? {
+                res.push(PkgDb {
+                    baseurl,
+                    path,
+                    security: repo.security,
+                    distribution: repo.distribution.clone(),
+                    arch: self.repo_arch(repo).to_owned(),
+                    repo: name.clone(),
+                    bases: bases.clone(),
+                });
+            }
         }
         Ok(res)
     }
@@ -88,10 +221,14 @@ impl LocalDb {
             Some(repo) => repo,
             None => bail!("Repository with name {} not found.", name),
         };
+        // Flat repositories don't provide contents metadata
+        if repo.flat {
+            return Ok(Vec::new());
+        }
 
         let mut files: Vec<(String, PathBuf)> = Vec::new();
         let distribution = &repo.distribution;
-        let arch = &self.arch;
+        let arch = self.repo_arch(repo);
         let repo_url = repo.get_url()?;
         for component in &repo.components {
             // First prepare arch-specific repo
@@ -134,10 +271,13 @@ impl LocalDb {
             Some(repo) => repo,
             None => bail!("Repository with name {} not found.", name),
         };
+        if repo.flat {
+            return Ok(Vec::new());
+        }
 
         let mut files: Vec<(String, PathBuf)> = Vec::new();
         let distribution = &repo.distribution;
-        let arch = &self.arch;
+        let arch = self.repo_arch(repo);
         let repo_url = repo.get_url()?;
 
         for component in &repo.components {
@@ -177,135 +317,634 @@ impl LocalDb {
     }
 
     pub async fn update(&self, downloader: &Downloader) -> Result<()> {
-        info!("Refreshing local repository metadata...");
+        self.update_repos(downloader, &[]).await
+    }
 
-        // HashMap<RepoName, HashMap<url, (size, checksum)>>
-        let mut dbs: HashMap<String, HashMap<String, (u64, Checksum)>> = HashMap::new();
-        // Step 1: Download InRelease for each repo
-        let mut inrelease_urls: Vec<DownloadJob> = Vec::with_capacity(self.repos.len());
-        for (name, repo) in &self.repos {
-            inrelease_urls.push(DownloadJob {
-                url: format!("{}/dists/{}/InRelease", repo.get_url()?, repo.distribution),
-                description: Some(format!("Repository metadata for {}", style(name).bold())),
-                filename: Some(format!("InRelease_{name}")),
-                size: None,
-                compression: Compression::None(None),
-            });
+    /// Refresh metadata of repositories in `names`, or all repositories if `names` is empty
+    ///
+    /// Local metadata of other repositories is left untouched. New metadata is downloaded to a
+    /// staging directory, and only moved into place once everything is downloaded and verified,
+    /// so a failed refresh leaves the previous metadata as it was.
+    pub async fn update_repos(&self, downloader: &Downloader, names: &[String]) -> Result<()> {
+        let repos = self.select_repos(names)?;
+        info!("Refreshing local repository metadata...");
+        if let Some(snapshot) = &self.snapshot {
+            info!(
+                "Only accepting metadata recorded in snapshot {}.",
+                style(&snapshot.name).bold()
+            );
         }
-        downloader.fetch(inrelease_urls, &self.root, false).await?;
+        let start = Instant::now();
 
-        // Step 2: Verify InRelease with PGP
-        for (name, repo) in &self.repos {
-            let inrelease_path = self.root.join(format!("InRelease_{name}"));
-            let inrelease_contents = std::fs::read(inrelease_path)?;
-            let bytes = bytes::Bytes::from(inrelease_contents);
-            let res = verify::verify_inrelease(&self.key_root, &repo.keys, &bytes)
-                .context(format!("Failed to verify metadata for repository {name}."))?;
-            let repo_dbs = parse_inrelease(&res)
-                .context(format!("Failed to parse metadata for repository {name}."))?;
-            dbs.insert(name.clone(), repo_dbs);
+        let staging = self.root.join(".refresh");
+        // Left behind by an interrupted refresh
+        if staging.is_dir() {
+            std::fs::remove_dir_all(&staging)?;
+        }
+        let res = self.update_staged(downloader, &repos, &staging).await;
+        if staging.is_dir() {
+            std::fs::remove_dir_all(&staging)?;
         }
+        res?;
+        crate::TIMINGS.record("Metadata refresh", start);
 
-        // Step 3: Download deb dbs
+        Ok(())
+    }
+
+    /// Download metadata of `repos` to `staging`, then move it into place
+    async fn update_staged(
+        &self,
+        downloader: &Downloader,
+        repos: &[(&String, &RepoConfig)],
+        staging: &Path,
+    ) -> Result<()> {
+        // Step 1 and 2: Download and verify InRelease for each repo
+        let dbs = self.fetch_inrelease(downloader, staging, repos).await?;
+
+        // Step 3: Download deb dbs, except those whose local copies are still valid
         let mut dbs_to_download = Vec::new();
-        for (name, repo) in &self.repos {
+        for &(name, repo) in repos {
             // Create sub-directory for each repo
+            std::fs::create_dir_all(staging.join(name))?;
+            let (url, repo_dbs) = dbs.get(name).unwrap();
+            for job in self.gen_db_jobs(name, repo, url, repo_dbs)? {
+                let path = self.root.join(job.filename.as_ref().unwrap());
+                if job.compression.local_copy_valid(&path) {
+                    debug!("{} is not modified.", path.display());
+                } else {
+                    dbs_to_download.push(job);
+                }
+            }
+        }
+
+        // Step 4: Call Downloader to down them all!
+        // The downloader will verify the checksum for us
+        if !dbs_to_download.is_empty() {
+            downloader.fetch(dbs_to_download, staging, false).await?;
+        }
+
+        // Step 5: Move everything into place, InRelease last so that it never describes files
+        // that are not there yet
+        for &(name, _) in repos {
             let db_subdir = self.root.join(name);
-            if !db_subdir.is_dir() {
-                std::fs::create_dir(&self.root.join(name))?;
+            std::fs::create_dir_all(&db_subdir)?;
+            for entry in std::fs::read_dir(staging.join(name))? {
+                let entry = entry?;
+                std::fs::rename(entry.path(), db_subdir.join(entry.file_name()))?;
             }
+            let filename = format!("InRelease_{name}");
+            std::fs::rename(staging.join(&filename), self.root.join(&filename))?;
+        }
 
-            for component in &repo.components {
-                let url = repo.get_url()?;
-                let distribution = &repo.distribution;
-
-                let pre_download_count = dbs_to_download.len();
-                let possible_archs = vec![self.arch.clone(), "all".to_owned()];
-                for arch in possible_archs {
-                    // 1. Download Packages db
-                    let compressed_rel_url = format!("{component}/binary-{arch}/Packages.xz");
-                    let decompressed_rel_url = format!("{component}/binary-{arch}/Packages");
-
-                    if let Some(compressed_meta) = dbs.get(name).unwrap().get(&compressed_rel_url) {
-                        let filename =
-                            format!("{name}/Packages_{distribution}_{component}_{arch}",);
-                        let decompressed_meta = match dbs.get(name).unwrap().get(&decompressed_rel_url) {
-                            Some(meta) => meta,
-                            None => bail!("Packages.xz exists but Packages does not, remote repository issue?")
-                        };
-                        dbs_to_download.push(DownloadJob {
-                            url: format!("{url}/dists/{distribution}/{compressed_rel_url}",),
-                            description: Some(format!(
-                                "Repository catalog for {} ({arch}).",
-                                style(name).bold(),
-                            )),
-                            filename: Some(filename),
-                            size: Some(compressed_meta.0),
-                            compression: Compression::Xz((
-                                Some(compressed_meta.1.clone()),
-                                Some(decompressed_meta.1.clone()),
-                            )),
-                        });
-                    }
-                    // 2. Download Contents db
-                    let compressed_rel_url = format!("{component}/Contents-{arch}.gz");
-                    if let Some(compressed_meta) = dbs.get(name).unwrap().get(&compressed_rel_url) {
-                        let filename =
-                            format!("{name}/Contents_{distribution}_{component}_{arch}.gz",);
-                        dbs_to_download.push(DownloadJob {
-                            url: format!("{url}/dists/{distribution}/{compressed_rel_url}",),
-                            description: Some(format!(
-                                "Package contents metadata for {} ({arch}).",
-                                style(name).bold(),
-                            )),
-                            filename: Some(filename),
-                            size: Some(compressed_meta.0),
-                            compression: Compression::None(Some(compressed_meta.1.clone())),
-                        });
+        Ok(())
+    }
+
+    /// Check which repositories have updated metadata, without downloading package databases
+    ///
+    /// Only repositories in `names` are checked, or all repositories if `names` is empty.
+    pub async fn check_update(
+        &self,
+        downloader: &Downloader,
+        names: &[String],
+    ) -> Result<Vec<RepoUpdateStatus>> {
+        let repos = self.select_repos(names)?;
+        info!("Checking local repository metadata...");
+        // Keep InRelease files away from local copies, repository sub-directories are named after repos
+        let check_root = self.root.join(".check");
+        let res = self.check_staged(downloader, &repos, &check_root).await;
+        if check_root.is_dir() {
+            std::fs::remove_dir_all(&check_root)?;
+        }
+
+        res
+    }
+
+    /// Download InRelease of `repos` to `check_root`, and compare it with local metadata
+    async fn check_staged(
+        &self,
+        downloader: &Downloader,
+        repos: &[(&String, &RepoConfig)],
+        check_root: &Path,
+    ) -> Result<Vec<RepoUpdateStatus>> {
+        let dbs = self.fetch_inrelease(downloader, check_root, repos).await?;
+
+        let mut res = Vec::with_capacity(repos.len());
+        for &(name, repo) in repos {
+            let filename = format!("InRelease_{name}");
+            let new_inrelease = std::fs::read(check_root.join(&filename))?;
+            let updated = match std::fs::read(self.root.join(&filename)) {
+                Ok(old_inrelease) => old_inrelease != new_inrelease,
+                Err(_) => true,
+            };
+            // Only count files that don't exist or differ from local copies
+            let mut download_size = 0;
+            let (url, repo_dbs) = dbs.get(name).unwrap();
+            for job in self.gen_db_jobs(name, repo, url, repo_dbs)? {
+                let path = self.root.join(job.filename.as_ref().unwrap());
+                if !job.compression.local_copy_valid(&path) {
+                    download_size += job.size.unwrap_or(0);
+                }
+            }
+            res.push(RepoUpdateStatus {
+                name: name.clone(),
+                updated,
+                download_size,
+            });
+        }
+        res.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Ok(res)
+    }
+
+    /// Check that repositories are reachable, verifiable and provide configured components
+    ///
+    /// Only repositories in `names` are checked, or all repositories if `names` is empty.
+    /// Nothing is written to local metadata.
+    pub async fn check_health(
+        &self,
+        downloader: &Downloader,
+        names: &[String],
+    ) -> Result<Vec<RepoHealth>> {
+        let repos = self.select_repos(names)?;
+        info!("Checking repositories...");
+        // Keep InRelease files away from local copies, repository sub-directories are named after repos
+        let check_root = self.root.join(".health");
+
+        let mut res = Vec::with_capacity(repos.len());
+        for (name, repo) in repos {
+            let mut health = RepoHealth {
+                name: name.clone(),
+                mirror: None,
+                components: Vec::new(),
+                archs: Vec::new(),
+                problems: Vec::new(),
+            };
+            for url in repo.get_urls()? {
+                match self
+                    .fetch_repo_inrelease(downloader, &check_root, name, repo, &url)
+                    .await
+                {
+                    Ok(content) => {
+                        self.inspect_release(&content, repo, &mut health);
+                        health.mirror = Some(url);
+                        break;
                     }
-                    // 3. Download BinContents db
-                    let rel_url = format!("{component}/BinContents-{arch}");
-                    if let Some(meta) = dbs.get(name).unwrap().get(&rel_url) {
-                        let filename =
-                            format!("{name}/BinContents_{distribution}_{component}_{arch}",);
-                        dbs_to_download.push(DownloadJob {
-                            url: format!("{url}/dists/{distribution}/{rel_url}",),
-                            description: Some(format!(
-                                "Package contents metadata for {} ({arch}).",
-                                style(name).bold(),
-                            )),
-                            filename: Some(filename),
-                            size: Some(meta.0),
-                            compression: Compression::None(Some(meta.1.clone())),
-                        });
+                    Err(InReleaseFailure::Fetch(e)) => health
+                        .problems
+                        .push(format!("Metadata from {url} can't be fetched: {e:#}")),
+                    Err(InReleaseFailure::Verify(e)) => health.problems.push(format!(
+                        "Metadata from {url} failed signature verification: {e:#}"
+                    )),
+                }
+            }
+            res.push(health);
+        }
+        if check_root.is_dir() {
+            std::fs::remove_dir_all(&check_root)?;
+        }
+        res.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Ok(res)
+    }
+
+    /// Find components and architectures in verified InRelease `content` of `repo`,
+    /// and whether configured components are provided
+    fn inspect_release(&self, content: &str, repo: &RepoConfig, health: &mut RepoHealth) {
+        let repo_dbs = match parse_inrelease(content, self.preferred_checksum) {
+            Ok(repo_dbs) => repo_dbs,
+            Err(e) => {
+                health.problems.push(format!("Malformed metadata: {e:#}"));
+                return;
+            }
+        };
+        // Package indices are at COMPONENT/binary-ARCH/Packages
+        let mut indices: Vec<(&str, &str)> = repo_dbs
+            .keys()
+            .filter_map(|path| {
+                let (component, rest) = path.split_once("/binary-")?;
+                let (arch, file) = rest.split_once('/')?;
+                file.starts_with("Packages").then_some((component, arch))
+            })
+            .collect();
+        indices.sort();
+        indices.dedup();
+        // Prefer what the repository declares, fall back to what it actually lists
+        let header = debcontrol::parse_str(content)
+            .ok()
+            .and_then(|paragraphs| paragraphs.into_iter().next());
+        let header_field = |name: &str| -> Option<Vec<String>> {
+            let field = header.as_ref()?.fields.iter().find(|f| f.name == name)?;
+            Some(
+                field
+                    .value
+                    .split_whitespace()
+                    .map(|s| s.to_owned())
+                    .collect(),
+            )
+        };
+        health.components = header_field("Components").unwrap_or_else(|| {
+            let mut components: Vec<String> = indices.iter().map(|(c, _)| c.to_string()).collect();
+            components.dedup();
+            components
+        });
+        health.archs = header_field("Architectures").unwrap_or_else(|| {
+            let mut archs: Vec<String> = indices.iter().map(|(_, a)| a.to_string()).collect();
+            archs.sort();
+            archs.dedup();
+            archs
+        });
+
+        if repo.flat && !repo_dbs.keys().any(|path| path.starts_with("Packages")) {
+            health
+                .problems
+                .push("No package index is provided by this repository.".to_string());
+        }
+        let arch = self.repo_arch(repo);
+        let possible_archs = vec![arch.to_owned(), "all".to_owned()];
+        for component in &repo.components {
+            if !health.components.contains(component) {
+                health.problems.push(format!(
+                    "Component {component} is not provided by this repository."
+                ));
+            } else if available_archs(&repo_dbs, component, &possible_archs).is_empty() {
+                health.problems.push(format!(
+                    "Component {component} provides no packages for {arch} architecture."
+                ));
+            }
+        }
+    }
+
+    /// Remove metadata of repositories that are no longer configured, returns removed paths
+    ///
+    /// Only repository sub-directories, InRelease files and leftovers of interrupted checks are
+    /// considered, anything else in the database directory is left alone.
+    pub fn clean_orphans(&self) -> Result<Vec<PathBuf>> {
+        let mut removed = Vec::new();
+        if !self.root.is_dir() {
+            return Ok(removed);
+        }
+        for entry in std::fs::read_dir(&self.root)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            // Doesn't follow symlinks
+            let file_type = entry.file_type()?;
+            let orphaned = if let Some(repo) = name.strip_prefix("InRelease_") {
+                file_type.is_file() && !self.repos.contains_key(repo)
+            } else if [".check", ".health", ".snapshot", ".refresh"].contains(&name.as_str()) {
+                file_type.is_dir()
+            } else {
+                file_type.is_dir() && !self.repos.contains_key(&name)
+            };
+            if !orphaned {
+                continue;
+            }
+            let path = entry.path();
+            debug!("Removing {} ...", path.display());
+            if file_type.is_dir() {
+                std::fs::remove_dir_all(&path)
+            } else {
+                std::fs::remove_file(&path)
+            }
+            .context(format!("Failed to remove {}.", path.display()))?;
+            removed.push(path);
+        }
+        removed.sort();
+
+        Ok(removed)
+    }
+
+    /// Architecture of packages taken from `repo`
+    fn repo_arch<'a>(&'a self, repo: &'a RepoConfig) -> &'a str {
+        repo.arch.as_deref().unwrap_or(&self.arch)
+    }
+
+    /// Find configured repositories by name, or all of them if `names` is empty
+    pub fn select_repos(&self, names: &[String]) -> Result<Vec<(&String, &RepoConfig)>> {
+        if names.is_empty() {
+            return Ok(self.repos.iter().collect());
+        }
+        let mut res = Vec::with_capacity(names.len());
+        for name in names {
+            match self.repos.get_key_value(name) {
+                Some(repo) => {
+                    if !res.iter().any(|(selected, _)| *selected == name) {
+                        res.push(repo);
                     }
                 }
+                None => bail!("Repository with name {} not found.", style(name).bold()),
+            }
+        }
+        Ok(res)
+    }
 
-                if pre_download_count == dbs_to_download.len() {
-                    warn!("No repository available for {name}/{component}.");
-                    warn!(
-                        "Please check if this repository provides packages for {} architecture.",
-                        self.arch
-                    );
+    /// Download and verify InRelease of `repos` into `path`
+    // -> HashMap<RepoName, (MirrorURL, HashMap<url, (size, checksum)>)>
+    async fn fetch_inrelease(
+        &self,
+        downloader: &Downloader,
+        path: &Path,
+        repos: &[(&String, &RepoConfig)],
+    ) -> Result<HashMap<String, (String, HashMap<String, (u64, Checksum)>)>> {
+        let mut dbs = HashMap::new();
+        // InRelease of pinned repositories is checked here first, so that local copies are kept if it differs
+        let staging = path.join(".snapshot");
+        for &(name, repo) in repos {
+            let pinned = match &self.snapshot {
+                Some(snapshot) => match snapshot.repo.get(name) {
+                    Some(id) => Some((&snapshot.name, id)),
+                    None => bail!(
+                        "Repository {} is not in snapshot {}, create a new snapshot to use it.",
+                        style(name).bold(),
+                        style(&snapshot.name).bold()
+                    ),
+                },
+                None => None,
+            };
+            let fetch_path = if pinned.is_some() { &staging } else { path };
+            let mut failures = Vec::new();
+            for url in repo.get_urls()? {
+                let content = match self
+                    .fetch_repo_inrelease(downloader, fetch_path, name, repo, &url)
+                    .await
+                {
+                    Ok(content) => content,
+                    Err(InReleaseFailure::Fetch(e)) => {
+                        warn!("Failed to fetch metadata for {name} from {url}: {e:#}");
+                        failures.push(format!("{url}: {e:#}"));
+                        continue;
+                    }
+                    Err(InReleaseFailure::Verify(e)) => {
+                        // A bad signature may be an attack, don't hide it by switching mirrors
+                        if !self.fallback_on_bad_signature {
+                            return Err(e).context(format!(
+                                "Metadata for repository {} from {url} failed signature verification. Other mirrors are not tried, as this may indicate a compromised mirror.",
+                                style(name).bold()
+                            ));
+                        }
+                        warn!(
+                            "Metadata for repository {} from {url} failed signature verification: {e:#}",
+                            style(name).bold()
+                        );
+                        failures.push(format!("{url}: {e:#}"));
+                        continue;
+                    }
+                };
+                if let Some((snapshot, id)) = pinned {
+                    let filename = format!("InRelease_{name}");
+                    let fetched = staging.join(&filename);
+                    if snapshot::InReleaseId::new(&std::fs::read(&fetched)?).sha256 != id.sha256 {
+                        warn!(
+                            "Mirror {url} no longer serves metadata of repository {} in snapshot {}.",
+                            style(name).bold(),
+                            style(snapshot).bold()
+                        );
+                        failures.push(format!(
+                            "{url}: metadata differs from snapshot {snapshot} (dated {})",
+                            id.date.as_deref().unwrap_or("unknown")
+                        ));
+                        continue;
+                    }
+                    std::fs::create_dir_all(path)?;
+                    std::fs::rename(&fetched, path.join(&filename))?;
                 }
+                if !failures.is_empty() {
+                    info!("Using mirror {url} for repository {}.", style(name).bold());
+                }
+                let repo_dbs = parse_inrelease(&content, self.preferred_checksum)
+                    .context(format!("Failed to parse metadata for repository {name}."))?;
+                dbs.insert(name.clone(), (url, repo_dbs));
+                break;
+            }
+            if staging.is_dir() {
+                std::fs::remove_dir_all(&staging)?;
+            }
+            if !dbs.contains_key(name) {
+                bail!(
+                    "Failed to refresh metadata for repository {}, all mirrors failed:\n{}",
+                    style(name).bold(),
+                    failures.join("\n")
+                );
             }
         }
 
-        // Step 4: Call Downloader to down them all!
-        // The downloader will verify the checksum for us
-        downloader.fetch(dbs_to_download, &self.root, false).await?;
+        Ok(dbs)
+    }
 
-        Ok(())
+    /// Download and verify InRelease of a repo from the mirror at `url`
+    async fn fetch_repo_inrelease(
+        &self,
+        downloader: &Downloader,
+        path: &Path,
+        name: &str,
+        repo: &RepoConfig,
+        url: &str,
+    ) -> Result<String, InReleaseFailure> {
+        let filename = format!("InRelease_{name}");
+        let url = format!("{url}/{}", repo.release_path());
+        let job = DownloadJob {
+            url,
+            mirrors: Vec::new(),
+            repos: vec![name.to_string()],
+            description: Some(format!("Repository metadata for {}", style(name).bold())),
+            filename: Some(filename.clone()),
+            size: None,
+            compression: Compression::None(None),
+        };
+        downloader
+            .fetch(vec![job], path, false)
+            .await
+            .map_err(InReleaseFailure::Fetch)?;
+
+        let inrelease_contents =
+            std::fs::read(path.join(&filename)).map_err(|e| InReleaseFailure::Fetch(e.into()))?;
+        if repo.trusted {
+            debug!("Skipping signature verification of InRelease of {name}.");
+            return verify::strip_signature(&inrelease_contents).map_err(InReleaseFailure::Verify);
+        }
+        let bytes = bytes::Bytes::from(inrelease_contents);
+        let key_files: Vec<String> = repo.keys.iter().map(|key| key.file().to_owned()).collect();
+        let (content, signers) =
+            verify::verify_inrelease(&self.key_root, &key_files, &bytes, self.reject_expired_keys)
+                .map_err(InReleaseFailure::Verify)?;
+        debug!("InRelease of {} is signed by {}.", name, signers.join(", "));
+        // Not a problem as long as a key that is still in use also signed it
+        let deprecated = |file: &String| {
+            repo.keys
+                .iter()
+                .any(|key| key.file() == file && key.deprecated())
+        };
+        if signers.iter().all(deprecated) {
+            warn!(
+                "InRelease of repository {} is only signed by deprecated key(s) {}, please check with the repository maintainers.",
+                style(name).bold(),
+                style(signers.join(", ")).bold()
+            );
+        }
+        Ok(content)
     }
+
+    /// Generate download jobs for deb dbs of a repo, from the mirror at `url`
+    fn gen_db_jobs(
+        &self,
+        name: &str,
+        repo: &RepoConfig,
+        url: &str,
+        repo_dbs: &HashMap<String, (u64, Checksum)>,
+    ) -> Result<Vec<DownloadJob>> {
+        // Paths in InRelease are relative to its directory
+        let release_url = repo.release_dir_url(url);
+        if repo.flat {
+            let job = gen_flat_db_jobs(name, &release_url, repo_dbs);
+            if job.is_none() {
+                warn!("No package index available for {name}.");
+            }
+            return Ok(job.into_iter().collect());
+        }
+        let mut jobs = Vec::new();
+        let repo_arch = self.repo_arch(repo);
+        for component in &repo.components {
+            let distribution = &repo.distribution;
+
+            let possible_archs = vec![repo_arch.to_owned(), "all".to_owned()];
+            let available_archs = available_archs(repo_dbs, component, &possible_archs);
+            if available_archs.is_empty() {
+                warn!("No repository available for {name}/{component}.");
+                warn!(
+                    "Please check if this repository provides packages for {repo_arch} architecture."
+                );
+            } else if !available_archs.contains(&repo_arch) {
+                // Arch-independent repositories (e.g. fonts) are fine
+                debug!("{name}/{component} only provides architecture-independent packages.");
+            }
+            for arch in possible_archs {
+                // 1. Download Packages db
+                let compressed_rel_url = format!("{component}/binary-{arch}/Packages.xz");
+                let decompressed_rel_url = format!("{component}/binary-{arch}/Packages");
+
+                if let Some(compressed_meta) = repo_dbs.get(&compressed_rel_url) {
+                    let filename = format!("{name}/Packages_{distribution}_{component}_{arch}",);
+                    let decompressed_meta = match repo_dbs.get(&decompressed_rel_url) {
+                        Some(meta) => meta,
+                        None => bail!(
+                            "Packages.xz exists but Packages does not, remote repository issue?"
+                        ),
+                    };
+                    jobs.push(DownloadJob {
+                        url: format!("{release_url}/{compressed_rel_url}"),
+                        mirrors: Vec::new(),
+                        repos: vec![name.to_string()],
+                        description: Some(format!(
+                            "Repository catalog for {} ({arch}).",
+                            style(name).bold(),
+                        )),
+                        filename: Some(filename),
+                        size: Some(compressed_meta.0),
+                        compression: Compression::Xz((
+                            Some(compressed_meta.1.clone()),
+                            Some(decompressed_meta.1.clone()),
+                        )),
+                    });
+                }
+                // 2. Download Contents db, kept compressed
+                let compressed_rel_url = format!("{component}/Contents-{arch}.gz");
+                let decompressed_rel_url = format!("{component}/Contents-{arch}");
+                if let Some(compressed_meta) = repo_dbs.get(&compressed_rel_url) {
+                    let filename = format!("{name}/Contents_{distribution}_{component}_{arch}.gz",);
+                    jobs.push(DownloadJob {
+                        url: format!("{release_url}/{compressed_rel_url}"),
+                        mirrors: Vec::new(),
+                        repos: vec![name.to_string()],
+                        description: Some(format!(
+                            "Package contents metadata for {} ({arch}).",
+                            style(name).bold(),
+                        )),
+                        filename: Some(filename),
+                        size: Some(compressed_meta.0),
+                        compression: Compression::KeepGzip((
+                            Some(compressed_meta.1.clone()),
+                            repo_dbs
+                                .get(&decompressed_rel_url)
+                                .map(|meta| meta.1.clone()),
+                        )),
+                    });
+                }
+                // 3. Download BinContents db
+                let rel_url = format!("{component}/BinContents-{arch}");
+                if let Some(meta) = repo_dbs.get(&rel_url) {
+                    let filename = format!("{name}/BinContents_{distribution}_{component}_{arch}",);
+                    jobs.push(DownloadJob {
+                        url: format!("{release_url}/{rel_url}"),
+                        mirrors: Vec::new(),
+                        repos: vec![name.to_string()],
+                        description: Some(format!(
+                            "Package contents metadata for {} ({arch}).",
+                            style(name).bold(),
+                        )),
+                        filename: Some(filename),
+                        size: Some(meta.0),
+                        compression: Compression::None(Some(meta.1.clone())),
+                    });
+                }
+            }
+        }
+
+        Ok(jobs)
+    }
+}
+
+/// Generate the download job for the package index of a flat repository, from `url` holding InRelease
+///
+/// Flat repositories only have Packages, possibly compressed, next to InRelease.
+fn gen_flat_db_jobs(
+    name: &str,
+    url: &str,
+    repo_dbs: &HashMap<String, (u64, Checksum)>,
+) -> Option<DownloadJob> {
+    let decompressed = repo_dbs.get("Packages").map(|meta| meta.1.clone());
+    let (rel_url, (size, checksum)) = ["Packages.xz", "Packages.gz", "Packages"]
+        .into_iter()
+        .find_map(|rel_url| Some((rel_url, repo_dbs.get(rel_url)?)))?;
+    let compression = match rel_url {
+        "Packages.xz" => Compression::Xz((Some(checksum.clone()), decompressed)),
+        "Packages.gz" => Compression::Gzip((Some(checksum.clone()), decompressed)),
+        _ => Compression::None(Some(checksum.clone())),
+    };
+    Some(DownloadJob {
+        url: format!("{url}/{rel_url}"),
+        mirrors: Vec::new(),
+        repos: vec![name.to_string()],
+        description: Some(format!("Repository catalog for {}.", style(name).bold())),
+        filename: Some(format!("{name}/Packages_flat")),
+        size: Some(*size),
+        compression,
+    })
 }
 
-fn parse_inrelease(s: &str) -> Result<HashMap<String, (u64, Checksum)>> {
+/// Architectures in `archs` that have a package index for `component` in InRelease
+fn available_archs<'a>(
+    repo_dbs: &HashMap<String, (u64, Checksum)>,
+    component: &str,
+    archs: &'a [String],
+) -> Vec<&'a str> {
+    archs
+        .iter()
+        .filter(|arch| {
+            repo_dbs.contains_key(&format!("{component}/binary-{arch}/Packages"))
+                || repo_dbs.contains_key(&format!("{component}/binary-{arch}/Packages.xz"))
+        })
+        .map(|arch| arch.as_str())
+        .collect()
+}
+
+/// Parse checksums of files listed in InRelease, using `preferred` if several are available
+fn parse_inrelease(s: &str, preferred: ChecksumType) -> Result<HashMap<String, (u64, Checksum)>> {
     lazy_static! {
         static ref CHKSUM: Regex =
             Regex::new("^(?P<chksum>[0-9a-z]+) +(?P<size>[0-9]+) +(?P<path>.+)$").unwrap();
     }
 
-    let mut dbs: HashMap<String, (u64, Checksum)> = HashMap::new();
+    // Path -> (Size, available checksums)
+    let mut available: HashMap<String, (u64, Vec<Checksum>)> = HashMap::new();
     let paragraphs = debcontrol::parse_str(s).unwrap();
     for p in paragraphs {
         for field in p.fields {
@@ -335,12 +974,704 @@ fn parse_inrelease(s: &str) -> Result<HashMap<String, (u64, Checksum)>> {
                             _ => panic!(),
                         }
                     };
-                    dbs.insert(rel_path, (size, chksum));
+                    available
+                        .entry(rel_path)
+                        .or_insert_with(|| (size, Vec::new()))
+                        .1
+                        .push(chksum);
                 }
-                return Ok(dbs);
             }
         }
     }
 
-    bail!("No metadata hash found in InRelease. Supported Hash: SHA256")
+    if available.is_empty() {
+        bail!("No metadata hash found in InRelease. Supported Hash: SHA256, SHA512")
+    }
+    let dbs = available
+        .into_iter()
+        .map(|(path, (size, chksums))| {
+            let chksum = Checksum::select(&chksums, preferred).unwrap().clone();
+            (path, (size, chksum))
+        })
+        .collect();
+    Ok(dbs)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::utils::downloader::test_server;
+    use crate::utils::temp_dir::TempDir;
+
+    /// LocalDb of `repos` for amd64, with both metadata and keys in `root`
+    fn test_db(root: &Path, repos: HashMap<String, RepoConfig>) -> LocalDb {
+        LocalDb::new(
+            root.to_owned(),
+            root.to_owned(),
+            repos,
+            "amd64",
+            ChecksumType::default(),
+            false,
+            false,
+        )
+    }
+
+    /// LocalDb with only a trusted flat repository named `local` at `url`
+    fn trusted_flat_db(root: &Path, url: &str) -> LocalDb {
+        let repo: RepoConfig = toml::from_str(&format!(
+            "source = \"{url}\"\nflat = true\nkeys = []\ntrusted = true"
+        ))
+        .unwrap();
+        test_db(root, HashMap::from([("local".to_string(), repo)]))
+    }
+
+    /// Serve an unsigned flat repository. InRelease has the extra `fields` and lists `listed` as
+    /// Packages, while `served` is what is actually served.
+    fn serve_flat_repo(fields: &str, listed: &str, served: &str) -> String {
+        use sha2::{Digest, Sha256};
+
+        let inrelease = format!(
+            "Origin: Local\n{fields}SHA256:\n {} {} Packages\n",
+            hex::encode(Sha256::digest(listed.as_bytes())),
+            listed.len()
+        );
+        test_server(HashMap::from([
+            ("/InRelease".to_string(), inrelease.into_bytes()),
+            ("/Packages".to_string(), served.as_bytes().to_vec()),
+        ]))
+    }
+
+    /// Packages of a flat repository with `version` of a single package
+    fn tool_packages(version: &str) -> String {
+        format!("Package: tool\nArchitecture: amd64\nVersion: {version}\nSection: misc\nInstalled-Size: 1\nFilename: ./tool_{version}_amd64.deb\nSize: 1\nDescription: tool\n")
+    }
+
+    /// Downloader without repository settings, and a runtime to run it on
+    fn test_downloader() -> (Downloader, tokio::runtime::Runtime) {
+        let downloader = Downloader::new(&Default::default(), &HashMap::new()).unwrap();
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        (downloader, runtime)
+    }
+
+    #[test]
+    fn test_noarch_repo() {
+        let fixture = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/repo-noarch");
+        let repo: RepoConfig = toml::from_str(
+            r#"
+            source = "https://fonts.example.com"
+            distribution = "stable"
+            components = ["main"]
+            keys = ["fonts.asc"]
+            "#,
+        )
+        .unwrap();
+        let db = LocalDb::new(
+            fixture.join("db"),
+            fixture.clone(),
+            HashMap::from([("fonts".to_string(), repo.clone())]),
+            "amd64",
+            ChecksumType::default(),
+            false,
+            false,
+        );
+
+        // Only binary-all is published
+        let release = std::fs::read_to_string(fixture.join("dists/stable/Release")).unwrap();
+        let repo_dbs = parse_inrelease(&release, ChecksumType::default()).unwrap();
+        let archs = vec!["amd64".to_string(), "all".to_string()];
+        assert_eq!(available_archs(&repo_dbs, "main", &archs), vec!["all"]);
+        assert!(available_archs(&repo_dbs, "contrib", &archs).is_empty());
+        let url = repo.get_url().unwrap();
+        let jobs = db.gen_db_jobs("fonts", &repo, &url, &repo_dbs).unwrap();
+        assert_eq!(jobs.len(), 1);
+        assert_eq!(
+            jobs[0].url,
+            "https://fonts.example.com/debs/dists/stable/main/binary-all/Packages.xz"
+        );
+
+        let dbs = db.get_package_db("fonts").unwrap();
+        assert_eq!(dbs.len(), 1);
+        assert!(dbs[0].1.ends_with("fonts/Packages_stable_main_all"));
+    }
+
+    #[test]
+    fn test_inspect_release() {
+        let fixture = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/repo-noarch");
+        let repo: RepoConfig = toml::from_str(
+            r#"
+            source = "https://fonts.example.com"
+            distribution = "stable"
+            components = ["main", "contrib"]
+            keys = ["fonts.asc"]
+            "#,
+        )
+        .unwrap();
+        let health = |db: &LocalDb, release: &str| {
+            let mut health = RepoHealth {
+                name: "fonts".to_string(),
+                mirror: None,
+                components: Vec::new(),
+                archs: Vec::new(),
+                problems: Vec::new(),
+            };
+            db.inspect_release(release, &repo, &mut health);
+            health
+        };
+        let db = |arch| {
+            LocalDb::new(
+                fixture.join("db"),
+                fixture.clone(),
+                HashMap::new(),
+                arch,
+                ChecksumType::default(),
+                false,
+                false,
+            )
+        };
+
+        let release = std::fs::read_to_string(fixture.join("dists/stable/Release")).unwrap();
+        let res = health(&db("amd64"), &release);
+        assert_eq!(res.components, vec!["main"]);
+        assert_eq!(res.archs, vec!["amd64", "all"]);
+        // Architecture-independent packages are fine
+        assert_eq!(
+            res.problems,
+            vec!["Component contrib is not provided by this repository."]
+        );
+
+        // Without Components and Architectures fields, use package indices listed
+        let release: String = release
+            .lines()
+            .filter(|line| !line.starts_with("Components:") && !line.starts_with("Architectures:"))
+            .map(|line| format!("{line}\n"))
+            .collect();
+        let res = health(&db("amd64"), &release);
+        assert_eq!(res.components, vec!["main"]);
+        assert_eq!(res.archs, vec!["all"]);
+        assert_eq!(res.problems.len(), 1);
+
+        let res = health(&db("amd64"), "Origin: Fonts\n");
+        assert!(res.problems[0].starts_with("Malformed metadata"));
+    }
+
+    #[test]
+    fn test_repo_arch() {
+        let root = TempDir::new("repo-arch");
+        let repo = |arch: &str| -> RepoConfig {
+            toml::from_str(&format!(
+                "source = \"https://repo.example.org\"\ndistribution = \"stable\"\ncomponents = [\"main\"]\nkeys = []\n{arch}"
+            ))
+            .unwrap()
+        };
+        let (native, foreign) = (repo(""), repo("arch = \"arm64\""));
+        let db = test_db(
+            &root,
+            HashMap::from([
+                ("native".to_string(), native.clone()),
+                ("foreign".to_string(), foreign.clone()),
+            ]),
+        );
+
+        let sha256 = "a".repeat(64);
+        let release = format!(
+            "SHA256:\n {sha256} 10 main/binary-amd64/Packages\n {sha256} 10 main/binary-amd64/Packages.xz\n {sha256} 10 main/binary-arm64/Packages\n {sha256} 10 main/binary-arm64/Packages.xz\n"
+        );
+        let repo_dbs = parse_inrelease(&release, ChecksumType::default()).unwrap();
+        let url = native.get_url().unwrap();
+        let jobs = db.gen_db_jobs("native", &native, &url, &repo_dbs).unwrap();
+        assert_eq!(
+            jobs[0].url,
+            "https://repo.example.org/debs/dists/stable/main/binary-amd64/Packages.xz"
+        );
+        let jobs = db
+            .gen_db_jobs("foreign", &foreign, &url, &repo_dbs)
+            .unwrap();
+        assert_eq!(jobs.len(), 1);
+        assert_eq!(
+            jobs[0].url,
+            "https://repo.example.org/debs/dists/stable/main/binary-arm64/Packages.xz"
+        );
+        assert_eq!(
+            jobs[0].filename.as_deref(),
+            Some("foreign/Packages_stable_main_arm64")
+        );
+
+        // Package databases are tagged with the architecture of their repository
+        for (name, arch) in [("native", "amd64"), ("foreign", "arm64")] {
+            std::fs::create_dir_all(root.join(name)).unwrap();
+            std::fs::write(root.join(format!("{name}/Packages_stable_main_{arch}")), "").unwrap();
+        }
+        let dbs = db.get_all_package_db().unwrap();
+        let archs: Vec<(&str, &str)> = dbs
+            .iter()
+            .map(|db| (db.repo.as_str(), db.arch.as_str()))
+            .collect();
+        assert_eq!(archs, vec![("foreign", "arm64"), ("native", "amd64")]);
+    }
+
+    #[test]
+    fn test_contents_refetch() {
+        use flate2::{write::GzEncoder, Compression as GzLevel};
+        use sha2::{Digest, Sha256};
+        use std::io::Write;
+
+        let root = TempDir::new("contents");
+        let repo: RepoConfig = toml::from_str(
+            "source = \"https://repo.example.org\"\ndistribution = \"stable\"\ncomponents = [\"main\"]\nkeys = []",
+        )
+        .unwrap();
+        let db = test_db(&root, HashMap::from([("main".to_string(), repo.clone())]));
+        let gzip = |content: &[u8]| {
+            let mut encoder = GzEncoder::new(Vec::new(), GzLevel::default());
+            encoder.write_all(content).unwrap();
+            encoder.finish().unwrap()
+        };
+        let contents_job = |contents: &[u8]| {
+            let gz = gzip(contents);
+            let release = format!(
+                "Origin: AOSC\nSHA256:\n {} {} main/Contents-amd64.gz\n {} {} main/Contents-amd64\n",
+                hex::encode(Sha256::digest(&gz)),
+                gz.len(),
+                hex::encode(Sha256::digest(contents)),
+                contents.len()
+            );
+            let repo_dbs = parse_inrelease(&release, ChecksumType::Sha256).unwrap();
+            let jobs = db
+                .gen_db_jobs("main", &repo, "https://repo.example.org", &repo_dbs)
+                .unwrap();
+            assert_eq!(jobs.len(), 1);
+            jobs.into_iter().next().unwrap()
+        };
+
+        let old = b"usr/bin/bash shells/bash\n";
+        let job = contents_job(old);
+        let path = root.join(job.filename.as_ref().unwrap());
+        // Both checksums are validated, the file is kept compressed
+        match &job.compression {
+            Compression::KeepGzip((Some(_), Some(_))) => (),
+            _ => panic!("Contents must be validated before and after decompression"),
+        }
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, gzip(old)).unwrap();
+        assert!(job.compression.local_copy_valid(&path));
+        // Contents has changed in Release, the local copy is stale
+        let job = contents_job(b"usr/bin/zsh shells/zsh\n");
+        assert!(!job.compression.local_copy_valid(&path));
+    }
+
+    #[test]
+    fn test_inrelease_checksums() {
+        let sha256 = "a".repeat(64);
+        let sha512 = "b".repeat(128);
+        let release = format!(
+            "Origin: AOSC\nSuite: stable\nSHA256:\n {sha256} 100 main/binary-amd64/Packages\n {sha256} 10 main/binary-amd64/Release\nSHA512:\n {sha512} 100 main/binary-amd64/Packages\n"
+        );
+        let dbs = parse_inrelease(&release, ChecksumType::Sha512).unwrap();
+        assert_eq!(dbs.len(), 2);
+        let (size, chksum) = &dbs["main/binary-amd64/Packages"];
+        assert_eq!(*size, 100);
+        assert_eq!(chksum.checksum_type(), ChecksumType::Sha512);
+        assert_eq!(chksum, &Checksum::from_sha512_str(&sha512).unwrap());
+        // Only listed in the SHA256 section
+        let (_, chksum) = &dbs["main/binary-amd64/Release"];
+        assert_eq!(chksum.checksum_type(), ChecksumType::Sha256);
+
+        let dbs = parse_inrelease(&release, ChecksumType::Sha256).unwrap();
+        let (_, chksum) = &dbs["main/binary-amd64/Packages"];
+        assert_eq!(chksum, &Checksum::from_sha256_str(&sha256).unwrap());
+        assert!(parse_inrelease("Origin: AOSC\n", ChecksumType::Sha512).is_err());
+    }
+
+    #[test]
+    fn test_inrelease_section_order() {
+        let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+        let index = fixtures.join("repo-noarch/dists/stable/main/binary-all");
+        for release in ["sha256-first.Release", "sha512-first.Release"] {
+            let release =
+                std::fs::read_to_string(fixtures.join("release-checksums").join(release)).unwrap();
+            for preferred in [ChecksumType::Sha256, ChecksumType::Sha512] {
+                let dbs = parse_inrelease(&release, preferred).unwrap();
+                assert_eq!(dbs.len(), 2);
+                for file in ["Packages", "Packages.xz"] {
+                    let (_, chksum) = &dbs[&format!("main/binary-all/{file}")];
+                    assert_eq!(chksum.checksum_type(), preferred);
+                    assert!(chksum.cmp_file(&index.join(file)).unwrap());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_select_repos() {
+        let repo = |url: &str| -> RepoConfig {
+            toml::from_str(&format!(
+                "source = \"{url}\"\ndistribution = \"stable\"\ncomponents = [\"main\"]\nkeys = []"
+            ))
+            .unwrap()
+        };
+        let db = test_db(
+            Path::new("/nonexistent"),
+            HashMap::from([
+                ("main".to_string(), repo("https://repo.example.org")),
+                ("extra".to_string(), repo("https://extra.example.org")),
+            ]),
+        );
+
+        let mut all: Vec<&String> = db
+            .select_repos(&[])
+            .unwrap()
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        all.sort();
+        assert_eq!(all, vec!["extra", "main"]);
+        // Only the named repository is refreshed, duplicates are ignored
+        let selected = db
+            .select_repos(&["extra".to_string(), "extra".to_string()])
+            .unwrap();
+        assert_eq!(selected.len(), 1);
+        assert_eq!(selected[0].0, "extra");
+        assert_eq!(
+            selected[0].1.get_url().unwrap(),
+            "https://extra.example.org/debs"
+        );
+        assert!(db.select_repos(&["unknown".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_flat_repo() {
+        let root = TempDir::new("flat");
+        let repo: RepoConfig =
+            toml::from_str("source = \"https://example.org/flat/\"\nkeys = []\nflat = true")
+                .unwrap();
+        let db = test_db(
+            &root,
+            HashMap::from([("third-party".to_string(), repo.clone())]),
+        );
+        let release = format!(
+            "Origin: Third party\nSHA256:\n {} 400 Packages\n {} 200 Packages.gz\n",
+            "a".repeat(64),
+            "b".repeat(64)
+        );
+        let repo_dbs = parse_inrelease(&release, ChecksumType::default()).unwrap();
+        let url = repo.get_url().unwrap();
+        let jobs = db
+            .gen_db_jobs("third-party", &repo, &url, &repo_dbs)
+            .unwrap();
+        assert_eq!(jobs.len(), 1);
+        assert_eq!(jobs[0].url, "https://example.org/flat/Packages.gz");
+        assert_eq!(jobs[0].size, Some(200));
+        assert!(matches!(
+            jobs[0].compression,
+            Compression::Gzip((Some(_), Some(_)))
+        ));
+        let mut health = RepoHealth {
+            name: "third-party".to_string(),
+            mirror: None,
+            components: Vec::new(),
+            archs: Vec::new(),
+            problems: Vec::new(),
+        };
+        db.inspect_release(&release, &repo, &mut health);
+        assert!(health.problems.is_empty());
+        db.inspect_release("SHA256:\n 0000 1 Release\n", &repo, &mut health);
+        assert_eq!(health.problems.len(), 1);
+
+        // The index has packages of all architectures
+        assert!(db.get_package_db("third-party").is_err());
+        std::fs::create_dir_all(root.join("third-party")).unwrap();
+        let pkg = |name: &str, arch: &str| {
+            format!("Package: {name}\nArchitecture: {arch}\nVersion: 1.0\nSection: misc\nInstalled-Size: 1\nFilename: ./{name}_1.0_{arch}.deb\nSize: 1\nSHA256: {}\nDescription: {name}\n\n", "c".repeat(64))
+        };
+        std::fs::write(
+            root.join("third-party/Packages_flat"),
+            pkg("tool", "amd64") + &pkg("tool-arm", "arm64") + &pkg("tool-data", "all"),
+        )
+        .unwrap();
+        let dbs = db.get_all_package_db().unwrap();
+        assert_eq!(dbs.len(), 1);
+        assert_eq!(dbs[0].baseurl, "https://example.org/flat");
+        assert!(db.get_contents_db("third-party").unwrap().is_empty());
+        let pool = crate::pool::source::create_pool(
+            &dbs,
+            &[],
+            crate::pool::PkgFields::All,
+            &Default::default(),
+        )
+        .unwrap();
+        assert!(pool.get_pkgs_by_name("tool").is_some());
+        assert!(pool.get_pkgs_by_name("tool-data").is_some());
+        assert!(pool.get_pkgs_by_name("tool-arm").is_none());
+    }
+
+    #[test]
+    fn test_trusted_repo() {
+        use sha2::{Digest, Sha256};
+
+        let packages = format!("Package: tool\nArchitecture: amd64\nVersion: 1.0\nSection: misc\nInstalled-Size: 1\nFilename: ./tool_1.0_amd64.deb\nSize: 1\nSHA256: {}\nDescription: tool\n", "c".repeat(64));
+        // Signed by some key, but not one that is configured
+        let inrelease = format!(
+            "-----BEGIN PGP SIGNED MESSAGE-----\nHash: SHA256\n\nOrigin: Local\nSHA256:\n {} {} Packages\n-----BEGIN PGP SIGNATURE-----\n\nbogus\n-----END PGP SIGNATURE-----\n",
+            hex::encode(Sha256::digest(packages.as_bytes())),
+            packages.len()
+        );
+        let url = test_server(HashMap::from([
+            ("/repo/InRelease".to_string(), inrelease.into_bytes()),
+            ("/repo/Packages".to_string(), packages.into_bytes()),
+        ]));
+        let root = TempDir::new("trusted");
+        let local_db = |trusted: bool| {
+            let repo: RepoConfig = toml::from_str(&format!(
+                "source = \"{url}/repo\"\nflat = true\nkeys = [\"missing.asc\"]\ntrusted = {trusted}"
+            ))
+            .unwrap();
+            test_db(&root, HashMap::from([("local".to_string(), repo)]))
+        };
+        let (downloader, runtime) = test_downloader();
+
+        // The signature can't be verified
+        assert!(runtime
+            .block_on(local_db(false).update(&downloader))
+            .is_err());
+        let db = local_db(true);
+        runtime.block_on(db.update(&downloader)).unwrap();
+        let dbs = db.get_package_db("local").unwrap();
+        assert!(std::fs::read_to_string(&dbs[0].1)
+            .unwrap()
+            .starts_with("Package: tool"));
+    }
+
+    #[test]
+    fn test_release_path() {
+        use sha2::{Digest, Sha256};
+        use std::io::Write;
+
+        let packages = "Package: tool\nArchitecture: amd64\nVersion: 1.0\nSection: misc\nInstalled-Size: 1\nFilename: pool/tool_1.0_amd64.deb\nSize: 1\nDescription: tool\n";
+        let mut encoder = xz2::write::XzEncoder::new(Vec::new(), 6);
+        encoder.write_all(packages.as_bytes()).unwrap();
+        let compressed = encoder.finish().unwrap();
+        let inrelease = format!(
+            "Origin: Internal\nSHA256:\n {} {} main/binary-amd64/Packages.xz\n {} {} main/binary-amd64/Packages\n",
+            hex::encode(Sha256::digest(&compressed)),
+            compressed.len(),
+            hex::encode(Sha256::digest(packages.as_bytes())),
+            packages.len()
+        );
+        // Indices are next to InRelease, not in dists/
+        let url = test_server(HashMap::from([
+            (
+                "/debs/internal/stable/signed/Release.signed".to_string(),
+                inrelease.into_bytes(),
+            ),
+            (
+                "/debs/internal/stable/signed/main/binary-amd64/Packages.xz".to_string(),
+                compressed,
+            ),
+        ]));
+        let root = TempDir::new("release-path");
+        let repo: RepoConfig = toml::from_str(&format!(
+            "source = \"{url}\"\ndistribution = \"stable\"\ncomponents = [\"main\"]\nkeys = []\ntrusted = true\nrelease_path = \"internal/{{distribution}}/signed/Release.signed\""
+        ))
+        .unwrap();
+        repo.check_sanity().unwrap();
+        let db = test_db(&root, HashMap::from([("internal".to_string(), repo)]));
+        let (downloader, runtime) = test_downloader();
+        runtime.block_on(db.update(&downloader)).unwrap();
+        let dbs = db.get_package_db("internal").unwrap();
+        assert_eq!(std::fs::read_to_string(&dbs[0].1).unwrap(), packages);
+    }
+
+    #[test]
+    fn test_snapshot() {
+        // Each mirror is on its own server, `date` tells them apart
+        let serve = |date: &str| {
+            let packages = tool_packages("1.0");
+            serve_flat_repo(&format!("Date: {date}\n"), &packages, &packages)
+        };
+        let root = TempDir::new("snapshot-db");
+        let local_db = |url: &str| trusted_flat_db(&root, url);
+        let (downloader, runtime) = test_downloader();
+
+        let old = serve("Mon, 01 Jun 2026 00:00:00 UTC");
+        let new = serve("Tue, 02 Jun 2026 00:00:00 UTC");
+        // Nothing to record before refreshing
+        assert!(local_db(&old).take_snapshot("base").is_err());
+        runtime
+            .block_on(local_db(&old).update(&downloader))
+            .unwrap();
+        let snapshot = local_db(&old).take_snapshot("base").unwrap();
+        assert_eq!(
+            snapshot.repo["local"].date.as_deref(),
+            Some("Mon, 01 Jun 2026 00:00:00 UTC")
+        );
+        let inrelease = std::fs::read(root.join("InRelease_local")).unwrap();
+
+        // The mirror moved on, local metadata is kept
+        let mut db = local_db(&new);
+        db.pin(snapshot.clone());
+        assert!(runtime.block_on(db.update(&downloader)).is_err());
+        assert_eq!(
+            std::fs::read(root.join("InRelease_local")).unwrap(),
+            inrelease
+        );
+        assert!(!root.join(".snapshot").exists());
+        // Still served by another one
+        let mut db = local_db(&old);
+        db.pin(snapshot.clone());
+        runtime.block_on(db.update(&downloader)).unwrap();
+        assert_eq!(
+            std::fs::read(root.join("InRelease_local")).unwrap(),
+            inrelease
+        );
+        // Not pinned
+        runtime
+            .block_on(local_db(&new).update(&downloader))
+            .unwrap();
+        assert_ne!(
+            std::fs::read(root.join("InRelease_local")).unwrap(),
+            inrelease
+        );
+        // Repositories added later are refused
+        let mut snapshot = snapshot;
+        snapshot.repo.clear();
+        let mut db = local_db(&old);
+        db.pin(snapshot);
+        assert!(runtime.block_on(db.update(&downloader)).is_err());
+    }
+
+    #[test]
+    fn test_clean_orphans() {
+        let root = TempDir::new("orphans");
+        let repo: RepoConfig = toml::from_str(
+            "source = \"https://repo.example.org\"\ndistribution = \"stable\"\ncomponents = [\"main\"]\nkeys = []",
+        )
+        .unwrap();
+        let db = test_db(&root, HashMap::from([("main".to_string(), repo)]));
+        assert!(db.clean_orphans().unwrap().is_empty());
+        for dir in ["main", "removed", ".check"] {
+            std::fs::create_dir_all(root.join(dir)).unwrap();
+            std::fs::write(root.join(dir).join("Packages_stable_main_amd64"), "").unwrap();
+        }
+        for file in ["InRelease_main", "InRelease_removed", "notes.txt"] {
+            std::fs::write(root.join(file), "").unwrap();
+        }
+
+        assert_eq!(
+            db.clean_orphans().unwrap(),
+            vec![
+                root.join(".check"),
+                root.join("InRelease_removed"),
+                root.join("removed")
+            ]
+        );
+        // Configured repositories and unknown files are left alone
+        assert!(root.join("main/Packages_stable_main_amd64").is_file());
+        assert!(root.join("InRelease_main").is_file());
+        assert!(root.join("notes.txt").is_file());
+        assert!(!root.join("removed").exists());
+        assert!(db.clean_orphans().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_failed_refresh() {
+        // Packages served may not match InRelease
+        let serve = |listed: &str, served: &str| serve_flat_repo("", listed, served);
+        let root = TempDir::new("refresh");
+        let local_db = |url: &str| trusted_flat_db(&root, url);
+        let (downloader, runtime) = test_downloader();
+        let (old, new) = (tool_packages("1.0"), tool_packages("2.0"));
+        runtime
+            .block_on(local_db(&serve(&old, &old)).update(&downloader))
+            .unwrap();
+        let inrelease = std::fs::read(root.join("InRelease_local")).unwrap();
+        let index = root.join("local/Packages_flat");
+        assert_eq!(std::fs::read_to_string(&index).unwrap(), old);
+
+        // The mirror is halfway through syncing: new InRelease, old Packages
+        let db = local_db(&serve(&new, &old));
+        assert!(runtime.block_on(db.update(&downloader)).is_err());
+        // Previous metadata is still there and consistent
+        assert_eq!(
+            std::fs::read(root.join("InRelease_local")).unwrap(),
+            inrelease
+        );
+        assert_eq!(std::fs::read_to_string(&index).unwrap(), old);
+        assert!(!root.join(".refresh").exists());
+        assert_eq!(db.get_package_db("local").unwrap()[0].1, index);
+
+        // Retrying once the mirror is done
+        runtime
+            .block_on(local_db(&serve(&new, &new)).update(&downloader))
+            .unwrap();
+        assert_ne!(
+            std::fs::read(root.join("InRelease_local")).unwrap(),
+            inrelease
+        );
+        assert_eq!(std::fs::read_to_string(&index).unwrap(), new);
+        assert!(!root.join(".refresh").exists());
+    }
+
+    #[test]
+    fn test_check_update() {
+        let root = TempDir::new("check-update");
+        let packages = tool_packages("1.0");
+        let url = serve_flat_repo("", &packages, &packages);
+        let (downloader, runtime) = test_downloader();
+        let res = runtime
+            .block_on(trusted_flat_db(&root, &url).check_update(&downloader, &[]))
+            .unwrap();
+        assert!(res[0].updated);
+        assert_eq!(res[0].download_size, packages.len() as u64);
+        assert!(!root.join(".check").exists());
+
+        // Nothing is served by the second repository
+        let repo = |url: &str| -> RepoConfig {
+            toml::from_str(&format!(
+                "source = \"{url}\"\nflat = true\nkeys = []\ntrusted = true"
+            ))
+            .unwrap()
+        };
+        let db = test_db(
+            &root,
+            HashMap::from([
+                ("local".to_string(), repo(&url)),
+                ("broken".to_string(), repo(&test_server(HashMap::new()))),
+            ]),
+        );
+        assert!(runtime.block_on(db.check_update(&downloader, &[])).is_err());
+        assert!(!root.join(".check").exists());
+    }
+
+    #[test]
+    fn test_stale_for() {
+        let root = TempDir::new("stale");
+        let repo = |name: &str| {
+            let repo: RepoConfig = toml::from_str(&format!(
+                "source = \"https://{name}.example.org\"\ndistribution = \"stable\"\ncomponents = [\"main\"]\nkeys = []"
+            ))
+            .unwrap();
+            (name.to_string(), repo)
+        };
+        let db = test_db(&root, HashMap::from([repo("main"), repo("extra")]));
+        let day = Duration::from_secs(86400);
+        // Never refreshed
+        assert_eq!(db.stale_for(day), None);
+        for name in ["main", "extra"] {
+            std::fs::write(root.join(format!("InRelease_{name}")), "").unwrap();
+        }
+        assert_eq!(db.stale_for(day), None);
+
+        // One of the repositories was last refreshed 10 days ago
+        let file = std::fs::File::options()
+            .write(true)
+            .open(root.join("InRelease_extra"))
+            .unwrap();
+        file.set_modified(SystemTime::now() - 10 * day).unwrap();
+        let age = db.stale_for(7 * day).unwrap();
+        assert!(age >= 10 * day && age < 11 * day);
+        assert_eq!(db.stale_for(14 * day), None);
+    }
 }
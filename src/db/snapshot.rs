@@ -0,0 +1,171 @@
+use crate::utils::name::check_name;
+use anyhow::{bail, Context, Result};
+use console::style;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+// Relative to the snapshot directory, snapshots themselves end with .toml
+const ACTIVE_FILE: &str = "active";
+
+/// InRelease of each repository at some point, so that later refreshes get exactly the same metadata
+///
+/// Snapshots are kept in `SNAPSHOT_PATH` as `NAME.toml`.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct Snapshot {
+    #[serde(skip)]
+    pub name: String,
+    pub repo: BTreeMap<String, InReleaseId>,
+}
+
+/// Identity of an InRelease file
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct InReleaseId {
+    /// SHA256 of the file as downloaded, including the signature
+    pub sha256: String,
+    /// `Date` field, only for display
+    pub date: Option<String>,
+}
+
+impl InReleaseId {
+    pub fn new(inrelease: &[u8]) -> Self {
+        let date = String::from_utf8_lossy(inrelease)
+            .lines()
+            .find_map(|line| line.strip_prefix("Date:"))
+            .map(|date| date.trim().to_string());
+        InReleaseId {
+            sha256: hex::encode(Sha256::digest(inrelease)),
+            date,
+        }
+    }
+}
+
+fn snapshot_path(dir: &Path, name: &str) -> PathBuf {
+    dir.join(format!("{name}.toml"))
+}
+
+impl Snapshot {
+    /// Load snapshot `name` from `dir`
+    pub fn load(dir: &Path, name: &str) -> Result<Self> {
+        check_name("snapshot", name)?;
+        let path = snapshot_path(dir, name);
+        if !path.is_file() {
+            bail!(
+                "Snapshot {} does not exist, use {} to create it.",
+                style(name).bold(),
+                style(format!("oma snapshot create {name}")).bold()
+            );
+        }
+        let content = fs::read_to_string(&path)
+            .context(format!("Failed to read snapshot {}.", path.display()))?;
+        let mut snapshot: Snapshot = toml::from_str(&content)
+            .context(format!("Failed to parse snapshot {}.", path.display()))?;
+        snapshot.name = name.to_owned();
+        Ok(snapshot)
+    }
+
+    /// Save the snapshot to `dir`, fails if one with the same name exists
+    pub fn save(&self, dir: &Path) -> Result<()> {
+        check_name("snapshot", &self.name)?;
+        let path = snapshot_path(dir, &self.name);
+        if path.exists() {
+            bail!("Snapshot {} already exists.", style(&self.name).bold());
+        }
+        fs::create_dir_all(dir)?;
+        fs::write(&path, toml::to_string(self)?)
+            .context(format!("Failed to write snapshot {}.", path.display()))?;
+        Ok(())
+    }
+}
+
+/// Names of snapshots in `dir`, sorted
+pub fn list(dir: &Path) -> Result<Vec<String>> {
+    let mut res = Vec::new();
+    if !dir.is_dir() {
+        return Ok(res);
+    }
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "toml") {
+            if let Some(name) = path.file_stem() {
+                res.push(name.to_string_lossy().into_owned());
+            }
+        }
+    }
+    res.sort();
+    Ok(res)
+}
+
+/// Name of the snapshot in use, set by `set_active`
+pub fn active(dir: &Path) -> Result<Option<String>> {
+    let path = dir.join(ACTIVE_FILE);
+    if !path.is_file() {
+        return Ok(None);
+    }
+    let name = fs::read_to_string(&path)
+        .context(format!(
+            "Failed to read active snapshot from {}.",
+            path.display()
+        ))?
+        .trim()
+        .to_owned();
+    check_name("snapshot", &name)?;
+    Ok(Some(name))
+}
+
+/// Use snapshot `name` from now on, or stop using snapshots if None
+pub fn set_active(dir: &Path, name: Option<&str>) -> Result<()> {
+    let path = dir.join(ACTIVE_FILE);
+    match name {
+        Some(name) => {
+            // Make sure it exists
+            Snapshot::load(dir, name)?;
+            fs::write(&path, format!("{name}\n"))
+        }
+        None if path.is_file() => fs::remove_file(&path),
+        None => Ok(()),
+    }
+    .context(format!(
+        "Failed to save active snapshot to {}.",
+        path.display()
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::utils::temp_dir::TempDir;
+
+    #[test]
+    fn test_snapshots() {
+        let dir = TempDir::new("snapshot");
+        let id = InReleaseId::new(b"Origin: Test\nDate: Thu, 01 Jan 2026 00:00:00 UTC\n");
+        assert_eq!(id.date.as_deref(), Some("Thu, 01 Jan 2026 00:00:00 UTC"));
+        assert_eq!(id.sha256.len(), 64);
+        assert_ne!(InReleaseId::new(b"Origin: Test\n").sha256, id.sha256);
+
+        let snapshot = Snapshot {
+            name: "base".to_string(),
+            repo: BTreeMap::from([("main".to_string(), id)]),
+        };
+        assert!(list(&dir).unwrap().is_empty());
+        snapshot.save(&dir).unwrap();
+        // Never overwritten
+        assert!(snapshot.save(&dir).is_err());
+        assert_eq!(Snapshot::load(&dir, "base").unwrap(), snapshot);
+        assert!(Snapshot::load(&dir, "missing").is_err());
+        assert!(Snapshot::load(&dir, "../base").is_err());
+        assert_eq!(list(&dir).unwrap(), vec!["base".to_string()]);
+
+        assert_eq!(active(&dir).unwrap(), None);
+        assert!(set_active(&dir, Some("missing")).is_err());
+        set_active(&dir, Some("base")).unwrap();
+        assert_eq!(active(&dir).unwrap().as_deref(), Some("base"));
+        set_active(&dir, None).unwrap();
+        assert_eq!(active(&dir).unwrap(), None);
+    }
+}
@@ -1,18 +1,30 @@
-use anyhow::{bail, Result};
+use crate::{utils::time::format_date, warn};
+
+use anyhow::{bail, Context, Result};
 use bytes::Bytes;
+use console::style;
 use sequoia_openpgp::{
+    cert::prelude::*,
     parse::{stream::*, Parse},
-    policy::StandardPolicy,
-    Cert, KeyHandle,
+    policy::{Policy, StandardPolicy},
+    Cert, Fingerprint, KeyHandle,
 };
 use std::io::Read;
 use std::path::Path;
+use std::time::SystemTime;
 
+/// Verify InRelease and return the signed content, along with files of the keys that signed it
+///
+/// InRelease is valid if any key in `cert_filenames` made a good signature. Signatures made
+/// before the signing key expired are still valid, but a warning is shown, or an error if
+/// `reject_expired` is set.
+// -> (Content, Vec<KeyFilename>)
 pub fn verify_inrelease(
     cert_root: &Path,
     cert_filenames: &[String],
     msg: &Bytes,
-) -> Result<String> {
+    reject_expired: bool,
+) -> Result<(String, Vec<String>)> {
     let mut cert_paths = Vec::new();
     for cert_file in cert_filenames {
         let cert_path = cert_root.join(&cert_file);
@@ -32,31 +44,93 @@ pub fn verify_inrelease(
     let mut content = String::new();
     v.read_to_string(&mut content)?;
 
-    Ok(content)
+    for (fingerprint, expiry) in &v.helper_ref().expired {
+        let msg = format!(
+            "InRelease is signed by key {}, which has expired on {}.",
+            style(fingerprint.to_spaced_hex()).bold(),
+            style(format_date(*expiry)).bold()
+        );
+        if reject_expired {
+            bail!("{msg}");
+        }
+        warn!("{msg}");
+    }
+
+    let mut signers = v.helper_ref().signers.clone();
+    signers.sort_unstable();
+    let signers = signers
+        .into_iter()
+        .map(|i| cert_filenames[i].clone())
+        .collect();
+
+    Ok((content, signers))
+}
+
+/// Content of InRelease without verifying its signature, for trusted repositories
+///
+/// InRelease may be clearsigned, or just the content of Release.
+pub fn strip_signature(msg: &[u8]) -> Result<String> {
+    let msg = std::str::from_utf8(msg).context("InRelease is not valid UTF-8.")?;
+    if !msg.starts_with("-----BEGIN PGP SIGNED MESSAGE-----") {
+        return Ok(msg.to_owned());
+    }
+    let mut lines = msg.lines();
+    // Skip armor headers, they end with an empty line
+    for line in lines.by_ref() {
+        if line.trim().is_empty() {
+            break;
+        }
+    }
+    let mut content = Vec::new();
+    for line in lines {
+        if line == "-----BEGIN PGP SIGNATURE-----" {
+            return Ok(content.join("\n"));
+        }
+        // Lines starting with a dash are escaped
+        content.push(line.strip_prefix("- ").unwrap_or(line));
+    }
+    bail!("Malformed InRelease, signature is missing.")
 }
 
 pub struct InReleaseVerifier {
-    certs: Vec<Cert>,
+    /// Certificates, and the index of the key file they come from
+    certs: Vec<(usize, Cert)>,
+    /// Indices of key files that made good signatures
+    signers: Vec<usize>,
+    /// Keys that made good signatures, but have expired by now
+    expired: Vec<(Fingerprint, SystemTime)>,
 }
 
 impl InReleaseVerifier {
+    /// Load all certificates in the key files into one keyring
     pub fn new<P: AsRef<Path>>(cert_paths: &[P]) -> Result<Self> {
-        let mut certs: Vec<Cert> = Vec::new();
-        for path in cert_paths.iter() {
-            certs.push(Cert::from_file(path)?);
+        let mut certs = Vec::new();
+        for (i, path) in cert_paths.iter().enumerate() {
+            let path = path.as_ref();
+            let file_certs = read_certs(path).context(format!(
+                "Failed to load public key file {}.",
+                style(path.display()).bold()
+            ))?;
+            certs.extend(file_certs.into_iter().map(|cert| (i, cert)));
         }
-        Ok(InReleaseVerifier { certs })
+        Ok(InReleaseVerifier {
+            certs,
+            signers: Vec::new(),
+            expired: Vec::new(),
+        })
     }
 }
 
 impl VerificationHelper for InReleaseVerifier {
     fn get_certs(&mut self, ids: &[KeyHandle]) -> Result<Vec<Cert>> {
         let mut certs = Vec::new();
-        for id in ids {
-            for cert in &self.certs {
-                if &cert.key_handle() == id {
-                    certs.push(cert.clone());
-                }
+        for (_, cert) in &self.certs {
+            // The signature may be made by a subkey, and identified by either key ID or fingerprint
+            let matched = cert
+                .keys()
+                .any(|ka| ids.iter().any(|id| ka.key().key_handle().aliases(id)));
+            if matched {
+                certs.push(cert.clone());
             }
         }
         Ok(certs)
@@ -66,10 +140,34 @@ impl VerificationHelper for InReleaseVerifier {
         for layer in structure {
             if let MessageLayer::SignatureGroup { results } = layer {
                 for r in results {
-                    if let Err(e) = r {
-                        bail!("InRelease contains bad signature: {} .", e);
+                    match r {
+                        Ok(GoodChecksum { ka, .. }) => {
+                            let cert_fingerprint = ka.cert().cert().fingerprint();
+                            for (i, cert) in &self.certs {
+                                if cert.fingerprint() == cert_fingerprint
+                                    && !self.signers.contains(i)
+                                {
+                                    self.signers.push(*i);
+                                }
+                            }
+                            // Signing key is checked at the time of signature, check it again now
+                            let fingerprint = ka.key().fingerprint();
+                            let p = &StandardPolicy::new();
+                            if let Some(expiry) = key_expiration(ka.cert().cert(), &fingerprint, p)
+                            {
+                                if expiry <= SystemTime::now() {
+                                    self.expired.push((fingerprint, expiry));
+                                }
+                            }
+                        }
+                        // During key rotation, InRelease may also be signed by keys we don't know yet
+                        Err(VerificationError::MissingKey { .. }) => (),
+                        Err(e) => bail!("InRelease contains bad signature: {} .", e),
                     }
                 }
+                if self.signers.is_empty() {
+                    bail!("InRelease is not signed by any of the configured keys.");
+                }
             } else {
                 bail!("Malformed PGP signature, InRelease must be signed.")
             }
@@ -78,3 +176,152 @@ impl VerificationHelper for InReleaseVerifier {
         Ok(())
     }
 }
+
+/// Read certificates from an ASCII-armored or binary key file, which may contain multiple keys
+pub fn read_certs(path: &Path) -> Result<Vec<Cert>> {
+    let data = std::fs::read(path)?;
+    parse_certs(&data)
+}
+
+/// Parse certificates from the content of a key file
+pub fn parse_certs(data: &[u8]) -> Result<Vec<Cert>> {
+    let format = match sniff_key_format(data) {
+        Some(format) => format,
+        None => bail!("Not an OpenPGP public key file."),
+    };
+    let certs = CertParser::from_bytes(data)?
+        .collect::<Result<Vec<Cert>>>()
+        .context(format!("Malformed {format} key file."))?;
+    if certs.is_empty() {
+        bail!("No public key found in {format} key file.");
+    }
+
+    Ok(certs)
+}
+
+/// Guess the format of a key file by its content, regardless of the extension
+fn sniff_key_format(data: &[u8]) -> Option<&'static str> {
+    let start = data.iter().position(|c| !c.is_ascii_whitespace())?;
+    if data[start..].starts_with(b"-----BEGIN PGP") {
+        Some("ASCII-armored")
+    } else if data[start] & 0x80 != 0 {
+        // Binary OpenPGP packets always have the highest bit of the tag set
+        Some("binary")
+    } else {
+        None
+    }
+}
+
+/// When the key in cert expires, considering the expiration of the primary key
+fn key_expiration(cert: &Cert, fingerprint: &Fingerprint, p: &dyn Policy) -> Option<SystemTime> {
+    let vc = cert.with_policy(p, None).ok()?;
+    let primary = vc.primary_key().key_expiration_time();
+    let key = vc
+        .keys()
+        .key_handle(fingerprint)
+        .next()
+        .and_then(|ka| ka.key_expiration_time());
+    primary.into_iter().chain(key).min()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn fixture(name: &str) -> Bytes {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures/keys")
+            .join(name);
+        Bytes::from(std::fs::read(path).unwrap())
+    }
+
+    #[test]
+    fn test_strip_signature() {
+        let signed = b"-----BEGIN PGP SIGNED MESSAGE-----\nHash: SHA512\n\nOrigin: Test\n- --dashed\nSuite: stable\n-----BEGIN PGP SIGNATURE-----\n\nnot a signature\n-----END PGP SIGNATURE-----\n";
+        assert_eq!(
+            strip_signature(signed).unwrap(),
+            "Origin: Test\n--dashed\nSuite: stable"
+        );
+        // Not signed at all
+        assert_eq!(
+            strip_signature(b"Origin: Test\n").unwrap(),
+            "Origin: Test\n"
+        );
+        assert!(strip_signature(b"-----BEGIN PGP SIGNED MESSAGE-----\n\nOrigin: Test\n").is_err());
+    }
+
+    #[test]
+    fn test_sniff_key_format() {
+        let keys = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/keys");
+        let read = |name: &str| std::fs::read(keys.join(name)).unwrap();
+        assert_eq!(sniff_key_format(&read("valid.asc")), Some("ASCII-armored"));
+        assert_eq!(sniff_key_format(&read("binary.gpg")), Some("binary"));
+        assert_eq!(
+            sniff_key_format(b"\n  -----BEGIN PGP PUBLIC KEY BLOCK-----"),
+            Some("ASCII-armored")
+        );
+        // Leading whitespace is skipped for binary keys too
+        let mut padded = b"\n  ".to_vec();
+        padded.extend(read("binary.gpg"));
+        assert_eq!(sniff_key_format(&padded), Some("binary"));
+        assert_eq!(sniff_key_format(b"\n  not a key"), None);
+        assert_eq!(sniff_key_format(b"not a key"), None);
+        assert_eq!(sniff_key_format(b""), None);
+    }
+
+    #[test]
+    fn test_key_formats() {
+        let keys = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/keys");
+        // One armored and one binary key in the same keyring
+        let both = vec!["valid.asc".to_string(), "binary.gpg".to_string()];
+        for msg in ["valid.InRelease", "binary.InRelease"] {
+            let (content, _) = verify_inrelease(&keys, &both, &fixture(msg), true).unwrap();
+            assert!(content.starts_with("Origin: Test"));
+        }
+        // Binary keyring with multiple keys, regardless of the extension
+        let keyring = vec!["keyring.gpg".to_string()];
+        for msg in ["valid.InRelease", "binary.InRelease"] {
+            assert!(verify_inrelease(&keys, &keyring, &fixture(msg), true).is_ok());
+        }
+        let armored = vec!["valid.asc".to_string()];
+        assert!(verify_inrelease(&keys, &armored, &fixture("binary.InRelease"), true).is_err());
+    }
+
+    #[test]
+    fn test_expired_key() {
+        let keys = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/keys");
+        let expired = vec!["expired.asc".to_string()];
+        let valid = vec!["valid.asc".to_string()];
+
+        // Signed in 2020-06 by a key that expired in 2020-12
+        let msg = fixture("expired.InRelease");
+        let (content, _) = verify_inrelease(&keys, &expired, &msg, false).unwrap();
+        assert!(content.starts_with("Origin: Test"));
+        let err = verify_inrelease(&keys, &expired, &msg, true).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("E1AC 7260 F037 D481 E7C0  2634 AE96 56F8 9845 F1FF"));
+        assert!(err.to_string().contains("2020-12-31"));
+
+        // Keys without expiration are fine
+        let msg = fixture("valid.InRelease");
+        assert!(verify_inrelease(&keys, &valid, &msg, true).is_ok());
+        // Signature by the wrong key
+        assert!(verify_inrelease(&keys, &valid, &fixture("expired.InRelease"), false).is_err());
+    }
+
+    #[test]
+    fn test_key_rotation() {
+        let keys = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/keys");
+        // Only the second key made the signature
+        let rotating = vec!["expired.asc".to_string(), "valid.asc".to_string()];
+        let (content, signers) =
+            verify_inrelease(&keys, &rotating, &fixture("valid.InRelease"), true).unwrap();
+        assert!(content.starts_with("Origin: Test"));
+        assert_eq!(signers, vec!["valid.asc"]);
+
+        let (_, signers) =
+            verify_inrelease(&keys, &rotating, &fixture("expired.InRelease"), false).unwrap();
+        assert_eq!(signers, vec!["expired.asc"]);
+    }
+}
@@ -0,0 +1,135 @@
+use anyhow::{format_err, Context, Result};
+use debcontrol::{BufParse, Streaming};
+use std::{collections::HashSet, fs, path::Path, process::Command};
+
+/// Parse the Conffiles field of dpkg status db
+// -> Vec<(Path, md5sum)>, obsolete config files are left out
+fn parse_conffiles(value: &str) -> Vec<(String, String)> {
+    let mut res = Vec::new();
+    for line in value.lines() {
+        let mut tokens: Vec<&str> = line.split_whitespace().collect();
+        if let Some(&flag) = tokens.last() {
+            if flag == "obsolete" || flag == "remove-on-upgrade" {
+                continue;
+            }
+        }
+        if tokens.len() < 2 {
+            continue;
+        }
+        let hash = tokens.pop().unwrap();
+        // dpkg uses a placeholder for config files that are not installed yet
+        if hash.len() != 32 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+            continue;
+        }
+        res.push((tokens.join(" "), hash.to_ascii_lowercase()));
+    }
+    res
+}
+
+/// md5sum of `path`, None if it can't be read
+fn md5sum(path: &Path) -> Option<String> {
+    let output = Command::new("md5sum").arg("--").arg(path).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let stdout = String::from_utf8(output.stdout).ok()?;
+    // A leading backslash means the file name is escaped
+    let hash = stdout.trim_start_matches('\\').get(..32)?;
+    Some(hash.to_string())
+}
+
+/// Find config files of packages in `names` that have been modified since they were installed
+///
+/// Config files that have been deleted are not considered modified.
+// -> Vec<(PkgName, Path)>
+pub fn modified_conffiles(
+    root: &Path,
+    admindir: Option<&Path>,
+    names: &HashSet<&str>,
+) -> Result<Vec<(String, String)>> {
+    let admindir = admindir.unwrap_or_else(|| Path::new(crate::DPKG_ADMINDIR));
+    let status_path = crate::utils::alt_root::join_root(root, admindir).join("status");
+    let status_file = fs::File::open(&status_path).context(format!(
+        "Failed to open dpkg status file {}.",
+        status_path.display()
+    ))?;
+
+    let mut res = Vec::new();
+    let mut buf_parse = BufParse::new(status_file, 16384);
+    while let Some(result) = buf_parse
+        .try_next()
+        .map_err(|e| format_err!("Malformed dpkg status database: {e}"))?
+    {
+        match result {
+            Streaming::Item(paragraph) => {
+                let name = match paragraph.fields.iter().find(|f| f.name == "Package") {
+                    Some(field) if names.contains(field.value.as_str()) => field.value.clone(),
+                    _ => continue,
+                };
+                let conffiles = match paragraph.fields.iter().find(|f| f.name == "Conffiles") {
+                    Some(field) => parse_conffiles(&field.value),
+                    None => continue,
+                };
+                for (path, hash) in conffiles {
+                    let full_path = crate::utils::alt_root::join_root(root, Path::new(&path));
+                    if !full_path.is_file() {
+                        continue;
+                    }
+                    match md5sum(&full_path) {
+                        Some(current) if current != hash => res.push((name.clone(), path)),
+                        _ => (),
+                    }
+                }
+            }
+            Streaming::Incomplete => buf_parse.buffer().unwrap(),
+        }
+    }
+    res.sort();
+    Ok(res)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::utils::temp_dir::TempDir;
+
+    #[test]
+    fn test_parse_conffiles() {
+        let value = "\n /etc/foo.conf 0123456789abcdef0123456789ABCDEF\n /etc/my dir/bar.conf fedcba9876543210fedcba9876543210\n /etc/old.conf 0123456789abcdef0123456789abcdef obsolete\n /etc/new.conf newconffile";
+        assert_eq!(
+            parse_conffiles(value),
+            vec![
+                (
+                    "/etc/foo.conf".to_string(),
+                    "0123456789abcdef0123456789abcdef".to_string()
+                ),
+                (
+                    "/etc/my dir/bar.conf".to_string(),
+                    "fedcba9876543210fedcba9876543210".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_modified_conffiles() {
+        let root = TempDir::new("conffiles");
+        fs::create_dir_all(root.join("var/lib/dpkg")).unwrap();
+        fs::create_dir_all(root.join("etc")).unwrap();
+        // md5sum of "hello\n"
+        let hash = "b1946ac92492d2347c6235b4d2611184";
+        fs::write(
+            root.join("var/lib/dpkg/status"),
+            format!(
+                "Package: a\nStatus: install ok installed\nVersion: 1.0\nConffiles:\n /etc/a.conf {hash}\n /etc/b.conf {hash}\n /etc/gone.conf {hash}\n\nPackage: c\nStatus: install ok installed\nVersion: 1.0\nConffiles:\n /etc/c.conf {hash}\n"
+            ),
+        )
+        .unwrap();
+        fs::write(root.join("etc/a.conf"), "hello\n").unwrap();
+        fs::write(root.join("etc/b.conf"), "modified\n").unwrap();
+        fs::write(root.join("etc/c.conf"), "modified\n").unwrap();
+
+        let res = modified_conffiles(&root, None, &HashSet::from(["a"])).unwrap();
+        assert_eq!(res, vec![("a".to_string(), "/etc/b.conf".to_string())]);
+    }
+}
@@ -1,66 +1,109 @@
+use super::{journal::Journal, MachineStatus, PkgStatus};
 use crate::{
-    info,
-    types::{PkgActions, PkgSource},
-    utils::downloader::{Compression, DownloadJob, Downloader},
+    cli::{gen_prefix, log::Level},
+    debug, info,
+    types::{config::DpkgOrder, PkgActions, PkgMeta, PkgSource},
+    utils::{
+        alt_root::join_root,
+        downloader::{Compression, DownloadJob, Downloader},
+    },
 };
 
-use anyhow::{bail, Context, Result};
-use std::{path::Path, process::Command, sync::atomic::Ordering};
+use anyhow::{bail, format_err, Context, Result};
+use console::style;
+use std::{
+    collections::{HashMap, HashSet},
+    ffi::OsString,
+    io::{Read, Write},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::{atomic::Ordering, Arc, Mutex},
+    time::Instant,
+};
+use thiserror::Error;
 
-pub async fn execute_pkg_actions(
+/// Run dpkg to apply `actions`, with packages fetched by `fetch_pkgs`
+pub fn execute_pkg_actions(
     actions: PkgActions<'_>,
+    download_res: &HashMap<String, PathBuf>,
     root: &Path,
-    downloader: &Downloader,
-    unsafe_io: bool,
+    admindir: Option<&Path>,
+    force: &[&str],
+    order: DpkgOrder,
 ) -> Result<()> {
-    // Download packages
-    let download_jobs = get_download_jobs(&actions);
-    info!("Fetching requested packages...");
-    let download_res = downloader
-        .fetch(download_jobs, &root.join(crate::PKG_CACHE_PATH), true)
-        .await
-        .context("Failed to fetch requested packages from repository.")?;
+    let installed = MachineStatus::new(root, admindir)?.pkgs;
+    let steps = pkg_action_steps(actions, download_res, &installed, order)?;
+    let journal = Journal::create(root, steps)?;
+    resume_pkg_actions(journal, root, admindir, force)
+}
 
-    let mut install_deb_paths: Vec<String> = actions
+/// Arguments of each dpkg run needed to apply `actions` to a system with `installed`
+/// packages, in order
+pub(super) fn pkg_action_steps(
+    actions: PkgActions<'_>,
+    download_res: &HashMap<String, PathBuf>,
+    installed: &HashMap<String, PkgStatus>,
+    order: DpkgOrder,
+) -> Result<Vec<Vec<String>>> {
+    let to_install: Vec<&PkgMeta> = actions
         .install
         .iter()
-        .map(|(install, _)| match &install.source {
-            PkgSource::Http((url, _, _)) => download_res.get(url).unwrap(),
-            PkgSource::Local(p) => p,
-        })
-        .map(|p| p.to_str().unwrap().to_owned())
+        .map(|(install, _)| *install)
+        .chain(actions.downgrade.iter().map(|(downgrade, _)| *downgrade))
         .collect();
-
-    let mut unpack_deb_paths: Vec<String> = actions
-        .unpack
+    // Essential packages replacing installed versions
+    let essential: Vec<bool> = actions
+        .install
         .iter()
-        .map(|(unpack, _)| match &unpack.source {
-            PkgSource::Http((url, _, _)) => download_res.get(url).unwrap(),
-            PkgSource::Local(p) => p,
-        })
-        .map(|p| p.to_str().unwrap().to_owned())
+        .map(|(install, old)| install.essential && old.is_some())
+        .chain(
+            actions
+                .downgrade
+                .iter()
+                .map(|(downgrade, _)| downgrade.essential),
+        )
         .collect();
+    // Install packages in stages, so that pre-dependencies and essential packages are configured first
+    let stages = install_stages(&to_install, &essential);
+    let mut install_stages: Vec<Vec<String>> =
+        vec![Vec::new(); stages.iter().max().map_or(0, |s| s + 1)];
+    for (install, stage) in to_install.iter().zip(stages) {
+        let path = deb_path(install, download_res)?;
+        install_stages[stage].push(path);
+    }
 
-    info!("Processing package changes...");
-    // Purge stuff
-    if !actions.purge.is_empty() {
-        let mut cmd = vec!["--purge".to_string()];
-        let mut pkgnames: Vec<String> =
-            actions.purge.into_iter().map(|(name, _, _)| name).collect();
-        cmd.append(&mut pkgnames);
-        dpkg_run(&cmd, root, unsafe_io).context("Failed to purge package configuration(s).")?;
+    let mut unpack_deb_paths = Vec::new();
+    for (unpack, _) in &actions.unpack {
+        unpack_deb_paths.push(deb_path(unpack, download_res)?);
     }
-    // Remove stuff
-    if !actions.remove.is_empty() {
-        let mut cmd = vec!["--remove".to_string()];
-        let mut pkgnames: Vec<String> = actions
+
+    // Figure out which removals have to happen before new packages are in place
+    let incoming: Vec<&PkgMeta> = actions
+        .install
+        .iter()
+        .map(|(install, _)| *install)
+        .chain(actions.downgrade.iter().map(|(downgrade, _)| *downgrade))
+        .chain(actions.unpack.iter().map(|(unpack, _)| *unpack))
+        .collect();
+    let (purge_early, purge_late) = split_removals(
+        actions.purge.into_iter().map(|(name, _, _)| name).collect(),
+        &incoming,
+        installed,
+        order,
+    );
+    let (remove_early, remove_late) = split_removals(
+        actions
             .remove
             .into_iter()
             .map(|(name, _, _)| name)
-            .collect();
-        cmd.append(&mut pkgnames);
-        dpkg_run(&cmd, root, unsafe_io).context("Failed to remove package(s).")?;
-    }
+            .collect(),
+        &incoming,
+        installed,
+        order,
+    );
+
+    // Purge and remove stuff that is in the way
+    let mut steps = removal_steps(purge_early, remove_early);
     // Configure stuff
     if !actions.configure.is_empty() {
         let mut cmd = vec!["--configure".to_string()];
@@ -70,40 +113,352 @@ pub async fn execute_pkg_actions(
             .map(|(name, _)| name)
             .collect();
         cmd.append(&mut pkgnames);
-        dpkg_run(&cmd, root, unsafe_io).context("Failed to configure package(s).")?;
+        steps.push(cmd);
     }
     // Install stuff
-    if !install_deb_paths.is_empty() {
+    for mut install_deb_paths in install_stages {
         let mut cmd = vec!["--install".to_string()];
         cmd.append(&mut install_deb_paths);
-        dpkg_run(&cmd, root, unsafe_io).context("Failed to install package(s).")?;
+        steps.push(cmd);
     }
     // Unpack stuff
     if !unpack_deb_paths.is_empty() {
         let mut cmd = vec!["--unpack".to_string()];
         cmd.append(&mut unpack_deb_paths);
-        dpkg_run(&cmd, root, unsafe_io).context("Failed to unpack package(s).")?;
+        steps.push(cmd);
+    }
+    // Purge and remove the rest, now that new packages are in place
+    steps.append(&mut removal_steps(purge_late, remove_late));
+
+    Ok(steps)
+}
+
+/// Run the remaining steps in `journal`, and remove it once all of them are done
+pub fn resume_pkg_actions(
+    mut journal: Journal,
+    root: &Path,
+    admindir: Option<&Path>,
+    force: &[&str],
+) -> Result<()> {
+    info!("Processing package changes...");
+    let start = Instant::now();
+    while let Some(step) = journal.remaining().first().cloned() {
+        let context = match step.first().map(|op| op.as_str()) {
+            Some("--purge") => "Failed to purge package configuration(s).",
+            Some("--remove") => "Failed to remove package(s).",
+            Some("--configure") => "Failed to configure package(s).",
+            Some("--unpack") => "Failed to unpack package(s).",
+            _ => "Failed to install package(s).",
+        };
+        dpkg_run(&step, root, admindir, force).context(context)?;
+        journal.complete_step()?;
     }
+    journal.finish()?;
+    crate::TIMINGS.record("dpkg execution", start);
 
     Ok(())
 }
 
-fn dpkg_run<T: AsRef<std::ffi::OsStr>>(args: &[T], root: &Path, unsafe_io: bool) -> Result<()> {
-    let mut cmd = Command::new("dpkg");
-    if unsafe_io {
-        cmd.arg("--force-unsafe-io");
+/// Find the local path of the deb file of `pkg`
+fn deb_path(pkg: &PkgMeta, download_res: &HashMap<String, PathBuf>) -> Result<String> {
+    let path = match &pkg.source {
+        PkgSource::Http((url, _, _)) => download_res.get(url).ok_or_else(|| {
+            format_err!(
+                "Package {} has not been downloaded.",
+                style(&pkg.name).bold()
+            )
+        })?,
+        PkgSource::Local(p) => p,
+        PkgSource::Installed => bail!(
+            "Package {} is only known as the installed version.",
+            style(&pkg.name).bold()
+        ),
+    };
+    Ok(path.to_str().unwrap().to_owned())
+}
+
+/// Download packages needed by `actions` into `cache`, returns a map of URL -> local path
+///
+/// If `cache_only` is set, packages are taken from `cache` instead. If `skip_failed`
+/// is set, packages that fail to download are left out of the result, see `missing_pkgs`.
+pub async fn fetch_pkgs(
+    actions: &PkgActions<'_>,
+    cache: &Path,
+    downloader: &Downloader,
+    cache_only: bool,
+    skip_failed: bool,
+) -> Result<HashMap<String, PathBuf>> {
+    if cache_only {
+        return cached_pkgs(actions, cache, skip_failed);
     }
-    // Add root position
-    cmd.arg("--root");
-    cmd.arg(root.as_os_str());
-    // Ignore dependency/break checks and essential. These will be guaranteed by Omakase
-    cmd.args(&[
-        "--force-downgrade",
-        "--force-breaks",
-        "--force-conflicts",
-        "--force-depends",
-        "--force-remove-essential",
-    ]);
+    let download_jobs = get_download_jobs(actions);
+    info!("Fetching requested packages...");
+    let start = Instant::now();
+    let download_res = if skip_failed {
+        downloader.fetch_available(download_jobs, cache, true).await
+    } else {
+        downloader.fetch(download_jobs, cache, true).await
+    }
+    .context("Failed to fetch requested packages from repository.")?;
+    crate::TIMINGS.record("Package download", start);
+
+    Ok(download_res)
+}
+
+/// Find packages needed by `actions` in `cache`, without touching the network
+///
+/// Every package must be present and match its checksum, otherwise the missing ones are listed.
+/// If `skip_failed` is set, missing packages are left out of the result instead.
+fn cached_pkgs(
+    actions: &PkgActions<'_>,
+    cache: &Path,
+    skip_failed: bool,
+) -> Result<HashMap<String, PathBuf>> {
+    // URL -> filename in cache, the same way they are stored by fetch_pkgs
+    let filenames: HashMap<String, String> = get_download_jobs(actions)
+        .into_iter()
+        .filter_map(|job| {
+            let filename = job
+                .filename
+                .or_else(|| url_filename(&job.url).map(|s| s.to_owned()))?;
+            Some((job.url, filename))
+        })
+        .collect();
+
+    info!("Looking for requested packages in local cache...");
+    let pkgs = actions
+        .install
+        .iter()
+        .map(|(pkg, _)| *pkg)
+        .chain(actions.downgrade.iter().map(|(pkg, _)| *pkg))
+        .chain(actions.unpack.iter().map(|(pkg, _)| *pkg));
+    let mut res = HashMap::new();
+    let mut missing = Vec::new();
+    for pkg in pkgs {
+        if let PkgSource::Http((url, _, checksum)) = &pkg.source {
+            if res.contains_key(url) || missing.contains(&pkg.name) {
+                continue;
+            }
+            match filenames.get(url).map(|filename| cache.join(filename)) {
+                Some(path) if checksum.cmp_file(&path).unwrap_or(false) => {
+                    res.insert(url.clone(), path);
+                }
+                _ => missing.push(pkg.name.clone()),
+            }
+        }
+    }
+    if !missing.is_empty() && !skip_failed {
+        crate::WRITER.write_chunks("", &missing)?;
+        bail!(
+            "{} package(s) are missing from {} or failed validation.",
+            style(missing.len()).bold(),
+            style(cache.display()).bold()
+        );
+    }
+
+    Ok(res)
+}
+
+/// Names of packages needed by `actions` that are not in `download_res`
+pub fn missing_pkgs(
+    actions: &PkgActions<'_>,
+    download_res: &HashMap<String, PathBuf>,
+) -> HashSet<String> {
+    actions
+        .install
+        .iter()
+        .map(|(pkg, _)| *pkg)
+        .chain(actions.downgrade.iter().map(|(pkg, _)| *pkg))
+        .chain(actions.unpack.iter().map(|(pkg, _)| *pkg))
+        .filter(|pkg| match &pkg.source {
+            PkgSource::Http((url, _, _)) => !download_res.contains_key(url),
+            PkgSource::Local(_) | PkgSource::Installed => false,
+        })
+        .map(|pkg| pkg.name.clone())
+        .collect()
+}
+
+/// Validate all packages needed by `actions`, as fetched by `fetch_pkgs`, without running dpkg
+///
+/// Returns the number of packages validated.
+pub fn verify_pkg_actions(
+    actions: &PkgActions<'_>,
+    download_res: &HashMap<String, PathBuf>,
+) -> Result<usize> {
+    info!("Validating fetched packages...");
+    let pkgs = actions
+        .install
+        .iter()
+        .map(|(pkg, _)| *pkg)
+        .chain(actions.downgrade.iter().map(|(pkg, _)| *pkg))
+        .chain(actions.unpack.iter().map(|(pkg, _)| *pkg));
+    let mut count = 0;
+    let mut failed = Vec::new();
+    for pkg in pkgs {
+        let valid = match &pkg.source {
+            PkgSource::Http((url, _, checksum)) => match download_res.get(url) {
+                Some(path) => checksum.cmp_file(path).unwrap_or(false),
+                None => false,
+            },
+            PkgSource::Local(path) => path.is_file(),
+            PkgSource::Installed => false,
+        };
+        if valid {
+            count += 1;
+        } else {
+            failed.push(pkg.name.clone());
+        }
+    }
+    if !failed.is_empty() {
+        crate::WRITER.write_chunks("", &failed)?;
+        bail!(
+            "{} package(s) failed validation.",
+            style(failed.len()).bold()
+        );
+    }
+
+    Ok(count)
+}
+
+/// Assign an installation stage to each package
+///
+/// A package is installed in a later stage than all of its pre-dependencies, so they are
+/// unpacked and configured by dpkg before the package itself is unpacked.
+fn pre_depends_stages(pkgs: &[&PkgMeta]) -> Vec<usize> {
+    let mut stages = vec![0; pkgs.len()];
+    // A stage can be no larger than the number of packages, this also stops on circular pre-dependencies
+    for _ in 0..pkgs.len() {
+        let mut changed = false;
+        for (i, pkg) in pkgs.iter().enumerate() {
+            for (pre_dep, _) in &pkg.pre_depends {
+                for (j, target) in pkgs.iter().enumerate() {
+                    if i != j && &target.name == pre_dep && stages[i] <= stages[j] {
+                        stages[i] = stages[j] + 1;
+                        changed = true;
+                    }
+                }
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+
+    stages
+}
+
+/// Assign an installation stage to each package, with upgrades of essential packages first
+///
+/// Packages with `essential[i]` set, and whatever they pre-depend on, are installed before all
+/// other packages, so that the rest of the transaction runs with essential packages already
+/// upgraded and configured. Within each group, stages follow `pre_depends_stages`.
+fn install_stages(pkgs: &[&PkgMeta], essential: &[bool]) -> Vec<usize> {
+    let mut early = essential.to_vec();
+    loop {
+        let mut changed = false;
+        for i in 0..pkgs.len() {
+            if !early[i] {
+                continue;
+            }
+            for (pre_dep, _) in &pkgs[i].pre_depends {
+                for (j, target) in pkgs.iter().enumerate() {
+                    if !early[j] && &target.name == pre_dep {
+                        early[j] = true;
+                        changed = true;
+                    }
+                }
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+
+    let (early_idx, late_idx): (Vec<usize>, Vec<usize>) = (0..pkgs.len()).partition(|&i| early[i]);
+    let group = |idx: &[usize]| -> Vec<usize> {
+        let group: Vec<&PkgMeta> = idx.iter().map(|&i| pkgs[i]).collect();
+        pre_depends_stages(&group)
+    };
+    let mut stages = vec![0; pkgs.len()];
+    let early_stages = group(&early_idx);
+    let offset = early_stages.iter().max().map_or(0, |s| s + 1);
+    for (i, stage) in early_idx.into_iter().zip(early_stages) {
+        stages[i] = stage;
+    }
+    for (i, stage) in late_idx.iter().zip(group(&late_idx)) {
+        stages[*i] = stage + offset;
+    }
+
+    stages
+}
+
+/// dpkg steps to purge and remove packages
+fn removal_steps(purge: Vec<String>, remove: Vec<String>) -> Vec<Vec<String>> {
+    let mut steps = Vec::new();
+    if !purge.is_empty() {
+        let mut cmd = vec!["--purge".to_string()];
+        cmd.extend(purge);
+        steps.push(cmd);
+    }
+    if !remove.is_empty() {
+        let mut cmd = vec!["--remove".to_string()];
+        cmd.extend(remove);
+        steps.push(cmd);
+    }
+    steps
+}
+
+/// Split packages to remove into (early, late) batches
+///
+/// With [`DpkgOrder::InstallFirst`], a package is only removed before new packages are
+/// unpacked if it conflicts with a new package, in either direction, and the new package doesn't
+/// declare `Replaces` on it. Otherwise, dpkg will take over files from the old package when
+/// unpacking the new one, so the system is never left without them.
+fn split_removals(
+    names: Vec<String>,
+    incoming: &[&PkgMeta],
+    installed: &HashMap<String, PkgStatus>,
+    order: DpkgOrder,
+) -> (Vec<String>, Vec<String>) {
+    if order == DpkgOrder::RemoveFirst {
+        return (names, Vec::new());
+    }
+
+    names.into_iter().partition(|name| {
+        // Conflicts declared by the package going away, on names the new package has
+        let outgoing: &[String] = installed.get(name).map_or(&[], |pkg| &pkg.conflicts);
+        incoming.iter().any(|pkg| {
+            let conflicts = pkg
+                .conflicts
+                .iter()
+                .chain(pkg.breaks.iter())
+                .any(|(conflict, _)| conflict == name);
+            let conflicted = outgoing.iter().any(|conflict| {
+                conflict == &pkg.name
+                    || pkg
+                        .provides
+                        .iter()
+                        .flatten()
+                        .any(|(provide, _)| provide == conflict)
+            });
+            let replaces = pkg
+                .replaces
+                .iter()
+                .flatten()
+                .any(|(replace, _)| replace == name);
+            (conflicts || conflicted) && !replaces
+        })
+    })
+}
+
+fn dpkg_run<T: AsRef<std::ffi::OsStr>>(
+    args: &[T],
+    root: &Path,
+    admindir: Option<&Path>,
+    force: &[&str],
+) -> Result<()> {
+    let mut cmd = Command::new("dpkg");
+    cmd.args(dpkg_global_args(root, admindir, force));
     // If no stuff is specified, success automatically
     if args.len() <= 1 {
         return Ok(());
@@ -111,43 +466,206 @@ fn dpkg_run<T: AsRef<std::ffi::OsStr>>(args: &[T], root: &Path, unsafe_io: bool)
     // Add rest of the arguments
     cmd.args(args);
 
+    // Capture output, so that it can be shown consistently with our own messages
+    // stdin is still inherited, so interactive prompts still work
+    cmd.stdout(Stdio::piped());
+    cmd.stderr(Stdio::piped());
+
     // Tell the signal handler we are going to run dpkg
     crate::DPKG_RUNNING.store(true, Ordering::Relaxed);
+    debug!("Running {:?} ...", cmd);
     // Run it!
-    let res = cmd.status().context("Failed to execute dpkg command(s).")?;
+    let mut child = match cmd.spawn() {
+        Ok(child) => child,
+        Err(e) => {
+            crate::DPKG_RUNNING.store(false, Ordering::Relaxed);
+            return Err(e).context("Failed to execute dpkg command(s).");
+        }
+    };
+    let lock = Arc::new(Mutex::new(()));
+    let stdout_handle = child.stdout.take().map(|pipe| {
+        let lock = lock.clone();
+        std::thread::spawn(move || forward_output(pipe, &lock))
+    });
+    // Find out which packages failed from stderr
+    // Errors reading the output are only returned after dpkg exits, so that it's always waited for
+    let stderr = match child.stderr.take() {
+        Some(pipe) => forward_output(pipe, &lock),
+        None => Ok(Vec::new()),
+    };
+    let stdout = match stdout_handle {
+        Some(handle) => handle
+            .join()
+            .map_err(|_| format_err!("Failed to read output of dpkg."))
+            .and_then(|res| res),
+        None => Ok(Vec::new()),
+    };
+    let res = child.wait();
+    // We are done with dpkg
+    crate::DPKG_RUNNING.store(false, Ordering::Relaxed);
+    let res = res.context("Failed to execute dpkg command(s).")?;
+    let stderr = stderr?;
+    stdout?;
     if !res.success() {
         match res.code() {
-            Some(code) => bail!("dpkg exited with non-zero return code: {}.", code),
+            Some(code) => {
+                return Err(DpkgError {
+                    code,
+                    failed: parse_failed_pkgs(&stderr),
+                }
+                .into())
+            }
             None => bail!("dpkg process was terminated by signal."),
         }
     }
 
-    // We are done with dpkg
-    crate::DPKG_RUNNING.store(false, Ordering::Relaxed);
     Ok(())
 }
 
-fn get_download_jobs(actions: &PkgActions) -> Vec<DownloadJob> {
-    let mut res = Vec::new();
-    for i in &actions.install {
-        if let PkgSource::Http((url, size, checksum)) = &i.0.source {
-            let job = DownloadJob {
-                url: url.clone(),
-                description: None,
-                filename: None,
-                size: Some(*size),
-                compression: Compression::None(Some(checksum.clone())),
-            };
-            res.push(job);
+/// Arguments passed to every dpkg run, before the action
+pub(super) fn dpkg_global_args(
+    root: &Path,
+    admindir: Option<&Path>,
+    force: &[&str],
+) -> Vec<OsString> {
+    // Add root position
+    let mut args = vec![OsString::from("--root"), root.into()];
+    // Must come after --root, which also sets the admin directory
+    if let Some(admindir) = admindir {
+        args.push("--admindir".into());
+        args.push(join_root(root, admindir).into());
+    }
+    // Checks overridden by config, see `DpkgForce`
+    args.extend(force.iter().map(OsString::from));
+    args
+}
+
+/// Re-emit output of dpkg line by line with a prefix. Returns all lines.
+fn forward_output(mut pipe: impl Read, lock: &Mutex<()>) -> Result<Vec<String>> {
+    let prefix = style("dpkg:").dim().to_string();
+    let mut lines = Vec::new();
+    let mut buf = [0; 4096];
+    let mut pending: Vec<u8> = Vec::new();
+    // Bytes of the pending line that have been shown already
+    let mut shown = 0;
+    loop {
+        let len = pipe.read(&mut buf)?;
+        if len == 0 {
+            break;
+        }
+        pending.extend_from_slice(&buf[..len]);
+        while let Some(pos) = pending.iter().position(|c| *c == b'\n') {
+            let line: Vec<u8> = pending.drain(..=pos).collect();
+            let line = &line[..pos];
+            // A line that was shown in part is always finished
+            if shown > 0 {
+                let _guard = lock.lock().unwrap();
+                let mut writer = crate::WRITER.get_writer();
+                writer.write_all(&line[shown..])?;
+                writer.write_all(b"\n")?;
+            } else if !crate::quiet() {
+                let _guard = lock.lock().unwrap();
+                crate::WRITER.writeln(&prefix, &String::from_utf8_lossy(line))?;
+            }
+            shown = 0;
+            let line = String::from_utf8_lossy(line).to_string();
+            crate::LOG.write(Level::Info, &format!("dpkg: {line}"));
+            lines.push(line);
         }
+        // Show incomplete line right away, it may be a prompt waiting for user input. Also with
+        // --quiet, as dpkg waits until it's answered.
+        if pending.len() > shown {
+            let _guard = lock.lock().unwrap();
+            let mut writer = crate::WRITER.get_writer();
+            if shown == 0 {
+                writer.write_all(gen_prefix(&prefix).as_bytes())?;
+            }
+            writer.write_all(&pending[shown..])?;
+            writer.flush()?;
+            shown = pending.len();
+        }
+    }
+    if !pending.is_empty() {
+        // Shown already, as an incomplete line
+        crate::WRITER.get_writer().write_all(b"\n")?;
+        let line = String::from_utf8_lossy(&pending).to_string();
+        crate::LOG.write(Level::Info, &format!("dpkg: {line}"));
+        lines.push(line);
+    }
+
+    Ok(lines)
+}
+
+/// dpkg exited with a non-zero return code. Some packages may have been processed successfully.
+#[derive(Debug, Error)]
+#[error("dpkg exited with non-zero return code: {code}.")]
+pub struct DpkgError {
+    pub code: i32,
+    /// Packages that dpkg failed to process
+    pub failed: Vec<String>,
+}
+
+/// Find failed packages from the summary at the end of dpkg's stderr
+fn parse_failed_pkgs(stderr: &[String]) -> Vec<String> {
+    let mut res = Vec::new();
+    let mut lines = stderr
+        .iter()
+        .skip_while(|line| !line.starts_with("Errors were encountered while processing:"))
+        .skip(1);
+    while let Some(line) = lines.next().and_then(|l| l.strip_prefix(' ')) {
+        let line = line.trim();
+        // dpkg reports deb paths when installing, like /path/to/name_version_arch.deb
+        let name = match line.strip_suffix(".deb") {
+            Some(path) => {
+                let filename = path.rsplit('/').next().unwrap_or(path);
+                filename.split('_').next().unwrap_or(filename)
+            }
+            None => line,
+        };
+        res.push(name.to_string());
     }
 
-    for i in &actions.unpack {
-        if let PkgSource::Http((url, size, checksum)) = &i.0.source {
+    res
+}
+
+pub(super) fn get_download_jobs(actions: &PkgActions) -> Vec<DownloadJob> {
+    let pkgs = actions
+        .install
+        .iter()
+        .map(|(pkg, _)| *pkg)
+        .chain(actions.downgrade.iter().map(|(pkg, _)| *pkg))
+        .chain(actions.unpack.iter().map(|(pkg, _)| *pkg));
+
+    let mut res = Vec::new();
+    // URL -> filename in cache
+    let mut urls: HashMap<&str, String> = HashMap::new();
+    for pkg in pkgs {
+        if let PkgSource::Http((url, size, checksum)) = &pkg.source {
+            // Same package pulled in more than once, only fetch it once
+            if urls.contains_key(url.as_str()) {
+                continue;
+            }
+            // Different packages may have the same filename, e.g. from different repositories
+            // Store them under different names, so that they don't overwrite each other in cache
+            let mut filename = None;
+            if let Some(name) = url_filename(url) {
+                if urls.values().any(|used| used == name) {
+                    filename = Some(url_unique_filename(url));
+                }
+            }
+            urls.insert(
+                url,
+                filename
+                    .clone()
+                    .or_else(|| url_filename(url).map(|s| s.to_owned()))
+                    .unwrap_or_default(),
+            );
             let job = DownloadJob {
                 url: url.clone(),
+                mirrors: pkg.mirror_urls(),
+                repos: pkg.repos.clone(),
                 description: None,
-                filename: None,
+                filename,
                 size: Some(*size),
                 compression: Compression::None(Some(checksum.clone())),
             };
@@ -156,3 +674,204 @@ fn get_download_jobs(actions: &PkgActions) -> Vec<DownloadJob> {
     }
     res
 }
+
+#[inline]
+pub(super) fn url_filename(url: &str) -> Option<&str> {
+    url.rsplit('/').next().filter(|name| !name.is_empty())
+}
+
+/// Generate a filename for `url` that includes its full path
+fn url_unique_filename(url: &str) -> String {
+    let path = url.split_once("://").map_or(url, |(_, rest)| rest);
+    path.replace('/', "_")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::types::{test_pkg, Checksum, VersionRequirement};
+    use crate::utils::temp_dir::TempDir;
+
+    #[test]
+    fn test_pre_depends_stages() {
+        // c Pre-Depends on b, b Pre-Depends on a, d depends on nothing
+        let a = test_pkg("a", "1.0");
+        let mut b = test_pkg("b", "1.0");
+        b.pre_depends = vec![("a".to_string(), VersionRequirement::default())];
+        let mut c = test_pkg("c", "1.0");
+        c.pre_depends = vec![("b".to_string(), VersionRequirement::default())];
+        let d = test_pkg("d", "1.0");
+        assert_eq!(pre_depends_stages(&[&c, &d, &b, &a]), vec![2, 0, 1, 0]);
+
+        // Circular pre-dependencies still terminate
+        let mut x = test_pkg("x", "1.0");
+        x.pre_depends = vec![("y".to_string(), VersionRequirement::default())];
+        let mut y = test_pkg("y", "1.0");
+        y.pre_depends = vec![("x".to_string(), VersionRequirement::default())];
+        assert_eq!(pre_depends_stages(&[&x, &y]).len(), 2);
+    }
+
+    #[test]
+    fn test_essential_stages() {
+        // libc6 is essential and pre-depends on libgcc, bash depends on libc6
+        let libgcc = test_pkg("libgcc", "13");
+        let mut libc = test_pkg("libc6", "2.38");
+        libc.essential = true;
+        libc.pre_depends = vec![("libgcc".to_string(), VersionRequirement::default())];
+        let mut bash = test_pkg("bash", "5.2");
+        bash.depends = vec![("libc6".to_string(), VersionRequirement::default())];
+        let mut vim = test_pkg("vim", "9.0");
+        vim.pre_depends = vec![("bash".to_string(), VersionRequirement::default())];
+        let pkgs = [&bash, &vim, &libc, &libgcc];
+        assert_eq!(
+            install_stages(&pkgs, &[false, false, true, false]),
+            vec![2, 3, 1, 0]
+        );
+        // Nothing changes without essential upgrades
+        assert_eq!(
+            install_stages(&pkgs, &[false; 4]),
+            pre_depends_stages(&pkgs)
+        );
+    }
+
+    #[test]
+    fn test_download_jobs_dedup() {
+        let http = |name: &str, url: &str| {
+            let mut pkg = test_pkg(name, "1.0");
+            pkg.source = PkgSource::Http((
+                url.to_string(),
+                0,
+                Checksum::from_sha256_str(&"0".repeat(64)).unwrap(),
+            ));
+            pkg
+        };
+        let a = http(
+            "a",
+            "https://repo.example.org/debs/pool/main/a/a_1.0_amd64.deb",
+        );
+        let a_again = http(
+            "a",
+            "https://repo.example.org/debs/pool/main/a/a_1.0_amd64.deb",
+        );
+        let a_other = http(
+            "a",
+            "https://other.example.org/debs/pool/main/a/a_1.0_amd64.deb",
+        );
+        let b = http(
+            "b",
+            "https://repo.example.org/debs/pool/main/b/b_1.0_amd64.deb",
+        );
+        let mut actions = PkgActions::default();
+        actions.install.push((&a, None));
+        actions.install.push((&b, None));
+        actions.unpack.push((&a_again, None));
+        let jobs = get_download_jobs(&actions);
+        assert_eq!(jobs.len(), 2);
+        assert!(jobs.iter().all(|job| job.filename.is_none()));
+
+        // Same filename, but different URL
+        actions.unpack.push((&a_other, None));
+        let jobs = get_download_jobs(&actions);
+        assert_eq!(jobs.len(), 3);
+        assert_eq!(
+            jobs[2].filename.as_deref(),
+            Some("other.example.org_debs_pool_main_a_a_1.0_amd64.deb")
+        );
+    }
+
+    #[test]
+    fn test_cached_pkgs() {
+        let cache = TempDir::new("pkg-cache");
+        std::fs::write(cache.join("a_1.0_amd64.deb"), "hello").unwrap();
+        std::fs::write(cache.join("b_1.0_amd64.deb"), "corrupted").unwrap();
+        // sha256 of "hello"
+        let checksum = Checksum::from_sha256_str(
+            "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824",
+        )
+        .unwrap();
+        let http = |name: &str| {
+            let mut pkg = test_pkg(name, "1.0");
+            pkg.source = PkgSource::Http((
+                format!("https://repo.example.org/debs/pool/main/{name}/{name}_1.0_amd64.deb"),
+                5,
+                checksum.clone(),
+            ));
+            pkg
+        };
+        let (a, b, c) = (http("a"), http("b"), http("c"));
+
+        let mut actions = PkgActions::default();
+        actions.install.push((&a, None));
+        let res = cached_pkgs(&actions, &cache, false).unwrap();
+        assert_eq!(
+            res.get("https://repo.example.org/debs/pool/main/a/a_1.0_amd64.deb"),
+            Some(&cache.join("a_1.0_amd64.deb"))
+        );
+
+        // Checksum mismatch and missing file
+        actions.install.push((&b, None));
+        actions.unpack.push((&c, None));
+        assert!(cached_pkgs(&actions, &cache, false).is_err());
+        assert_eq!(cached_pkgs(&actions, &cache, true).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_split_removals() {
+        // new-pkg takes over files from old-pkg
+        let mut new_pkg = test_pkg("new-pkg", "2.0");
+        new_pkg.conflicts = vec![("old-pkg".to_string(), VersionRequirement::default())];
+        new_pkg.replaces = Some(vec![("old-pkg".to_string(), VersionRequirement::default())]);
+        // other-pkg cannot be installed alongside foo
+        let mut other_pkg = test_pkg("other-pkg", "1.0");
+        other_pkg.breaks = vec![("foo".to_string(), VersionRequirement::default())];
+        // The installed baz conflicts with what other-pkg provides
+        other_pkg.provides = Some(vec![("virtual".to_string(), VersionRequirement::default())]);
+        let incoming = vec![&new_pkg, &other_pkg];
+        let mut baz = PkgStatus::try_from(HashMap::from([
+            ("Package", "baz".to_string()),
+            ("Status", "install ok installed".to_string()),
+            ("Version", "1.0".to_string()),
+            ("Installed-Size", "0".to_string()),
+            ("Conflicts", "virtual".to_string()),
+        ]))
+        .unwrap();
+        let installed = HashMap::from([("baz".to_string(), baz.clone())]);
+        let names = vec![
+            "old-pkg".to_string(),
+            "foo".to_string(),
+            "bar".to_string(),
+            "baz".to_string(),
+        ];
+
+        let split = |installed| {
+            split_removals(names.clone(), &incoming, installed, DpkgOrder::InstallFirst)
+        };
+        let (early, late) = split(&installed);
+        assert_eq!(early, vec!["foo", "baz"]);
+        assert_eq!(late, vec!["old-pkg", "bar"]);
+        baz.conflicts.clear();
+        let (early, _) = split(&HashMap::from([("baz".to_string(), baz)]));
+        assert_eq!(early, vec!["foo"]);
+
+        let (early, late) =
+            split_removals(names.clone(), &incoming, &installed, DpkgOrder::RemoveFirst);
+        assert_eq!(early, names);
+        assert!(late.is_empty());
+    }
+
+    #[test]
+    fn test_parse_failed_pkgs() {
+        let stderr: Vec<String> = [
+            "dpkg: error processing package foo (--configure):",
+            " installed foo package post-installation script subprocess returned error exit status 1",
+            "Errors were encountered while processing:",
+            " foo",
+            " /var/cache/omakase/pkgs/bar_1.0-1_amd64.deb",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+        assert_eq!(parse_failed_pkgs(&stderr), vec!["foo", "bar"]);
+        assert!(parse_failed_pkgs(&[]).is_empty());
+    }
+}
@@ -0,0 +1,114 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// dpkg operations of a transaction being applied, so that it can be resumed if interrupted
+///
+/// The journal is written before running dpkg, updated after each step and removed once the
+/// transaction is done. If it's still there on the next run, the transaction was interrupted.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct Journal {
+    #[serde(skip)]
+    path: PathBuf,
+    /// How many steps are done
+    pub completed: usize,
+    /// Arguments of dpkg for each step, in order
+    pub steps: Vec<Vec<String>>,
+}
+
+impl Journal {
+    /// Start a journal for `steps` in `root`
+    pub fn create(root: &Path, steps: Vec<Vec<String>>) -> Result<Self> {
+        let journal = Journal {
+            path: root.join(crate::JOURNAL_PATH),
+            completed: 0,
+            steps,
+        };
+        journal.write()?;
+        Ok(journal)
+    }
+
+    /// Load the journal left by an interrupted transaction in `root`, if any
+    pub fn load(root: &Path) -> Result<Option<Self>> {
+        let path = root.join(crate::JOURNAL_PATH);
+        if !path.is_file() {
+            return Ok(None);
+        }
+        let content = fs::read_to_string(&path).context(format!(
+            "Failed to read transaction journal {}.",
+            path.display()
+        ))?;
+        let mut journal: Journal = toml::from_str(&content).context(format!(
+            "Failed to parse transaction journal {}.",
+            path.display()
+        ))?;
+        journal.path = path;
+        Ok(Some(journal))
+    }
+
+    /// Steps that are not done yet
+    pub fn remaining(&self) -> &[Vec<String>] {
+        &self.steps[self.completed.min(self.steps.len())..]
+    }
+
+    /// Mark the next step as done
+    pub fn complete_step(&mut self) -> Result<()> {
+        self.completed += 1;
+        self.write()
+    }
+
+    /// Remove the journal, after all steps are done
+    pub fn finish(self) -> Result<()> {
+        fs::remove_file(&self.path).context(format!(
+            "Failed to remove transaction journal {}.",
+            self.path.display()
+        ))
+    }
+
+    fn write(&self) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        // Replace the old journal at once, so that it's never half-written
+        let tmp_path = self.path.with_extension("tmp");
+        fs::write(&tmp_path, toml::to_string(self)?).context(format!(
+            "Failed to write transaction journal {}.",
+            tmp_path.display()
+        ))?;
+        fs::rename(&tmp_path, &self.path).context(format!(
+            "Failed to write transaction journal {}.",
+            self.path.display()
+        ))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::utils::temp_dir::TempDir;
+
+    #[test]
+    fn test_journal() {
+        let root = TempDir::new("journal");
+        assert!(Journal::load(&root).unwrap().is_none());
+
+        let steps = vec![
+            vec!["--remove".to_string(), "foo".to_string()],
+            vec!["--install".to_string(), "/tmp/bar_1.0_all.deb".to_string()],
+        ];
+        let mut journal = Journal::create(&root, steps.clone()).unwrap();
+        journal.complete_step().unwrap();
+        // As if interrupted before the second step
+        drop(journal);
+
+        let journal = Journal::load(&root).unwrap().unwrap();
+        assert_eq!(journal.completed, 1);
+        assert_eq!(journal.remaining(), &steps[1..]);
+        journal.finish().unwrap();
+        assert!(Journal::load(&root).unwrap().is_none());
+    }
+}
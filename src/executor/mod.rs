@@ -1,13 +1,147 @@
+pub mod conffiles;
 pub mod dpkg;
+pub mod journal;
 pub mod modifier;
+pub mod script;
 mod types;
 
-use crate::types::{PkgActions, PkgMeta};
+use crate::{
+    types::{PkgActions, PkgMeta, PkgSource, PkgVersion, Priority},
+    utils::debcontrol::parse_pkg_list,
+};
 pub use types::{PkgState, PkgStatus};
 
-use anyhow::{Context, Result};
+use anyhow::{bail, format_err, Context, Result};
+use console::style;
 use debcontrol::{BufParse, Streaming};
-use std::{collections::HashMap, fs, path::Path};
+use lazy_static::lazy_static;
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::SystemTime,
+};
+
+// Fields of dpkg status db used by PkgStatus
+const STATUS_FIELDS: &[&str] = &[
+    "Package",
+    "Status",
+    "Version",
+    "Installed-Size",
+    "Essential",
+    "Pre-Depends",
+    "Depends",
+];
+
+// (mtime, size) of the status file
+type StatusCacheKey = (Option<SystemTime>, u64);
+type StatusCache = HashMap<PathBuf, (StatusCacheKey, HashMap<String, PkgStatus>)>;
+
+lazy_static! {
+    // Parsed dpkg status db, keyed by the path of the status file
+    static ref STATUS_CACHE: Mutex<StatusCache> = Mutex::new(HashMap::new());
+}
+
+/// State of a package that applying `PkgActions` should lead to
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ExpectedState {
+    Installed(PkgVersion),
+    /// Unpacked or configured
+    Unpacked(PkgVersion),
+    /// Not installed, config files may remain
+    Removed,
+}
+
+/// Expected state of each package touched by `actions`
+pub fn expected_states(actions: &PkgActions) -> Vec<(String, ExpectedState)> {
+    let mut res = Vec::new();
+    for (pkg, _) in &actions.install {
+        res.push((
+            pkg.name.clone(),
+            ExpectedState::Installed(pkg.version.clone()),
+        ));
+    }
+    for (pkg, _) in &actions.downgrade {
+        res.push((
+            pkg.name.clone(),
+            ExpectedState::Installed(pkg.version.clone()),
+        ));
+    }
+    for (pkg, _) in &actions.unpack {
+        res.push((
+            pkg.name.clone(),
+            ExpectedState::Unpacked(pkg.version.clone()),
+        ));
+    }
+    for (name, version) in &actions.configure {
+        // Packages being upgraded are configured first, the new version takes precedence
+        if !res.iter().any(|(n, _)| n == name) {
+            res.push((name.clone(), ExpectedState::Installed(version.clone())));
+        }
+    }
+    for (name, _, _) in actions.remove.iter().chain(actions.purge.iter()) {
+        res.push((name.clone(), ExpectedState::Removed));
+    }
+    res
+}
+
+/// Path of dpkg's status db in `admindir` (within `root`, even if absolute), or the default one
+fn status_path(root: &Path, admindir: Option<&Path>) -> Result<PathBuf> {
+    match admindir {
+        Some(admindir) => {
+            let path = crate::utils::alt_root::join_root(root, admindir).join("status");
+            if !path.is_file() {
+                bail!(
+                    "dpkg status file {} does not exist, please check dpkg_admindir in config.",
+                    style(path.display()).bold()
+                );
+            }
+            Ok(path)
+        }
+        None => Ok(root.join(crate::DPKG_ADMINDIR).join("status")),
+    }
+}
+
+/// Package metadata from the fields of an entry in dpkg's status db
+fn status_to_meta(mut f: HashMap<&str, String>, arch: &str) -> Result<PkgMeta> {
+    let status = PkgStatus::try_from(f.clone())?;
+    let list = |f: &HashMap<&str, String>, field: &str| -> Result<Vec<_>> {
+        parse_pkg_list(f.get(field).map_or("", |s| s.as_str()), arch).context(format!(
+            "Malformed dpkg status database: cannot parse {} of {}.",
+            field, status.name
+        ))
+    };
+    let optional_list = |f: &HashMap<&str, String>, field: &str| -> Result<Option<Vec<_>>> {
+        if f.contains_key(field) {
+            list(f, field).map(Some)
+        } else {
+            Ok(None)
+        }
+    };
+    Ok(PkgMeta {
+        name: status.name.clone(),
+        section: f.remove("Section").unwrap_or_default(),
+        description: String::new(),
+        version: status.version.clone(),
+        depends: list(&f, "Depends")?,
+        pre_depends: list(&f, "Pre-Depends")?,
+        breaks: list(&f, "Breaks")?,
+        conflicts: list(&f, "Conflicts")?,
+        recommends: optional_list(&f, "Recommends")?,
+        suggests: None,
+        provides: optional_list(&f, "Provides")?,
+        replaces: optional_list(&f, "Replaces")?,
+        install_size: status.install_size,
+        essential: status.essential,
+        priority: f.get("Priority").and_then(|p| Priority::parse(p)),
+        source: PkgSource::Installed,
+        security: false,
+        distributions: Vec::new(),
+        mirrors: Vec::new(),
+        repos: Vec::new(),
+    })
+}
 
 /// Status of this machine
 pub struct MachineStatus {
@@ -15,14 +149,20 @@ pub struct MachineStatus {
 }
 
 impl MachineStatus {
-    pub fn new(root: &Path) -> Result<Self> {
+    /// Read dpkg's status db in `admindir` (within `root`, even if absolute)
+    ///
+    /// If `admindir` is not specified, the default one is used, and created if necessary.
+    pub fn new(root: &Path, admindir: Option<&Path>) -> Result<Self> {
         let mut res = HashMap::new();
-        // Load or create dpkg's status db
-        let status_file_dir = root.join("var/lib/dpkg");
-        if !status_file_dir.is_dir() {
-            fs::create_dir_all(&status_file_dir).context("Failed to initialize dpkg directory.")?;
+        let stauts_file_path = status_path(root, admindir)?;
+        if admindir.is_none() {
+            // Load or create dpkg's status db
+            let status_file_dir = root.join(crate::DPKG_ADMINDIR);
+            if !status_file_dir.is_dir() {
+                fs::create_dir_all(&status_file_dir)
+                    .context("Failed to initialize dpkg directory.")?;
+            }
         }
-        let stauts_file_path = root.join("var/lib/dpkg/status");
         let status_file = if stauts_file_path.is_file() {
             fs::File::open(&stauts_file_path).context("Failed to open dpkg status file.")?
         } else {
@@ -34,24 +174,151 @@ impl MachineStatus {
                 .context("Failed to initialize dpkg status file.")?
         };
 
+        // Reuse the parsed result if the status file has not changed since last time
+        let metadata = status_file
+            .metadata()
+            .context("Failed to read metadata of dpkg status file.")?;
+        let cache_key = (metadata.modified().ok(), metadata.len());
+        if let Some((key, pkgs)) = STATUS_CACHE.lock().unwrap().get(&stauts_file_path) {
+            if cache_key.0.is_some() && *key == cache_key {
+                return Ok(MachineStatus { pkgs: pkgs.clone() });
+            }
+        }
+
         let mut buf_parse = BufParse::new(status_file, 16384);
-        while let Some(result) = buf_parse.try_next().unwrap() {
+        while let Some(result) = buf_parse
+            .try_next()
+            .map_err(|e| format_err!("Malformed dpkg status database: {e}"))?
+        {
             match result {
                 Streaming::Item(paragraph) => {
-                    let mut fields = HashMap::new();
-                    for field in paragraph.fields {
-                        fields.insert(field.name, field.value);
-                    }
+                    // Only keep the fields we need
+                    let fields: HashMap<&str, String> = paragraph
+                        .fields
+                        .into_iter()
+                        .filter(|field| STATUS_FIELDS.contains(&field.name))
+                        .map(|field| (field.name, field.value))
+                        .collect();
                     let pkgstatus = PkgStatus::try_from(fields)?;
                     res.insert(pkgstatus.name.clone(), pkgstatus);
                 }
                 Streaming::Incomplete => buf_parse.buffer().unwrap(),
             }
         }
+        STATUS_CACHE
+            .lock()
+            .unwrap()
+            .insert(stauts_file_path, (cache_key, res.clone()));
 
         Ok(MachineStatus { pkgs: res })
     }
 
+    /// Full metadata of installed packages in `names`, read from dpkg's status db
+    ///
+    /// These are added to the pool to keep packages at their installed version, when no
+    /// repository has it any more.
+    pub fn installed_meta(
+        root: &Path,
+        admindir: Option<&Path>,
+        names: &HashSet<&str>,
+        arch: &str,
+    ) -> Result<Vec<PkgMeta>> {
+        let path = status_path(root, admindir)?;
+        if !path.is_file() {
+            return Ok(Vec::new());
+        }
+        let status_file = fs::File::open(&path).context("Failed to open dpkg status file.")?;
+        let mut res = Vec::new();
+        let mut buf_parse = BufParse::new(status_file, 16384);
+        while let Some(result) = buf_parse
+            .try_next()
+            .map_err(|e| format_err!("Malformed dpkg status database: {e}"))?
+        {
+            match result {
+                Streaming::Item(paragraph) => {
+                    let fields: HashMap<&str, String> = paragraph
+                        .fields
+                        .into_iter()
+                        .map(|field| (field.name, field.value))
+                        .collect();
+                    if fields
+                        .get("Package")
+                        .is_some_and(|name| names.contains(name.as_str()))
+                    {
+                        res.push(status_to_meta(fields, arch)?);
+                    }
+                }
+                Streaming::Incomplete => buf_parse.buffer().unwrap(),
+            }
+        }
+        Ok(res)
+    }
+
+    /// Compare with an earlier machine status
+    // -> Vec<(PkgName, OldVersion, NewVersion)>, None means not installed
+    pub fn changes_since(
+        &self,
+        old: &MachineStatus,
+    ) -> Vec<(String, Option<PkgVersion>, Option<PkgVersion>)> {
+        let installed = |ms: &MachineStatus, name: &str| {
+            ms.pkgs
+                .get(name)
+                .filter(|pkg| pkg.state == PkgState::Installed)
+                .map(|pkg| pkg.version.clone())
+        };
+        let mut names: Vec<&String> = self.pkgs.keys().chain(old.pkgs.keys()).collect();
+        names.sort();
+        names.dedup();
+
+        let mut res = Vec::new();
+        for name in names {
+            let old_ver = installed(old, name);
+            let new_ver = installed(self, name);
+            if old_ver != new_ver {
+                res.push((name.clone(), old_ver, new_ver));
+            }
+        }
+        res
+    }
+
+    /// Find packages that are not in the `expected` state
+    // -> Vec<(PkgName, Expected, Found)>
+    pub fn diverged_from(
+        &self,
+        expected: &[(String, ExpectedState)],
+    ) -> Vec<(String, String, String)> {
+        let mut res = Vec::new();
+        for (name, state) in expected {
+            let pkg = self
+                .pkgs
+                .get(name)
+                .filter(|pkg| !matches!(pkg.state, PkgState::NotInstalled | PkgState::ConfigFiles));
+            let ok = match (state, pkg) {
+                (ExpectedState::Installed(version), Some(pkg)) => {
+                    pkg.state == PkgState::Installed && &pkg.version == version
+                }
+                (ExpectedState::Unpacked(version), Some(pkg)) => {
+                    pkg.state != PkgState::HalfInstalled && &pkg.version == version
+                }
+                (ExpectedState::Removed, None) => true,
+                _ => false,
+            };
+            if !ok {
+                let expected = match state {
+                    ExpectedState::Installed(version) => format!("{version} installed"),
+                    ExpectedState::Unpacked(version) => format!("{version} unpacked"),
+                    ExpectedState::Removed => "not installed".to_string(),
+                };
+                let found = match pkg {
+                    Some(pkg) => format!("{} {}", pkg.version, pkg.state),
+                    None => "not installed".to_string(),
+                };
+                res.push((name.clone(), expected, found));
+            }
+        }
+        res
+    }
+
     /// Generate a list of actions according to machine status and package blueprint
     pub fn gen_actions<'a>(&self, blueprint: &[&'a PkgMeta], purge_config: bool) -> PkgActions<'a> {
         let mut res = PkgActions::default();
@@ -74,7 +341,10 @@ impl MachineStatus {
                     PkgState::Installed => {
                         // Check version. If installed is different,
                         //   then install the one in the blueprint
-                        if oldpkg.version != newpkg.version {
+                        if oldpkg.version > newpkg.version {
+                            res.downgrade
+                                .push((newpkg, (oldpkg.version, oldpkg.install_size)));
+                        } else if oldpkg.version != newpkg.version {
                             res.install
                                 .push((newpkg, Some((oldpkg.version, oldpkg.install_size))));
                         }
@@ -86,7 +356,10 @@ impl MachineStatus {
                         // Reconfigure this package, then if have updates, do it
                         res.configure
                             .push((oldpkg.name.clone(), oldpkg.version.clone()));
-                        if oldpkg.version != newpkg.version {
+                        if oldpkg.version > newpkg.version {
+                            res.downgrade
+                                .push((newpkg, (oldpkg.version, oldpkg.install_size)));
+                        } else if oldpkg.version != newpkg.version {
                             res.install
                                 .push((newpkg, Some((oldpkg.version, oldpkg.install_size))));
                         }
@@ -120,3 +393,140 @@ impl MachineStatus {
         res
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::types::test_pkg;
+
+    fn installed(name: &str, version: &str) -> (String, PkgStatus) {
+        let status = PkgStatus {
+            name: name.to_string(),
+            version: PkgVersion::try_from(version).unwrap(),
+            install_size: 0,
+            essential: false,
+            state: PkgState::Installed,
+            depends: Vec::new(),
+            conflicts: Vec::new(),
+        };
+        (name.to_string(), status)
+    }
+
+    #[test]
+    fn test_gen_actions_downgrade() {
+        let ms = MachineStatus {
+            pkgs: HashMap::from([
+                installed("a", "2.0"),
+                installed("b", "1.0"),
+                installed("c", "1.0"),
+            ]),
+        };
+        let a = test_pkg("a", "1.0");
+        let b = test_pkg("b", "1.1");
+        let c = test_pkg("c", "1.0");
+        let actions = ms.gen_actions(&[&a, &b, &c], false);
+
+        assert_eq!(actions.downgrade.len(), 1);
+        assert_eq!(actions.downgrade[0].0.name, "a");
+        assert_eq!(
+            actions.downgrade[0].1 .0,
+            PkgVersion::try_from("2.0").unwrap()
+        );
+        assert_eq!(actions.install.len(), 1);
+        assert_eq!(actions.install[0].0.name, "b");
+        assert!(actions.remove.is_empty());
+    }
+
+    #[test]
+    fn test_custom_admindir() {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+        let ms = MachineStatus::new(root, Some(Path::new("tests/fixtures/admindir"))).unwrap();
+        assert_eq!(ms.pkgs.len(), 2);
+        assert!(ms.pkgs["bash"].essential);
+        assert_eq!(
+            ms.pkgs["zlib"].version,
+            PkgVersion::try_from("1:1.2.12").unwrap()
+        );
+        // Absolute paths are resolved within root as well
+        let admindir = root.join("tests/fixtures/admindir");
+        assert!(MachineStatus::new(Path::new("/"), Some(&admindir)).is_ok());
+        assert!(MachineStatus::new(Path::new("/nonexistent"), Some(&admindir)).is_err());
+        // Missing status file is an error instead of an empty db
+        assert!(MachineStatus::new(root, Some(Path::new("tests/fixtures/nonexistent"))).is_err());
+    }
+
+    #[test]
+    fn test_installed_meta() {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+        let admindir = Path::new("tests/fixtures/admindir");
+        let names = HashSet::from(["zlib"]);
+        let metas = MachineStatus::installed_meta(root, Some(admindir), &names, "amd64").unwrap();
+        assert_eq!(metas.len(), 1);
+        assert_eq!(metas[0].name, "zlib");
+        assert_eq!(metas[0].section, "libs");
+        assert_eq!(metas[0].version, PkgVersion::try_from("1:1.2.12").unwrap());
+        assert_eq!(metas[0].install_size, 256 * 1024);
+        assert!(matches!(metas[0].source, PkgSource::Installed));
+    }
+
+    #[test]
+    fn test_changes_since() {
+        let old = MachineStatus {
+            pkgs: HashMap::from([installed("a", "1.0"), installed("b", "1.0")]),
+        };
+        let mut c = installed("c", "1.0");
+        c.1.state = PkgState::HalfConfigured;
+        let new = MachineStatus {
+            pkgs: HashMap::from([installed("a", "1.1"), installed("b", "1.0"), c]),
+        };
+        let v = |s| Some(PkgVersion::try_from(s).unwrap());
+        assert_eq!(
+            new.changes_since(&old),
+            vec![("a".to_string(), v("1.0"), v("1.1"))]
+        );
+        assert_eq!(
+            old.changes_since(&new),
+            vec![("a".to_string(), v("1.1"), v("1.0"))]
+        );
+    }
+    #[test]
+    fn test_diverged_from() {
+        // a was upgraded, b was silently skipped, c failed to configure, d was removed
+        let mut c = installed("c", "1.0");
+        c.1.state = PkgState::HalfConfigured;
+        let ms = MachineStatus {
+            pkgs: HashMap::from([installed("a", "1.1"), installed("b", "1.0"), c]),
+        };
+        let (new_a, new_b, new_c) = (
+            test_pkg("a", "1.1"),
+            test_pkg("b", "1.1"),
+            test_pkg("c", "1.0"),
+        );
+        let mut actions = PkgActions::default();
+        actions.install.push((&new_a, None));
+        actions.install.push((&new_b, None));
+        actions.unpack.push((&new_c, None));
+        actions.remove.push(("d".to_string(), 0, false));
+        let expected = expected_states(&actions);
+        assert_eq!(expected.len(), 4);
+
+        let diverged = ms.diverged_from(&expected);
+        assert_eq!(
+            diverged,
+            vec![(
+                "b".to_string(),
+                "1.1 installed".to_string(),
+                "1.0 installed".to_string()
+            )]
+        );
+
+        // c should have been configured
+        actions.unpack.clear();
+        actions
+            .configure
+            .push(("c".to_string(), new_c.version.clone()));
+        let diverged = ms.diverged_from(&expected_states(&actions));
+        assert_eq!(diverged.len(), 2);
+        assert_eq!(diverged[1].2, "1.0 half-configured");
+    }
+}
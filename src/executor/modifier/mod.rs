@@ -1,2 +1,6 @@
+mod never_autoremove;
+mod skip_missing;
 mod unpack_only;
+pub use never_autoremove::NeverAutoRemove;
+pub use skip_missing::SkipMissing;
 pub use unpack_only::UnpackOnly;
@@ -0,0 +1,110 @@
+use crate::{
+    info,
+    types::{PkgActionModifier, PkgActions, PkgMeta},
+    utils::glob::glob_match,
+};
+use std::collections::HashSet;
+
+/// Keep installed packages matching `never_autoremove` patterns, even if nothing requires them,
+/// unless the user asked to remove them
+#[derive(Default)]
+pub struct NeverAutoRemove {
+    /// Glob patterns of package names
+    pub patterns: Vec<String>,
+    /// Packages the user asked to remove
+    pub explicit: HashSet<String>,
+}
+
+impl NeverAutoRemove {
+    pub fn is_protected(&self, name: &str) -> bool {
+        !self.explicit.contains(name) && self.patterns.iter().any(|p| glob_match(p, name))
+    }
+
+    /// Protected packages in `res` that are only kept because of this protection
+    pub fn orphans(&self, res: &[&PkgMeta], requested: &HashSet<String>) -> Vec<String> {
+        let required: HashSet<&str> = res
+            .iter()
+            .flat_map(|pkg| pkg.pre_depends.iter().chain(pkg.depends.iter()))
+            .map(|(name, _)| name.as_str())
+            .collect();
+        let mut orphans: Vec<String> = res
+            .iter()
+            .filter(|pkg| {
+                self.is_protected(&pkg.name)
+                    && !requested.contains(&pkg.name)
+                    && !required.contains(pkg.name.as_str())
+            })
+            .map(|pkg| pkg.name.clone())
+            .collect();
+        orphans.sort();
+        orphans
+    }
+}
+
+impl PkgActionModifier for NeverAutoRemove {
+    fn apply(&self, actions: &mut PkgActions) {
+        // Protected packages are usually kept by the solver,
+        // unless they are no longer available from any repository
+        let mut kept = Vec::new();
+        for list in [&mut actions.remove, &mut actions.purge] {
+            list.retain(|(name, _, _)| {
+                if self.is_protected(name) {
+                    kept.push(name.clone());
+                    return false;
+                }
+                true
+            });
+        }
+        if !kept.is_empty() {
+            kept.sort();
+            info!(
+                "Keeping {} package(s) that match never_autoremove, although they are not available from any repository:",
+                kept.len()
+            );
+            crate::WRITER.write_chunks("", &kept).ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::types::{test_pkg as pkg, VersionRequirement};
+
+    #[test]
+    fn test_never_autoremove() {
+        let modifier = NeverAutoRemove {
+            patterns: vec!["htop".to_string(), "linux-kernel-*".to_string()],
+            explicit: HashSet::from(["linux-kernel-5.10".to_string()]),
+        };
+        let mut actions = PkgActions::default();
+        actions.remove.push(("htop".to_string(), 0, false));
+        actions
+            .remove
+            .push(("linux-kernel-5.10".to_string(), 0, false));
+        actions
+            .purge
+            .push(("linux-kernel-5.15".to_string(), 0, false));
+        actions.remove.push(("nano".to_string(), 0, false));
+        modifier.apply(&mut actions);
+        // Protected orphans stay, unless removed explicitly
+        let removed: Vec<&str> = actions
+            .remove
+            .iter()
+            .chain(actions.purge.iter())
+            .map(|(name, _, _)| name.as_str())
+            .collect();
+        assert_eq!(removed, vec!["linux-kernel-5.10", "nano"]);
+
+        // htop is kept by the protection only, linux-kernel-6.1 is needed by linux-meta
+        let htop = pkg("htop", "3.0");
+        let kernel = pkg("linux-kernel-6.1", "6.1");
+        let mut meta = pkg("linux-meta", "6.1");
+        meta.depends = vec![(kernel.name.clone(), VersionRequirement::default())];
+        let requested = HashSet::from(["linux-meta".to_string()]);
+        assert_eq!(
+            modifier.orphans(&[&meta, &kernel, &htop], &requested),
+            vec!["htop"]
+        );
+    }
+}
@@ -0,0 +1,262 @@
+use crate::{
+    executor::PkgStatus,
+    types::{PkgActionModifier, PkgActions, PkgMeta, PkgVersion},
+    warn,
+};
+use std::collections::{HashMap, HashSet};
+
+/// Leave out packages that could not be fetched, along with packages that depend on them.
+/// Skipped packages stay at their installed version, or are not installed at all, and packages
+/// that were only removed because of them are kept.
+pub struct SkipMissing<'a> {
+    /// Names of packages that could not be fetched
+    pub missing: HashSet<String>,
+    /// Installed packages, see MachineStatus
+    pub installed: &'a HashMap<String, PkgStatus>,
+}
+
+impl PkgActionModifier for SkipMissing<'_> {
+    fn apply(&self, actions: &mut PkgActions) {
+        // PkgName -> version that stays on the system, None if not installed
+        let mut skipped: HashMap<String, Option<PkgVersion>> = HashMap::new();
+        // The versions that were going to be installed
+        let mut skipped_pkgs: Vec<&PkgMeta> = Vec::new();
+        let mut to_skip = self.missing.clone();
+        while !to_skip.is_empty() {
+            for (pkg, old) in actions.install.iter().chain(actions.unpack.iter()) {
+                if to_skip.contains(&pkg.name) {
+                    skipped.insert(pkg.name.clone(), old.as_ref().map(|(v, _)| v.clone()));
+                    skipped_pkgs.push(pkg);
+                }
+            }
+            for (pkg, (old, _)) in &actions.downgrade {
+                if to_skip.contains(&pkg.name) {
+                    skipped.insert(pkg.name.clone(), Some(old.clone()));
+                    skipped_pkgs.push(pkg);
+                }
+            }
+            actions
+                .install
+                .retain(|(pkg, _)| !to_skip.contains(&pkg.name));
+            actions
+                .downgrade
+                .retain(|(pkg, _)| !to_skip.contains(&pkg.name));
+            actions
+                .unpack
+                .retain(|(pkg, _)| !to_skip.contains(&pkg.name));
+
+            // Names only provided by skipped packages are gone as well, unless another
+            // incoming or installed package has them
+            let incoming = || {
+                actions
+                    .install
+                    .iter()
+                    .map(|(pkg, _)| *pkg)
+                    .chain(actions.downgrade.iter().map(|(pkg, _)| *pkg))
+                    .chain(actions.unpack.iter().map(|(pkg, _)| *pkg))
+            };
+            let provided: HashSet<&str> = incoming()
+                .flat_map(|pkg| pkg.provides.iter().flatten())
+                .map(|(name, _)| name.as_str())
+                .collect();
+            let lost: HashSet<&str> = skipped_pkgs
+                .iter()
+                .flat_map(|pkg| pkg.provides.iter().flatten())
+                .map(|(name, _)| name.as_str())
+                .filter(|name| !provided.contains(name) && !self.installed.contains_key(*name))
+                .collect();
+
+            // Packages that depend on a skipped package can't be installed either,
+            // unless the version that stays on the system satisfies the dependency
+            to_skip = incoming()
+                .filter(|pkg| {
+                    pkg.pre_depends
+                        .iter()
+                        .chain(pkg.depends.iter())
+                        .any(|(name, ver_req)| match skipped.get(name) {
+                            Some(Some(version)) => !ver_req.contains(version),
+                            Some(None) => true,
+                            None => lost.contains(name.as_str()),
+                        })
+                })
+                .map(|pkg| pkg.name.clone())
+                .collect();
+        }
+
+        let reverted = self.revert_removals(actions, &skipped, &skipped_pkgs);
+        if !skipped.is_empty() {
+            let mut names: Vec<&String> = skipped.keys().collect();
+            names.sort();
+            warn!(
+                "Skipped {} package(s) that could not be fetched, or depend on such packages:",
+                skipped.len()
+            );
+            crate::WRITER.write_chunks("", &names).ok();
+        }
+        if !reverted.is_empty() {
+            warn!(
+                "Keeping {} package(s) that were to be removed because of skipped packages:",
+                reverted.len()
+            );
+            crate::WRITER.write_chunks("", &reverted).ok();
+        }
+    }
+}
+
+impl SkipMissing<'_> {
+    /// Keep installed packages that were only removed for the new versions of skipped packages,
+    /// or that the versions staying on the system depend on. Returns names of kept packages.
+    fn revert_removals(
+        &self,
+        actions: &mut PkgActions,
+        skipped: &HashMap<String, Option<PkgVersion>>,
+        skipped_pkgs: &[&PkgMeta],
+    ) -> Vec<String> {
+        let removed: HashSet<String> = actions
+            .remove
+            .iter()
+            .chain(actions.purge.iter())
+            .map(|(name, _, _)| name.clone())
+            .collect();
+        let mut queue: Vec<String> = Vec::new();
+        for pkg in skipped_pkgs {
+            let relations = pkg
+                .conflicts
+                .iter()
+                .chain(pkg.breaks.iter())
+                .chain(pkg.replaces.iter().flatten());
+            for (name, ver_req) in relations {
+                if let Some(installed) = self.installed.get(name) {
+                    if ver_req.contains(&installed.version) {
+                        queue.push(name.clone());
+                    }
+                }
+            }
+        }
+        for (name, old) in skipped {
+            if old.is_some() {
+                if let Some(installed) = self.installed.get(name) {
+                    queue.extend(installed.depends.iter().cloned());
+                }
+            }
+        }
+
+        // Dependencies of kept packages are kept as well
+        let mut reverted: HashSet<String> = HashSet::new();
+        while let Some(name) = queue.pop() {
+            if !removed.contains(&name) || !reverted.insert(name.clone()) {
+                continue;
+            }
+            if let Some(installed) = self.installed.get(&name) {
+                queue.extend(installed.depends.iter().cloned());
+            }
+        }
+        actions
+            .remove
+            .retain(|(name, _, _)| !reverted.contains(name));
+        actions
+            .purge
+            .retain(|(name, _, _)| !reverted.contains(name));
+
+        let mut reverted: Vec<String> = reverted.into_iter().collect();
+        reverted.sort();
+        reverted
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        executor::PkgState,
+        types::{test_pkg as pkg, VersionRequirement},
+    };
+
+    fn installed(name: &str, version: &str, depends: &[&str]) -> (String, PkgStatus) {
+        let status = PkgStatus {
+            name: name.to_string(),
+            version: PkgVersion::try_from(version).unwrap(),
+            install_size: 0,
+            essential: false,
+            state: PkgState::Installed,
+            depends: depends.iter().map(|d| d.to_string()).collect(),
+            conflicts: Vec::new(),
+        };
+        (name.to_string(), status)
+    }
+
+    #[test]
+    fn test_skip_missing() {
+        let ver = |v| PkgVersion::try_from(v).unwrap();
+        let at_least = |v| VersionRequirement {
+            lower_bond: Some((ver(v), true)),
+            upper_bond: None,
+        };
+        // a failed to download, b depends on the new a, c depends on b, d is independent
+        let a = pkg("a", "2.0");
+        let mut b = pkg("b", "1.0");
+        b.depends = vec![("a".to_string(), at_least("2.0"))];
+        let mut c = pkg("c", "1.0");
+        c.pre_depends = vec![("b".to_string(), VersionRequirement::default())];
+        let d = pkg("d", "1.0");
+        // e depends on a, and the installed version of a is good enough
+        let mut e = pkg("e", "1.0");
+        e.depends = vec![("a".to_string(), at_least("1.0"))];
+        let mut actions = PkgActions::default();
+        actions.install.push((&a, Some((ver("1.0"), 0))));
+        actions.install.push((&b, None));
+        actions.unpack.push((&c, None));
+        actions.install.push((&d, None));
+        actions.install.push((&e, None));
+
+        let installed = HashMap::from([installed("a", "1.0", &[])]);
+        let modifier = SkipMissing {
+            missing: HashSet::from(["a".to_string()]),
+            installed: &installed,
+        };
+        modifier.apply(&mut actions);
+        let names: Vec<&str> = actions
+            .install
+            .iter()
+            .chain(actions.unpack.iter())
+            .map(|(pkg, _)| pkg.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["d", "e"]);
+    }
+
+    #[test]
+    fn test_skip_missing_removals() {
+        let ver = |v| PkgVersion::try_from(v).unwrap();
+        // The new a failed to download. It replaces old-a and provides virtual-a, which b needs.
+        let mut a = pkg("a", "2.0");
+        a.replaces = Some(vec![("old-a".to_string(), VersionRequirement::default())]);
+        a.provides = Some(vec![(
+            "virtual-a".to_string(),
+            VersionRequirement::default(),
+        )]);
+        let mut b = pkg("b", "1.0");
+        b.depends = vec![("virtual-a".to_string(), VersionRequirement::default())];
+        let mut actions = PkgActions::default();
+        actions.install.push((&a, Some((ver("1.0"), 0))));
+        actions.install.push((&b, None));
+        actions.remove.push(("old-a".to_string(), 0, false));
+        // The installed a depends on liba, which was only removed because the new a doesn't
+        actions.purge.push(("liba".to_string(), 0, false));
+        actions.remove.push(("unrelated".to_string(), 0, false));
+
+        let installed = HashMap::from([
+            installed("a", "1.0", &["liba"]),
+            installed("old-a", "0.9", &[]),
+            installed("liba", "1.0", &[]),
+            installed("unrelated", "1.0", &[]),
+        ]);
+        let modifier = SkipMissing {
+            missing: HashSet::from(["a".to_string()]),
+            installed: &installed,
+        };
+        modifier.apply(&mut actions);
+        assert!(actions.install.is_empty());
+        assert!(actions.purge.is_empty());
+        assert_eq!(actions.remove, vec![("unrelated".to_string(), 0, false)]);
+    }
+}
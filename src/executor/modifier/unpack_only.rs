@@ -9,6 +9,9 @@ impl PkgActionModifier for UnpackOnly {
     fn apply(&self, actions: &mut PkgActions) {
         // All installs should be unpacks
         actions.unpack.append(&mut actions.install);
+        for (pkg, old) in actions.downgrade.drain(..) {
+            actions.unpack.push((pkg, Some(old)));
+        }
         // Configures should be ignored
         actions.configure.clear();
     }
@@ -0,0 +1,176 @@
+use super::{
+    dpkg::{self, dpkg_global_args, get_download_jobs, pkg_action_steps},
+    MachineStatus, PkgStatus,
+};
+use crate::{
+    types::{config::DpkgOrder, Checksum, PkgActions},
+    utils::downloader::Compression,
+};
+
+use anyhow::{Context, Result};
+use std::{collections::HashMap, fs, os::unix::fs::PermissionsExt, path::Path};
+
+/// Write a shell script that performs the same steps as applying `actions`: fetching packages
+/// into `cache`, then running dpkg. Nothing is downloaded or changed otherwise.
+pub fn write_script(
+    path: &Path,
+    actions: PkgActions<'_>,
+    cache: &Path,
+    root: &Path,
+    admindir: Option<&Path>,
+    force: &[&str],
+    order: DpkgOrder,
+) -> Result<()> {
+    let installed = MachineStatus::new(root, admindir)?.pkgs;
+    let script = gen_script(actions, cache, &installed, root, admindir, force, order)?;
+    fs::write(path, script).context(format!("Failed to write script to {} .", path.display()))?;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o755))?;
+    Ok(())
+}
+
+fn gen_script(
+    actions: PkgActions<'_>,
+    cache: &Path,
+    installed: &HashMap<String, PkgStatus>,
+    root: &Path,
+    admindir: Option<&Path>,
+    force: &[&str],
+    order: DpkgOrder,
+) -> Result<String> {
+    let mut lines = vec![
+        "#!/bin/sh".to_string(),
+        "# Generated by Omakase, review before running".to_string(),
+        "set -e".to_string(),
+    ];
+
+    // Packages are stored in the same place as they would be by fetch_pkgs
+    let mut download_res = HashMap::new();
+    let jobs = get_download_jobs(&actions);
+    if !jobs.is_empty() {
+        lines.push(String::new());
+        lines.push("# Fetch packages".to_string());
+        lines.push(format!(
+            "mkdir -p {}",
+            shell_quote(&cache.to_string_lossy())
+        ));
+    }
+    for job in jobs {
+        let filename = job
+            .filename
+            .as_deref()
+            .or_else(|| dpkg::url_filename(&job.url))
+            .unwrap_or_default();
+        let path = cache.join(filename);
+        let path_str = path.to_string_lossy();
+        lines.push(format!(
+            "curl -fL -o {} {}",
+            shell_quote(&path_str),
+            shell_quote(&job.url)
+        ));
+        if let Compression::None(Some(checksum)) = &job.compression {
+            let (tool, hash) = match checksum {
+                Checksum::Sha256(hash) => ("sha256sum", hash),
+                Checksum::Sha512(hash) => ("sha512sum", hash),
+            };
+            lines.push(format!(
+                "echo {} | {tool} -c -",
+                shell_quote(&format!("{}  {path_str}", hex::encode(hash)))
+            ));
+        }
+        download_res.insert(job.url, path);
+    }
+
+    let global_args: Vec<String> = dpkg_global_args(root, admindir, force)
+        .iter()
+        .map(|arg| arg.to_string_lossy().into_owned())
+        .collect();
+    let steps = pkg_action_steps(actions, &download_res, installed, order)?;
+    if !steps.is_empty() {
+        lines.push(String::new());
+        lines.push("# Apply changes".to_string());
+    }
+    for step in steps {
+        // Same as dpkg_run, steps without packages are skipped
+        if step.len() <= 1 {
+            continue;
+        }
+        let cmd: Vec<String> = std::iter::once("dpkg")
+            .chain(global_args.iter().map(|arg| arg.as_str()))
+            .chain(step.iter().map(|arg| arg.as_str()))
+            .map(shell_quote)
+            .collect();
+        lines.push(cmd.join(" "));
+    }
+    lines.push(String::new());
+
+    Ok(lines.join("\n"))
+}
+
+/// Quote `s` for POSIX shells, if needed
+fn shell_quote(s: &str) -> String {
+    let plain = !s.is_empty()
+        && s.chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./:=+,@%".contains(c));
+    if plain {
+        s.to_string()
+    } else {
+        format!("'{}'", s.replace('\'', r"'\''"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::types::{test_pkg, PkgSource, PkgVersion};
+
+    #[test]
+    fn test_gen_script() {
+        let mut bash = test_pkg("bash", "5.2");
+        bash.source = PkgSource::Http((
+            "https://repo.example.org/debs/pool/b/bash_5.2_amd64.deb".to_string(),
+            0,
+            Checksum::from_sha256_str(&"0".repeat(64)).unwrap(),
+        ));
+        let mut local = test_pkg("hello", "1.0");
+        local.source = PkgSource::Local("/tmp/it's here/hello_1.0_all.deb".into());
+        let mut actions = PkgActions::default();
+        actions
+            .install
+            .push((&bash, Some((PkgVersion::try_from("5.1").unwrap(), 0))));
+        actions.install.push((&local, None));
+        actions.remove.push(("vim".to_string(), 0, false));
+        actions
+            .configure
+            .push(("zlib".to_string(), PkgVersion::try_from("1.3").unwrap()));
+
+        let script = gen_script(
+            actions,
+            Path::new("/var/cache/omakase/pkgs"),
+            &HashMap::new(),
+            Path::new("/"),
+            None,
+            &["--force-confold"],
+            DpkgOrder::RemoveFirst,
+        )
+        .unwrap();
+        let lines: Vec<&str> = script.lines().collect();
+        assert_eq!(
+            lines,
+            vec![
+                "#!/bin/sh",
+                "# Generated by Omakase, review before running",
+                "set -e",
+                "",
+                "# Fetch packages",
+                "mkdir -p /var/cache/omakase/pkgs",
+                "curl -fL -o /var/cache/omakase/pkgs/bash_5.2_amd64.deb https://repo.example.org/debs/pool/b/bash_5.2_amd64.deb",
+                &format!("echo '{}  /var/cache/omakase/pkgs/bash_5.2_amd64.deb' | sha256sum -c -", "0".repeat(64)),
+                "",
+                "# Apply changes",
+                "dpkg --root / --force-confold --remove vim",
+                "dpkg --root / --force-confold --configure zlib",
+                r"dpkg --root / --force-confold --install /var/cache/omakase/pkgs/bash_5.2_amd64.deb '/tmp/it'\''s here/hello_1.0_all.deb'",
+            ]
+        );
+    }
+}
@@ -39,6 +39,22 @@ impl std::convert::TryFrom<&str> for PkgState {
     }
 }
 
+impl std::fmt::Display for PkgState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Self::NotInstalled => "not-installed",
+            Self::ConfigFiles => "config-files",
+            Self::HalfInstalled => "half-installed",
+            Self::Unpacked => "unpacked",
+            Self::HalfConfigured => "half-configured",
+            Self::TriggerAwaited => "triggers-awaited",
+            Self::TriggerPending => "triggers-pending",
+            Self::Installed => "installed",
+        };
+        write!(f, "{s}")
+    }
+}
+
 /// Status of package on this instance, extracted from dpkg status db
 #[derive(Clone)]
 pub struct PkgStatus {
@@ -47,6 +63,10 @@ pub struct PkgStatus {
     pub install_size: u64,
     pub essential: bool,
     pub state: PkgState,
+    /// Names in Depends and Pre-Depends, including all alternatives
+    pub depends: Vec<String>,
+    /// Names in Conflicts
+    pub conflicts: Vec<String>,
 }
 
 impl TryFrom<HashMap<&str, String>> for PkgStatus {
@@ -101,6 +121,15 @@ impl TryFrom<HashMap<&str, String>> for PkgStatus {
         }
 
         let state = PkgState::try_from(*status.get(2).unwrap())?;
+        let depends = ["Pre-Depends", "Depends"]
+            .iter()
+            .filter_map(|field| f.remove(field))
+            .flat_map(|list| relation_names(&list))
+            .collect();
+        let conflicts = f
+            .remove("Conflicts")
+            .map(|list| relation_names(&list))
+            .unwrap_or_default();
 
         let res = PkgStatus {
             name,
@@ -108,8 +137,26 @@ impl TryFrom<HashMap<&str, String>> for PkgStatus {
             install_size,
             essential,
             state,
+            depends,
+            conflicts,
         };
 
         Ok(res)
     }
 }
+
+/// Package names in a relationship field, like `libc (>= 2.35), mawk | gawk`
+fn relation_names(s: &str) -> Vec<String> {
+    s.split([',', '|'])
+        .map(|rel| {
+            let rel = rel.trim();
+            // Drop version, architecture qualifier and restrictions
+            let end = rel
+                .find(|c: char| c.is_whitespace() || "(:[<".contains(c))
+                .unwrap_or(rel.len());
+            &rel[..end]
+        })
+        .filter(|name| !name.is_empty())
+        .map(|name| name.to_owned())
+        .collect()
+}
@@ -0,0 +1,98 @@
+//! Omakase, an apt-like package manager for dpkg-based systems.
+//!
+//! Besides the `oma` binary, this crate exposes the SAT-based dependency resolver.
+//! Build a pool from a list of [`PkgMeta`], then ask for the resolved package set:
+//!
+//! ```no_run
+//! use omakase::{PkgMeta, VersionRequirement};
+//!
+//! fn install(pkgs: Vec<PkgMeta>) -> Result<(), omakase::SolverError> {
+//!     let requests = vec![("bash".to_string(), VersionRequirement::default())];
+//!     for pkg in omakase::resolve(pkgs, &requests)? {
+//!         println!("{} {}", pkg.name, pkg.version);
+//!     }
+//!     Ok(())
+//! }
+//! ```
+mod actions;
+mod app;
+mod cli;
+mod db;
+mod executor;
+mod pool;
+mod solver;
+mod types;
+mod utils;
+
+pub use solver::{Reason, SolverError};
+pub use types::{
+    Checksum, PkgMeta, PkgMirrors, PkgSource, PkgVersion, Priority, VersionRequirement,
+};
+
+use pool::{BasicPkgPool, InMemoryPool};
+use solver::Solver;
+
+use lazy_static::lazy_static;
+use std::sync::atomic::{AtomicBool, AtomicI32};
+
+// Initialize writer
+lazy_static! {
+    static ref WRITER: cli::Writer = cli::Writer::new();
+    static ref TIMINGS: utils::timing::Timings = utils::timing::Timings::default();
+    // Plain text log, if enabled by --log-file or config
+    static ref LOG: cli::log::FileLog = cli::log::FileLog::default();
+}
+// Debug flag
+static VERBOSE: AtomicBool = AtomicBool::new(false);
+// Hide dpkg output
+static QUIET: AtomicBool = AtomicBool::new(false);
+// Global states
+static DPKG_RUNNING: AtomicBool = AtomicBool::new(false);
+static LOCKED: AtomicBool = AtomicBool::new(false);
+static SUBPROCESS: AtomicI32 = AtomicI32::new(-1);
+// Global constants
+const DB_KEY_PATH: &str = "etc/omakase/keys";
+const DB_CACHE_PATH: &str = "var/cache/omakase/db";
+const PKG_CACHE_PATH: &str = "var/cache/omakase/pkgs";
+const CONTENTS_INDEX_PATH: &str = "var/cache/omakase/contents-index";
+const LOCK_PATH: &str = "var/lib/omakase/lock";
+const LOCAL_REPO_PATH: &str = "var/lib/omakase/local_repo";
+const JOURNAL_PATH: &str = "var/lib/omakase/journal";
+const SNAPSHOT_PATH: &str = "var/lib/omakase/snapshots";
+const DPKG_ADMINDIR: &str = "var/lib/dpkg";
+// Relative to config_root
+const LOCKFILE_NAME: &str = "omakase.lock";
+
+/// Check if in verbose mode
+fn verbose() -> bool {
+    crate::VERBOSE.load(std::sync::atomic::Ordering::Relaxed)
+}
+
+/// Check if in quiet mode
+fn quiet() -> bool {
+    crate::QUIET.load(std::sync::atomic::Ordering::Relaxed)
+}
+
+/// Entry point of the `oma` binary, not part of the library API
+#[doc(hidden)]
+pub async fn cli_main() -> i32 {
+    app::main().await
+}
+
+/// Resolve `requests`, a list of (PkgName, VersionRequirement), against `pkgs`
+///
+/// Returns the packages to install, sorted in installation order.
+pub fn resolve(
+    pkgs: Vec<PkgMeta>,
+    requests: &[(String, VersionRequirement)],
+) -> Result<Vec<PkgMeta>, SolverError> {
+    let mut pool = InMemoryPool::new();
+    for pkg in pkgs {
+        pool.add(pkg);
+    }
+    pool.finalize();
+
+    let solver = Solver::from(Box::new(pool) as Box<dyn pool::PkgPool>);
+    let res = solver.resolve(requests)?;
+    Ok(res.into_iter().cloned().collect())
+}
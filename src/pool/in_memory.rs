@@ -1,19 +1,358 @@
-use super::{BasicPkgPool, PkgPool};
-use crate::types::{PkgMeta, PkgVersion};
+use super::{
+    BasicPkgPool, Pinned, PkgPool, RepoFilter, TargetRelease, VersionLimit, VersionPins,
+    VersionPreference,
+};
+use crate::{
+    debug,
+    types::{config::UpgradePolicy, PkgMeta, PkgMirrors, PkgSource, PkgVersion},
+    warn,
+};
 
-use std::collections::HashMap;
+use anyhow::{bail, Result};
+use console::style;
+use std::{
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
 
+#[derive(Default)]
 pub struct InMemoryPool {
     pkgs: Vec<PkgMeta>,
     // The id of packages for each name, sorted by version
     name_to_ids: HashMap<String, Vec<(usize, PkgVersion)>>,
+    // The repository each package is imported from, only used while importing
+    origins: HashMap<usize, String>,
 }
 
 impl InMemoryPool {
     pub fn new() -> Self {
-        InMemoryPool {
-            pkgs: Vec::new(),
-            name_to_ids: HashMap::new(),
+        Self::default()
+    }
+
+    /// Add a package from repository `repo`, returns the package ID
+    ///
+    /// If a package with the same name and version is already in the pool, the new one is not
+    /// added. Instead, its URL is recorded as a download fallback of the existing package, which
+    /// is also marked as provided by `repo`. Repositories are imported in order of preference
+    /// (see `LocalDb::get_all_package_db`), so the one added first is kept.
+    pub fn add_from_repo(&mut self, mut meta: PkgMeta, repo: &str) -> usize {
+        let existing = self.name_to_ids.get(&meta.name).and_then(|ids| {
+            ids.iter()
+                .find(|(_, version)| version == &meta.version)
+                .map(|(id, _)| *id)
+        });
+        let id = match existing {
+            Some(id) => id,
+            None => {
+                meta.repos = vec![repo.to_owned()];
+                let id = self.add(meta);
+                self.origins.insert(id, repo.to_owned());
+                return id;
+            }
+        };
+
+        let origin = self
+            .origins
+            .get(&id)
+            .map(|s| s.as_str())
+            .unwrap_or_default();
+        let pkg = &mut self.pkgs[id - 1];
+        if let (PkgSource::Http((_, _, checksum)), PkgSource::Http((new_url, _, new_checksum))) =
+            (&pkg.source, &meta.source)
+        {
+            if checksum == new_checksum {
+                // Mirrors of the new package usually include its own URL, but not always
+                let covered = meta.mirrors.iter().any(|mirrors| {
+                    mirrors
+                        .bases
+                        .iter()
+                        .any(|base| new_url == &format!("{base}/{}", mirrors.path))
+                });
+                if !covered {
+                    if let Some((base, path)) = new_url.rsplit_once('/') {
+                        meta.mirrors.push(PkgMirrors {
+                            bases: Arc::from(vec![base.to_string()]),
+                            path: path.to_string(),
+                        });
+                    }
+                }
+                for mirrors in meta.mirrors {
+                    if !pkg.mirrors.contains(&mirrors) {
+                        pkg.mirrors.push(mirrors);
+                    }
+                }
+                if !pkg.repos.iter().any(|r| r == repo) {
+                    pkg.repos.push(repo.to_owned());
+                }
+                // The same package, so it's a security update if any repository says so
+                pkg.security |= meta.security;
+                for distribution in meta.distributions {
+                    if !pkg.distributions.contains(&distribution) {
+                        pkg.distributions.push(distribution);
+                    }
+                }
+            } else if origin == repo {
+                warn!(
+                    "Repository {} lists {} {} more than once with different content, using the first one.",
+                    style(repo).bold(),
+                    style(&meta.name).bold(),
+                    meta.version
+                );
+            } else {
+                debug!(
+                    "{} {} differs between repositories {} and {}, using the one from {}.",
+                    meta.name, meta.version, origin, repo, origin
+                );
+            }
+        }
+
+        id
+    }
+
+    /// Drop candidates of installed packages that their pin doesn't allow. Must be called after
+    /// finalize.
+    ///
+    /// If no repository has the installed version of such a package, it's added from `pins`,
+    /// so that the package can stay installed. Returns the packages that had candidates dropped.
+    pub fn pin_installed(&mut self, pins: &VersionPins) -> Vec<Pinned> {
+        let mut keep = vec![true; self.pkgs.len()];
+        let mut res = Vec::new();
+        let mut missing = Vec::new();
+        for (name, (installed, pin)) in &pins.pkgs {
+            let ids = match self.name_to_ids.get(name) {
+                Some(ids) => ids,
+                None => continue,
+            };
+            let mut pinned = Pinned {
+                name: name.clone(),
+                version: installed.version.clone(),
+                pin: *pin,
+                newer: false,
+                older: false,
+            };
+            let mut has_installed = false;
+            for (id, version) in ids {
+                match version.cmp(&installed.version) {
+                    Ordering::Equal => has_installed = true,
+                    Ordering::Greater => {
+                        if pin.hold || (pin.security_only && !self.pkgs[id - 1].security) {
+                            keep[id - 1] = false;
+                            pinned.newer = true;
+                        }
+                    }
+                    Ordering::Less => {
+                        if pin.hold || pin.no_downgrade {
+                            keep[id - 1] = false;
+                            pinned.older = true;
+                        }
+                    }
+                }
+            }
+            if pinned.newer || pinned.older {
+                if !has_installed {
+                    missing.push(installed.clone());
+                }
+                res.push(pinned);
+            }
+        }
+
+        let dropped = self.retain(keep);
+        debug!("Dropped {dropped} package versions not allowed for installed packages.");
+        if !missing.is_empty() {
+            for meta in missing {
+                self.add(meta);
+            }
+            self.finalize();
+        }
+        res.sort_by(|a, b| a.name.cmp(&b.name));
+        res
+    }
+
+    /// Drop old versions of packages according to `limit`. Must be called after finalize.
+    /// Returns the number of dropped packages.
+    ///
+    /// With a `target` distribution, the newest `limit.max` versions from it are kept as well,
+    /// so that `prefer_distribution` still has them to prefer.
+    pub fn limit_versions(
+        &mut self,
+        limit: &VersionLimit,
+        target: Option<&TargetRelease>,
+    ) -> usize {
+        let mut keep = vec![false; self.pkgs.len()];
+        for (name, pkgs) in &self.name_to_ids {
+            let installed = limit.installed.get(name);
+            // Versions seen so far that count toward the limit, overall and in the target
+            let (mut counted, mut in_target) = (0, 0);
+            for (id, version) in pkgs {
+                let pos = id - 1;
+                // Installed and local packages are always kept, and don't count toward the limit
+                let exempt = Some(version) == installed
+                    || matches!(
+                        self.pkgs[pos].source,
+                        PkgSource::Local(_) | PkgSource::Installed
+                    );
+                if exempt {
+                    keep[pos] = true;
+                    continue;
+                }
+                let is_target = target.is_some_and(|target| {
+                    self.pkgs[pos].distributions.contains(&target.distribution)
+                });
+                keep[pos] = counted < limit.max || (is_target && in_target < limit.max);
+                counted += 1;
+                if is_target {
+                    in_target += 1;
+                }
+            }
+        }
+
+        self.retain(keep)
+    }
+
+    /// Drop packages whose entry in `keep` is false, returns the number of dropped packages
+    fn retain(&mut self, keep: Vec<bool>) -> usize {
+        let dropped = keep.iter().filter(|k| !**k).count();
+        if dropped == 0 {
+            return 0;
+        }
+        // Package IDs are positions in the list, so re-build the pool
+        let pkgs = std::mem::take(&mut self.pkgs);
+        self.name_to_ids.clear();
+        for (pkg, keep) in pkgs.into_iter().zip(keep) {
+            if keep {
+                self.add(pkg);
+            }
+        }
+        self.finalize();
+
+        dropped
+    }
+
+    /// Drop candidates from other repositories than those of `filter`. Must be called after finalize.
+    ///
+    /// Only requested packages are restricted, along with their dependencies if `filter.strict`
+    /// is set. Other packages may come from any repository.
+    pub fn restrict_repos(&mut self, filter: &RepoFilter) -> Result<()> {
+        let allowed = |pkg: &PkgMeta| pkg.repos.iter().any(|repo| filter.repos.contains(repo));
+        let candidates = |name: &str| {
+            self.name_to_ids
+                .get(name)
+                .into_iter()
+                .flatten()
+                .map(|(id, _)| &self.pkgs[id - 1])
+                .filter(|pkg| allowed(pkg))
+        };
+        for name in &filter.names {
+            if candidates(name).next().is_none() {
+                bail!(
+                    "Package {} is not available from {}.",
+                    style(name).bold(),
+                    style(filter.repos.join(", ")).bold()
+                );
+            }
+        }
+
+        // Requested packages, and dependencies of their candidates if strict
+        let mut restricted: HashSet<String> = HashSet::new();
+        let mut queue: Vec<String> = filter.names.clone();
+        while let Some(name) = queue.pop() {
+            if !restricted.insert(name.clone()) || !filter.strict {
+                continue;
+            }
+            for pkg in candidates(&name) {
+                for (dep, _) in pkg.pre_depends.iter().chain(pkg.depends.iter()) {
+                    if !restricted.contains(dep) {
+                        queue.push(dep.clone());
+                    }
+                }
+            }
+        }
+
+        let keep = self
+            .pkgs
+            .iter()
+            .map(|pkg| !restricted.contains(&pkg.name) || allowed(pkg))
+            .collect();
+        let dropped = self.retain(keep);
+        debug!("Dropped {dropped} packages from other repositories than the selected ones.");
+        Ok(())
+    }
+
+    /// Re-order candidates according to `target`. Must be called after finalize.
+    ///
+    /// Requested packages and their dependencies prefer candidates from the target distribution,
+    /// while other packages prefer candidates from other distributions.
+    pub fn prefer_distribution(&mut self, target: &TargetRelease) -> Result<()> {
+        let is_target = |pkg: &PkgMeta| pkg.distributions.contains(&target.distribution);
+        if !self.pkgs.iter().any(is_target) {
+            bail!(
+                "No package is available from distribution {}.",
+                style(&target.distribution).bold()
+            );
+        }
+
+        // Find requested packages and dependencies of their candidates in the target distribution
+        let mut preferred: HashSet<String> = HashSet::new();
+        let mut queue: Vec<String> = target.names.clone();
+        while let Some(name) = queue.pop() {
+            if !preferred.insert(name.clone()) {
+                continue;
+            }
+            let candidate = self.name_to_ids.get(&name).and_then(|pkgs| {
+                pkgs.iter()
+                    .map(|(id, _)| &self.pkgs[id - 1])
+                    .find(|pkg| is_target(pkg))
+            });
+            if let Some(pkg) = candidate {
+                for (dep, _) in pkg.pre_depends.iter().chain(pkg.depends.iter()) {
+                    if !preferred.contains(dep) {
+                        queue.push(dep.clone());
+                    }
+                }
+            }
+        }
+
+        let pkgs = &self.pkgs;
+        for (name, ids) in self.name_to_ids.iter_mut() {
+            let prefer_target = preferred.contains(name);
+            // Stable sort keeps the version order within each group
+            ids.sort_by_key(|(id, _)| is_target(&pkgs[id - 1]) != prefer_target);
+        }
+
+        Ok(())
+    }
+
+    /// Re-order candidates according to `preference`. Must be called after finalize.
+    ///
+    /// Packages that are installed and not explicitly requested prefer the installed version.
+    /// With the security policy, security updates are still preferred over the installed version.
+    pub fn prefer_installed(&mut self, preference: &VersionPreference) {
+        if preference.policy == UpgradePolicy::Aggressive {
+            return;
+        }
+        let pkgs = &self.pkgs;
+        for (name, ids) in self.name_to_ids.iter_mut() {
+            if preference.explicit.contains(name) {
+                continue;
+            }
+            let installed = match preference.installed.get(name) {
+                Some(version) => version,
+                None => continue,
+            };
+            // Stable sort keeps the version order within each group
+            ids.sort_by_key(|(id, version)| {
+                let security_update = preference.policy == UpgradePolicy::Security
+                    && pkgs[id - 1].security
+                    && version > installed;
+                if security_update {
+                    0
+                } else if version == installed {
+                    1
+                } else {
+                    2
+                }
+            });
         }
     }
 }
@@ -36,6 +375,8 @@ impl BasicPkgPool for InMemoryPool {
     }
 
     fn finalize(&mut self) {
+        // Package IDs may change from now on
+        self.origins.clear();
         // Sort versions
         self.name_to_ids.iter_mut().for_each(|(_, pkgs)| {
             // Sort in descending order
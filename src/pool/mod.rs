@@ -4,14 +4,111 @@ pub use in_memory::InMemoryPool;
 
 use crate::{
     msg,
-    types::{PkgMeta, PkgSource, PkgVersion, VersionRequirement},
+    solver::SolverError,
+    types::{config::UpgradePolicy, PkgMeta, PkgSource, PkgVersion, VersionRequirement},
     warn,
 };
 
 use anyhow::{anyhow, bail, format_err, Context, Result};
 use console::style;
+use indicatif::ProgressBar;
+use std::collections::HashMap;
 use varisat::{lit::Lit, CnfFormula, ExtendFormula};
 
+/// Transitional packages may still ship documentation, but nothing larger than this (in bytes)
+const TRANSITIONAL_MAX_SIZE: u64 = 64 * 1024;
+
+/// Which fields of package metadata are loaded into the pool
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PkgFields {
+    All,
+    /// Only fields needed for dependency resolution, leaving out Description and Suggests.
+    /// See `source::debrepo::load_display_fields` to load them afterwards
+    Resolve,
+}
+
+/// Only keep the newest `max` versions of each package in the pool
+pub struct VersionLimit {
+    pub max: usize,
+    /// PkgName -> installed version, which is always kept
+    pub installed: HashMap<String, PkgVersion>,
+}
+
+/// Only take packages from certain repositories
+pub struct RepoFilter {
+    pub repos: Vec<String>,
+    /// Names of requested packages, only these are restricted to `repos`
+    pub names: Vec<String>,
+    /// Restrict dependencies of requested packages as well
+    pub strict: bool,
+}
+
+/// Prefer packages from a certain distribution (suite)
+pub struct TargetRelease {
+    pub distribution: String,
+    /// Names of requested packages. These and their dependencies prefer the target distribution,
+    /// other packages only use it if necessary
+    pub names: Vec<String>,
+}
+
+/// Prefer installed versions of packages according to an upgrade policy
+pub struct VersionPreference {
+    pub policy: UpgradePolicy,
+    /// PkgName -> installed version
+    pub installed: HashMap<String, PkgVersion>,
+    /// Names of packages requested by the user, these always prefer the newest version
+    pub explicit: Vec<String>,
+}
+
+/// How an installed package may change, see [`VersionPins`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Pin {
+    /// Stay at the installed version
+    pub hold: bool,
+    /// Only move to newer versions from security repositories
+    pub security_only: bool,
+    /// Never move to older versions
+    pub no_downgrade: bool,
+}
+
+impl Pin {
+    pub fn is_empty(&self) -> bool {
+        *self == Pin::default()
+    }
+}
+
+/// Constrain candidates of installed packages before solving, so that the solver either finds
+/// a consistent set of packages within these limits, or fails
+#[derive(Default)]
+pub struct VersionPins {
+    /// PkgName -> (installed package, pin). The installed package is read from the dpkg status
+    /// database, and only added to the pool if no repository has its version.
+    pub pkgs: HashMap<String, (PkgMeta, Pin)>,
+}
+
+/// An installed package that had candidates dropped by its pin
+#[derive(Debug, PartialEq, Eq)]
+pub struct Pinned {
+    pub name: String,
+    /// The installed version
+    pub version: PkgVersion,
+    pub pin: Pin,
+    /// Newer versions were dropped
+    pub newer: bool,
+    /// Older versions were dropped
+    pub older: bool,
+}
+
+/// Adjustments applied to the pool after importing packages, none by default
+#[derive(Default)]
+pub struct PoolOptions<'a> {
+    pub version_pins: Option<&'a VersionPins>,
+    pub repo_filter: Option<&'a RepoFilter>,
+    pub version_limit: Option<&'a VersionLimit>,
+    pub target_release: Option<&'a TargetRelease>,
+    pub version_preference: Option<&'a VersionPreference>,
+}
+
 /// The basic PkgPool interface
 pub trait BasicPkgPool {
     // Add a package to the pool
@@ -29,13 +126,13 @@ pub trait BasicPkgPool {
 }
 
 /// Additional tools
-pub trait PkgPool: BasicPkgPool {
+pub trait PkgPool: BasicPkgPool + Send + Sync {
     fn get_deps(&self, pkgid: usize) -> Result<Vec<Vec<usize>>> {
         let pkg = self
             .get_pkg_by_id(pkgid)
             .ok_or_else(|| format_err!("Package with ID {pkgid} not found."))?;
         let mut res = Vec::new();
-        for dep in &pkg.depends {
+        for dep in pkg.pre_depends.iter().chain(pkg.depends.iter()) {
             let mut deps_id = Vec::new();
             let available = match self.get_pkgs_by_name(&dep.0) {
                 Some(d) => d,
@@ -99,7 +196,7 @@ pub trait PkgPool: BasicPkgPool {
         pkgname: &str,
         ver_req: &VersionRequirement,
         need_local: bool,
-    ) -> Result<usize> {
+    ) -> Result<usize, SolverError> {
         if let Some(pkgs) = self.get_pkgs_by_name(pkgname) {
             let mut first_valid_version = true;
             for id in pkgs {
@@ -123,9 +220,12 @@ pub trait PkgPool: BasicPkgPool {
                 }
             }
             // We haven't found a suitable candidate
-            bail!("Cannot find a suitable version for {pkgname}.");
+            Err(SolverError::VersionConflict {
+                name: pkgname.to_string(),
+                requirement: ver_req.clone(),
+            })
         } else {
-            bail!("Package {pkgname} not found.");
+            Err(SolverError::PackageNotFound(pkgname.to_string()))
         }
     }
 
@@ -158,11 +258,60 @@ pub trait PkgPool: BasicPkgPool {
         None
     }
 
+    /// If the newest version of `name` is a transitional package, find the package it leads to
+    ///
+    /// A transitional package has no files but documentation, and only depends on its successor,
+    /// which Provides or Replaces it.
+    fn find_successor(&self, name: &str) -> Option<String> {
+        let id = *self.get_pkgs_by_name(name)?.first()?;
+        let pkg = self.get_pkg_by_id(id)?;
+        if pkg.install_size > TRANSITIONAL_MAX_SIZE {
+            return None;
+        }
+        let (successor, ver_req) = match (pkg.pre_depends.as_slice(), pkg.depends.as_slice()) {
+            ([dep], []) | ([], [dep]) => dep,
+            _ => return None,
+        };
+        // Successors usually replace versions before the rename, not the transitional package itself
+        let relates = |list: &Option<Vec<(String, VersionRequirement)>>| {
+            list.iter().flatten().any(|(n, _)| n == name)
+        };
+        self.get_pkgs_by_name(successor)?
+            .into_iter()
+            .filter_map(|id| self.get_pkg_by_id(id))
+            .find(|p| ver_req.contains(&p.version))
+            .filter(|p| relates(&p.provides) || relates(&p.replaces))
+            .map(|p| p.name.clone())
+    }
+
+    /// Find packages that both Conflict with and Replace the package `pkgid`
+    ///
+    /// Such packages supersede the package, e.g. when it has been renamed into a transitional package.
+    fn find_superseding(&self, pkgid: usize) -> Vec<usize> {
+        let target = match self.get_pkg_by_id(pkgid) {
+            Some(pkg) => pkg,
+            None => return Vec::new(),
+        };
+        let relates = |list: &[(String, VersionRequirement)]| {
+            list.iter()
+                .any(|(name, ver_req)| name == &target.name && ver_req.contains(&target.version))
+        };
+
+        self.pkgid_iter()
+            .filter(|(_, pkg)| {
+                pkg.name != target.name
+                    && relates(&pkg.conflicts)
+                    && pkg.replaces.as_deref().map(relates).unwrap_or(false)
+            })
+            .map(|(id, _)| id)
+            .collect()
+    }
+
     fn pkg_to_rule(&self, pkgid: usize, subset: Option<&[usize]>) -> Result<Vec<Vec<Lit>>> {
         let pkg = self.get_pkg_by_id(pkgid).unwrap();
         let mut res = Vec::new();
         // Enroll dependencies
-        for dep in &pkg.depends {
+        for dep in pkg.pre_depends.iter().chain(pkg.depends.iter()) {
             let available = match self.get_pkgs_by_name(&dep.0) {
                 Some(pkgs) => match subset {
                     Some(ids) => {
@@ -261,9 +410,21 @@ pub trait PkgPool: BasicPkgPool {
 
     fn gen_formula(&self, subset: Option<&[usize]>) -> CnfFormula {
         let mut formula = CnfFormula::new();
+        // Only show progress for the whole pool, subsets are small
+        let bar = match subset {
+            Some(_) => ProgressBar::hidden(),
+            None => crate::cli::progress_bar(
+                "Generating dependency rules",
+                Some(self.pkgid_iter().count() as u64),
+            ),
+        };
+
+        // Warnings are shown after the progress bar finishes
+        let mut ignored = Vec::new();
 
         // Generating rules from pool
         for (id, meta) in self.pkgid_iter() {
+            bar.inc(1);
             let valid = match subset {
                 Some(ids) => ids.contains(&id),
                 // If there's no subset requirement, then all packages are valid
@@ -277,11 +438,15 @@ pub trait PkgPool: BasicPkgPool {
                         }
                     }
                     Err(e) => {
-                        warn!("Ignoring package {}: {}", style(&meta.name).bold(), e);
+                        ignored.push((&meta.name, e));
                     }
                 }
             }
         }
+        bar.finish_and_clear();
+        for (name, e) in ignored {
+            warn!("Ignoring package {}: {}", style(name).bold(), e);
+        }
 
         // Generate conflict for different versions of the same package
         for (_, versions) in self.pkgname_iter() {
@@ -309,106 +474,24 @@ pub trait PkgPool: BasicPkgPool {
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::types::{PkgMeta, PkgVersion, VersionRequirement};
-    use std::path::PathBuf;
+    use crate::types::{PkgVersion, VersionRequirement};
 
     #[test]
     fn trivial_pool() {
+        use crate::types::test_pkg;
+
         let mut pool = InMemoryPool::new();
-        let a_id = pool.add(PkgMeta {
-            name: "a".to_string(),
-            description: "".to_string(),
-            section: "".to_string(),
-            version: PkgVersion::try_from("1").unwrap(),
-            depends: vec![(
-                "c".to_string(),
-                VersionRequirement {
-                    lower_bond: None,
-                    upper_bond: None,
-                },
-            )],
-            breaks: vec![(
-                "d".to_string(),
-                VersionRequirement {
-                    lower_bond: None,
-                    upper_bond: None,
-                },
-            )],
-            conflicts: Vec::new(),
-            recommends: None,
-            suggests: None,
-            replaces: None,
-            provides: None,
-            install_size: 0,
-            essential: false,
-            source: PkgSource::Local(PathBuf::new()),
-        });
-        let b_id = pool.add(PkgMeta {
-            name: "b".to_string(),
-            description: "".to_string(),
-            section: "".to_string(),
-            version: PkgVersion::try_from("1").unwrap(),
-            depends: vec![(
-                "a".to_string(),
-                VersionRequirement {
-                    lower_bond: None,
-                    upper_bond: None,
-                },
-            )],
-            breaks: Vec::new(),
-            conflicts: Vec::new(),
-            recommends: None,
-            suggests: None,
-            replaces: None,
-            provides: None,
-            install_size: 0,
-            essential: false,
-            source: PkgSource::Local(PathBuf::new()),
-        });
-        let c_id = pool.add(PkgMeta {
-            name: "c".to_string(),
-            description: "".to_string(),
-            section: "".to_string(),
-            version: PkgVersion::try_from("1").unwrap(),
-            depends: vec![(
-                "b".to_string(),
-                VersionRequirement {
-                    lower_bond: None,
-                    upper_bond: None,
-                },
-            )],
-            breaks: Vec::new(),
-            conflicts: Vec::new(),
-            recommends: None,
-            suggests: None,
-            replaces: None,
-            provides: None,
-            install_size: 0,
-            essential: false,
-            source: PkgSource::Local(PathBuf::new()),
-        });
-        let d_id = pool.add(PkgMeta {
-            name: "d".to_string(),
-            description: "".to_string(),
-            section: "".to_string(),
-            version: PkgVersion::try_from("1").unwrap(),
-            depends: vec![(
-                "b".to_string(),
-                VersionRequirement {
-                    lower_bond: None,
-                    upper_bond: None,
-                },
-            )],
-            breaks: Vec::new(),
-            conflicts: Vec::new(),
-            recommends: None,
-            suggests: None,
-            replaces: None,
-            provides: None,
-            install_size: 0,
-            essential: false,
-            source: PkgSource::Local(PathBuf::new()),
-        });
+        let any = VersionRequirement::default;
+        let pkg = |name, depends: &str, breaks: Option<&str>| {
+            let mut pkg = test_pkg(name, "1");
+            pkg.depends = vec![(depends.to_string(), any())];
+            pkg.breaks = breaks.map(|b| (b.to_string(), any())).into_iter().collect();
+            pkg
+        };
+        let a_id = pool.add(pkg("a", "c", Some("d")));
+        let b_id = pool.add(pkg("b", "a", None));
+        let c_id = pool.add(pkg("c", "b", None));
+        let d_id = pool.add(pkg("d", "b", None));
         pool.finalize();
 
         let mut solver = varisat::Solver::new();
@@ -427,4 +510,416 @@ mod test {
             ]
         );
     }
+
+    #[test]
+    fn test_limit_versions() {
+        use crate::types::{test_pkg, Checksum};
+
+        let pkg = |name, version| {
+            let mut pkg = test_pkg(name, version);
+            let checksum = Checksum::from_sha256_str(&"0".repeat(64)).unwrap();
+            pkg.source = PkgSource::Http((String::new(), 0, checksum));
+            pkg
+        };
+        let mut pool = InMemoryPool::new();
+        for version in ["1.0", "1.1", "1.2", "1.3"] {
+            pool.add(pkg("a", version));
+        }
+        pool.add(pkg("b", "1.0"));
+        // Local packages are always kept
+        pool.add(test_pkg("a", "0.9"));
+        pool.finalize();
+
+        let limit = VersionLimit {
+            max: 2,
+            installed: HashMap::from([("a".to_string(), PkgVersion::try_from("1.0").unwrap())]),
+        };
+        assert_eq!(pool.limit_versions(&limit, None), 1);
+        let versions: Vec<String> = pool
+            .get_pkgs_by_name("a")
+            .unwrap()
+            .into_iter()
+            .map(|id| pool.get_pkg_by_id(id).unwrap().version.to_string())
+            .collect();
+        assert_eq!(versions, vec!["1.3", "1.2", "1.0", "0.9"]);
+        assert_eq!(pool.get_pkgs_by_name("b").unwrap().len(), 1);
+        assert_eq!(pool.pkgid_iter().count(), 5);
+
+        // The installed version doesn't take the place of an available one
+        let mut pool = InMemoryPool::new();
+        for version in ["1.0", "1.1", "1.2", "1.3"] {
+            pool.add(pkg("a", version));
+        }
+        pool.finalize();
+        let limit = VersionLimit {
+            max: 2,
+            installed: HashMap::from([("a".to_string(), PkgVersion::try_from("1.3").unwrap())]),
+        };
+        assert_eq!(pool.limit_versions(&limit, None), 1);
+        let versions: Vec<String> = pool
+            .get_pkgs_by_name("a")
+            .unwrap()
+            .into_iter()
+            .map(|id| pool.get_pkg_by_id(id).unwrap().version.to_string())
+            .collect();
+        assert_eq!(versions, vec!["1.3", "1.2", "1.1"]);
+
+        // Older versions from the target distribution are kept for prefer_distribution
+        let mut pool = InMemoryPool::new();
+        for (version, distribution) in [("1.0", "stable"), ("1.1", "stable"), ("2.0", "testing")] {
+            let mut pkg = pkg("a", version);
+            pkg.distributions = vec![distribution.to_string()];
+            pool.add(pkg);
+        }
+        pool.finalize();
+        let limit = VersionLimit {
+            max: 1,
+            installed: HashMap::new(),
+        };
+        let target = TargetRelease {
+            distribution: "stable".to_string(),
+            names: vec!["a".to_string()],
+        };
+        assert_eq!(pool.limit_versions(&limit, Some(&target)), 1);
+        pool.prefer_distribution(&target).unwrap();
+        let versions: Vec<String> = pool
+            .get_pkgs_by_name("a")
+            .unwrap()
+            .into_iter()
+            .map(|id| pool.get_pkg_by_id(id).unwrap().version.to_string())
+            .collect();
+        assert_eq!(versions, vec!["1.1", "2.0"]);
+    }
+
+    #[test]
+    fn test_duplicate_pkgs() {
+        use crate::types::{test_pkg, Checksum};
+
+        let pkg = |name, version, host: &str, hash: char| {
+            let mut pkg = test_pkg(name, version);
+            let url = format!("https://{host}/debs/pool/main/{name}_{version}_amd64.deb");
+            let checksum = Checksum::from_sha256_str(&hash.to_string().repeat(64)).unwrap();
+            pkg.source = PkgSource::Http((url, 0, checksum));
+            pkg
+        };
+        let mut pool = InMemoryPool::new();
+        let a = pool.add_from_repo(pkg("a", "1.0", "repo.example.org", '0'), "main");
+        let mut security_a = pkg("a", "1.0", "security.example.org", '0');
+        security_a.security = true;
+        security_a.distributions = vec!["security".to_string()];
+        let b = pool.add_from_repo(pkg("b", "1.0", "repo.example.org", '0'), "main");
+        // Same package in an overlapping repository
+        assert_eq!(
+            pool.add_from_repo(pkg("a", "1.0", "mirror.example.org", '0'), "mirror"),
+            a
+        );
+        assert_ne!(
+            pool.add_from_repo(pkg("a", "1.1", "mirror.example.org", '0'), "mirror"),
+            a
+        );
+        assert_eq!(pool.add_from_repo(security_a, "security"), a);
+        // Different content under the same name and version, the first one wins
+        assert_eq!(
+            pool.add_from_repo(pkg("b", "1.0", "mirror.example.org", '1'), "mirror"),
+            b
+        );
+        assert_eq!(
+            pool.add_from_repo(pkg("b", "1.0", "repo.example.org", '1'), "main"),
+            b
+        );
+        pool.finalize();
+
+        assert_eq!(pool.pkgid_iter().count(), 3);
+        assert_eq!(pool.get_pkgs_by_name("a").unwrap().len(), 2);
+        assert_eq!(
+            pool.get_pkg_by_id(a).unwrap().mirror_urls(),
+            vec![
+                "https://mirror.example.org/debs/pool/main/a_1.0_amd64.deb",
+                "https://security.example.org/debs/pool/main/a_1.0_amd64.deb"
+            ]
+        );
+        assert!(pool.get_pkg_by_id(b).unwrap().mirror_urls().is_empty());
+        assert_eq!(
+            pool.get_pkg_by_id(a).unwrap().repos,
+            vec!["main", "mirror", "security"]
+        );
+        // Provided by a security repository as well
+        assert!(pool.get_pkg_by_id(a).unwrap().security);
+        assert_eq!(
+            pool.get_pkg_by_id(a).unwrap().distributions,
+            vec!["security"]
+        );
+        assert_eq!(pool.get_pkg_by_id(b).unwrap().repos, vec!["main"]);
+    }
+
+    #[test]
+    fn test_restrict_repos() {
+        use crate::{
+            solver::Solver,
+            types::{test_pkg, Checksum},
+        };
+
+        let pkg = |name, version, repo: &str| {
+            let mut pkg = test_pkg(name, version);
+            let url = format!("https://{repo}.example.org/debs/{name}_{version}_amd64.deb");
+            pkg.source = PkgSource::Http((url, 0, Checksum::Sha256(Vec::new())));
+            pkg
+        };
+        let build_pool = || {
+            let mut pool = InMemoryPool::new();
+            // foo is in both repositories, the one in extra is newer
+            for (version, repo) in [("1.0", "main"), ("2.0", "extra")] {
+                let mut foo = pkg("foo", version, repo);
+                foo.depends = vec![("libfoo".to_string(), VersionRequirement::default())];
+                pool.add_from_repo(foo, repo);
+                pool.add_from_repo(pkg("libfoo", version, repo), repo);
+            }
+            pool.finalize();
+            pool
+        };
+        let filter = |strict| RepoFilter {
+            repos: vec!["main".to_string()],
+            names: vec!["foo".to_string()],
+            strict,
+        };
+        let install = |pool: InMemoryPool| {
+            let solver = Solver::from(Box::new(pool) as Box<dyn PkgPool>);
+            let mut res: Vec<String> = solver
+                .resolve(&[("foo".to_string(), VersionRequirement::default())])
+                .unwrap()
+                .into_iter()
+                .map(|pkg| format!("{}={}", pkg.name, pkg.version))
+                .collect();
+            res.sort();
+            res
+        };
+        assert_eq!(install(build_pool()), vec!["foo=2.0", "libfoo=2.0"]);
+
+        // Dependencies may still come from other repositories
+        let mut pool = build_pool();
+        pool.restrict_repos(&filter(false)).unwrap();
+        assert_eq!(pool.get_pkgs_by_name("foo").unwrap().len(), 1);
+        assert_eq!(install(pool), vec!["foo=1.0", "libfoo=2.0"]);
+
+        let mut pool = build_pool();
+        pool.restrict_repos(&filter(true)).unwrap();
+        assert_eq!(install(pool), vec!["foo=1.0", "libfoo=1.0"]);
+
+        let mut pool = build_pool();
+        let mut missing = filter(false);
+        missing.repos = vec!["third-party".to_string()];
+        assert!(pool.restrict_repos(&missing).is_err());
+    }
+
+    #[test]
+    fn test_pin_installed() {
+        use crate::types::test_pkg;
+
+        let pkg = |name, version, security| {
+            let mut pkg = test_pkg(name, version);
+            pkg.security = security;
+            pkg
+        };
+        let build_pool = || {
+            let mut pool = InMemoryPool::new();
+            pool.add(pkg("a", "1.0", false));
+            pool.add(pkg("a", "1.1", true));
+            pool.add(pkg("a", "1.2", false));
+            // The installed version 2.0 of b is no longer available
+            pool.add(pkg("b", "1.0", false));
+            pool.add(pkg("b", "3.0", false));
+            pool.finalize();
+            pool
+        };
+        let versions = |pool: &InMemoryPool, name| -> Vec<String> {
+            pool.get_pkgs_by_name(name)
+                .unwrap()
+                .into_iter()
+                .map(|id| pool.get_pkg_by_id(id).unwrap().version.to_string())
+                .collect()
+        };
+        let pins = |pin: Pin| VersionPins {
+            pkgs: HashMap::from([
+                ("a".to_string(), (test_pkg("a", "1.0"), pin)),
+                ("b".to_string(), (test_pkg("b", "2.0"), pin)),
+            ]),
+        };
+
+        let mut pool = build_pool();
+        let security_only = Pin {
+            security_only: true,
+            ..Default::default()
+        };
+        let pinned = pool.pin_installed(&pins(security_only));
+        assert_eq!(versions(&pool, "a"), vec!["1.1", "1.0"]);
+        assert_eq!(versions(&pool, "b"), vec!["2.0", "1.0"]);
+        assert_eq!(pinned.len(), 2);
+        assert!(pinned.iter().all(|p| p.newer && !p.older));
+
+        let mut pool = build_pool();
+        let hold = Pin {
+            hold: true,
+            ..Default::default()
+        };
+        let pinned = pool.pin_installed(&pins(hold));
+        assert_eq!(versions(&pool, "a"), vec!["1.0"]);
+        assert_eq!(versions(&pool, "b"), vec!["2.0"]);
+        assert!(pinned.iter().all(|p| p.newer));
+        assert_eq!(
+            pinned.iter().map(|p| p.older).collect::<Vec<_>>(),
+            vec![false, true]
+        );
+
+        let mut pool = build_pool();
+        let no_downgrade = Pin {
+            no_downgrade: true,
+            ..Default::default()
+        };
+        let pinned = pool.pin_installed(&pins(no_downgrade));
+        assert_eq!(versions(&pool, "a"), vec!["1.2", "1.1", "1.0"]);
+        assert_eq!(versions(&pool, "b"), vec!["3.0", "2.0"]);
+        assert_eq!(pinned.len(), 1);
+        assert!(pinned[0].older && !pinned[0].newer);
+
+        // Nothing to drop
+        let mut pool = build_pool();
+        assert!(pool.pin_installed(&pins(Pin::default())).is_empty());
+        assert_eq!(versions(&pool, "b"), vec!["3.0", "1.0"]);
+    }
+
+    #[test]
+    fn test_prefer_installed() {
+        use crate::types::test_pkg;
+
+        let pkg = |name, version, security| {
+            let mut pkg = test_pkg(name, version);
+            pkg.security = security;
+            pkg
+        };
+        let build_pool = || {
+            let mut pool = InMemoryPool::new();
+            pool.add(pkg("a", "1.0", false));
+            pool.add(pkg("a", "1.1", true));
+            pool.add(pkg("a", "1.2", false));
+            pool.add(pkg("b", "1.0", false));
+            pool.add(pkg("b", "2.0", false));
+            pool.add(pkg("c", "1.0", false));
+            pool.add(pkg("c", "2.0", false));
+            pool.finalize();
+            pool
+        };
+        let best = |pool: &InMemoryPool, name| {
+            let id = pool.get_pkgs_by_name(name).unwrap()[0];
+            pool.get_pkg_by_id(id).unwrap().version.to_string()
+        };
+        let preference = |policy| VersionPreference {
+            policy,
+            installed: HashMap::from([
+                ("a".to_string(), PkgVersion::try_from("1.0").unwrap()),
+                ("b".to_string(), PkgVersion::try_from("1.0").unwrap()),
+                ("c".to_string(), PkgVersion::try_from("1.0").unwrap()),
+            ]),
+            explicit: vec!["c".to_string()],
+        };
+
+        let mut pool = build_pool();
+        pool.prefer_installed(&preference(UpgradePolicy::Aggressive));
+        assert_eq!(best(&pool, "a"), "1.2");
+        assert_eq!(best(&pool, "b"), "2.0");
+
+        let mut pool = build_pool();
+        pool.prefer_installed(&preference(UpgradePolicy::Conservative));
+        assert_eq!(best(&pool, "a"), "1.0");
+        assert_eq!(best(&pool, "b"), "1.0");
+        // Explicitly requested packages still move to the newest version
+        assert_eq!(best(&pool, "c"), "2.0");
+
+        let mut pool = build_pool();
+        pool.prefer_installed(&preference(UpgradePolicy::Security));
+        assert_eq!(best(&pool, "a"), "1.1");
+        assert_eq!(best(&pool, "b"), "1.0");
+        assert_eq!(best(&pool, "c"), "2.0");
+    }
+
+    #[test]
+    fn test_prefer_distribution() {
+        use crate::types::test_pkg;
+
+        let pkg = |name, version, distribution: &str| {
+            let mut pkg = test_pkg(name, version);
+            pkg.distributions = vec![distribution.to_string()];
+            pkg
+        };
+        let mut pool = InMemoryPool::new();
+        // foo in testing depends on libfoo, which is also in testing
+        let mut foo = pkg("foo", "1.0", "testing");
+        foo.depends = vec![("libfoo".to_string(), VersionRequirement::default())];
+        pool.add(foo);
+        pool.add(pkg("foo", "2.0", "stable"));
+        pool.add(pkg("libfoo", "1.0", "testing"));
+        pool.add(pkg("libfoo", "2.0", "stable"));
+        // bar is not requested, it should stay in stable
+        pool.add(pkg("bar", "2.0", "testing"));
+        pool.add(pkg("bar", "1.0", "stable"));
+        pool.finalize();
+
+        let target = TargetRelease {
+            distribution: "testing".to_string(),
+            names: vec!["foo".to_string()],
+        };
+        pool.prefer_distribution(&target).unwrap();
+        let best = |name| {
+            let id = pool.get_pkgs_by_name(name).unwrap()[0];
+            let pkg = pool.get_pkg_by_id(id).unwrap();
+            format!("{}={}", pkg.version, pkg.distributions.join(","))
+        };
+        assert_eq!(best("foo"), "1.0=testing");
+        assert_eq!(best("libfoo"), "1.0=testing");
+        assert_eq!(best("bar"), "1.0=stable");
+
+        let target = TargetRelease {
+            distribution: "unstable".to_string(),
+            names: vec!["foo".to_string()],
+        };
+        assert!(pool.prefer_distribution(&target).is_err());
+    }
+
+    #[test]
+    fn test_find_successor() {
+        use crate::types::test_pkg;
+
+        let any = VersionRequirement::default;
+        let mut pool = InMemoryPool::new();
+        // foo has been renamed to foo-ng, foo is now an empty transitional package
+        let mut foo = test_pkg("foo", "2.0");
+        foo.depends = vec![("foo-ng".to_string(), any())];
+        pool.add(foo);
+        let mut foo_ng = test_pkg("foo-ng", "2.0");
+        foo_ng.replaces = Some(vec![(
+            "foo".to_string(),
+            VersionRequirement::try_from("<< 2.0").unwrap(),
+        )]);
+        foo_ng.install_size = 1024 * 1024;
+        pool.add(foo_ng);
+        // bar is a small package that depends on libbar, which has nothing to do with it
+        let mut bar = test_pkg("bar", "1.0");
+        bar.depends = vec![("libbar".to_string(), any())];
+        pool.add(bar);
+        pool.add(test_pkg("libbar", "1.0"));
+        // baz provides the virtual package baz-compat, which has files of its own
+        let mut compat = test_pkg("baz-compat", "1.0");
+        compat.depends = vec![("baz".to_string(), any())];
+        compat.install_size = 1024 * 1024;
+        pool.add(compat);
+        let mut baz = test_pkg("baz", "1.0");
+        baz.provides = Some(vec![("baz-compat".to_string(), any())]);
+        pool.add(baz);
+        pool.finalize();
+
+        assert_eq!(pool.find_successor("foo").as_deref(), Some("foo-ng"));
+        assert_eq!(pool.find_successor("foo-ng"), None);
+        assert_eq!(pool.find_successor("bar"), None);
+        assert_eq!(pool.find_successor("baz-compat"), None);
+        assert_eq!(pool.find_successor("nonexistent"), None);
+    }
 }
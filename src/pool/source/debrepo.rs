@@ -1,22 +1,27 @@
 /// Utilities to deal with deb package db
+use super::PROGRESS_BATCH;
 use crate::{
-    pool::PkgPool,
-    types::{Checksum, PkgMeta, PkgSource, PkgVersion},
+    db::PkgDb,
+    pool::{InMemoryPool, PkgFields},
+    types::{Checksum, PkgMeta, PkgMirrors, PkgSource, PkgVersion, Priority},
     utils::debcontrol::parse_pkg_list,
     warn,
 };
 use anyhow::{bail, format_err, Result};
 use debcontrol::{BufParse, Streaming};
+use indicatif::ProgressBar;
 use rayon::prelude::*;
-use std::{collections::HashMap, fs::File, path::Path};
+use std::{collections::HashMap, fs::File};
 
 const INTERESTED_FIELDS: &[&str] = &[
     "Package",
+    "Architecture",
     "Description",
     "Filename",
     "Section",
     "Version",
     "Depends",
+    "Pre-Depends",
     "Breaks",
     "Conflicts",
     "Recommends",
@@ -28,79 +33,158 @@ const INTERESTED_FIELDS: &[&str] = &[
     "SHA256",
     "SHA512",
     "Essential",
+    "Priority",
 ];
 
+/// Fields only used to show packages, left out with `PkgFields::Resolve`
+const DISPLAY_FIELDS: &[&str] = &["Description", "Suggests"];
+
 #[inline]
-pub fn import(db: &Path, pool: &mut dyn PkgPool, baseurl: &str) -> Result<()> {
-    let f = File::open(db)?;
-    let mut buf_parse = BufParse::new(f, 16384);
+pub fn import(
+    db: &PkgDb,
+    pool: &mut InMemoryPool,
+    fields: PkgFields,
+    bar: &ProgressBar,
+) -> Result<()> {
     let mut pkgs = Vec::new();
+    for_each_paragraph(db, fields, |paragraph| {
+        // Indices of flat repositories have packages of all architectures
+        let arch = paragraph.get("Architecture");
+        if arch.is_none_or(|arch| arch == &db.arch || arch == "all") {
+            pkgs.push(paragraph);
+        }
+        true
+    })?;
+
+    // Parse fields in parallel
+    let pkgmetas: Vec<PkgMeta> = pkgs
+        .into_par_iter()
+        .filter_map(
+            |paragraph| match fields_to_packagemeta(paragraph, db, fields) {
+                Ok(res) => Some(res),
+                Err(e) => {
+                    warn!("Invalid entry in package metadata: {} .", e);
+                    None
+                }
+            },
+        )
+        .collect();
+    // Import results into pool
+    let len = pkgmetas.len();
+    bar.inc_length(len as u64);
+    for (i, pkgmeta) in pkgmetas.into_iter().enumerate() {
+        pool.add_from_repo(pkgmeta, &db.repo);
+        if (i + 1) % PROGRESS_BATCH == 0 {
+            bar.inc(PROGRESS_BATCH as u64);
+        }
+    }
+    bar.inc((len % PROGRESS_BATCH) as u64);
+
+    Ok(())
+}
+
+/// Fill in fields left out with `PkgFields::Resolve`, by reading the package from `dbs` again
+///
+/// Returns false if the package can't be found in `dbs`.
+pub fn load_display_fields(dbs: &[PkgDb], pkg: &mut PkgMeta) -> Result<bool> {
+    let url = match &pkg.source {
+        PkgSource::Http((url, _, _)) => url,
+        // Local packages are always complete
+        PkgSource::Local(_) | PkgSource::Installed => return Ok(true),
+    };
+    let version = pkg.version.to_string();
+    for db in dbs.iter().filter(|db| url.starts_with(&db.baseurl)) {
+        let mut found = None;
+        for_each_paragraph(db, PkgFields::All, |paragraph| {
+            if paragraph.get("Package") == Some(&pkg.name)
+                && paragraph.get("Version") == Some(&version)
+            {
+                found = Some(paragraph);
+            }
+            found.is_none()
+        })?;
+        if let Some(paragraph) = found {
+            let full = fields_to_packagemeta(paragraph, db, PkgFields::All)?;
+            pkg.description = full.description;
+            pkg.suggests = full.suggests;
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+/// Call `f` with fields of each package in `db`, until it returns false
+fn for_each_paragraph(
+    db: &PkgDb,
+    fields: PkgFields,
+    mut f: impl FnMut(HashMap<String, String>) -> bool,
+) -> Result<()> {
+    let file = File::open(&db.path)?;
+    let mut buf_parse = BufParse::new(file, 16384);
+    let wanted = |name: &str| {
+        INTERESTED_FIELDS.contains(&name)
+            && (fields == PkgFields::All || !DISPLAY_FIELDS.contains(&name))
+    };
 
     while let Some(result) = buf_parse.try_next().unwrap() {
         match result {
             Streaming::Item(paragraph) => {
                 let mut fields = HashMap::new();
                 for field in paragraph.fields {
-                    if INTERESTED_FIELDS.contains(&field.name) {
+                    if wanted(field.name) {
                         fields.insert(field.name.to_string(), field.value);
                     }
                 }
-                pkgs.push(fields);
+                if !f(fields) {
+                    break;
+                }
             }
             Streaming::Incomplete => buf_parse.buffer().unwrap(),
         }
     }
 
-    // Parse fields in parallel
-    let pkgmetas: Vec<PkgMeta> = pkgs
-        .into_par_iter()
-        .filter_map(|fields| match fields_to_packagemeta(fields, baseurl) {
-            Ok(res) => Some(res),
-            Err(e) => {
-                warn!("Invalid entry in package metadata: {} .", e);
-                None
-            }
-        })
-        .collect();
-    // Import results into pool
-    for pkgmeta in pkgmetas {
-        pool.add(pkgmeta);
-    }
-
     Ok(())
 }
 
 #[inline]
-fn fields_to_packagemeta(mut f: HashMap<String, String>, baseurl: &str) -> Result<PkgMeta> {
+fn fields_to_packagemeta(
+    mut f: HashMap<String, String>,
+    db: &PkgDb,
+    fields: PkgFields,
+) -> Result<PkgMeta> {
     // Get name first, for error reporting
     let name = f.remove("Package").ok_or_else(|| {
         format_err!("Package metadata does not define a package name (Package field missing).")
     })?;
     // Generate real url
-    let mut path = baseurl.to_string();
-    path.push('/');
-    path.push_str(f.get("Filename").ok_or_else(|| {
+    let filename = f.remove("Filename").ok_or_else(|| {
         format_err!(
             "Metadata for package {} does not contain the Filename field.",
             name
         )
-    })?);
+    })?;
+    let path = format!("{}/{}", db.baseurl, filename);
     Ok(PkgMeta {
         name: name.clone(),
         section: f
             .remove("Section")
             .ok_or_else(|| format_err!("Metadata for package {} does not contain the Section field.", name))?,
-        description: f
-            .remove("Description")
-            .ok_or_else(|| format_err!("Metadata for package {} does not contain the Description field.", name))?,
+        description: match fields {
+            PkgFields::All => f
+                .remove("Description")
+                .ok_or_else(|| format_err!("Metadata for package {} does not contain the Description field.", name))?,
+            PkgFields::Resolve => String::new(),
+        },
         version: PkgVersion::try_from(
             f.get("Version")
                 .ok_or_else(|| format_err!("Metadata for package {} does not contain the Version field.", name))?
                 .as_str(),
         )?,
-        depends: parse_pkg_list(f.get("Depends").unwrap_or(&String::new()))?,
-        breaks: parse_pkg_list(f.get("Breaks").unwrap_or(&String::new()))?,
-        conflicts: parse_pkg_list(f.get("Conflicts").unwrap_or(&String::new()))?,
+        depends: parse_pkg_list(f.get("Depends").unwrap_or(&String::new()), &db.arch)?,
+        pre_depends: parse_pkg_list(f.get("Pre-Depends").unwrap_or(&String::new()), &db.arch)?,
+        breaks: parse_pkg_list(f.get("Breaks").unwrap_or(&String::new()), &db.arch)?,
+        conflicts: parse_pkg_list(f.get("Conflicts").unwrap_or(&String::new()), &db.arch)?,
         // Installed-Size is in kilobytes, multiply by 1024 to convert it to bytes
         install_size: f
             .remove("Installed-Size")
@@ -109,19 +193,19 @@ fn fields_to_packagemeta(mut f: HashMap<String, String>, baseurl: &str) -> Resul
             .parse()
             .map(|kb: u64| 1024 * kb)?,
         recommends: match f.get("Recommends") {
-            Some(recomm) => Some(parse_pkg_list(recomm)?),
+            Some(recomm) => Some(parse_pkg_list(recomm, &db.arch)?),
             None => None,
         },
         suggests: match f.get("Suggests") {
-            Some(suggests) => Some(parse_pkg_list(suggests)?),
+            Some(suggests) => Some(parse_pkg_list(suggests, &db.arch)?),
             None => None,
         },
         provides: match f.get("Provides") {
-            Some(provides) => Some(parse_pkg_list(provides)?),
+            Some(provides) => Some(parse_pkg_list(provides, &db.arch)?),
             None => None,
         },
         replaces: match f.get("Replaces") {
-            Some(replaces) => Some(parse_pkg_list(replaces)?),
+            Some(replaces) => Some(parse_pkg_list(replaces, &db.arch)?),
             None => None,
         },
         essential: match f.get("Essential") {
@@ -136,6 +220,8 @@ fn fields_to_packagemeta(mut f: HashMap<String, String>, baseurl: &str) -> Resul
             },
             None => false,
         },
+        // Unknown priorities are no different from missing ones
+        priority: f.get("Priority").and_then(|p| Priority::parse(p)),
         source: PkgSource::Http((
             path,
             f.remove("Size")
@@ -155,5 +241,129 @@ fn fields_to_packagemeta(mut f: HashMap<String, String>, baseurl: &str) -> Resul
                 }
             },
         )),
+        security: db.security,
+        distributions: vec![db.distribution.clone()],
+        // All mirrors of the repository, the URL above is skipped when downloading
+        mirrors: vec![PkgMirrors {
+            bases: db.bases.clone(),
+            path: filename,
+        }],
+        repos: Vec::new(),
     })
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::types::test_pkg;
+    use crate::utils::temp_dir::TempDir;
+    use std::{path::PathBuf, sync::Arc};
+
+    #[test]
+    fn test_parse_replaces() {
+        let fields: HashMap<String, String> = [
+            ("Package", "new-pkg"),
+            ("Section", "utils"),
+            ("Description", "A renamed package"),
+            ("Version", "2.0"),
+            ("Filename", "pool/main/n/new-pkg_2.0_amd64.deb"),
+            ("Installed-Size", "4"),
+            ("Size", "1024"),
+            ("SHA256", &"0".repeat(64)),
+            ("Conflicts", "old-pkg (<< 2.0)"),
+            ("Replaces", "old-pkg (<< 2.0), other-pkg"),
+            ("Priority", "optional"),
+        ]
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+        let db = PkgDb {
+            baseurl: "https://example.org".to_string(),
+            path: PathBuf::new(),
+            security: false,
+            distribution: "stable".to_string(),
+            arch: "amd64".to_string(),
+            repo: "test".to_string(),
+            bases: Arc::from(vec![
+                "https://example.org".to_string(),
+                "https://mirror.example.org/debs".to_string(),
+            ]),
+        };
+        let pkg = fields_to_packagemeta(fields, &db, PkgFields::All).unwrap();
+        // Same file on the other mirror
+        assert_eq!(
+            pkg.mirror_urls(),
+            vec!["https://mirror.example.org/debs/pool/main/n/new-pkg_2.0_amd64.deb"]
+        );
+        assert_eq!(pkg.distributions, vec!["stable"]);
+        assert_eq!(pkg.priority, Some(Priority::Optional));
+        let replaces = pkg.replaces.unwrap();
+        assert_eq!(replaces.len(), 2);
+        assert_eq!(replaces[0].0, "old-pkg");
+        assert!(replaces[0]
+            .1
+            .contains(&PkgVersion::try_from("1.0").unwrap()));
+        assert!(!replaces[0]
+            .1
+            .contains(&PkgVersion::try_from("2.0").unwrap()));
+        assert_eq!(replaces[1].0, "other-pkg");
+        assert_eq!(pkg.conflicts[0].0, "old-pkg");
+    }
+
+    #[test]
+    fn test_trimmed_fields() {
+        use crate::pool::BasicPkgPool;
+
+        let dir = TempDir::new("packages");
+        let path = dir.join("Packages");
+        let entry = |name: &str, version: &str| {
+            format!(
+                "Package: {name}\nVersion: {version}\nSection: shells\nInstalled-Size: 4\nFilename: pool/{name}_{version}_amd64.deb\nSize: 1024\nSHA256: {}\nSuggests: {name}-doc\nPriority: standard\nDescription: The {name} shell\n",
+                "0".repeat(64)
+            )
+        };
+        std::fs::write(
+            &path,
+            [
+                entry("bash", "5.1"),
+                entry("bash", "5.2"),
+                entry("zsh", "5.9"),
+            ]
+            .join("\n"),
+        )
+        .unwrap();
+        let db = PkgDb {
+            baseurl: "https://example.org".to_string(),
+            path: path.clone(),
+            security: false,
+            distribution: "stable".to_string(),
+            arch: "amd64".to_string(),
+            repo: "test".to_string(),
+            bases: Arc::from(Vec::new()),
+        };
+        let mut pool = InMemoryPool::new();
+        let bar = ProgressBar::hidden();
+        import(&db, &mut pool, PkgFields::Resolve, &bar).unwrap();
+        pool.finalize();
+        assert_eq!(bar.position(), pool.pkgid_iter().count() as u64);
+        let id = pool.get_pkgs_by_name("bash").unwrap()[1];
+        let mut pkg = pool.get_pkg_by_id(id).unwrap().clone();
+        assert_eq!(pkg.version, PkgVersion::try_from("5.1").unwrap());
+        assert!(pkg.description.is_empty());
+        assert!(pkg.suggests.is_none());
+        // Needed to pick packages by priority
+        assert_eq!(pkg.priority, Some(Priority::Standard));
+
+        // Read again for display
+        assert!(load_display_fields(std::slice::from_ref(&db), &mut pkg).unwrap());
+        assert_eq!(pkg.description, "The bash shell");
+        assert_eq!(pkg.suggests.unwrap()[0].0, "bash-doc");
+        let mut missing = test_pkg("fish", "3.0");
+        missing.source = PkgSource::Http((
+            "https://example.org/pool/fish_3.0_amd64.deb".to_string(),
+            0,
+            Checksum::Sha256(Vec::new()),
+        ));
+        assert!(!load_display_fields(&[db], &mut missing).unwrap());
+    }
+}
@@ -1,12 +1,20 @@
 use crate::{
     debug,
-    types::{PkgMeta, PkgSource, PkgVersion},
+    types::{PkgMeta, PkgSource, PkgVersion, Priority},
     utils::debcontrol::parse_pkg_list,
 };
 
 use anyhow::{bail, format_err, Context, Result};
+use flate2::read::GzDecoder;
 use rayon::prelude::*;
-use std::{collections::HashMap, ffi::OsStr, fs::File, io::prelude::*, path::Path};
+use std::{
+    collections::HashMap,
+    ffi::OsStr,
+    fs::File,
+    io::{prelude::*, Cursor},
+    path::Path,
+    process::{Command, Stdio},
+};
 use tar::Archive;
 use xz2::read::XzDecoder;
 
@@ -41,31 +49,115 @@ pub fn read_control_from_deb(p: &Path) -> Result<PkgMeta> {
         File::open(p).context(format!("Failed to open deb file {} .", p.display()))?,
     );
     while let Some(entry) = archive.next_entry() {
-        let entry = entry?;
-        let filename = std::str::from_utf8(entry.header().identifier())?;
-        if filename == "control.tar.xz" {
-            let xzdecoder = XzDecoder::new(entry);
-            let mut tar = Archive::new(xzdecoder);
-            for file in tar.entries()? {
-                let mut file = file?;
-                let path = file
-                    .header()
-                    .path()?
-                    .to_str()
-                    .unwrap_or_default()
-                    .to_owned();
-                if path == "./control" {
-                    let mut res = String::new();
-                    file.read_to_string(&mut res)?;
-                    let res = parse_debcontrol(&res, p)?;
-                    return Ok(res);
-                }
+        let reader = match tar_member(entry?, "control", p)? {
+            Some(reader) => reader,
+            None => continue,
+        };
+        let mut tar = Archive::new(reader);
+        for file in tar.entries()? {
+            let mut file = file?;
+            let path = file
+                .header()
+                .path()?
+                .to_str()
+                .unwrap_or_default()
+                .to_owned();
+            if path == "./control" {
+                let mut res = String::new();
+                file.read_to_string(&mut res)?;
+                let res = parse_debcontrol(&res, p)?;
+                return Ok(res);
             }
         }
     }
     bail!("Malformed deb file.")
 }
 
+/// Read the first of `paths` (like `./usr/share/doc/...`) found in the data member of a deb
+pub fn read_file_from_deb(p: &Path, paths: &[&str]) -> Result<Option<Vec<u8>>> {
+    let mut archive = ar::Archive::new(
+        File::open(p).context(format!("Failed to open deb file {} .", p.display()))?,
+    );
+    while let Some(entry) = archive.next_entry() {
+        let reader = match tar_member(entry?, "data", p)? {
+            Some(reader) => reader,
+            None => continue,
+        };
+        let mut found: HashMap<String, Vec<u8>> = HashMap::new();
+        let mut tar = Archive::new(reader);
+        for file in tar.entries()? {
+            let mut file = file?;
+            let path = file
+                .header()
+                .path()?
+                .to_str()
+                .unwrap_or_default()
+                .to_owned();
+            if paths.contains(&path.as_str()) {
+                let mut content = Vec::new();
+                file.read_to_end(&mut content)?;
+                found.insert(path, content);
+            }
+        }
+        return Ok(paths.iter().find_map(|path| found.remove(*path)));
+    }
+    bail!("Malformed deb file.")
+}
+
+/// Decompressing reader of the `{name}.tar` member of a deb, None if `entry` is another member
+fn tar_member<'a>(
+    entry: ar::Entry<'a, File>,
+    name: &str,
+    p: &Path,
+) -> Result<Option<Box<dyn Read + 'a>>> {
+    let filename = std::str::from_utf8(entry.header().identifier())?.to_owned();
+    let ext = match filename
+        .strip_prefix(name)
+        .and_then(|s| s.strip_prefix(".tar"))
+    {
+        Some(ext) => ext,
+        None => return Ok(None),
+    };
+    // dpkg supports all of these for both members
+    let reader: Box<dyn Read> = match ext {
+        ".xz" => Box::new(XzDecoder::new(entry)),
+        ".gz" => Box::new(GzDecoder::new(entry)),
+        ".zst" => Box::new(decompress_zstd(entry).context(format!(
+            "Failed to decompress {filename} in {} .",
+            p.display()
+        ))?),
+        "" => Box::new(entry),
+        _ => return Ok(None),
+    };
+    Ok(Some(reader))
+}
+
+/// Decompress zstd data with the zstd command, the whole member is kept in memory
+fn decompress_zstd(mut r: impl Read) -> Result<Cursor<Vec<u8>>> {
+    let mut input = Vec::new();
+    r.read_to_end(&mut input)?;
+    let mut child = Command::new("zstd")
+        .args(["-d", "-c", "-q"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("Failed to run zstd, is it installed?")?;
+    let mut stdin = child.stdin.take().unwrap();
+    // Feed input from another thread, so that a full stdout pipe doesn't block us
+    let writer = std::thread::spawn(move || stdin.write_all(&input));
+    let output = child.wait_with_output()?;
+    writer.join().unwrap()?;
+    if !output.status.success() {
+        bail!(
+            "zstd exited with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(Cursor::new(output.stdout))
+}
+
 fn parse_debcontrol(i: &str, p: &Path) -> Result<PkgMeta> {
     let paragraphs = match debcontrol::parse_str(i) {
         Ok(p) => p,
@@ -82,6 +174,8 @@ fn parse_debcontrol(i: &str, p: &Path) -> Result<PkgMeta> {
 }
 
 fn parse_debcontrol_fields(mut f: HashMap<&str, String>, p: &Path) -> Result<PkgMeta> {
+    // Local packages are built for this machine
+    let arch = f.get("Architecture").cloned().unwrap_or_default();
     Ok(PkgMeta {
         name: f
             .remove("Package")
@@ -97,23 +191,24 @@ fn parse_debcontrol_fields(mut f: HashMap<&str, String>, p: &Path) -> Result<Pkg
                 .ok_or_else(|| format_err!("deb control file does not contain the Version field."))?
                 .as_str(),
         )?,
-        depends: parse_pkg_list(f.get("Depends").unwrap_or(&String::new()))?,
-        breaks: parse_pkg_list(f.get("Breaks").unwrap_or(&String::new()))?,
-        conflicts: parse_pkg_list(f.get("Conflicts").unwrap_or(&String::new()))?,
+        depends: parse_pkg_list(f.get("Depends").unwrap_or(&String::new()), &arch)?,
+        pre_depends: parse_pkg_list(f.get("Pre-Depends").unwrap_or(&String::new()), &arch)?,
+        breaks: parse_pkg_list(f.get("Breaks").unwrap_or(&String::new()), &arch)?,
+        conflicts: parse_pkg_list(f.get("Conflicts").unwrap_or(&String::new()), &arch)?,
         recommends: match f.get("Recommends") {
-            Some(recomm) => Some(parse_pkg_list(recomm)?),
+            Some(recomm) => Some(parse_pkg_list(recomm, &arch)?),
             None => None,
         },
         suggests: match f.get("Suggests") {
-            Some(suggests) => Some(parse_pkg_list(suggests)?),
+            Some(suggests) => Some(parse_pkg_list(suggests, &arch)?),
             None => None,
         },
         provides: match f.get("Provides") {
-            Some(provides) => Some(parse_pkg_list(provides)?),
+            Some(provides) => Some(parse_pkg_list(provides, &arch)?),
             None => None,
         },
         replaces: match f.get("Replaces") {
-            Some(replaces) => Some(parse_pkg_list(replaces)?),
+            Some(replaces) => Some(parse_pkg_list(replaces, &arch)?),
             None => None,
         },
         // Installed-Size is in kilobytes, multiply by 1024 to convert it to bytes
@@ -135,6 +230,55 @@ fn parse_debcontrol_fields(mut f: HashMap<&str, String>, p: &Path) -> Result<Pkg
             },
             None => false,
         },
+        priority: f.get("Priority").and_then(|p| Priority::parse(p)),
         source: PkgSource::Local(p.to_owned()),
+        security: false,
+        distributions: Vec::new(),
+        mirrors: Vec::new(),
+        repos: Vec::new(),
     })
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_control_members() {
+        let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/debs");
+        let meta = read_control_from_deb(&fixtures.join("hello-gz_1.0-1_all.deb")).unwrap();
+        assert_eq!(meta.name, "hello-gz");
+        assert_eq!(meta.version, PkgVersion::try_from("1.0-1").unwrap());
+
+        let meta = read_control_from_deb(&fixtures.join("hello-zst_1.0-1_all.deb")).unwrap();
+        assert_eq!(meta.name, "hello-zst");
+        assert_eq!(meta.section, "utils");
+        assert_eq!(meta.version, PkgVersion::try_from("1.0-1").unwrap());
+        assert_eq!(meta.depends.len(), 1);
+        assert_eq!(meta.depends[0].0, "bash");
+    }
+
+    #[test]
+    fn test_read_file_from_deb() {
+        let deb = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures/debs/hello-changelog_1.1-1_all.deb");
+        let doc = "./usr/share/doc/hello-changelog";
+        let content = read_file_from_deb(
+            &deb,
+            &[
+                &format!("{doc}/changelog.gz"),
+                &format!("{doc}/changelog.Debian.gz"),
+            ],
+        )
+        .unwrap()
+        .unwrap();
+        let mut changelog = String::new();
+        GzDecoder::new(content.as_slice())
+            .read_to_string(&mut changelog)
+            .unwrap();
+        assert!(changelog.starts_with("hello-changelog (1.1-1) stable;"));
+        assert!(read_file_from_deb(&deb, &["./usr/bin/hello"])
+            .unwrap()
+            .is_none());
+    }
+}
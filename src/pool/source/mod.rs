@@ -1,26 +1,70 @@
 pub mod debrepo;
 pub mod local;
 
-use super::{BasicPkgPool, InMemoryPool, PkgPool};
+use super::{BasicPkgPool, InMemoryPool, Pinned, PkgFields, PkgPool, PoolOptions};
+use crate::{db::PkgDb, debug};
+
 use anyhow::Result;
 use std::path::PathBuf;
 
+/// Packages added to the pool between progress bar updates, so that it costs next to nothing
+const PROGRESS_BATCH: usize = 4096;
+
 pub fn create_pool(
-    deb_dbs: &[(String, PathBuf)],
+    deb_dbs: &[PkgDb],
     local_deb_roots: &[PathBuf],
+    fields: PkgFields,
+    options: &PoolOptions,
 ) -> Result<Box<dyn PkgPool>> {
+    let (pool, _) = create_pool_pinned(deb_dbs, local_deb_roots, fields, options)?;
+    Ok(pool)
+}
+
+/// Like `create_pool`, also returns installed packages that had candidates dropped by
+/// `options.version_pins`
+pub fn create_pool_pinned(
+    deb_dbs: &[PkgDb],
+    local_deb_roots: &[PathBuf],
+    fields: PkgFields,
+    options: &PoolOptions,
+) -> Result<(Box<dyn PkgPool>, Vec<Pinned>)> {
     let mut pool = InMemoryPool::new();
-    for (root_url, deb_db) in deb_dbs {
-        debrepo::import(deb_db, &mut pool, root_url)?;
+    // The length grows as each database is parsed, since it's unknown until then
+    let bar = crate::cli::progress_bar("Building package pool", Some(0));
+    for deb_db in deb_dbs {
+        debrepo::import(deb_db, &mut pool, fields, &bar)?;
     }
 
     // Import debs
     for deb_root in local_deb_roots {
-        for pkg in local::read_debs_from_path(deb_root)? {
+        let pkgs = local::read_debs_from_path(deb_root)?;
+        // Reading them takes much longer than adding them
+        bar.inc_length(pkgs.len() as u64);
+        bar.inc(pkgs.len() as u64);
+        for pkg in pkgs {
             pool.add(pkg);
         }
     }
 
     pool.finalize();
-    Ok(Box::new(pool))
+    bar.finish_and_clear();
+    // Before limiting versions, so that only allowed versions count
+    let pinned = match options.version_pins {
+        Some(pins) => pool.pin_installed(pins),
+        None => Vec::new(),
+    };
+    if let Some(limit) = options.version_limit {
+        let dropped = pool.limit_versions(limit, options.target_release);
+        debug!("Dropped {dropped} old package versions from the pool.");
+    }
+    if let Some(filter) = options.repo_filter {
+        pool.restrict_repos(filter)?;
+    }
+    if let Some(target) = options.target_release {
+        pool.prefer_distribution(target)?;
+    }
+    if let Some(preference) = options.version_preference {
+        pool.prefer_installed(preference);
+    }
+    Ok((Box::new(pool), pinned))
 }
@@ -0,0 +1,44 @@
+use crate::types::VersionRequirement;
+use std::time::Duration;
+use thiserror::Error;
+
+/// Errors returned by dependency resolution
+#[derive(Error, Debug)]
+pub enum SolverError {
+    #[error("Package {0} not found.")]
+    PackageNotFound(String),
+    /// No version of the package satisfies the requirement
+    #[error("Cannot find a suitable version for {name}.")]
+    VersionConflict {
+        name: String,
+        requirement: VersionRequirement,
+    },
+    /// The requested packages cannot be installed together
+    #[error("Omakase cannot satisfy package requirements.")]
+    Unsatisfiable {
+        /// IDs of conflicting packages in the pool
+        core: Vec<usize>,
+        #[source]
+        reason: Reason,
+    },
+    /// Packages excluded in config are needed by the requested packages
+    #[error("Omakase cannot satisfy package requirements without excluded packages.")]
+    Excluded {
+        #[source]
+        reason: Reason,
+    },
+    /// Resolution did not finish in time
+    #[error("Dependency resolution timed out after {timeout:?} while {stage}.")]
+    Timeout {
+        timeout: Duration,
+        /// What the solver was doing when it timed out
+        stage: &'static str,
+    },
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+/// Human-readable explanation of why packages cannot be installed
+#[derive(Error, Debug)]
+#[error("{0}")]
+pub struct Reason(pub String);
@@ -1,11 +1,10 @@
 use crate::{pool::PkgPool, types::PkgMeta};
 use varisat::{Lit, Solver};
 
-pub fn find_incompatible_friendly(pool: &dyn PkgPool, to_install: &[usize]) -> String {
-    let incompatible = find_incompatible(pool, to_install);
+pub fn find_incompatible_friendly(pool: &dyn PkgPool, incompatible: &[usize]) -> String {
     let pkgs: Vec<&PkgMeta> = incompatible
-        .into_iter()
-        .map(|id| pool.get_pkg_by_id(id).unwrap())
+        .iter()
+        .map(|id| pool.get_pkg_by_id(*id).unwrap())
         .collect();
 
     if pkgs.is_empty() {
@@ -35,7 +34,7 @@ pub fn find_incompatible_friendly(pool: &dyn PkgPool, to_install: &[usize]) -> S
     }
 }
 
-fn find_incompatible(pool: &dyn PkgPool, to_install: &[usize]) -> Vec<usize> {
+pub fn find_incompatible(pool: &dyn PkgPool, to_install: &[usize]) -> Vec<usize> {
     // Set up solver
     let mut solver = Solver::new();
     let formula = pool.gen_formula(None);
@@ -1,70 +1,382 @@
+mod error;
 mod improve;
 mod incompatible;
 mod sort;
+pub use error::{Reason, SolverError};
 
 use crate::debug;
 use crate::pool::PkgPool;
-use crate::types::{config::Blueprints, PkgMeta};
-use anyhow::{bail, format_err, Context, Result};
-use varisat::{lit::Lit, ExtendFormula};
+use crate::types::{config::Blueprints, PkgMeta, VersionRequirement};
+use crate::utils::glob::glob_match;
+use anyhow::{anyhow, bail, Context, Result};
+use std::{
+    ffi::OsString,
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{self, RecvTimeoutError},
+        Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+use varisat::{
+    dimacs::{write_dimacs_clauses, write_dimacs_header, DimacsHeader},
+    lit::Lit,
+    CnfFormula, ExtendFormula,
+};
 
 pub struct Solver {
-    pub pool: Box<dyn PkgPool>,
+    pub pool: Arc<dyn PkgPool>,
+    /// Give up if resolution takes longer than this
+    pub timeout: Option<Duration>,
+    /// Glob patterns of packages that must not be in the result, whatever requires them
+    pub exclude: Vec<String>,
+    /// Write the formula, the model and what each variable means to this path, for debugging
+    pub dump: Option<PathBuf>,
 }
 
 impl From<Box<dyn PkgPool>> for Solver {
     fn from(pool: Box<dyn PkgPool>) -> Self {
-        Solver { pool }
+        Solver {
+            pool: Arc::from(pool),
+            timeout: None,
+            exclude: Vec::new(),
+            dump: None,
+        }
     }
 }
 
 impl Solver {
-    pub fn install(&self, blueprints: &Blueprints) -> Result<Vec<&PkgMeta>> {
-        let mut formula = self.pool.gen_formula(None);
-        debug!("Adding requested packages to solver formula...");
-        let mut ids = Vec::new();
-        for req in blueprints.get_pkg_requests() {
-            let id = self
-                .pool
-                .pick_best_pkg(&req.name, &req.version, req.local)?;
-            formula.add_clause(&[Lit::from_dimacs(id as isize)]);
-            ids.push(id);
-        }
-        // Add rules to solver
-        let mut solver = varisat::Solver::new();
-        solver.add_formula(&formula);
-
-        // Initial solve
-        debug!("Computing initial solution...");
-        let mut res = match solve(&mut solver) {
-            Ok(r) => r,
-            Err(_) => {
-                return Err(format_err!(incompatible::find_incompatible_friendly(
-                    self.pool.as_ref(),
-                    &ids
-                )))
-                .context("Omakase cannot satisfy package requirements.")
+    /// Resolve the packages requested in `blueprints`, also keeping the packages named in `keep`
+    /// (and their dependencies)
+    pub fn install_keeping(
+        &self,
+        blueprints: &Blueprints,
+        keep: &[String],
+    ) -> Result<Vec<&PkgMeta>, SolverError> {
+        let mut requests: Vec<(String, VersionRequirement, bool)> = blueprints
+            .get_pkg_requests()
+            .into_iter()
+            .map(|req| (req.name, req.version, req.local))
+            .collect();
+        for name in keep {
+            if !requests.iter().any(|(n, _, _)| n == name) {
+                requests.push((name.clone(), VersionRequirement::default(), false));
             }
-        };
+        }
+        self.solve_requests(&requests)
+    }
 
-        // Improve the result to remove redundant packages
-        // and select best possible packages
-        debug!("Refining dependency solution...");
-        improve::upgrade(self.pool.as_ref(), &mut res, &mut solver)?;
-        improve::reduce(self.pool.as_ref(), &mut res, &ids)?;
-        // Sort result
-        sort::sort_pkgs(self.pool.as_ref(), &mut res).context("Failed to sort packages")?;
+    /// Resolve the packages needed to install `requests`, a list of (PkgName, VersionRequirement)
+    pub fn resolve(
+        &self,
+        requests: &[(String, VersionRequirement)],
+    ) -> Result<Vec<&PkgMeta>, SolverError> {
+        let requests: Vec<(String, VersionRequirement, bool)> = requests
+            .iter()
+            .map(|(name, ver_req)| (name.clone(), ver_req.clone(), false))
+            .collect();
+        self.solve_requests(&requests)
+    }
+
+    // requests: (PkgName, VersionRequirement, Local?)
+    fn solve_requests(
+        &self,
+        requests: &[(String, VersionRequirement, bool)],
+    ) -> Result<Vec<&PkgMeta>, SolverError> {
+        let formula = self.pool.gen_formula(None);
+        let excluded: Vec<usize> = self
+            .pool
+            .pkgname_iter()
+            .filter(|(name, _)| self.exclude.iter().any(|p| glob_match(p, name)))
+            .flat_map(|(_, versions)| versions.iter().map(|(id, _)| *id))
+            .collect();
+        let spinner = crate::cli::progress_bar("Solving dependencies", None);
+        let stage = Arc::new(Mutex::new("adding requested packages"));
+        let res = match self.timeout {
+            None => solve_formula(
+                self.pool.as_ref(),
+                formula,
+                requests,
+                &excluded,
+                &stage,
+                self.dump.as_deref(),
+            ),
+            Some(timeout) => {
+                // varisat can't be interrupted, so solve on another thread and stop waiting for it
+                let (tx, rx) = mpsc::channel();
+                let pool = self.pool.clone();
+                let requests = requests.to_vec();
+                let thread_stage = stage.clone();
+                let dump = self.dump.clone();
+                thread::spawn(move || {
+                    let res = solve_formula(
+                        pool.as_ref(),
+                        formula,
+                        &requests,
+                        &excluded,
+                        &thread_stage,
+                        dump.as_deref(),
+                    );
+                    tx.send(res).ok();
+                });
+                match rx.recv_timeout(timeout) {
+                    Ok(res) => res,
+                    Err(RecvTimeoutError::Timeout) => Err(SolverError::Timeout {
+                        timeout,
+                        stage: *stage.lock().unwrap(),
+                    }),
+                    Err(RecvTimeoutError::Disconnected) => Err(SolverError::Other(anyhow!(
+                        "Dependency resolution stopped unexpectedly."
+                    ))),
+                }
+            }
+        };
+        spinner.finish_and_clear();
+        if let Some(path) = &self.dump {
+            dump_model(self.pool.as_ref(), path, &res).context("Failed to write solver model.")?;
+        }
 
         // Generate result
-        let pkgs: Vec<&PkgMeta> = res
+        let pkgs: Vec<&PkgMeta> = res?
             .into_iter()
             .map(|pkgid| self.pool.get_pkg_by_id(pkgid).unwrap())
             .collect();
-
         Ok(pkgs)
     }
 }
 
+/// Resolve `requests` with the rules in `formula`, `stage` is updated with what is being done
+///
+/// Packages in `excluded` are never part of the result. If `dump` is set, the formula is written
+/// there before solving.
+// -> Vec<PkgID>, sorted in installation order
+fn solve_formula(
+    pool: &dyn PkgPool,
+    mut formula: CnfFormula,
+    requests: &[(String, VersionRequirement, bool)],
+    excluded: &[usize],
+    stage: &Mutex<&'static str>,
+    dump: Option<&Path>,
+) -> Result<Vec<usize>, SolverError> {
+    debug!("Adding requested packages to solver formula...");
+    let mut ids = Vec::new();
+    // Requested packages that may be superseded by others: (PkgID, Superseding PkgIDs)
+    let mut supersedable = Vec::new();
+    for (name, ver_req, local) in requests {
+        let id = pool.pick_best_pkg(name, ver_req, *local)?;
+        let superseding = pool.find_superseding(id);
+        if superseding.is_empty() {
+            formula.add_clause(&[Lit::from_dimacs(id as isize)]);
+        } else {
+            // Either install the requested package, or a package that supersedes it
+            let clause: Vec<Lit> = std::iter::once(id)
+                .chain(superseding.iter().copied())
+                .map(|id| Lit::from_dimacs(id as isize))
+                .collect();
+            formula.add_clause(&clause);
+            supersedable.push((id, superseding));
+        }
+        ids.push(id);
+    }
+    if let Some(path) = dump {
+        dump_formula(pool, &formula, excluded, path).context("Failed to write solver formula.")?;
+    }
+    // Add rules to solver
+    *stage.lock().unwrap() = "checking superseded packages";
+    let mut solver = varisat::Solver::new();
+    solver.add_formula(&formula);
+    // Not in `formula`, so that it can tell which excluded packages are needed
+    for id in excluded {
+        solver.add_clause(&[!Lit::from_dimacs(*id as isize)]);
+    }
+    // Keep requested packages whenever possible, so that they are only superseded when necessary
+    for (id, _) in &supersedable {
+        let lit = Lit::from_dimacs(*id as isize);
+        solver.assume(&[lit]);
+        if solver.solve().unwrap() {
+            solver.add_clause(&[lit]);
+        }
+    }
+    solver.assume(&[]);
+
+    // Initial solve
+    debug!("Computing initial solution...");
+    *stage.lock().unwrap() = "computing the initial solution";
+    let mut res = match solve(&mut solver) {
+        Ok(r) => r,
+        Err(_) => {
+            if !excluded.is_empty() {
+                if let Some(reason) = explain_excluded(pool, &formula, &ids, excluded) {
+                    return Err(SolverError::Excluded {
+                        reason: Reason(reason),
+                    });
+                }
+            }
+            *stage.lock().unwrap() = "looking for incompatible packages";
+            let core = incompatible::find_incompatible(pool, &ids);
+            let reason = incompatible::find_incompatible_friendly(pool, &core);
+            return Err(SolverError::Unsatisfiable {
+                core,
+                reason: Reason(reason),
+            });
+        }
+    };
+
+    // Improve the result to remove redundant packages
+    // and select best possible packages
+    debug!("Refining dependency solution...");
+    *stage.lock().unwrap() = "upgrading packages to their newest versions";
+    improve::upgrade(pool, &mut res, &mut solver)?;
+    // Requested packages that are superseded are replaced by their successors
+    for (id, superseding) in &supersedable {
+        if res.contains(id) {
+            continue;
+        }
+        if let Some(successor) = superseding.iter().find(|s| res.contains(s)) {
+            let name = |id| &pool.get_pkg_by_id(id).unwrap().name;
+            debug!("{} is superseded by {}.", name(*id), name(*successor));
+            ids.retain(|i| i != id);
+            if !ids.contains(successor) {
+                ids.push(*successor);
+            }
+        }
+    }
+    *stage.lock().unwrap() = "removing redundant packages";
+    improve::reduce(pool, &mut res, &ids)?;
+    // Sort result
+    sort::sort_pkgs(pool, &mut res).context("Failed to sort packages")?;
+
+    Ok(res)
+}
+
+/// Explain which excluded packages are needed, if `formula` can be satisfied with them
+fn explain_excluded(
+    pool: &dyn PkgPool,
+    formula: &CnfFormula,
+    requested: &[usize],
+    excluded: &[usize],
+) -> Option<String> {
+    let mut solver = varisat::Solver::new();
+    solver.add_formula(formula);
+    let res = solve(&mut solver).ok()?;
+    let pkgs: Vec<&PkgMeta> = res
+        .iter()
+        .map(|id| pool.get_pkg_by_id(*id).unwrap())
+        .collect();
+    let mut lines = Vec::new();
+    for id in res.iter().filter(|id| excluded.contains(id)) {
+        let pkg = pool.get_pkg_by_id(*id).unwrap();
+        let mut required_by: Vec<&str> = pkgs
+            .iter()
+            .filter(|p| {
+                p.pre_depends
+                    .iter()
+                    .chain(p.depends.iter())
+                    .any(|(name, _)| name == &pkg.name)
+            })
+            .map(|p| p.name.as_str())
+            .collect();
+        if requested.contains(id) {
+            required_by.insert(0, "the request");
+        }
+        if required_by.is_empty() {
+            continue;
+        }
+        lines.push(format!(
+            "{}({}) is excluded in config, but required by {}.",
+            pkg.name,
+            console::style(&pkg.version).dim(),
+            required_by.join(", ")
+        ));
+    }
+    if lines.is_empty() {
+        None
+    } else {
+        Some(lines.join("\n"))
+    }
+}
+
+/// `path` with `suffix` appended to the file name
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+/// Write `formula` and the `excluded` packages as DIMACS CNF to `path`, and the package each
+/// variable stands for to `path`.map
+///
+/// Each line of the map is "ID NAME VERSION", where ID is the DIMACS variable.
+fn dump_formula(
+    pool: &dyn PkgPool,
+    formula: &CnfFormula,
+    excluded: &[usize],
+    path: &Path,
+) -> Result<()> {
+    let mut ids: Vec<(usize, &PkgMeta)> = pool.pkgid_iter().collect();
+    ids.sort_by_key(|(id, _)| *id);
+
+    let mut cnf = Vec::new();
+    let header = DimacsHeader {
+        // Packages without any rule are still variables
+        var_count: ids.last().map_or(0, |(id, _)| *id).max(formula.var_count()),
+        clause_count: formula.len() + excluded.len(),
+    };
+    write_dimacs_header(&mut cnf, header)?;
+    write_dimacs_clauses(&mut cnf, formula.iter())?;
+    let excluded = excluded.iter().map(|id| [!Lit::from_dimacs(*id as isize)]);
+    write_dimacs_clauses(&mut cnf, excluded)?;
+    fs::write(path, cnf)?;
+
+    let mut map = String::new();
+    for (id, pkg) in ids {
+        map.push_str(&format!("{} {} {}\n", id, pkg.name, pkg.version));
+    }
+    fs::write(with_suffix(path, ".map"), map)?;
+
+    Ok(())
+}
+
+/// Write the outcome of solving to `path`.model in DIMACS solution format, packages in `res`
+/// are true and all others false
+fn dump_model(
+    pool: &dyn PkgPool,
+    path: &Path,
+    res: &Result<Vec<usize>, SolverError>,
+) -> Result<()> {
+    let mut file = fs::File::create(with_suffix(path, ".model"))?;
+    match res {
+        Ok(res) => {
+            let mut ids: Vec<usize> = pool.pkgid_iter().map(|(id, _)| id).collect();
+            ids.sort_unstable();
+            let lits: Vec<String> = ids
+                .into_iter()
+                .map(|id| {
+                    if res.contains(&id) {
+                        id.to_string()
+                    } else {
+                        format!("-{id}")
+                    }
+                })
+                .collect();
+            writeln!(file, "s SATISFIABLE")?;
+            writeln!(file, "v {} 0", lits.join(" "))?;
+        }
+        Err(SolverError::Unsatisfiable { .. } | SolverError::Excluded { .. }) => {
+            writeln!(file, "s UNSATISFIABLE")?;
+        }
+        Err(e) => {
+            writeln!(file, "c {e}")?;
+            writeln!(file, "s UNKNOWN")?;
+        }
+    }
+
+    Ok(())
+}
+
 /// Helper function to get PkgID list
 pub fn solve(solver: &mut varisat::Solver) -> Result<Vec<usize>> {
     let mut res = Vec::new();
@@ -81,3 +393,174 @@ pub fn solve(solver: &mut varisat::Solver) -> Result<Vec<usize>> {
     }
     Ok(res)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::utils::temp_dir::TempDir;
+    use crate::{
+        pool::{BasicPkgPool, InMemoryPool},
+        types::{test_pkg as pkg, VersionRequirement},
+    };
+    use std::collections::HashMap;
+
+    /// Pigeonhole problem: `holes + 1` pigeons need different seats out of `holes`.
+    /// This is unsatisfiable, and proving it is notoriously slow for SAT solvers.
+    fn pigeonhole(holes: usize) -> Solver {
+        let mut pool = InMemoryPool::new();
+        for i in 0..=holes {
+            let mut pigeon = pkg(&format!("pigeon{i}"), "1");
+            pigeon.depends = vec![(format!("seat{i}"), VersionRequirement::default())];
+            pool.add(pigeon);
+            for j in 1..=holes {
+                let mut seat = pkg(&format!("seat{i}"), &j.to_string());
+                seat.breaks = (0..=holes)
+                    .filter(|k| *k != i)
+                    .map(|k| {
+                        let ver_req = VersionRequirement::try_from(format!("={j}").as_str());
+                        (format!("seat{k}"), ver_req.unwrap())
+                    })
+                    .collect();
+                pool.add(seat);
+            }
+        }
+        pool.finalize();
+        Solver::from(Box::new(pool) as Box<dyn PkgPool>)
+    }
+
+    #[test]
+    fn test_solver_timeout() {
+        // Test packages are local ones
+        let requests = |n: usize| -> Vec<(String, VersionRequirement, bool)> {
+            (0..=n)
+                .map(|i| (format!("pigeon{i}"), VersionRequirement::default(), true))
+                .collect()
+        };
+        let mut solver = pigeonhole(3);
+        solver.timeout = Some(Duration::from_secs(60));
+        assert!(matches!(
+            solver.solve_requests(&requests(3)),
+            Err(SolverError::Unsatisfiable { .. })
+        ));
+
+        // Takes a few hundred milliseconds, so that the solving thread doesn't keep running for
+        // the rest of the tests after timing out
+        let mut solver = pigeonhole(7);
+        solver.timeout = Some(Duration::from_millis(20));
+        let start = std::time::Instant::now();
+        match solver.solve_requests(&requests(7)) {
+            Err(SolverError::Timeout { timeout, stage }) => {
+                assert_eq!(timeout, Duration::from_millis(20));
+                assert_eq!(stage, "computing the initial solution");
+            }
+            res => panic!("Expected timeout, got {:?}", res.map(|_| ())),
+        }
+        assert!(start.elapsed() < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn test_exclude() {
+        let any = VersionRequirement::default;
+        let mut pool = InMemoryPool::new();
+        let mut a = pkg("a", "1.0");
+        a.depends = vec![("libb".to_string(), any())];
+        pool.add(a);
+        pool.add(pkg("libb", "1.0"));
+        pool.add(pkg("c", "1.0"));
+        pool.finalize();
+        let mut solver = Solver::from(Box::new(pool) as Box<dyn PkgPool>);
+        let requests = |names: &[&str]| -> Vec<(String, VersionRequirement, bool)> {
+            names
+                .iter()
+                .map(|name| (name.to_string(), any(), true))
+                .collect()
+        };
+        assert_eq!(solver.solve_requests(&requests(&["a"])).unwrap().len(), 2);
+
+        solver.exclude = vec!["lib*".to_string()];
+        // Unrelated packages are not affected
+        assert_eq!(solver.solve_requests(&requests(&["c"])).unwrap().len(), 1);
+        match solver.solve_requests(&requests(&["a", "c"])) {
+            Err(SolverError::Excluded { reason }) => {
+                let reason = console::strip_ansi_codes(&reason.0).to_string();
+                assert_eq!(
+                    reason,
+                    "libb(1.0) is excluded in config, but required by a."
+                );
+            }
+            res => panic!("Expected exclusion error, got {:?}", res.map(|_| ())),
+        }
+        match solver.solve_requests(&requests(&["libb"])) {
+            Err(SolverError::Excluded { reason }) => {
+                let reason = console::strip_ansi_codes(&reason.0).to_string();
+                assert_eq!(
+                    reason,
+                    "libb(1.0) is excluded in config, but required by the request."
+                );
+            }
+            res => panic!("Expected exclusion error, got {:?}", res.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn test_dump() {
+        let mut pool = InMemoryPool::new();
+        let mut a = pkg("a", "1.0");
+        a.depends = vec![("libb".to_string(), VersionRequirement::default())];
+        pool.add(a);
+        pool.add(pkg("libb", "1.0"));
+        pool.add(pkg("libb", "2.0"));
+        pool.add(pkg("c", "1.0"));
+        pool.finalize();
+        let mut solver = Solver::from(Box::new(pool) as Box<dyn PkgPool>);
+        let dir = TempDir::new("dump");
+        let path = dir.join("solver.cnf");
+        solver.dump = Some(path.clone());
+        solver.exclude = vec!["c".to_string()];
+        let requests = vec![("a".to_string(), VersionRequirement::default(), true)];
+        assert_eq!(solver.solve_requests(&requests).unwrap().len(), 2);
+
+        // The map tells which package and version each variable is
+        let map = fs::read_to_string(with_suffix(&path, ".map")).unwrap();
+        let names: HashMap<isize, &str> = map
+            .lines()
+            .map(|line| {
+                let (id, pkg) = line.split_once(' ').unwrap();
+                (id.parse().unwrap(), pkg)
+            })
+            .collect();
+        assert_eq!(names.len(), 4);
+        // The formula can be solved again, and the model satisfies it
+        let formula = varisat::dimacs::DimacsParser::parse(&fs::read(&path).unwrap()[..]).unwrap();
+        let model = fs::read_to_string(with_suffix(&path, ".model")).unwrap();
+        let mut lines = model.lines();
+        assert_eq!(lines.next(), Some("s SATISFIABLE"));
+        let model: Vec<Lit> = lines.next().unwrap()[2..]
+            .split(' ')
+            .map(|lit| lit.parse::<isize>().unwrap())
+            .take_while(|lit| *lit != 0)
+            .map(Lit::from_dimacs)
+            .collect();
+        assert!(formula
+            .iter()
+            .all(|clause| clause.iter().any(|lit| model.contains(lit))));
+        let mut installed: Vec<&str> = model
+            .iter()
+            .filter(|lit| lit.is_positive())
+            .map(|lit| names[&lit.to_dimacs()])
+            .collect();
+        installed.sort();
+        assert_eq!(installed, vec!["a 1.0", "libb 2.0"]);
+        // Excluded packages are part of the formula
+        let mut excluded = varisat::Solver::new();
+        excluded.add_formula(&formula);
+        let c = names.iter().find(|(_, pkg)| **pkg == "c 1.0").unwrap().0;
+        excluded.assume(&[Lit::from_dimacs(*c)]);
+        assert!(!excluded.solve().unwrap());
+
+        solver.exclude = vec!["lib*".to_string()];
+        assert!(solver.solve_requests(&requests).is_err());
+        let model = fs::read_to_string(with_suffix(&path, ".model")).unwrap();
+        assert_eq!(model, "s UNSATISFIABLE\n");
+    }
+}
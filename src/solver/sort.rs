@@ -14,7 +14,7 @@ pub fn sort_pkgs(pool: &dyn PkgPool, pkgs: &mut Vec<usize>) -> Result<()> {
             // Sort via the number of dependencies
             pkgids.sort_by_key(|id| {
                 let pkg = pool.get_pkg_by_id(*id).unwrap();
-                pkg.depends.len()
+                pkg.pre_depends.len() + pkg.depends.len()
             });
             pkgs.append(&mut pkgids);
         }
@@ -1,14 +1,21 @@
 mod table;
 
-use super::{Checksum, PkgMeta, PkgSource, PkgVersion};
+use super::{
+    config::{ActionSort, Opts},
+    Checksum, PkgMeta, PkgSource, PkgVersion,
+};
 
 use anyhow::Result;
 use console::style;
 use indicatif::HumanBytes;
+use serde_json::json;
+use std::time::Duration;
 
-#[derive(Default, Debug)]
+#[derive(Default, Debug, Clone)]
 pub struct PkgActions<'a> {
     pub install: Vec<(&'a PkgMeta, Option<(PkgVersion, u64)>)>,
+    // (NewPkg, (OldVersion, OldInstallSize)), NewPkg is older than the installed one
+    pub downgrade: Vec<(&'a PkgMeta, (PkgVersion, u64))>,
     pub unpack: Vec<(&'a PkgMeta, Option<(PkgVersion, u64)>)>,
     // (Name, InstallSize, Essential?)
     pub remove: Vec<(String, u64, bool)>,
@@ -26,6 +33,54 @@ pub struct PkgInstallAction {
     pub version: PkgVersion,
 }
 
+/// What has been done by executing PkgActions, shown after dpkg finishes
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ActionSummary {
+    pub installed: usize,
+    pub upgraded: usize,
+    pub downgraded: usize,
+    pub removed: usize,
+    pub download_size: u64,
+    pub size_change: i128,
+    pub elapsed: Duration,
+}
+
+impl ActionSummary {
+    /// A few lines for humans, e.g. `Installed 1, upgraded 2, downgraded 0, removed 0 package(s) in 3.2s.`
+    pub fn to_lines(&self) -> Vec<String> {
+        let sign = if self.size_change >= 0 { "+" } else { "-" };
+        vec![
+            format!(
+                "Installed {}, upgraded {}, downgraded {}, removed {} package(s) in {:.1}s.",
+                self.installed,
+                self.upgraded,
+                self.downgraded,
+                self.removed,
+                self.elapsed.as_secs_f64()
+            ),
+            format!(
+                "Downloaded {}, storage usage {}{}.",
+                HumanBytes(self.download_size),
+                sign,
+                HumanBytes(self.size_change.unsigned_abs() as u64)
+            ),
+        ]
+    }
+
+    pub fn to_json(&self) -> String {
+        json!({
+            "installed": self.installed,
+            "upgraded": self.upgraded,
+            "downgraded": self.downgraded,
+            "removed": self.removed,
+            "download_size": self.download_size,
+            "size_change": self.size_change as i64,
+            "elapsed": self.elapsed.as_millis() as f64 / 1000.0,
+        })
+        .to_string()
+    }
+}
+
 /// Alter PkgActions based on user configuration, system state, etc.
 pub trait PkgActionModifier {
     fn apply(&self, actions: &mut PkgActions);
@@ -34,6 +89,7 @@ pub trait PkgActionModifier {
 impl PkgActions<'_> {
     pub fn is_empty(&self) -> bool {
         self.install.is_empty()
+            && self.downgrade.is_empty()
             && self.unpack.is_empty()
             && self.remove.is_empty()
             && self.purge.is_empty()
@@ -56,6 +112,74 @@ impl PkgActions<'_> {
         false
     }
 
+    /// Names of essential packages replacing installed versions, either upgraded or downgraded
+    pub fn essential_upgrades(&self) -> Vec<&str> {
+        self.install
+            .iter()
+            .filter(|(pkg, old)| pkg.essential && old.is_some())
+            .map(|(pkg, _)| pkg.name.as_str())
+            .chain(
+                self.downgrade
+                    .iter()
+                    .filter(|(pkg, _)| pkg.essential)
+                    .map(|(pkg, _)| pkg.name.as_str()),
+            )
+            .collect()
+    }
+
+    /// A copy with packages in each category sorted by `key`, for display
+    ///
+    /// Sizes are sorted from the largest. Packages without a size or section are sorted by name.
+    pub fn sorted(&self, key: ActionSort) -> Self {
+        let mut res = self.clone();
+        let by_pkg = |a: &PkgMeta, b: &PkgMeta| match key {
+            ActionSort::Name => a.name.cmp(&b.name),
+            ActionSort::Size => b
+                .install_size
+                .cmp(&a.install_size)
+                .then(a.name.cmp(&b.name)),
+            ActionSort::Section => a.section.cmp(&b.section).then(a.name.cmp(&b.name)),
+        };
+        res.install.sort_by(|(a, _), (b, _)| by_pkg(a, b));
+        res.downgrade.sort_by(|(a, _), (b, _)| by_pkg(a, b));
+        res.unpack.sort_by(|(a, _), (b, _)| by_pkg(a, b));
+        for list in [&mut res.remove, &mut res.purge] {
+            list.sort_by(|(a, a_size, _), (b, b_size, _)| match key {
+                ActionSort::Size => b_size.cmp(a_size).then(a.cmp(b)),
+                _ => a.cmp(b),
+            });
+        }
+        res.configure.sort_by(|(a, _), (b, _)| a.cmp(b));
+        res
+    }
+
+    /// Packages that have to be downloaded from repositories, each listed once
+    pub fn install_actions(&self) -> Vec<PkgInstallAction> {
+        let pkgs = self
+            .install
+            .iter()
+            .map(|(pkg, _)| *pkg)
+            .chain(self.downgrade.iter().map(|(pkg, _)| *pkg))
+            .chain(self.unpack.iter().map(|(pkg, _)| *pkg));
+        let mut res: Vec<PkgInstallAction> = Vec::new();
+        for pkg in pkgs {
+            if let PkgSource::Http((url, size, checksum)) = &pkg.source {
+                if res.iter().any(|action| &action.url == url) {
+                    continue;
+                }
+                res.push(PkgInstallAction {
+                    name: pkg.name.clone(),
+                    url: url.clone(),
+                    download_size: *size,
+                    install_size: pkg.install_size,
+                    checksum: checksum.clone(),
+                    version: pkg.version.clone(),
+                });
+            }
+        }
+        res
+    }
+
     pub fn show(&self) {
         let to_install: Vec<String> = self
             .install
@@ -78,18 +202,18 @@ impl PkgActions<'_> {
         let to_upgrade: Vec<String> = self
             .install
             .iter()
-            .filter_map(|(install, oldpkg)| match oldpkg {
-                Some(oldpkg) => {
-                    if install.version > oldpkg.0 {
-                        let mut msg = install.name.clone();
-                        let ver_str = format!("({} -> {})", oldpkg.0, install.version);
-                        msg.push_str(&style(ver_str).dim().to_string());
-                        Some(msg)
-                    } else {
-                        None
-                    }
-                }
-                None => None,
+            .filter_map(|(install, oldpkg)| {
+                oldpkg.as_ref().map(|oldpkg| {
+                    let mut msg = install.name.clone();
+                    let ver_str = gen_version_change_str(&oldpkg.0, &install.version);
+                    msg.push_str(&format!(
+                        "{}{}{}",
+                        style("(").dim(),
+                        ver_str,
+                        style(")").dim()
+                    ));
+                    msg
+                })
             })
             .collect();
         let upgrade_prefix = style("UPGRADE").on_green().black().bold().to_string();
@@ -98,20 +222,18 @@ impl PkgActions<'_> {
             .unwrap();
 
         let to_downgrade: Vec<String> = self
-            .install
+            .downgrade
             .iter()
-            .filter_map(|(install, oldpkg)| match oldpkg {
-                Some(oldpkg) => {
-                    if install.version < oldpkg.0 {
-                        let mut msg = install.name.clone();
-                        let ver_str = format!("({} -> {})", oldpkg.0, install.version);
-                        msg.push_str(&style(ver_str).dim().to_string());
-                        Some(msg)
-                    } else {
-                        None
-                    }
-                }
-                None => None,
+            .map(|(downgrade, oldpkg)| {
+                let mut msg = downgrade.name.clone();
+                let ver_str = gen_version_change_str(&oldpkg.0, &downgrade.version);
+                msg.push_str(&format!(
+                    "{}{}{}",
+                    style("(").dim(),
+                    ver_str,
+                    style(")").dim()
+                ));
+                msg
             })
             .collect();
         let downgrade_prefix = style("DOWNGRADE").on_yellow().white().bold().to_string();
@@ -126,8 +248,13 @@ impl PkgActions<'_> {
                 let mut msg = install.name.clone();
                 match oldpkg {
                     Some(oldpkg) => {
-                        let ver_str = format!("({} -> {})", oldpkg.0, install.version);
-                        msg.push_str(&style(ver_str).dim().to_string());
+                        let ver_str = gen_version_change_str(&oldpkg.0, &install.version);
+                        msg.push_str(&format!(
+                            "{}{}{}",
+                            style("(").dim(),
+                            ver_str,
+                            style(")").dim()
+                        ));
                     }
                     None => {
                         let ver_str = format!("({})", install.version);
@@ -183,8 +310,8 @@ impl PkgActions<'_> {
         crate::WRITER.write_chunks(&purge_prefix, &purges).unwrap();
     }
 
-    pub fn show_tables(&self, no_pager: bool) -> Result<()> {
-        table::show_table(self, no_pager)
+    pub fn show_tables(&self, opts: &Opts, pager_cmd: Option<&str>) -> Result<()> {
+        table::show_table(self, opts, pager_cmd)
     }
 
     pub fn show_size_change(&self) {
@@ -229,6 +356,36 @@ impl PkgActions<'_> {
         }
     }
 
+    /// Summarize what executing these actions does, `elapsed` is filled in by the caller
+    pub fn summary(&self) -> ActionSummary {
+        let (upgraded, installed): (Vec<_>, Vec<_>) = self
+            .install
+            .iter()
+            .chain(self.unpack.iter())
+            .partition(|(_, old)| old.is_some());
+        ActionSummary {
+            installed: installed.len(),
+            upgraded: upgraded.len(),
+            downgraded: self.downgrade.len(),
+            removed: self.remove.len() + self.purge.len(),
+            download_size: self.calculate_download_size(),
+            size_change: self.calculate_size_change(),
+            elapsed: Duration::default(),
+        }
+    }
+
+    /// Summarize download size and storage usage change, e.g. `will download 420 MB, free 1.2 GB`
+    pub fn gen_size_summary(&self) -> String {
+        let size_change = self.calculate_size_change();
+        let usage = if size_change >= 0 { "use" } else { "free" };
+        format!(
+            "will download {}, {} {}",
+            HumanBytes(self.calculate_download_size()),
+            usage,
+            HumanBytes(size_change.unsigned_abs() as u64)
+        )
+    }
+
     fn calculate_size_change(&self) -> i128 {
         let mut res: i128 = 0;
         for install in &self.install {
@@ -238,6 +395,11 @@ impl PkgActions<'_> {
             }
         }
 
+        for downgrade in &self.downgrade {
+            res += i128::from(downgrade.0.install_size);
+            res -= i128::from(downgrade.1 .1);
+        }
+
         for unpack in &self.unpack {
             res += i128::from(unpack.0.install_size);
             if let Some(oldpkg) = &unpack.1 {
@@ -264,6 +426,12 @@ impl PkgActions<'_> {
             }
         }
 
+        for downgrade in &self.downgrade {
+            if let PkgSource::Http((_, size, _)) = downgrade.0.source {
+                res += size;
+            }
+        }
+
         for unpack in &self.unpack {
             if let PkgSource::Http((_, size, _)) = unpack.0.source {
                 res += size;
@@ -273,3 +441,158 @@ impl PkgActions<'_> {
         res
     }
 }
+
+/// Show a version change as `OLD -> NEW`, with the unchanged prefix of NEW dimmed and the rest in green
+///
+/// Styles are no-ops if colors are disabled (non-tty or `NO_COLOR`), so this falls back to plain text.
+pub fn gen_version_change_str(old: &PkgVersion, new: &PkgVersion) -> String {
+    let old = old.to_string();
+    let new = new.to_string();
+    let split = changed_at(&old, &new);
+    format!(
+        "{} -> {}{}",
+        style(&old).dim(),
+        style(&new[..split]).dim(),
+        style(&new[split..]).green()
+    )
+}
+
+/// Find where `new` starts to differ from `old`, rounded down to the beginning of a version component
+fn changed_at(old: &str, new: &str) -> usize {
+    let common = old
+        .char_indices()
+        .zip(new.chars())
+        .find(|((_, a), b)| a != b)
+        .map(|((i, _), _)| i)
+        .unwrap_or_else(|| old.len().min(new.len()));
+    if common == old.len() && !new[common..].starts_with(|c: char| c.is_ascii_alphanumeric()) {
+        // Identical, or new components appended, e.g. 1.0 -> 1.0.1
+        return common;
+    }
+    // Don't split in the middle of a component, e.g. 1.2.9 -> 1.2.10 should highlight "10"
+    new[..common]
+        .rfind(|c: char| !c.is_ascii_alphanumeric())
+        .map(|i| i + 1)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_size_summary() {
+        let mut new = crate::types::test_pkg("new", "1.0");
+        new.install_size = 3 * 1024 * 1024;
+        new.source = PkgSource::Http((
+            String::new(),
+            1024 * 1024,
+            Checksum::from_sha256_str(&"0".repeat(64)).unwrap(),
+        ));
+        let mut actions = PkgActions::default();
+        actions.install.push((&new, None));
+        assert_eq!(
+            actions.gen_size_summary(),
+            "will download 1.00MiB, use 3.00MiB"
+        );
+        actions
+            .remove
+            .push(("old".to_string(), 5 * 1024 * 1024, false));
+        assert_eq!(
+            actions.gen_size_summary(),
+            "will download 1.00MiB, free 2.00MiB"
+        );
+    }
+
+    #[test]
+    fn test_summary() {
+        let mut new = crate::types::test_pkg("new", "1.0");
+        new.install_size = 2048;
+        new.source = PkgSource::Http((
+            String::new(),
+            1024,
+            Checksum::from_sha256_str(&"0".repeat(64)).unwrap(),
+        ));
+        let upgraded = crate::types::test_pkg("upgraded", "2.0");
+        let mut actions = PkgActions::default();
+        actions.install.push((&new, None));
+        actions.install.push((
+            &upgraded,
+            Some((PkgVersion::try_from("1.0").unwrap(), 4096)),
+        ));
+        actions.purge.push(("old".to_string(), 1024, false));
+        let mut summary = actions.summary();
+        summary.elapsed = Duration::from_millis(1500);
+        assert_eq!(
+            summary.to_lines(),
+            vec![
+                "Installed 1, upgraded 1, downgraded 0, removed 1 package(s) in 1.5s.",
+                "Downloaded 1.00KiB, storage usage -3.00KiB."
+            ]
+        );
+        assert_eq!(
+            summary.to_json(),
+            r#"{"installed":1,"upgraded":1,"downgraded":0,"removed":1,"download_size":1024,"size_change":-3072,"elapsed":1.5}"#
+        );
+    }
+
+    #[test]
+    fn test_sorted() {
+        let pkg = |name: &str, size, section: &str| {
+            let mut pkg = crate::types::test_pkg(name, "1.0");
+            pkg.install_size = size;
+            pkg.section = section.to_string();
+            pkg
+        };
+        let (a, b, c, d) = (
+            pkg("a", 10, "utils"),
+            pkg("b", 30, "libs"),
+            pkg("c", 20, "utils"),
+            pkg("d", 30, "devel"),
+        );
+        let mut actions = PkgActions::default();
+        for p in [&c, &a, &d, &b] {
+            actions.install.push((p, None));
+        }
+        for (name, size) in [("y", 1), ("z", 2), ("x", 1)] {
+            actions.remove.push((name.to_string(), size, false));
+        }
+        fn names<'a>(actions: &'a PkgActions) -> (Vec<&'a str>, Vec<&'a str>) {
+            (
+                actions
+                    .install
+                    .iter()
+                    .map(|(p, _)| p.name.as_str())
+                    .collect(),
+                actions.remove.iter().map(|(n, _, _)| n.as_str()).collect(),
+            )
+        }
+
+        assert_eq!(
+            names(&actions.sorted(ActionSort::Name)),
+            (vec!["a", "b", "c", "d"], vec!["x", "y", "z"])
+        );
+        // Largest first, ties broken by name
+        assert_eq!(
+            names(&actions.sorted(ActionSort::Size)),
+            (vec!["b", "d", "c", "a"], vec!["z", "x", "y"])
+        );
+        // Removed packages have no section
+        assert_eq!(
+            names(&actions.sorted(ActionSort::Section)),
+            (vec!["d", "b", "a", "c"], vec!["x", "y", "z"])
+        );
+        // The original order is kept
+        assert_eq!(names(&actions).0, vec!["c", "a", "d", "b"]);
+    }
+
+    #[test]
+    fn test_changed_at() {
+        assert_eq!(changed_at("1.2.3", "1.2.4"), 4);
+        assert_eq!(changed_at("1.2.9", "1.2.10"), 4);
+        assert_eq!(changed_at("1.2.3", "2.0.0"), 0);
+        assert_eq!(changed_at("1:1.0-1", "1:1.0-2"), 6);
+        assert_eq!(changed_at("1.0", "1.0.1"), 3);
+        assert_eq!(changed_at("1.0", "1.0"), 3);
+    }
+}
@@ -1,6 +1,9 @@
 /// Show actions in tables
-use super::PkgActions;
-use crate::utils::pager::Pager;
+use super::{gen_version_change_str, PkgActions};
+use crate::{
+    types::config::Opts,
+    utils::pager::{count_lines, Pager},
+};
 
 use anyhow::Result;
 use console::style;
@@ -37,49 +40,40 @@ struct ConfigureRow {
     version: String,
 }
 
-pub fn show_table(actions: &PkgActions, no_pager: bool) -> Result<()> {
+pub fn show_table(actions: &PkgActions, opts: &Opts, pager_cmd: Option<&str>) -> Result<()> {
     let mut install_rows = Vec::new();
     let mut upgrade_rows = Vec::new();
     let mut downgrade_rows = Vec::new();
     let mut remove_rows = Vec::new();
     let mut configure_rows = Vec::new();
 
-    for (new, old) in actions.install.iter().rev() {
-        let mut install_size_change: i128 = new.install_size.into();
-        if let Some((_, oldsize)) = old {
-            install_size_change -= *oldsize as i128;
-        }
-        let mut install_size_change_str = HumanBytes(install_size_change.abs() as u64).to_string();
-        if install_size_change >= 0 {
-            install_size_change_str.insert(0, '+');
-        } else {
-            install_size_change_str.insert(0, '-');
-        }
+    for (new, old) in &actions.install {
         let mut row = InstallRow {
             name: new.name.clone(),
             version: match old {
-                Some((oldver, _)) => format!("{} -> {}", oldver, new.version),
+                Some((oldver, _)) => gen_version_change_str(oldver, &new.version),
                 None => new.version.to_string(),
             },
-            size: install_size_change_str,
+            size: gen_size_change_str(new.install_size, old.as_ref().map(|(_, size)| *size)),
         };
         // Insert to different row based on operation
-        if let Some(old) = old {
-            // Upgrade/downgrade
-            if old.0 < new.version {
-                row.name = style(row.name).green().to_string();
-                upgrade_rows.push(row);
-            } else {
-                row.name = style(row.name).yellow().to_string();
-                downgrade_rows.push(row);
-            }
+        row.name = style(row.name).green().to_string();
+        if old.is_some() {
+            upgrade_rows.push(row);
         } else {
-            // New package
-            row.name = style(row.name).green().to_string();
             install_rows.push(row);
         }
     }
 
+    for (new, (oldver, oldsize)) in &actions.downgrade {
+        let row = InstallRow {
+            name: style(&new.name).yellow().to_string(),
+            version: gen_version_change_str(oldver, &new.version),
+            size: gen_size_change_str(new.install_size, Some(*oldsize)),
+        };
+        downgrade_rows.push(row);
+    }
+
     for (name, size, essential) in &actions.remove {
         let detail = if *essential {
             style("Essential").on_white().red().to_string()
@@ -116,15 +110,8 @@ pub fn show_table(actions: &PkgActions, no_pager: bool) -> Result<()> {
         configure_rows.push(row);
     }
 
-    let mut pager = Pager::new(no_pager)?;
-    let pager_name = pager.pager_name().to_owned();
-    let mut out = pager.get_writer()?;
-
-    write_review_help_message(&mut out)?;
-    // Show help message about how to exit review view
-    if pager_name == Some("less") {
-        writeln!(out, "{}", style("Press [q] to finish review.\n").bold())?;
-    }
+    // Render everything first, so we can decide whether to use a pager
+    let mut out: Vec<u8> = Vec::new();
 
     if !remove_rows.is_empty() {
         writeln!(
@@ -180,7 +167,7 @@ pub fn show_table(actions: &PkgActions, no_pager: bool) -> Result<()> {
         let table = Table::new(&downgrade_rows)
             .with(Modify::new(Full).with(Alignment::left()))
             // Install Size column should align right
-            .with(Modify::new(Column(1..2)).with(Alignment::right()))
+            .with(Modify::new(Column(2..3)).with(Alignment::right()))
             .with(Modify::new(Full).with(|s: &str| format!(" {s} ")))
             .with(Style::PSQL);
         writeln!(out, "{table}")?;
@@ -217,14 +204,41 @@ pub fn show_table(actions: &PkgActions, no_pager: bool) -> Result<()> {
         HumanBytes(abs_install_size_change)
     )?;
 
+    let mut header: Vec<u8> = Vec::new();
+    write_review_help_message(&mut header)?;
+    let lines = count_lines(&header) + count_lines(&out) + 2;
+    let mut pager = Pager::with_threshold(opts, pager_cmd, lines)?;
+    let pager_name = pager.pager_name().to_owned();
+    let mut writer = pager.get_writer()?;
+    writer.write_all(&header)?;
+    // Show help message about how to exit review view
+    if pager_name == Some("less") {
+        writeln!(writer, "{}", style("Press [q] to finish review.\n").bold())?;
+    }
+    writer.write_all(&out)?;
+
     // Finish writing
-    drop(out);
+    drop(writer);
     // Wait until pager exits
     pager.wait_for_exit()?;
 
     Ok(())
 }
 
+fn gen_size_change_str(new_size: u64, old_size: Option<u64>) -> String {
+    let mut install_size_change: i128 = new_size.into();
+    if let Some(oldsize) = old_size {
+        install_size_change -= oldsize as i128;
+    }
+    let mut install_size_change_str = HumanBytes(install_size_change.abs() as u64).to_string();
+    if install_size_change >= 0 {
+        install_size_change_str.insert(0, '+');
+    } else {
+        install_size_change_str.insert(0, '-');
+    }
+    install_size_change_str
+}
+
 fn write_review_help_message(w: &mut dyn Write) -> Result<()> {
     writeln!(w, "{}", style("Pending Operations").bold())?;
     writeln!(w)?;
@@ -3,6 +3,15 @@ use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256, Sha512};
 use std::{fmt::Display, fs::File, io, path::Path};
 
+/// Supported checksum algorithms, from the weakest to the strongest
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChecksumType {
+    Sha256,
+    #[default]
+    Sha512,
+}
+
 #[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
 pub enum Checksum {
     Sha256(Vec<u8>),
@@ -31,7 +40,34 @@ impl ChecksumValidator {
     }
 }
 
+/// Written data is fed to the validator, e.g. as the output of a decoder
+impl io::Write for ChecksumValidator {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
 impl Checksum {
+    pub fn checksum_type(&self) -> ChecksumType {
+        match self {
+            Checksum::Sha256(_) => ChecksumType::Sha256,
+            Checksum::Sha512(_) => ChecksumType::Sha512,
+        }
+    }
+
+    /// Pick the `preferred` checksum among `candidates`, or the strongest one if it's not available
+    pub fn select(candidates: &[Checksum], preferred: ChecksumType) -> Option<&Checksum> {
+        candidates
+            .iter()
+            .find(|c| c.checksum_type() == preferred)
+            .or_else(|| candidates.iter().max_by_key(|c| c.checksum_type()))
+    }
+
     pub fn from_file_sha256(path: &Path) -> Result<Self> {
         let mut file = File::open(path).context(format!(
             "Failed to open {} for checking checksum",
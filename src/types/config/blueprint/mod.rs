@@ -14,6 +14,8 @@ pub struct PkgRequest {
     pub version: VersionRequirement,
     pub added_by: Option<String>,
     pub local: bool,
+    /// Don't add recommended packages of this package
+    pub no_recommends: bool,
 }
 
 impl std::fmt::Display for PkgRequest {
@@ -30,6 +32,9 @@ impl std::fmt::Display for PkgRequest {
         if self.local {
             sections.push("local".to_owned());
         }
+        if self.no_recommends {
+            sections.push("no_recommends".to_owned());
+        }
         // Write it
         if !sections.is_empty() {
             let joined = sections.join(", ");
@@ -45,6 +50,8 @@ pub struct Blueprints {
     // If we need to export the blueprint back to disk
     user_blueprint_modified: bool,
     user: Vec<BlueprintLine>,
+    // Package requests in user blueprint as read from disk, to tell what has been changed
+    original: Vec<String>,
     vendor: Vec<(PathBuf, Vec<BlueprintLine>)>,
 }
 
@@ -59,6 +66,7 @@ impl Blueprints {
         Ok(Blueprints {
             user_blueprint_path: user,
             user_blueprint_modified: false,
+            original: requests_of(&user_blueprint),
             user: user_blueprint,
             vendor: vendor_blueprints,
         })
@@ -101,6 +109,7 @@ impl Blueprints {
         added_by: Option<&str>,
         ver_req: Option<VersionRequirement>,
         local: bool,
+        no_recommends: bool,
     ) -> Result<()> {
         if !modify && self.user_list_contains(pkgname) {
             bail!(
@@ -129,6 +138,7 @@ impl Blueprints {
             version,
             added_by: added_by.map(|pkgname| pkgname.to_owned()),
             local,
+            no_recommends,
         };
         if modify && self.user_list_contains(pkgname) {
             // Remove old entry first
@@ -184,6 +194,27 @@ impl Blueprints {
         }
     }
 
+    /// Changes to package requests in user blueprint since it's read, in the form of
+    /// `- pkg` for removed ones and `+ pkg` for added ones
+    ///
+    /// A request that is modified (e.g. version requirement changed) shows up as both.
+    pub fn diff(&self) -> Vec<String> {
+        if !self.user_blueprint_modified {
+            return Vec::new();
+        }
+        let current = requests_of(&self.user);
+        let removed = self
+            .original
+            .iter()
+            .filter(|req| !current.contains(req))
+            .map(|req| format!("- {req}"));
+        let added = current
+            .iter()
+            .filter(|req| !self.original.contains(req))
+            .map(|req| format!("+ {req}"));
+        removed.chain(added).collect()
+    }
+
     // Write back user blueprint
     pub fn export(&self) -> Result<bool> {
         if !self.user_blueprint_modified {
@@ -216,6 +247,14 @@ impl Blueprints {
         Ok(true)
     }
 
+    /// Entry of `pkgname` in user blueprint
+    pub fn user_request(&self, pkgname: &str) -> Option<&PkgRequest> {
+        self.user.iter().find_map(|line| match line {
+            BlueprintLine::PkgRequest(req) if req.name == pkgname => Some(req),
+            _ => None,
+        })
+    }
+
     pub fn user_list_contains(&self, pkgname: &str) -> bool {
         for line in &self.user {
             if let BlueprintLine::PkgRequest(req) = line {
@@ -240,3 +279,94 @@ impl Blueprints {
         None
     }
 }
+
+fn requests_of(lines: &[BlueprintLine]) -> Vec<String> {
+    lines
+        .iter()
+        .filter_map(|line| match line {
+            BlueprintLine::PkgRequest(req) => Some(req.to_string()),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::types::PkgVersion;
+    use crate::utils::temp_dir::TempDir;
+
+    #[test]
+    fn test_diff() {
+        let req = |name: &str| {
+            BlueprintLine::PkgRequest(PkgRequest {
+                name: name.to_string(),
+                ..Default::default()
+            })
+        };
+        let user = vec![
+            BlueprintLine::Comment(" Base".to_string()),
+            req("bash"),
+            req("vim"),
+            BlueprintLine::EmptyLine,
+            req("zsh"),
+        ];
+        let mut blueprint = Blueprints {
+            user_blueprint_path: PathBuf::new(),
+            user_blueprint_modified: false,
+            original: requests_of(&user),
+            user,
+            vendor: Vec::new(),
+        };
+        assert!(blueprint.diff().is_empty());
+
+        blueprint.remove("vim", false).unwrap();
+        blueprint
+            .add("nano", false, Some("vim"), None, false, false)
+            .unwrap();
+        let ver = VersionRequirement {
+            lower_bond: Some((PkgVersion::try_from("5.2").unwrap(), true)),
+            upper_bond: None,
+        };
+        blueprint
+            .add("zsh", true, None, Some(ver), false, false)
+            .unwrap();
+        assert_eq!(
+            blueprint.diff(),
+            vec!["- vim", "- zsh", "+ nano (added_by = vim)", "+ zsh (>=5.2)"]
+        );
+
+        // Back to where it was
+        blueprint.remove("nano", false).unwrap();
+        blueprint
+            .add("vim", false, None, None, false, false)
+            .unwrap();
+        blueprint
+            .add("zsh", true, None, None, false, false)
+            .unwrap();
+        assert!(blueprint.diff().is_empty());
+    }
+
+    #[test]
+    fn test_no_recommends_round_trip() {
+        let dir = TempDir::new("blueprint");
+        let path = dir.join("user.blueprint");
+        std::fs::write(&path, "# Heavy\nheavy (no_recommends)\nbash\n").unwrap();
+        let mut blueprint = Blueprints::from_files(path.clone(), &[]).unwrap();
+        assert!(blueprint.user_request("heavy").unwrap().no_recommends);
+        assert!(!blueprint.user_request("bash").unwrap().no_recommends);
+        blueprint
+            .add("vim", false, None, None, false, true)
+            .unwrap();
+        blueprint.export().unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "# Heavy\nheavy (no_recommends)\nbash\nvim (no_recommends)\n"
+        );
+
+        // Read back, with the annotation kept
+        let blueprint = Blueprints::from_files(path.clone(), &[]).unwrap();
+        assert!(blueprint.user_request("vim").unwrap().no_recommends);
+        assert_eq!(blueprint.get_pkg_requests().len(), 3);
+    }
+}
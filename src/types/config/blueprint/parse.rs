@@ -112,6 +112,7 @@ enum PkgOption {
     VersionRequirement(VersionRequirement),
     AddedBy(String),
     Local,
+    NoRecommends,
 }
 
 fn pkg_option(i: &str) -> IResult<&str, PkgOption> {
@@ -131,6 +132,10 @@ fn pkg_option(i: &str) -> IResult<&str, PkgOption> {
         return Ok((i, PkgOption::Local));
     }
 
+    if let Ok((i, _)) = tag::<_, _, Error<&str>>("no_recommends")(i) {
+        return Ok((i, PkgOption::NoRecommends));
+    }
+
     Err(nom::Err::Error(nom::error::Error::from_error_kind(
         i,
         ErrorKind::Alt,
@@ -146,6 +151,7 @@ fn package_line(i: &str) -> IResult<&str, PkgRequest> {
         version: VersionRequirement::default(),
         added_by: None,
         local: false,
+        no_recommends: false,
     };
 
     let i = if let Ok((i, opts)) = nom::sequence::delimited(
@@ -164,6 +170,9 @@ fn package_line(i: &str) -> IResult<&str, PkgRequest> {
                 PkgOption::Local => {
                     res.local = true;
                 }
+                PkgOption::NoRecommends => {
+                    res.no_recommends = true;
+                }
             }
         }
         i
@@ -268,6 +277,7 @@ mod tests {
                 },
                 added_by: Some("wow".to_string()),
                 local: false,
+                no_recommends: false,
             },
             (
                 "pkgname (>>1, local, <<2)",
@@ -279,6 +289,7 @@ mod tests {
                     },
                     added_by: None,
                     local: true,
+                    no_recommends: false,
                 },
             ),
         )];
@@ -286,5 +297,9 @@ mod tests {
         for t in tests {
             assert_eq!(package_line(t.0).unwrap().1, t.1);
         }
+        let req = package_line("heavy (no_recommends, >=2)").unwrap().1;
+        assert!(req.no_recommends);
+        assert!(!req.local);
+        assert!(!req.version.is_arbitary());
     }
 }
@@ -1,24 +1,87 @@
 mod blueprint;
 pub use blueprint::{Blueprints, PkgRequest};
 
-use crate::warn;
+use crate::{
+    pool::PkgFields,
+    types::{ChecksumType, Priority},
+    utils::downloader::Credential,
+    warn,
+};
 
-use anyhow::{bail, Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use clap::Parser;
 use console::style;
 use serde::{Deserialize, Serialize, Serializer};
 use std::{
     collections::{BTreeMap, HashMap},
-    fs,
+    fmt, fs,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
     path::PathBuf,
 };
 
 #[derive(Deserialize, Serialize, Clone)]
 pub struct Config {
     pub arch: String,
+    /// Treat recommended packages as mandatory when installing
+    #[serde(default)]
+    pub strict_recommends: bool,
+    /// Pager program for long output, overrides `APM_PAGER` and `PAGER`
+    pub pager: Option<String>,
+    /// Packages in these sections are not upgraded unless explicitly requested
+    #[serde(default)]
+    pub hold_sections: Vec<String>,
+    /// Installed packages matching these glob patterns are never removed automatically
+    #[serde(default)]
+    pub never_autoremove: Vec<String>,
+    /// Packages matching these glob patterns are never installed, see `Solver::exclude`
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    /// Only consider the newest N versions of each package when resolving dependencies
+    pub max_versions_per_package: Option<usize>,
+    /// Leave out package descriptions and suggestions when resolving dependencies to save memory
+    #[serde(default)]
+    pub trim_package_fields: bool,
+    /// dpkg database directory, within root, even if absolute. Defaults to `var/lib/dpkg`
+    pub dpkg_admindir: Option<PathBuf>,
+    /// In which order dpkg operations are performed
+    #[serde(default)]
+    pub dpkg_order: DpkgOrder,
+    /// Check that packages ended up in the expected state after running dpkg
+    #[serde(default)]
+    pub verify_after: bool,
+    /// Which versions the solver prefers for packages not explicitly requested
+    #[serde(default)]
+    pub upgrade_policy: UpgradePolicy,
+    /// Checksum used to validate repository metadata, if InRelease provides several
+    #[serde(default)]
+    pub preferred_checksum: ChecksumType,
+    /// Refuse repository metadata signed by expired keys, instead of showing a warning
+    #[serde(default)]
+    pub reject_expired_keys: bool,
+    /// Try other mirrors in the MirrorList if repository metadata fails signature verification
+    #[serde(default)]
+    pub fallback_on_bad_signature: bool,
+    /// Days after which local repository metadata is considered stale, see `LocalDb::stale_for`
+    pub max_metadata_age: Option<u64>,
+    /// Refresh stale metadata without asking
+    #[serde(default)]
+    pub auto_refresh: bool,
     #[serde(serialize_with = "ordered_map")]
     pub repo: HashMap<String, RepoConfig>,
+    /// Transactions above these limits need a confirmation phrase instead of y/N
+    #[serde(default)]
+    pub confirm_threshold: ConfirmThreshold,
+    /// Ask for the confirmation phrase of large transactions even with `--yes`
+    #[serde(default)]
+    pub require_explicit_large: bool,
+    /// Free space to keep on the root file system after a transaction
+    pub min_free_space: Option<FreeSpaceReserve>,
+    /// Which checks of dpkg are overridden
+    #[serde(default)]
+    pub dpkg_force: DpkgForce,
     pub r#unsafe: Option<UnsafeConfig>,
+    pub network: Option<NetworkConfig>,
+    pub log: Option<LogConfig>,
 }
 
 fn ordered_map<S>(value: &HashMap<String, RepoConfig>, serializer: S) -> Result<S::Ok, S::Error>
@@ -29,6 +92,167 @@ where
     ordered.serialize(serializer)
 }
 
+/// Free space to keep, either in bytes or a percentage of the file system like "10%"
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum FreeSpaceReserve {
+    Bytes(u64),
+    Percentage(String),
+}
+
+impl FreeSpaceReserve {
+    /// Reserved bytes on a file system of `total` bytes
+    pub fn bytes(&self, total: u64) -> Result<u64> {
+        match self {
+            FreeSpaceReserve::Bytes(bytes) => Ok(*bytes),
+            FreeSpaceReserve::Percentage(s) => {
+                let percent: f64 = s
+                    .strip_suffix('%')
+                    .and_then(|n| n.trim().parse().ok())
+                    .filter(|n| (0.0..=100.0).contains(n))
+                    .ok_or_else(|| {
+                        anyhow!(
+                            "Invalid min_free_space {}, expecting bytes or a percentage like \"10%\".",
+                            style(s).bold()
+                        )
+                    })?;
+                Ok((total as f64 * percent / 100.0) as u64)
+            }
+        }
+    }
+}
+
+/// Order of dpkg operations
+#[derive(Serialize, Deserialize, Default, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum DpkgOrder {
+    /// Unpack new packages first, then remove old ones, unless they would conflict with new packages
+    #[default]
+    InstallFirst,
+    /// Remove packages before installing new ones
+    RemoveFirst,
+}
+
+/// Format of the checksum manifest written by `--download-only`
+#[derive(clap::ArgEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ManifestFormat {
+    /// `SHA256SUMS`, as understood by `sha256sum --check`
+    Sha256,
+    /// `manifest.json`, with package names and versions
+    Json,
+}
+
+/// Order of packages in each category when showing what will be done
+#[derive(clap::ArgEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ActionSort {
+    Name,
+    /// From the largest
+    Size,
+    Section,
+}
+
+/// Format of the dependency graph written by `oma depends --graph`
+#[derive(clap::ArgEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GraphFormat {
+    /// Graphviz DOT
+    Dot,
+}
+
+/// Default version selection policy of the solver
+#[derive(Serialize, Deserialize, Default, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum UpgradePolicy {
+    /// Always prefer the newest versions
+    #[default]
+    Aggressive,
+    /// Keep installed versions, unless a newer version is required
+    Conservative,
+    /// Keep installed versions, unless a security update is available
+    Security,
+}
+
+/// Limits of a transaction, above which it is considered large
+#[derive(Serialize, Deserialize, Default, Clone, Debug, PartialEq, Eq)]
+pub struct ConfirmThreshold {
+    /// Number of packages installed, upgraded, downgraded or removed
+    pub packages: Option<usize>,
+    /// Total download size, in MiB
+    pub download_size_mib: Option<u64>,
+}
+
+impl ConfirmThreshold {
+    /// Whether a transaction of `packages` packages that downloads `download_size` bytes exceeds any limit
+    pub fn exceeded(&self, packages: usize, download_size: u64) -> bool {
+        let too_many = self.packages.is_some_and(|limit| packages > limit);
+        let too_big = self
+            .download_size_mib
+            .is_some_and(|limit| download_size > limit.saturating_mul(1024 * 1024));
+        too_many || too_big
+    }
+}
+
+/// `--force-*` flags passed to dpkg
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(default)]
+pub struct DpkgForce {
+    /// Install older versions over newer ones, which dpkg only warns about anyway
+    pub force_downgrade: bool,
+    /// Don't stop on missing dependencies, needed while packages are swapped in several steps
+    pub force_depends: bool,
+    /// Don't stop on broken Breaks, needed while packages are upgraded in several steps
+    pub force_breaks: bool,
+    /// Install packages that conflict with installed ones
+    pub force_conflicts: bool,
+    /// Remove essential packages, Omakase asks for confirmation before doing so
+    pub force_remove_essential: bool,
+    /// Overwrite files of other packages that are not declared in Replaces
+    pub force_overwrite: bool,
+    /// Keep modified config files without asking
+    pub force_confold: bool,
+    /// Install new versions of modified config files without asking
+    pub force_confnew: bool,
+    /// Take the default action for modified config files without asking
+    pub force_confdef: bool,
+}
+
+impl Default for DpkgForce {
+    fn default() -> Self {
+        DpkgForce {
+            force_downgrade: true,
+            force_depends: true,
+            force_breaks: true,
+            force_conflicts: false,
+            force_remove_essential: true,
+            force_overwrite: false,
+            force_confold: false,
+            force_confnew: false,
+            force_confdef: false,
+        }
+    }
+}
+
+impl DpkgForce {
+    /// Arguments for dpkg, `--force-unsafe-io` is added if `unsafe_io`
+    pub fn args(&self, unsafe_io: bool) -> Vec<&'static str> {
+        [
+            (unsafe_io, "--force-unsafe-io"),
+            (self.force_downgrade, "--force-downgrade"),
+            (self.force_depends, "--force-depends"),
+            (self.force_breaks, "--force-breaks"),
+            (self.force_conflicts, "--force-conflicts"),
+            (self.force_remove_essential, "--force-remove-essential"),
+            (self.force_overwrite, "--force-overwrite"),
+            (self.force_confold, "--force-confold"),
+            (self.force_confnew, "--force-confnew"),
+            (self.force_confdef, "--force-confdef"),
+        ]
+        .into_iter()
+        .filter(|(enabled, _)| *enabled)
+        .map(|(_, arg)| arg)
+        .collect()
+    }
+}
+
 #[derive(Serialize, Deserialize, Default, Clone)]
 pub struct UnsafeConfig {
     #[serde(default)]
@@ -37,15 +261,168 @@ pub struct UnsafeConfig {
     pub unsafe_io: bool,
     #[serde(default)]
     pub allow_remove_essential: bool,
+    /// Allow repositories with `trusted = true`
+    #[serde(default)]
+    pub allow_unauthenticated: bool,
+}
+
+/// Plain text log of each run, see `cli::log::FileLog`
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LogConfig {
+    /// Log file, within root even if absolute. Overridden by `--log-file`
+    pub path: PathBuf,
+    /// Rotate the log before a run if it's larger than this, in bytes
+    pub max_size: Option<u64>,
+    /// How many old logs are kept as `path.1`, `path.2`..., 0 to truncate the log instead
+    pub keep: Option<usize>,
+}
+
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct NetworkConfig {
+    /// Override the default `omakase/VERSION` User-Agent
+    pub user_agent: Option<String>,
+    /// Additional headers sent with every request
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    #[serde(default)]
+    pub http_version: HttpVersion,
+    /// Idle connections kept open for each host, to be reused by later requests
+    pub pool_max_idle_per_host: Option<usize>,
+    /// Seconds before an idle connection is closed
+    pub pool_idle_timeout: Option<u64>,
+    /// Seconds to wait for a connection to be made, no limit if not set
+    pub connect_timeout: Option<u64>,
+    /// Seconds to wait for a response or more data of it, no limit if not set
+    pub read_timeout: Option<u64>,
+}
+
+/// HTTP version used to talk to mirrors
+#[derive(Serialize, Deserialize, Default, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum HttpVersion {
+    /// HTTP/2 if the mirror offers it when setting up TLS, HTTP/1.1 otherwise
+    #[default]
+    Auto,
+    /// Always HTTP/1.1
+    Http1,
+    /// Always HTTP/2, even over plain HTTP. Fails on mirrors without HTTP/2 support
+    Http2,
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct RepoConfig {
     pub source: Mirror,
     pub tags: Option<Vec<String>>,
+    /// Not needed by flat repositories
+    #[serde(default)]
     pub distribution: String,
+    /// Not needed by flat repositories
+    #[serde(default)]
     pub components: Vec<String>,
-    pub keys: Vec<String>,
+    pub keys: Vec<RepoKey>,
+    /// Username for HTTP basic authentication
+    pub username: Option<String>,
+    /// Password for HTTP basic authentication
+    pub password: Option<Secret>,
+    /// Bearer token for authentication
+    pub auth_token: Option<Secret>,
+    /// Whether this repository provides security updates
+    #[serde(default)]
+    pub security: bool,
+    /// Architecture of packages taken from this repository, instead of `arch` of config.toml
+    pub arch: Option<String>,
+    /// InRelease and Packages are right at the source URL, without `dists/` and components
+    #[serde(default)]
+    pub flat: bool,
+    /// Use InRelease without verifying its signature, checksums of files are still validated
+    #[serde(default)]
+    pub trusted: bool,
+    /// Where InRelease is, relative to the source URL, instead of `dists/{distribution}/InRelease`
+    ///
+    /// `{distribution}` is replaced. Package indices are relative to the directory of it.
+    pub release_path: Option<String>,
+    /// Overrides `connect_timeout` of the network section for this repository
+    pub connect_timeout: Option<u64>,
+    /// Overrides `read_timeout` of the network section for this repository
+    pub read_timeout: Option<u64>,
+    /// When several repositories provide the same package, the one with the highest priority
+    /// is used, then the one configured first
+    #[serde(default)]
+    pub priority: i32,
+    /// Position in config.toml, see `Config::read_repo_order`
+    #[serde(skip)]
+    pub order: usize,
+}
+
+/// Public key file of a repository, either just the filename or with options
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum RepoKey {
+    File(String),
+    Detailed {
+        file: String,
+        /// The key is being rotated out, warn if InRelease is only signed by it
+        #[serde(default)]
+        deprecated: bool,
+    },
+}
+
+impl RepoKey {
+    /// Filename in the key directory
+    pub fn file(&self) -> &str {
+        match self {
+            RepoKey::File(file) | RepoKey::Detailed { file, .. } => file,
+        }
+    }
+
+    pub fn deprecated(&self) -> bool {
+        matches!(
+            self,
+            RepoKey::Detailed {
+                deprecated: true,
+                ..
+            }
+        )
+    }
+}
+
+/// A credential that can be written in plaintext, or read from an environment variable or a file
+#[derive(Deserialize, Serialize, Clone)]
+#[serde(untagged)]
+pub enum Secret {
+    Plain(String),
+    Env { env: String },
+    File { file: PathBuf },
+}
+
+impl std::fmt::Debug for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Secret::Plain(_) => write!(f, "<redacted>"),
+            Secret::Env { env } => write!(f, "<env {env}>"),
+            Secret::File { file } => write!(f, "<file {}>", file.display()),
+        }
+    }
+}
+
+impl Secret {
+    pub fn resolve(&self) -> Result<String> {
+        let res = match self {
+            Secret::Plain(s) => s.clone(),
+            Secret::Env { env } => std::env::var(env).context(format!(
+                "Failed to read credential from environment variable {}.",
+                style(env).bold()
+            ))?,
+            Secret::File { file } => fs::read_to_string(file)
+                .context(format!(
+                    "Failed to read credential from {}.",
+                    style(file.display()).bold()
+                ))?
+                .trim_end()
+                .to_owned(),
+        };
+        Ok(res)
+    }
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
@@ -55,9 +432,47 @@ pub enum Mirror {
     MirrorList {
         mirrorlist: PathBuf,
         preferred: String,
+        /// Connect to the preferred mirror only over this IP family, usually set by `oma bench`
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        ip_family: Option<IpFamily>,
     },
 }
 
+/// IP family used to connect to a mirror
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum IpFamily {
+    Ipv4,
+    Ipv6,
+}
+
+impl IpFamily {
+    /// Unspecified address of this family, binding to it forces connections over this family
+    pub fn local_address(self) -> IpAddr {
+        match self {
+            IpFamily::Ipv4 => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            IpFamily::Ipv6 => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        }
+    }
+
+    /// Value of `ip_family` in config.toml
+    pub fn as_config_str(self) -> &'static str {
+        match self {
+            IpFamily::Ipv4 => "ipv4",
+            IpFamily::Ipv6 => "ipv6",
+        }
+    }
+}
+
+impl fmt::Display for IpFamily {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IpFamily::Ipv4 => write!(f, "IPv4"),
+            IpFamily::Ipv6 => write!(f, "IPv6"),
+        }
+    }
+}
+
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct MirrorList {
     default: String,
@@ -73,34 +488,152 @@ pub struct MirrorMeta {
 }
 
 impl Config {
+    /// Which fields of package metadata are needed to resolve dependencies
+    pub fn resolve_fields(&self) -> PkgFields {
+        if self.trim_package_fields {
+            PkgFields::Resolve
+        } else {
+            PkgFields::All
+        }
+    }
+
+    /// Record the position of each repository in config.toml, whose content is `data`
+    ///
+    /// The map of repositories doesn't keep it, but it decides which repository is used when
+    /// several provide the same package with the same priority.
+    pub fn read_repo_order(&mut self, data: &str) -> Result<()> {
+        let doc: toml_edit::Document = data.parse()?;
+        let repos = match doc.get("repo").and_then(|repos| repos.as_table_like()) {
+            Some(repos) => repos,
+            None => return Ok(()),
+        };
+        for (order, (name, _)) in repos.iter().enumerate() {
+            if let Some(repo) = self.repo.get_mut(name) {
+                repo.order = order;
+            }
+        }
+        Ok(())
+    }
+
     pub fn check_sanity(&self) -> Result<()> {
+        if self.max_versions_per_package == Some(0) {
+            bail!("max_versions_per_package must be greater than 0.");
+        }
+        if let Some(reserve) = &self.min_free_space {
+            reserve.bytes(0)?;
+        }
+        if let Some(network) = &self.network {
+            check_timeouts(network.connect_timeout, network.read_timeout)?;
+        }
         for (name, repo) in &self.repo {
             // Check public key names
-            for key in &repo.keys {
+            for key in repo.keys.iter().map(|key| key.file()) {
                 if key.contains(|c| !key_filename_char(c)) {
                     bail!("Invalid character in public key filename {name} for repository {key}.",);
                 }
             }
-            repo.check_sanity()?;
+            repo.check_sanity()
+                .context(format!("Invalid configuration for repository {name}."))?;
+            let allow_unauthenticated = self
+                .r#unsafe
+                .as_ref()
+                .is_some_and(|u| u.allow_unauthenticated);
+            if repo.trusted && !allow_unauthenticated {
+                bail!(
+                    "Repository {} is trusted without signature verification, but {} is not set in the {} section.",
+                    style(name).bold(),
+                    style("allow_unauthenticated").bold(),
+                    style("unsafe").bold()
+                );
+            }
         }
 
         Ok(())
     }
 }
 
-fn key_filename_char(c: char) -> bool {
+/// Check that a `release_path` template stays within the repository
+fn check_release_path(template: &str) -> Result<()> {
+    if template.starts_with('/') || template.ends_with('/') {
+        bail!("It must be a relative path to a file.");
+    }
+    for segment in template.split('/') {
+        if segment.is_empty() || segment == "." || segment == ".." {
+            bail!("Empty, `.` and `..` components are not allowed.");
+        }
+    }
+    let rest = template.replace("{distribution}", "");
+    if rest.contains(['{', '}', '\\', '?', '#']) {
+        bail!(
+            "Only {} can be replaced, and `\\`, `?` and `#` are not allowed.",
+            style("{distribution}").bold()
+        );
+    }
+    Ok(())
+}
+
+/// Characters allowed in filenames of public keys
+pub fn key_filename_char(c: char) -> bool {
     c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.'
 }
 
+/// A timeout of 0 would fail every request
+fn check_timeouts(connect_timeout: Option<u64>, read_timeout: Option<u64>) -> Result<()> {
+    if connect_timeout == Some(0) {
+        bail!("connect_timeout must be greater than 0.");
+    }
+    if read_timeout == Some(0) {
+        bail!("read_timeout must be greater than 0.");
+    }
+    Ok(())
+}
+
 impl RepoConfig {
     /// Check if there's some mirror available
     pub fn check_sanity(&self) -> Result<()> {
+        // Check credential settings
+        if self.username.is_some() && self.auth_token.is_some() {
+            bail!("Cannot use both basic authentication (username) and auth_token.");
+        }
+        if self.password.is_some() && self.username.is_none() {
+            bail!("A password is set without username.");
+        }
+        check_timeouts(self.connect_timeout, self.read_timeout)?;
+
+        // Check repository layout
+        if self.flat {
+            if !self.components.is_empty() {
+                bail!(
+                    "Flat repositories don't have components, remove {}.",
+                    style("components").bold()
+                );
+            }
+        } else {
+            if self.distribution.is_empty() {
+                bail!("No {} is set.", style("distribution").bold());
+            }
+            if self.components.is_empty() {
+                bail!(
+                    "No components are set, add them to {} (e.g. [\"main\"]), or set {} for a repository without dists/.",
+                    style("components").bold(),
+                    style("flat = true").bold()
+                );
+            }
+        }
+
+        if let Some(template) = &self.release_path {
+            // The distribution is part of the path as well
+            check_release_path(template)
+                .and_then(|_| check_release_path(&self.release_path()))
+                .context(format!(
+                    "Invalid {} {}.",
+                    style("release_path").bold(),
+                    style(template).bold()
+                ))?;
+        }
+
         // If we are using MirrorList, test-parse here
-        if let Mirror::MirrorList {
-            preferred: _,
-            mirrorlist,
-        } = &self.source
-        {
+        if let Mirror::MirrorList { mirrorlist, .. } = &self.source {
             let path = mirrorlist;
             if !path.is_absolute() {
                 bail!(
@@ -130,19 +663,32 @@ impl RepoConfig {
         Ok(())
     }
 
+    /// Path of InRelease relative to the base URL of the repository
+    pub fn release_path(&self) -> String {
+        match &self.release_path {
+            Some(template) => template.replace("{distribution}", &self.distribution),
+            None if self.flat => "InRelease".to_string(),
+            None => format!("dists/{}/InRelease", self.distribution),
+        }
+    }
+
+    /// URL of the directory holding InRelease and package indices on the mirror at `url`
+    pub fn release_dir_url(&self, url: &str) -> String {
+        match self.release_path().rsplit_once('/') {
+            Some((dir, _)) => format!("{url}/{dir}"),
+            None => url.to_owned(),
+        }
+    }
+
     /// Get the first choice mirror
     pub fn get_url(&self) -> Result<String> {
         let url = match &self.source {
             Mirror::Simple(m) => {
                 let mut url = m.clone();
-                // Add `debs`
-                normalize_mirror_url(&mut url);
+                self.normalize_url(&mut url);
                 url
             }
-            Mirror::MirrorList {
-                preferred,
-                mirrorlist: _,
-            } => {
+            Mirror::MirrorList { preferred, .. } => {
                 let (mirrors, default) = self.get_mirrors()?;
                 // Check if the preferred mirror exists
                 if let Some(mirror) = mirrors.get(preferred) {
@@ -157,12 +703,65 @@ impl RepoConfig {
         Ok(url)
     }
 
+    /// Get all mirrors to try, the first choice comes first, then the default one and the others
+    pub fn get_urls(&self) -> Result<Vec<String>> {
+        let mut res = vec![self.get_url()?];
+        if let Mirror::MirrorList { .. } = &self.source {
+            let (mirrors, default) = self.get_mirrors()?;
+            let mut others: Vec<(String, MirrorMeta)> = mirrors.into_iter().collect();
+            others.sort_by(|a, b| a.0.cmp(&b.0));
+            for url in std::iter::once(default.url).chain(others.into_iter().map(|(_, m)| m.url)) {
+                if !res.contains(&url) {
+                    res.push(url);
+                }
+            }
+        }
+
+        Ok(res)
+    }
+
+    /// IP family to connect to the first choice mirror over, if it's restricted
+    pub fn ip_family(&self) -> Option<IpFamily> {
+        match &self.source {
+            Mirror::Simple(_) => None,
+            Mirror::MirrorList { ip_family, .. } => *ip_family,
+        }
+    }
+
+    /// Get the credential used to access this repository, if any
+    pub fn get_credential(&self) -> Result<Option<Credential>> {
+        if let Some(username) = &self.username {
+            let password = match &self.password {
+                Some(p) => Some(p.resolve()?),
+                None => None,
+            };
+            return Ok(Some(Credential::Basic {
+                username: username.clone(),
+                password,
+            }));
+        }
+        if let Some(token) = &self.auth_token {
+            return Ok(Some(Credential::Token(token.resolve()?)));
+        }
+
+        Ok(None)
+    }
+
+    /// Turn a configured mirror URL into the base URL of the repository
+    fn normalize_url(&self, url: &mut String) {
+        if self.flat {
+            // Flat repositories are used as is
+            while url.ends_with('/') {
+                url.pop();
+            }
+        } else {
+            // Add `debs`
+            normalize_mirror_url(url);
+        }
+    }
+
     pub fn get_mirrors(&self) -> Result<(HashMap<String, MirrorMeta>, MirrorMeta)> {
-        if let Mirror::MirrorList {
-            preferred: _,
-            mirrorlist,
-        } = &self.source
-        {
+        if let Mirror::MirrorList { mirrorlist, .. } = &self.source {
             let path = mirrorlist;
             let content = fs::read_to_string(&path).context(format!(
                 "Failed to read MirrorList file {}!",
@@ -171,8 +770,7 @@ impl RepoConfig {
             let mut mirrorlist: MirrorList = toml::from_str(&content)
                 .context(format!("Malformed MirrorList file {}!", path.display()))?;
             for mirror in &mut mirrorlist.mirrors {
-                let url = &mut mirror.1.url;
-                normalize_mirror_url(url);
+                self.normalize_url(&mut mirror.1.url);
             }
             let default = mirrorlist
                 .mirrors
@@ -213,6 +811,14 @@ pub struct Opts {
     pub config_root: PathBuf,
     #[clap(display_order = 3, long, help = "Say yes to every prompt")]
     pub yes: bool,
+    #[clap(
+        display_order = 3,
+        short = 'n',
+        long,
+        conflicts_with = "yes",
+        help = "Say no to every prompt, so that nothing is changed"
+    )]
+    pub assume_no: bool,
     #[clap(
         display_order = 4,
         short,
@@ -220,8 +826,161 @@ pub struct Opts {
         help = "Print additional debug information"
     )]
     pub verbose: bool,
+    #[clap(
+        display_order = 4,
+        short,
+        long,
+        conflicts_with = "verbose",
+        help = "Don't show output of dpkg and progress indicators"
+    )]
+    pub quiet: bool,
     #[clap(display_order = 5, long, help = "Don't pipe long output into a pager")]
     pub no_pager: bool,
+    #[clap(
+        display_order = 5,
+        long,
+        conflicts_with = "no-pager",
+        help = "Always pipe long output into a pager"
+    )]
+    pub pager: bool,
+    #[clap(
+        display_order = 5,
+        long,
+        help = "Use a pager only if output exceeds this number of lines (default: terminal height)"
+    )]
+    pub max_pager_lines: Option<usize>,
+    #[clap(
+        display_order = 6,
+        long,
+        help = "Allow downgrading installed packages that are newer than repository versions"
+    )]
+    pub allow_downgrades: bool,
+    #[clap(
+        display_order = 6,
+        long,
+        help = "Allow removing essential packages for this run, overriding the config"
+    )]
+    pub allow_remove_essential: bool,
+    #[clap(
+        display_order = 7,
+        long,
+        help = "Refuse to proceed if resolved packages differ from the lockfile"
+    )]
+    pub locked: bool,
+    #[clap(
+        display_order = 7,
+        long,
+        help = "Resolve, fetch and validate packages, but stop before running dpkg"
+    )]
+    pub no_act: bool,
+    #[clap(
+        display_order = 8,
+        long,
+        help = "Show how long each phase of the operation takes"
+    )]
+    pub timings: bool,
+    #[clap(
+        display_order = 9,
+        long,
+        value_name = "NAME",
+        help = "Use this blueprint profile instead of the active one"
+    )]
+    pub profile: Option<String>,
+    #[clap(
+        display_order = 10,
+        long,
+        help = "Install packages from local cache only, without accessing the network"
+    )]
+    pub reinstall_from_cache: bool,
+    #[clap(
+        display_order = 11,
+        long,
+        help = "Skip packages that fail to download, along with packages depending on them"
+    )]
+    pub ignore_missing: bool,
+    #[clap(
+        display_order = 12,
+        long,
+        help = "Operate on the system root even if it doesn't look self-contained"
+    )]
+    pub force_alt_root: bool,
+    #[clap(
+        display_order = 13,
+        long,
+        help = "Check that all packages ended up in the expected state after running dpkg"
+    )]
+    pub verify_after: bool,
+    #[clap(
+        display_order = 14,
+        long,
+        help = "Print the summary after applying changes as JSON"
+    )]
+    pub json: bool,
+    #[clap(
+        display_order = 15,
+        long,
+        value_name = "SECS",
+        default_value = "60",
+        help = "Give up resolving dependencies after this many seconds, 0 for no limit"
+    )]
+    pub solver_timeout: u64,
+    #[clap(
+        display_order = 16,
+        long,
+        help = "Resolve and fetch packages, then write a checksum manifest instead of running dpkg"
+    )]
+    pub download_only: bool,
+    #[clap(
+        display_order = 16,
+        long,
+        value_name = "DIR",
+        requires = "download-only",
+        help = "Save packages fetched by --download-only here instead of the package cache"
+    )]
+    pub download_dir: Option<PathBuf>,
+    #[clap(
+        display_order = 16,
+        long,
+        arg_enum,
+        value_name = "FORMAT",
+        default_value = "sha256",
+        help = "Format of the checksum manifest written by --download-only"
+    )]
+    pub manifest_format: ManifestFormat,
+    #[clap(
+        display_order = 16,
+        long,
+        value_name = "FILE",
+        conflicts_with = "download-only",
+        help = "Write the download and dpkg commands of the transaction to a shell script instead of running them"
+    )]
+    pub script: Option<PathBuf>,
+    #[clap(
+        display_order = 17,
+        long,
+        arg_enum,
+        value_name = "KEY",
+        default_value = "name",
+        help = "Sort packages in each category of the actions to perform"
+    )]
+    pub sort: ActionSort,
+    #[clap(
+        display_order = 18,
+        long,
+        value_name = "PATH",
+        help = "Write a timestamped log of messages and dpkg output to this file"
+    )]
+    pub log_file: Option<PathBuf>,
+    #[clap(
+        display_order = 19,
+        long,
+        value_name = "NAME",
+        help = "Only accept repository metadata recorded in this snapshot, instead of the one in use"
+    )]
+    pub snapshot: Option<String>,
+    /// Write the solver formula (DIMACS CNF) to PATH, the model to PATH.model and package IDs to PATH.map
+    #[clap(long, hide = true, value_name = "PATH")]
+    pub dump_solver: Option<PathBuf>,
     #[clap(subcommand)]
     pub subcmd: SubCmd,
 }
@@ -237,24 +996,63 @@ pub enum SubCmd {
     /// Pick a specific version of a package
     #[clap(display_order = 3)]
     Pick(PickPkg),
+    /// List or switch blueprint profiles
+    #[clap(display_order = 6)]
+    Profile(ProfileOpts),
     /// Refresh local package databases
     #[clap(display_order = 5, aliases = &["update"])]
-    Refresh,
+    Refresh(RefreshOpts),
     /// Install and upgrade all packages according to Blueprint
     #[clap(display_order = 4, aliases = &["upgrade", "full-upgrade", "dist-upgrade"])]
-    Execute,
+    Execute(UpgradeOpts),
     /// Search packages from package database
     #[clap(display_order = 11)]
     Search(SearchPkg),
     /// Search what packages provide a certain file
     #[clap(display_order = 12)]
     Provide(ProvideFile),
+    /// List files in an installed or available package
+    #[clap(display_order = 13)]
+    Files(FilesPkg),
+    /// Print the number of packages that can be upgraded
+    #[clap(display_order = 16)]
+    CheckUpdates(CheckUpdatesOpts),
+    /// Show dependencies of a package
+    #[clap(display_order = 15)]
+    Depends(DependsPkg),
+    /// Show installed, candidate and available versions of packages
+    #[clap(display_order = 17)]
+    Policy(PolicyPkg),
+    /// Show differences between blueprints and installed packages
+    #[clap(display_order = 14, aliases = &["diff"])]
+    Status(StatusOpts),
+    /// Compare package sets recorded in two lockfiles
+    #[clap(display_order = 18)]
+    DiffState(DiffStateOpts),
+    /// Show the changelog of the newest available version of a package
+    #[clap(display_order = 19)]
+    Changelog(ChangelogPkg),
+    /// Manage trusted public keys of repositories
+    #[clap(display_order = 22)]
+    Key(KeyOpts),
+    /// Diagnose configured repositories
+    #[clap(display_order = 23)]
+    Repo(RepoOpts),
+    /// Check configuration and environment for common problems
+    #[clap(display_order = 24)]
+    Doctor,
+    /// Show or change settings in config.toml
+    #[clap(display_order = 25)]
+    Config(ConfigOpts),
+    /// Record repository metadata, and pin later refreshes to it
+    #[clap(display_order = 26)]
+    Snapshot(SnapshotOpts),
     /// Delete local package cache (optionally metadata cache)
     #[clap(display_order = 21)]
     Clean(CleanConfig),
     /// Benchmark and pick optimal mirrors
     #[clap(display_order = 31)]
-    Bench,
+    Bench(BenchConfig),
     /// Download a package from remote repository
     #[clap(display_order = 32)]
     Download(DownloadPkg),
@@ -262,13 +1060,37 @@ pub enum SubCmd {
 
 #[derive(Parser)]
 pub struct InstallPkg {
-    /// Package names or deb file names to install
-    #[clap(min_values = 1)]
+    /// Package names or deb file names to install, "-" to read names from stdin
+    #[clap(
+        min_values = 1,
+        required_unless_present_any = &["build-deps", "from-file", "install-priority"]
+    )]
     pub names: Vec<String>,
+    /// Read package names from a file ("-" for stdin), one per line
+    #[clap(long, value_name = "FILE")]
+    pub from_file: Option<PathBuf>,
+    /// Install build dependencies listed in a control or dsc file
+    #[clap(long, value_name = "CONTROL", conflicts_with_all = &["local", "init"])]
+    pub build_deps: Option<PathBuf>,
+    /// Install all packages of this priority or higher from the repositories, for building base systems
+    #[clap(long, arg_enum, value_name = "LEVEL", conflicts_with_all = &["local", "init"])]
+    pub install_priority: Option<Priority>,
 
     /// Don't install recommended packages
     #[clap(long)]
     pub no_recommends: bool,
+    /// Treat recommended packages as hard dependencies, fail if any of them cannot be installed
+    #[clap(long, conflicts_with = "no-recommends")]
+    pub recommends_as_depends: bool,
+    /// Prefer packages from this distribution (suite) for requested packages and their dependencies
+    #[clap(short, long, value_name = "SUITE")]
+    pub target_release: Option<String>,
+    /// Only install requested packages from this repository, can be given multiple times
+    #[clap(long, value_name = "NAME", multiple_occurrences = true, conflicts_with_all = &["local", "init"])]
+    pub repo: Vec<String>,
+    /// Take dependencies of requested packages from the repositories of --repo as well
+    #[clap(long, requires = "repo")]
+    pub strict_repo: bool,
     /// Install local debs files rather from the repositories
     #[clap(long)]
     pub local: bool,
@@ -279,12 +1101,18 @@ pub struct InstallPkg {
 
 #[derive(Parser)]
 pub struct RemovePkg {
-    /// Package names to remove
-    #[clap(min_values = 1)]
+    /// Package names (or file paths with --by-file) to remove, "-" to read names from stdin
+    #[clap(min_values = 1, required_unless_present = "from-file")]
     pub names: Vec<String>,
+    /// Read package names from a file ("-" for stdin), one per line
+    #[clap(long, value_name = "FILE")]
+    pub from_file: Option<PathBuf>,
     /// Also remove recommended packages
     #[clap(long)]
     pub remove_recommends: bool,
+    /// Remove the installed packages that own the given files
+    #[clap(long)]
+    pub by_file: bool,
 }
 
 #[derive(Parser)]
@@ -293,10 +1121,139 @@ pub struct PickPkg {
     pub name: String,
 }
 
+#[derive(Parser)]
+pub struct KeyOpts {
+    #[clap(subcommand)]
+    pub cmd: KeyCmd,
+}
+
+#[derive(Parser)]
+pub enum KeyCmd {
+    /// Import and trust a public key from a URL or file
+    Add(AddKey),
+    /// List trusted public keys
+    List,
+    /// Remove a trusted public key
+    Remove(RemoveKey),
+}
+
+#[derive(Parser)]
+pub struct AddKey {
+    /// URL or path of the public key file
+    pub source: String,
+}
+
+#[derive(Parser)]
+pub struct RemoveKey {
+    /// Fingerprint of the public key
+    pub fingerprint: String,
+}
+
+#[derive(Parser)]
+pub struct ConfigOpts {
+    #[clap(subcommand)]
+    pub cmd: ConfigCmd,
+}
+
+#[derive(Parser)]
+pub enum ConfigCmd {
+    /// Show a setting, and what's detected on this system
+    Get(ConfigGet),
+    /// Change a setting
+    Set(ConfigSet),
+}
+
+/// Settings that can be changed with `oma config`
+#[derive(clap::ArgEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConfigKey {
+    /// Architecture of packages
+    Arch,
+}
+
+#[derive(Parser)]
+pub struct ConfigGet {
+    #[clap(arg_enum)]
+    pub key: ConfigKey,
+}
+
+#[derive(Parser)]
+pub struct ConfigSet {
+    #[clap(arg_enum)]
+    pub key: ConfigKey,
+    /// New value, detected from this system if not given
+    pub value: Option<String>,
+}
+
+#[derive(Parser)]
+pub struct SnapshotOpts {
+    #[clap(subcommand)]
+    pub cmd: SnapshotCmd,
+}
+
+#[derive(Parser)]
+pub enum SnapshotCmd {
+    /// Record metadata of all repositories from the last refresh
+    Create(SnapshotName),
+    /// List all snapshots
+    List,
+    /// Only accept metadata recorded in a snapshot from now on
+    Use(SnapshotName),
+    /// Stop using a snapshot
+    Clear,
+}
+
+#[derive(Parser)]
+pub struct SnapshotName {
+    /// Name of snapshot
+    pub name: String,
+}
+
+#[derive(Parser)]
+pub struct RepoOpts {
+    #[clap(subcommand)]
+    pub cmd: RepoCmd,
+}
+
+#[derive(Parser)]
+pub enum RepoCmd {
+    /// Check that repositories are reachable and provide configured components
+    Check(RepoCheck),
+}
+
+#[derive(Parser)]
+pub struct RepoCheck {
+    /// Only check these repositories
+    #[clap(value_name = "REPO")]
+    pub names: Vec<String>,
+}
+
+#[derive(Parser)]
+pub struct ProfileOpts {
+    #[clap(subcommand)]
+    pub cmd: ProfileCmd,
+}
+
+#[derive(Parser)]
+pub enum ProfileCmd {
+    /// List all profiles
+    List,
+    /// Switch the active profile, an empty profile is created if it doesn't exist
+    Switch(SwitchProfile),
+}
+
+#[derive(Parser)]
+pub struct SwitchProfile {
+    /// Name of profile
+    pub name: String,
+}
+
 #[derive(Parser)]
 pub struct SearchPkg {
     /// Search keyword for package name
     pub keyword: String,
+    /// Only search packages from this repository, can be given multiple times
+    #[clap(long, value_name = "NAME", multiple_occurrences = true)]
+    pub repo: Vec<String>,
 }
 
 #[derive(Parser)]
@@ -308,11 +1265,118 @@ pub struct ProvideFile {
     pub bin: bool,
 }
 
+#[derive(Parser)]
+pub struct StatusOpts {
+    /// Print the differences in JSON
+    #[clap(long)]
+    pub json: bool,
+}
+
+#[derive(Parser)]
+pub struct DiffStateOpts {
+    /// Lockfile of the earlier state
+    pub old: PathBuf,
+    /// Lockfile of the later state
+    pub new: PathBuf,
+    /// Print the differences in JSON
+    #[clap(long)]
+    pub json: bool,
+}
+
+#[derive(Parser)]
+pub struct CheckUpdatesOpts {
+    /// Print the count and the list of upgradable packages in JSON
+    #[clap(long)]
+    pub json: bool,
+    /// Exit with this code (1 if not specified) if any package can be upgraded
+    #[clap(
+        long,
+        value_name = "CODE",
+        min_values = 0,
+        require_equals = true,
+        default_missing_value = "1"
+    )]
+    pub exit_code: Option<i32>,
+}
+
+#[derive(Parser)]
+pub struct DependsPkg {
+    /// Name of package
+    pub name: String,
+    /// Show dependencies recursively as a tree
+    #[clap(long)]
+    pub tree: bool,
+    /// Include recommended packages
+    #[clap(long)]
+    pub recommends: bool,
+    /// Show packages that depend on this package instead
+    #[clap(long)]
+    pub reverse: bool,
+    /// Write all dependencies as a graph, to be rendered with other tools
+    #[clap(long, conflicts_with = "tree")]
+    pub graph: bool,
+    /// Format of the graph
+    #[clap(long, arg_enum, default_value = "dot", requires = "graph")]
+    pub format: GraphFormat,
+}
+
+#[derive(Parser)]
+pub struct PolicyPkg {
+    /// Names of packages
+    #[clap(min_values = 1, required = true)]
+    pub names: Vec<String>,
+}
+
+#[derive(Parser)]
+pub struct ChangelogPkg {
+    /// Name of package
+    pub name: String,
+}
+
+#[derive(Parser)]
+pub struct FilesPkg {
+    /// Name of package
+    pub name: String,
+}
+
+#[derive(Parser)]
+pub struct RefreshOpts {
+    /// Only refresh these repositories, other repositories are left untouched
+    #[clap(value_name = "REPO")]
+    pub names: Vec<String>,
+    /// Only check which repositories have updated metadata
+    #[clap(long)]
+    pub check: bool,
+}
+
+#[derive(Parser)]
+pub struct UpgradeOpts {
+    /// Only apply upgrades from security repositories
+    #[clap(long)]
+    pub security_only: bool,
+    /// Only configure packages that are unpacked but not configured, without refreshing or upgrading
+    #[clap(long, conflicts_with = "security-only")]
+    pub only_configure: bool,
+    /// Offer to show the changelog of each upgraded package before confirming
+    #[clap(long, conflicts_with = "only-configure")]
+    pub show_changelog: bool,
+}
+
 #[derive(Parser)]
 pub struct CleanConfig {
     /// Remove both package cache and local database
     #[clap(short, long)]
     pub all: bool,
+    /// Also remove local metadata of repositories that are no longer configured
+    #[clap(long, conflicts_with = "all")]
+    pub metadata: bool,
+}
+
+#[derive(Parser)]
+pub struct BenchConfig {
+    /// Benchmark each mirror over both IPv4 and IPv6, and pick the best combination
+    #[clap(long)]
+    pub ip_families: bool,
 }
 
 #[derive(Parser)]
@@ -323,3 +1387,303 @@ pub struct DownloadPkg {
     #[clap(long)]
     pub latest: bool,
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::utils::temp_dir::TempDir;
+
+    #[test]
+    fn test_mirror_urls() {
+        let dir = TempDir::new("mirrors");
+        let path = dir.join("mirrors.toml");
+        fs::write(
+            &path,
+            r#"
+            default = "origin"
+            [origin]
+            description = "Origin"
+            url = "https://repo.example.com"
+            [b-mirror]
+            description = "Mirror B"
+            url = "https://b.example.com/"
+            [a-mirror]
+            description = "Mirror A"
+            url = "https://a.example.com"
+            "#,
+        )
+        .unwrap();
+        let config: RepoConfig = toml::from_str(&format!(
+            r#"
+            source = {{ mirrorlist = "{}", preferred = "b-mirror" }}
+            distribution = "stable"
+            components = ["main"]
+            keys = ["main.asc"]
+            "#,
+            path.display()
+        ))
+        .unwrap();
+        assert_eq!(
+            config.get_urls().unwrap(),
+            vec![
+                "https://b.example.com/debs",
+                "https://repo.example.com/debs",
+                "https://a.example.com/debs"
+            ]
+        );
+        assert_eq!(config.ip_family(), None);
+
+        let config: RepoConfig = toml::from_str(&format!(
+            r#"
+            source = {{ mirrorlist = "{}", preferred = "a-mirror", ip_family = "ipv6" }}
+            distribution = "stable"
+            components = ["main"]
+            keys = ["main.asc"]
+            "#,
+            path.display()
+        ))
+        .unwrap();
+        assert_eq!(config.ip_family(), Some(IpFamily::Ipv6));
+        assert_eq!(config.get_urls().unwrap()[0], "https://a.example.com/debs");
+
+        let config: RepoConfig = toml::from_str(
+            r#"
+            source = "https://repo.example.com"
+            distribution = "stable"
+            components = ["main"]
+            keys = ["main.asc"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.get_urls().unwrap(),
+            vec!["https://repo.example.com/debs"]
+        );
+    }
+
+    #[test]
+    fn test_credential_redaction() {
+        let config: RepoConfig = toml::from_str(
+            r#"
+            source = "https://repo.example.com"
+            distribution = "stable"
+            components = ["main"]
+            keys = ["main.asc"]
+            username = "user"
+            password = "hunter2"
+            "#,
+        )
+        .unwrap();
+        assert!(!format!("{:?}", config).contains("hunter2"));
+        let credential = config.get_credential().unwrap().unwrap();
+        assert!(!format!("{:?}", credential).contains("hunter2"));
+
+        let config: RepoConfig = toml::from_str(
+            r#"
+            source = "https://repo.example.com"
+            distribution = "stable"
+            components = ["main"]
+            keys = ["main.asc"]
+            auth_token = { env = "OMAKASE_TEST_NONEXISTENT_TOKEN" }
+            "#,
+        )
+        .unwrap();
+        let e = config.get_credential().unwrap_err();
+        assert!(!format!("{:?}", e).contains("hunter2"));
+        assert!(format!("{:?}", config).contains("OMAKASE_TEST_NONEXISTENT_TOKEN"));
+    }
+
+    #[test]
+    fn test_repo_keys() {
+        let config: RepoConfig = toml::from_str(
+            r#"
+            source = "https://repo.example.com"
+            distribution = "stable"
+            components = ["main"]
+            keys = ["new.asc", { file = "old.asc", deprecated = true }, { file = "other.asc" }]
+            "#,
+        )
+        .unwrap();
+        let keys: Vec<(&str, bool)> = config
+            .keys
+            .iter()
+            .map(|key| (key.file(), key.deprecated()))
+            .collect();
+        assert_eq!(
+            keys,
+            vec![("new.asc", false), ("old.asc", true), ("other.asc", false)]
+        );
+    }
+
+    #[test]
+    fn test_dpkg_force_args() {
+        assert_eq!(
+            DpkgForce::default().args(false),
+            vec![
+                "--force-downgrade",
+                "--force-depends",
+                "--force-breaks",
+                "--force-remove-essential"
+            ]
+        );
+
+        let force: DpkgForce = toml::from_str(
+            r#"
+            force_depends = false
+            force_overwrite = true
+            force_confold = true
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            force.args(true),
+            vec![
+                "--force-unsafe-io",
+                "--force-downgrade",
+                "--force-breaks",
+                "--force-remove-essential",
+                "--force-overwrite",
+                "--force-confold"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_read_repo_order() {
+        let data = r#"
+arch = "amd64"
+
+[repo.zeta]
+source = "https://zeta.example.org"
+keys = []
+flat = true
+
+[repo.alpha]
+source = "https://alpha.example.org"
+keys = []
+flat = true
+priority = 10
+"#;
+        let mut config: Config = toml::from_str(data).unwrap();
+        config.read_repo_order(data).unwrap();
+        assert_eq!(config.repo["zeta"].order, 0);
+        assert_eq!(config.repo["alpha"].order, 1);
+        assert_eq!(config.repo["zeta"].priority, 0);
+        assert_eq!(config.repo["alpha"].priority, 10);
+    }
+
+    #[test]
+    fn test_min_free_space() {
+        const GIB: u64 = 1024 * 1024 * 1024;
+        let config: Config =
+            toml::from_str("arch = \"amd64\"\nmin_free_space = 1073741824\n[repo]").unwrap();
+        assert_eq!(
+            config.min_free_space.unwrap().bytes(100 * GIB).unwrap(),
+            GIB
+        );
+        let config: Config =
+            toml::from_str("arch = \"amd64\"\nmin_free_space = \"10%\"\n[repo]").unwrap();
+        assert!(config.check_sanity().is_ok());
+        assert_eq!(
+            config.min_free_space.unwrap().bytes(100 * GIB).unwrap(),
+            10 * GIB
+        );
+        for invalid in ["10", "150%", "ten%"] {
+            let reserve = FreeSpaceReserve::Percentage(invalid.to_string());
+            assert!(reserve.bytes(GIB).is_err());
+        }
+    }
+
+    #[test]
+    fn test_confirm_threshold() {
+        const MIB: u64 = 1024 * 1024;
+        // No limits by default
+        assert!(!ConfirmThreshold::default().exceeded(usize::MAX, u64::MAX));
+
+        let threshold: ConfirmThreshold =
+            toml::from_str("packages = 100\ndownload_size_mib = 512").unwrap();
+        assert!(!threshold.exceeded(100, 0));
+        assert!(threshold.exceeded(101, 0));
+        assert!(!threshold.exceeded(0, 512 * MIB));
+        assert!(threshold.exceeded(0, 512 * MIB + 1));
+
+        let threshold: ConfirmThreshold = toml::from_str("packages = 0").unwrap();
+        assert!(!threshold.exceeded(0, u64::MAX));
+        assert!(threshold.exceeded(1, 0));
+    }
+
+    #[test]
+    fn test_repo_layout() {
+        let repo = |extra: &str| -> RepoConfig {
+            toml::from_str(&format!(
+                "source = \"https://repo.example.com/\"\nkeys = []\n{extra}"
+            ))
+            .unwrap()
+        };
+        let config = repo("distribution = \"stable\"\ncomponents = [\"main\"]");
+        assert!(config.check_sanity().is_ok());
+        // Nothing would be fetched
+        let e = repo("distribution = \"stable\"\ncomponents = []")
+            .check_sanity()
+            .unwrap_err();
+        assert!(e.to_string().contains("No components"));
+        assert!(repo("components = [\"main\"]").check_sanity().is_err());
+
+        let config = repo("flat = true");
+        assert!(config.check_sanity().is_ok());
+        // Used as is, without `debs`
+        assert_eq!(config.get_url().unwrap(), "https://repo.example.com");
+        assert!(repo("flat = true\ncomponents = [\"main\"]")
+            .check_sanity()
+            .is_err());
+        assert!(repo("flat = true\nread_timeout = 0")
+            .check_sanity()
+            .is_err());
+
+        // Standard layout
+        let config = repo("distribution = \"stable\"\ncomponents = [\"main\"]");
+        assert_eq!(config.release_path(), "dists/stable/InRelease");
+        assert_eq!(
+            config.release_dir_url("https://repo.example.com/debs"),
+            "https://repo.example.com/debs/dists/stable"
+        );
+        assert_eq!(repo("flat = true").release_dir_url("http://a"), "http://a");
+        let config = repo("distribution = \"stable\"\ncomponents = [\"main\"]\nrelease_path = \"releases/{distribution}/x86/Signed\"");
+        assert!(config.check_sanity().is_ok());
+        assert_eq!(config.release_path(), "releases/stable/x86/Signed");
+        assert_eq!(
+            config.release_dir_url("http://a"),
+            "http://a/releases/stable/x86"
+        );
+        for invalid in [
+            "../InRelease",
+            "dists/{distribution}/../../InRelease",
+            "/InRelease",
+            "dists//InRelease",
+            "dists/{suite}/InRelease",
+            "dists/",
+            "",
+        ] {
+            let config = repo(&format!(
+                "distribution = \"stable\"\ncomponents = [\"main\"]\nrelease_path = \"{invalid}\""
+            ));
+            assert!(config.check_sanity().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_trusted_repo() {
+        let config = |unsafe_section: &str| -> Config {
+            toml::from_str(&format!(
+                "arch = \"amd64\"\n{unsafe_section}\n[repo.local]\nsource = \"http://localhost/repo\"\nflat = true\nkeys = []\ntrusted = true"
+            ))
+            .unwrap()
+        };
+        // Must be allowed explicitly
+        assert!(config("").check_sanity().is_err());
+        assert!(config("[unsafe]\nunsafe_io = true").check_sanity().is_err());
+        assert!(config("[unsafe]\nallow_unauthenticated = true")
+            .check_sanity()
+            .is_ok());
+    }
+}
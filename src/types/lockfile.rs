@@ -0,0 +1,176 @@
+use super::{PkgMeta, PkgSource};
+
+use anyhow::{bail, Context, Result};
+use console::style;
+use serde::{Deserialize, Serialize};
+use std::{fs, path::Path};
+
+/// A record of the resolved package set, used to make sure deployments are reproducible
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Default)]
+pub struct Lockfile {
+    #[serde(default)]
+    package: Vec<LockedPkg>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Debug, Clone)]
+struct LockedPkg {
+    name: String,
+    version: String,
+    // Local packages don't have a checksum
+    checksum: Option<String>,
+}
+
+/// Packages added, removed and changed between two lockfiles, each sorted by name
+#[derive(PartialEq, Eq, Debug, Default)]
+pub struct LockDiff {
+    // Vec<(Name, Version)>
+    pub added: Vec<(String, String)>,
+    pub removed: Vec<(String, String)>,
+    // Vec<(Name, Old, New)>
+    pub changed: Vec<(String, String, String)>,
+}
+
+impl LockDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+impl Lockfile {
+    pub fn from_pkgs(pkgs: &[&PkgMeta]) -> Self {
+        let mut package: Vec<LockedPkg> = pkgs
+            .iter()
+            .map(|pkg| LockedPkg {
+                name: pkg.name.clone(),
+                version: pkg.version.to_string(),
+                checksum: match &pkg.source {
+                    PkgSource::Http((_, _, checksum)) => Some(checksum.to_string()),
+                    PkgSource::Local(_) | PkgSource::Installed => None,
+                },
+            })
+            .collect();
+        // Keep it sorted so that it is diff-friendly
+        package.sort();
+        Lockfile { package }
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .context(format!("Failed to read lockfile {}.", path.display()))?;
+        let res = toml::from_str(&content)
+            .context(format!("Failed to parse lockfile {}.", path.display()))?;
+        Ok(res)
+    }
+
+    /// Write lockfile to disk. Returns false if the file is already up-to-date
+    pub fn write(&self, path: &Path) -> Result<bool> {
+        if path.is_file() {
+            if let Ok(old) = Self::from_file(path) {
+                if &old == self {
+                    return Ok(false);
+                }
+            }
+        }
+        let content = toml::to_string(self)?;
+        fs::write(path, content)
+            .context(format!("Failed to write lockfile {}.", path.display()))?;
+        Ok(true)
+    }
+
+    /// Human-readable differences between the locked package set and another one
+    pub fn diff(&self, other: &Lockfile) -> Vec<String> {
+        let mut res = Vec::new();
+        for pkg in &self.package {
+            match other.package.iter().find(|p| p.name == pkg.name) {
+                Some(p) => {
+                    if p.version != pkg.version {
+                        res.push(format!("{}: {} -> {}", pkg.name, pkg.version, p.version));
+                    } else if p.checksum != pkg.checksum {
+                        res.push(format!("{}: checksum changed", pkg.name));
+                    }
+                }
+                None => res.push(format!("{}: removed", pkg.name)),
+            }
+        }
+        for pkg in &other.package {
+            if !self.package.iter().any(|p| p.name == pkg.name) {
+                res.push(format!("{}: added ({})", pkg.name, pkg.version));
+            }
+        }
+        res
+    }
+
+    /// Version changes from the locked package set to another one, ignoring checksums
+    pub fn compare(&self, other: &Lockfile) -> LockDiff {
+        let mut res = LockDiff::default();
+        for pkg in &self.package {
+            match other.package.iter().find(|p| p.name == pkg.name) {
+                Some(p) if p.version != pkg.version => {
+                    res.changed
+                        .push((pkg.name.clone(), pkg.version.clone(), p.version.clone()));
+                }
+                Some(_) => (),
+                None => res.removed.push((pkg.name.clone(), pkg.version.clone())),
+            }
+        }
+        for pkg in &other.package {
+            if !self.package.iter().any(|p| p.name == pkg.name) {
+                res.added.push((pkg.name.clone(), pkg.version.clone()));
+            }
+        }
+        // Hand-edited lockfiles may not be sorted
+        res.added.sort();
+        res.removed.sort();
+        res.changed.sort();
+        res
+    }
+
+    /// Make sure the other package set is identical to the locked one
+    pub fn check(&self, other: &Lockfile) -> Result<()> {
+        let diff = self.diff(other);
+        if !diff.is_empty() {
+            bail!(
+                "Resolved packages differ from lockfile: {}.",
+                style(diff.join(", ")).bold()
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::types::test_pkg;
+
+    #[test]
+    fn test_lockfile_check() {
+        let a = test_pkg("a", "1.0");
+        let b = test_pkg("b", "2.0");
+        let locked = Lockfile::from_pkgs(&[&b, &a]);
+        // Order of packages does not matter
+        assert!(locked.check(&Lockfile::from_pkgs(&[&a, &b])).is_ok());
+
+        // Changed candidate version
+        let new_b = test_pkg("b", "2.1");
+        let new = Lockfile::from_pkgs(&[&a, &new_b]);
+        assert!(locked.check(&new).is_err());
+        assert_eq!(locked.diff(&new), vec!["b: 2.0 -> 2.1".to_string()]);
+
+        assert_eq!(
+            locked.compare(&new),
+            LockDiff {
+                changed: vec![("b".to_string(), "2.0".to_string(), "2.1".to_string())],
+                ..Default::default()
+            }
+        );
+        let diff = Lockfile::from_pkgs(&[&b]).compare(&Lockfile::from_pkgs(&[&a]));
+        assert_eq!(diff.added, vec![("a".to_string(), "1.0".to_string())]);
+        assert_eq!(diff.removed, vec![("b".to_string(), "2.0".to_string())]);
+        assert!(locked.compare(&locked).is_empty());
+
+        // Roundtrip
+        let content = toml::to_string(&locked).unwrap();
+        assert_eq!(toml::from_str::<Lockfile>(&content).unwrap(), locked);
+    }
+}
@@ -1,20 +1,18 @@
 mod actions;
 mod checksum;
 pub mod config;
+mod lockfile;
+mod priority;
 mod version;
 
 pub use actions::{PkgActionModifier, PkgActions, PkgInstallAction};
-pub use checksum::{Checksum, ChecksumValidator};
-pub use version::{parse_version, parse_version_requirement, PkgVersion, VersionRequirement};
+pub use checksum::{Checksum, ChecksumType, ChecksumValidator};
+pub use lockfile::{LockDiff, Lockfile};
+pub use priority::Priority;
+pub use version::{parse_version_requirement, PkgVersion, VersionRequirement};
 
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
-
-#[derive(Deserialize, Default)]
-pub struct PkgRequirement {
-    pub with_recommends: Option<bool>,
-    pub version: Option<VersionRequirement>,
-}
+use std::{path::PathBuf, sync::Arc};
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct PkgMeta {
@@ -23,6 +21,9 @@ pub struct PkgMeta {
     pub description: String,
     pub version: PkgVersion,
     pub depends: Vec<(String, VersionRequirement)>,
+    /// Must be configured before this package is unpacked
+    #[serde(default)]
+    pub pre_depends: Vec<(String, VersionRequirement)>,
     pub breaks: Vec<(String, VersionRequirement)>,
     pub conflicts: Vec<(String, VersionRequirement)>,
     pub recommends: Option<Vec<(String, VersionRequirement)>>,
@@ -32,7 +33,78 @@ pub struct PkgMeta {
     pub install_size: u64,
 
     pub essential: bool,
+    #[serde(default)]
+    pub priority: Option<Priority>,
     pub source: PkgSource,
+    /// Whether this package comes from a security repository
+    pub security: bool,
+    /// Distributions (suites) of the repositories that provide this package, empty for local
+    /// packages
+    #[serde(default)]
+    pub distributions: Vec<String>,
+    /// Other places to download the same package from, on mirrors of its repository or in other
+    /// repositories, see `mirror_urls`
+    #[serde(skip)]
+    pub mirrors: Vec<PkgMirrors>,
+    /// Repositories this package is available from, set when added to the pool. Empty for local packages
+    #[serde(default)]
+    pub repos: Vec<String>,
+}
+
+impl PkgMeta {
+    /// Package without any relationships or other metadata, to be filled in by the caller
+    pub fn new(name: &str, version: PkgVersion, source: PkgSource) -> Self {
+        PkgMeta {
+            name: name.to_string(),
+            description: String::new(),
+            section: String::new(),
+            version,
+            depends: Vec::new(),
+            pre_depends: Vec::new(),
+            breaks: Vec::new(),
+            conflicts: Vec::new(),
+            recommends: None,
+            suggests: None,
+            replaces: None,
+            provides: None,
+            install_size: 0,
+            essential: false,
+            priority: None,
+            source,
+            security: false,
+            distributions: Vec::new(),
+            mirrors: Vec::new(),
+            repos: Vec::new(),
+        }
+    }
+
+    /// URLs to download this package from if its own URL fails, in order of preference
+    pub fn mirror_urls(&self) -> Vec<String> {
+        let url = match &self.source {
+            PkgSource::Http((url, _, _)) => Some(url.as_str()),
+            _ => None,
+        };
+        let mut res: Vec<String> = Vec::new();
+        for mirrors in &self.mirrors {
+            for base in mirrors.bases.iter() {
+                let mirror = format!("{base}/{}", mirrors.path);
+                if Some(mirror.as_str()) != url && !res.contains(&mirror) {
+                    res.push(mirror);
+                }
+            }
+        }
+        res
+    }
+}
+
+/// The same path under several base URLs, like a package on all mirrors of a repository
+///
+/// Base URLs are shared by all packages of a repository, so full URLs are only built when needed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PkgMirrors {
+    pub bases: Arc<[String]>,
+    /// Path relative to each base URL
+    pub path: String,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -41,4 +113,17 @@ pub enum PkgSource {
     Http((String, u64, Checksum)),
     // Local(path)
     Local(PathBuf),
+    /// The installed version, only known from the dpkg status database. Never downloaded,
+    /// as it's only in the pool to keep the package at this version
+    Installed,
+}
+
+/// Create a minimal PkgMeta for testing
+#[cfg(test)]
+pub fn test_pkg(name: &str, version: &str) -> PkgMeta {
+    PkgMeta::new(
+        name,
+        PkgVersion::try_from(version).unwrap(),
+        PkgSource::Local(PathBuf::new()),
+    )
 }
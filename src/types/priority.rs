@@ -0,0 +1,66 @@
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+
+/// `Priority` field of a package, from the most important
+#[derive(
+    clap::ArgEnum, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug, Serialize, Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    Required,
+    Important,
+    Standard,
+    Optional,
+    /// Deprecated by Debian in favor of optional, but still seen in the wild
+    Extra,
+}
+
+impl Priority {
+    /// Parse the value of the `Priority` field, None for unknown ones (e.g. `source`)
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim() {
+            "required" => Some(Priority::Required),
+            "important" => Some(Priority::Important),
+            "standard" => Some(Priority::Standard),
+            "optional" => Some(Priority::Optional),
+            "extra" => Some(Priority::Extra),
+            _ => None,
+        }
+    }
+
+    /// Whether this is as important as `level`, or more
+    pub fn at_least(&self, level: Priority) -> bool {
+        *self <= level
+    }
+}
+
+impl Display for Priority {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Priority::Required => "required",
+            Priority::Important => "important",
+            Priority::Standard => "standard",
+            Priority::Optional => "optional",
+            Priority::Extra => "extra",
+        };
+        write!(f, "{s}")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_priority() {
+        for s in ["required", "important", "standard", "optional", "extra"] {
+            assert_eq!(Priority::parse(s).unwrap().to_string(), s);
+        }
+        assert_eq!(Priority::parse("source"), None);
+        assert_eq!(Priority::parse("Required"), None);
+        assert!(Priority::Required.at_least(Priority::Standard));
+        assert!(Priority::Standard.at_least(Priority::Standard));
+        assert!(!Priority::Optional.at_least(Priority::Standard));
+        assert!(!Priority::Extra.at_least(Priority::Optional));
+    }
+}
@@ -0,0 +1,127 @@
+use anyhow::{bail, Context, Result};
+use console::style;
+use std::{
+    fs,
+    os::unix::fs::MetadataExt,
+    path::{Component, Path, PathBuf},
+};
+
+/// Resolve `path` within `root`. Absolute paths are taken as relative to `root` as well.
+pub fn join_root(root: &Path, path: &Path) -> PathBuf {
+    let relative: PathBuf = path
+        .components()
+        .filter(|c| !matches!(c, Component::RootDir | Component::Prefix(_)))
+        .collect();
+    root.join(relative)
+}
+
+/// Check that `root` is a self-contained directory, which can be used as an alternative system root
+///
+/// `paths` (relative to `root`) are used by Omakase and dpkg, these must not lead outside of `root`.
+/// Returns a list of problems found, e.g. `root` being the host root through a symlink or bind mount.
+pub fn check_alt_root(root: &Path, paths: &[&Path]) -> Result<Vec<String>> {
+    if !root.is_dir() {
+        bail!(
+            "System root {} does not exist or is not a directory.",
+            style(root.display()).bold()
+        );
+    }
+    let real_root = root
+        .canonicalize()
+        .context(format!("Failed to resolve system root {}.", root.display()))?;
+    let mut problems = Vec::new();
+    if real_root == Path::new("/") || same_file(&real_root, Path::new("/")) {
+        problems.push(format!(
+            "{} is the root directory of this system.",
+            root.display()
+        ));
+        return Ok(problems);
+    }
+
+    for path in paths {
+        let full_path = join_root(root, path);
+        // The path may not exist yet, check the closest existing parent instead
+        let existing = match full_path.ancestors().find(|p| p.exists()) {
+            Some(p) => p,
+            None => continue,
+        };
+        let real_path = existing.canonicalize()?;
+        if !real_path.starts_with(&real_root) {
+            problems.push(format!(
+                "{} leads to {}, which is outside of the system root.",
+                existing.display(),
+                real_path.display()
+            ));
+            continue;
+        }
+        // Bind mounts can't be seen from paths, compare them with the same location on this system
+        let host_path = join_root(Path::new("/"), existing.strip_prefix(root).unwrap_or(path));
+        if existing != root && same_file(existing, &host_path) {
+            problems.push(format!(
+                "{} is the same directory as {} of this system, is it a bind mount?",
+                existing.display(),
+                host_path.display()
+            ));
+        }
+    }
+
+    Ok(problems)
+}
+
+/// Whether `a` and `b` are the same file or directory
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (fs::metadata(a), fs::metadata(b)) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::utils::temp_dir::TempDir;
+
+    #[test]
+    fn test_join_root() {
+        let root = Path::new("/mnt/target");
+        assert_eq!(
+            join_root(root, Path::new("/var/lib/dpkg")),
+            Path::new("/mnt/target/var/lib/dpkg")
+        );
+        assert_eq!(
+            join_root(root, Path::new("var/lib/dpkg")),
+            Path::new("/mnt/target/var/lib/dpkg")
+        );
+        assert_eq!(
+            join_root(Path::new("/"), Path::new("/var/lib/dpkg")),
+            Path::new("/var/lib/dpkg")
+        );
+    }
+
+    #[test]
+    fn test_check_alt_root() {
+        let base = TempDir::new("alt-root");
+        let root = base.join("root");
+        let outside = base.join("outside");
+        fs::create_dir_all(root.join("var/lib")).unwrap();
+        fs::create_dir_all(&outside).unwrap();
+        let dpkg = Path::new("var/lib/dpkg");
+        let cache = Path::new("var/cache/omakase/pkgs");
+
+        // A self-contained directory, paths that don't exist yet are fine
+        assert!(check_alt_root(&root, &[dpkg, cache]).unwrap().is_empty());
+
+        // Symlinks leading outside of the root
+        std::os::unix::fs::symlink(&outside, root.join("var/lib/dpkg")).unwrap();
+        let problems = check_alt_root(&root, &[dpkg, cache]).unwrap();
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].contains("outside of the system root"));
+
+        // The root itself is the root of this system
+        let host = base.join("host");
+        std::os::unix::fs::symlink("/", &host).unwrap();
+        assert_eq!(check_alt_root(&host, &[dpkg]).unwrap().len(), 1);
+
+        assert!(check_alt_root(&base.join("nonexistent"), &[dpkg]).is_err());
+    }
+}
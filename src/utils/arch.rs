@@ -0,0 +1,90 @@
+use crate::{debug, utils::alt_root::join_root};
+
+use std::{fs, path::Path, process::Command};
+
+/// dpkg architecture name of a machine type reported by uname, None if unknown
+pub fn from_machine(machine: &str) -> Option<&'static str> {
+    let arch = match machine {
+        "x86_64" => "amd64",
+        "i386" | "i486" | "i586" | "i686" => "i386",
+        "aarch64" | "arm64" => "arm64",
+        "armv7l" | "armv8l" => "armhf",
+        "armv5tel" | "armv6l" => "armel",
+        "ppc" => "powerpc",
+        "ppc64" => "ppc64",
+        "ppc64le" => "ppc64el",
+        "riscv64" => "riscv64",
+        "loongarch64" => "loong64",
+        "s390x" => "s390x",
+        _ => return None,
+    };
+    Some(arch)
+}
+
+/// Detect the native architecture of the system in `root`
+///
+/// The architecture list of dpkg in `root` is read first, since dpkg there decides which packages
+/// can be installed. It starts with the native architecture. Without it, the dpkg on this host is
+/// asked, and if it can't be run, this falls back to the machine type of the running kernel.
+pub fn detect(root: &Path, admindir: Option<&Path>) -> Option<String> {
+    let admindir = join_root(
+        root,
+        admindir.unwrap_or_else(|| Path::new(crate::DPKG_ADMINDIR)),
+    );
+    if let Ok(list) = fs::read_to_string(admindir.join("arch")) {
+        if let Some(arch) = list.lines().map(str::trim).find(|l| !l.is_empty()) {
+            return Some(arch.to_string());
+        }
+    }
+
+    let mut cmd = Command::new("dpkg");
+    cmd.arg("--root").arg(root).arg("--admindir").arg(&admindir);
+    match cmd.arg("--print-architecture").output() {
+        Ok(output) if output.status.success() => {
+            let arch = String::from_utf8_lossy(&output.stdout).trim().to_string();
+            if !arch.is_empty() {
+                return Some(arch);
+            }
+        }
+        Ok(output) => debug!(
+            "Failed to get architecture from dpkg: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ),
+        Err(e) => debug!("Failed to get architecture from dpkg: {e}"),
+    }
+    let uname = nix::sys::utsname::uname();
+    from_machine(uname.machine()).map(|arch| arch.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::utils::temp_dir::TempDir;
+
+    #[test]
+    fn test_from_machine() {
+        assert_eq!(from_machine("x86_64"), Some("amd64"));
+        assert_eq!(from_machine("i686"), Some("i386"));
+        assert_eq!(from_machine("aarch64"), Some("arm64"));
+        assert_eq!(from_machine("armv7l"), Some("armhf"));
+        assert_eq!(from_machine("ppc64le"), Some("ppc64el"));
+        assert_eq!(from_machine("loongarch64"), Some("loong64"));
+        assert_eq!(from_machine("pdp11"), None);
+    }
+
+    #[test]
+    fn test_detect() {
+        let root = TempDir::new("arch");
+        fs::create_dir_all(root.join("var/lib/dpkg")).unwrap();
+        // The native architecture comes first, then foreign ones
+        fs::write(root.join("var/lib/dpkg/arch"), "riscv64\ni386\n").unwrap();
+        assert_eq!(detect(&root, None).as_deref(), Some("riscv64"));
+
+        fs::create_dir_all(root.join("admin")).unwrap();
+        fs::write(root.join("admin/arch"), "\nloong64\n").unwrap();
+        assert_eq!(
+            detect(&root, Some(Path::new("/admin"))).as_deref(),
+            Some("loong64")
+        );
+    }
+}
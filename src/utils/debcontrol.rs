@@ -3,9 +3,13 @@ use crate::types::VersionRequirement;
 use anyhow::{format_err, Result};
 use nom::{
     branch::alt,
-    bytes::complete::{tag, take_while, take_while1},
-    character::{complete::alphanumeric1, complete::char, is_alphanumeric},
+    bytes::complete::{is_not, tag, take_while, take_while1},
+    character::{
+        complete::{alphanumeric1, char, space0},
+        is_alphanumeric,
+    },
     combinator::{eof, opt, recognize},
+    multi::many0,
     sequence::{delimited, pair, preceded, separated_pair},
     IResult,
 };
@@ -38,39 +42,120 @@ fn parse_version_expr(s: &[u8]) -> IResult<&[u8], &[u8]> {
 
 fn parse_relation_suffix(s: &[u8]) -> IResult<&[u8], &[u8]> {
     preceded(
-        char(' '),
-        delimited(char('('), parse_version_expr, char(')')),
+        space0,
+        delimited(
+            char('('),
+            delimited(space0, parse_version_expr, space0),
+            char(')'),
+        ),
     )(s)
 }
 
-fn parse_relational(s: &[u8]) -> IResult<&[u8], (&[u8], Option<&[u8]>)> {
-    let (s, res) = pair(parse_package_name, opt(parse_relation_suffix))(s)?;
-    let (s, _) = eof(s)?;
-    Ok((s, res))
+/// Architecture qualifier, like `:any` or `:native`
+fn parse_arch_qualifier(s: &[u8]) -> IResult<&[u8], &[u8]> {
+    preceded(
+        char(':'),
+        take_while1(|c: u8| c.is_ascii_lowercase() || c.is_ascii_digit() || c == b'-'),
+    )(s)
 }
 
-pub fn parse_pkg_list(s: &str) -> Result<Vec<(String, VersionRequirement)>> {
-    if s.is_empty() {
-        return Ok(Vec::new());
-    }
+/// Architecture restriction list, like `[amd64 arm64]` or `[!i386]`
+fn parse_arch_restriction(s: &[u8]) -> IResult<&[u8], &[u8]> {
+    preceded(space0, delimited(char('['), is_not("]"), char(']')))(s)
+}
+
+/// Build profile restriction, like `<!nocheck>`
+fn parse_profile_restriction(s: &[u8]) -> IResult<&[u8], &[u8]> {
+    preceded(space0, delimited(char('<'), is_not(">"), char('>')))(s)
+}
+
+/// A single package relationship
+#[derive(Debug, PartialEq, Eq)]
+struct Relation<'a> {
+    name: &'a [u8],
+    version: Option<&'a [u8]>,
+    archs: Option<&'a [u8]>,
+    profiles: Vec<&'a [u8]>,
+}
 
+fn parse_relational(s: &[u8]) -> IResult<&[u8], Relation<'_>> {
+    let (s, name) = parse_package_name(s)?;
+    // We only support one architecture, so arch qualifiers don't matter
+    let (s, _) = opt(parse_arch_qualifier)(s)?;
+    let (s, version) = opt(parse_relation_suffix)(s)?;
+    let (s, archs) = opt(parse_arch_restriction)(s)?;
+    let (s, profiles) = many0(parse_profile_restriction)(s)?;
+    let (s, _) = space0(s)?;
+    let (s, _) = eof(s)?;
+    Ok((
+        s,
+        Relation {
+            name,
+            version,
+            archs,
+            profiles,
+        },
+    ))
+}
+
+/// Parse a list of package relationships, like the Depends field
+///
+/// Entries restricted to other architectures than `arch`, or to build profiles, are skipped.
+pub fn parse_pkg_list(s: &str, arch: &str) -> Result<Vec<(String, VersionRequirement)>> {
     let mut res = Vec::new();
-    let pkgs: Vec<&str> = s.split(", ").collect();
-    for pkg in pkgs {
-        let (_, (name, version)) = parse_relational(pkg.as_bytes())
+    for pkg in s.split(',').map(|pkg| pkg.trim()) {
+        if pkg.is_empty() {
+            continue;
+        }
+        let (_, relation) = parse_relational(pkg.as_bytes())
             .map_err(|_| format_err!("Malformed version condition(s) in depends/breaks: {pkg}"))?;
-        // The regex should ensure name always exist
-        let ver_req = match version {
+        // No build profile is enabled when installing packages
+        let profiles = relation
+            .profiles
+            .iter()
+            .map(|list| std::str::from_utf8(list))
+            .collect::<Result<Vec<_>, _>>()?;
+        if !profiles_match(&profiles) {
+            continue;
+        }
+        if let Some(archs) = relation.archs {
+            if !arch_matches(std::str::from_utf8(archs)?, arch) {
+                continue;
+            }
+        }
+        let ver_req = match relation.version {
             Some(s) => VersionRequirement::try_from(std::str::from_utf8(s)?)?,
             None => VersionRequirement::new(),
         };
         // Add to result
-        res.push((std::str::from_utf8(name)?.to_string(), ver_req));
+        res.push((std::str::from_utf8(relation.name)?.to_string(), ver_req));
     }
 
     Ok(res)
 }
 
+/// Check build profile restriction formula, like `<!nocheck> <stage1 cross>`, with no profile
+/// enabled
+///
+/// One of the `<...>` lists has to be satisfied, which means all terms in it have to be negated.
+fn profiles_match(lists: &[&str]) -> bool {
+    lists.is_empty()
+        || lists
+            .iter()
+            .any(|list| list.split_whitespace().all(|term| term.starts_with('!')))
+}
+
+/// Check architecture restriction list, like `amd64 arm64` or `!i386`
+pub fn arch_matches(archs: &str, arch: &str) -> bool {
+    let is_match = |a: &str| a == arch || a == "any" || a == "linux-any";
+    let archs: Vec<&str> = archs.split_whitespace().collect();
+    if archs.iter().all(|a| a.starts_with('!')) {
+        !archs.iter().any(|a| is_match(&a[1..]))
+    } else {
+        archs.iter().any(|a| is_match(a))
+    }
+}
+
 #[test]
 fn test_parsers() {
     assert_eq!(parse_version_op(&b">>"[..]), Ok((&b""[..], &b">>"[..])));
@@ -87,13 +172,94 @@ fn test_parsers() {
         parse_package_name(&b"sqlite_ass"[..]),
         Ok((&b"_ass"[..], &b"sqlite"[..]))
     );
+    let relation = |name, version, archs, profiles| Relation {
+        name,
+        version,
+        archs,
+        profiles,
+    };
     assert_eq!(
         parse_relational(&b"libpcap (>= 1.9.1)"[..]),
-        Ok((&b""[..], (&b"libpcap"[..], Some(&b">= 1.9.1"[..]))))
+        Ok((
+            &b""[..],
+            relation(&b"libpcap"[..], Some(&b">= 1.9.1"[..]), None, vec![])
+        ))
     );
     assert_eq!(
         parse_relational(&b"libpcap"[..]),
-        Ok((&b""[..], (&b"libpcap"[..], None)))
+        Ok((&b""[..], relation(&b"libpcap"[..], None, None, vec![])))
     );
     assert!(parse_relational(&b"libpcap_invalid (>= 1.9.1)"[..]).is_err());
+    // Architecture qualifiers
+    assert_eq!(
+        parse_relational(&b"python3:any (>= 3.9)"[..]),
+        Ok((
+            &b""[..],
+            relation(&b"python3"[..], Some(&b">= 3.9"[..]), None, vec![])
+        ))
+    );
+    assert_eq!(
+        parse_relational(&b"perl:native"[..]),
+        Ok((&b""[..], relation(&b"perl"[..], None, None, vec![])))
+    );
+    // Architecture and profile restrictions
+    assert_eq!(
+        parse_relational(&b"libfoo [amd64 arm64]"[..]),
+        Ok((
+            &b""[..],
+            relation(&b"libfoo"[..], None, Some(&b"amd64 arm64"[..]), vec![])
+        ))
+    );
+    assert_eq!(
+        parse_relational(&b"check <!nocheck>"[..]),
+        Ok((
+            &b""[..],
+            relation(&b"check"[..], None, None, vec![&b"!nocheck"[..]])
+        ))
+    );
+    assert_eq!(
+        parse_relational(&b"gcc:native (>= 9) [!i386] <!nocheck> <cross>"[..]),
+        Ok((
+            &b""[..],
+            relation(
+                &b"gcc"[..],
+                Some(&b">= 9"[..]),
+                Some(&b"!i386"[..]),
+                vec![&b"!nocheck"[..], &b"cross"[..]]
+            )
+        ))
+    );
+    assert!(parse_relational(&b"libfoo [amd64"[..]).is_err());
+}
+
+#[test]
+fn test_parse_pkg_list() {
+    let list = "libc (>= 2.35), python3:any, libamd64 [amd64], libnoti386 [!i386], \
+        check <!nocheck>, gcc:native (>= 9) [amd64] <!cross>, crossgcc <cross>, \
+        stage <stage1> <!nocheck !cross>, stage1 <stage1 !cross>";
+    let names = |arch| -> Vec<String> {
+        parse_pkg_list(list, arch)
+            .unwrap()
+            .into_iter()
+            .map(|(name, ver_req)| format!("{name} {ver_req}"))
+            .collect()
+    };
+    assert_eq!(
+        names("amd64"),
+        vec![
+            "libc >=2.35",
+            "python3 ",
+            "libamd64 ",
+            "libnoti386 ",
+            "check ",
+            "gcc >=9",
+            "stage "
+        ]
+    );
+    assert_eq!(
+        names("i386"),
+        vec!["libc >=2.35", "python3 ", "check ", "stage "]
+    );
+    assert!(parse_pkg_list("", "amd64").unwrap().is_empty());
+    assert!(parse_pkg_list("libfoo (>> 1.0", "amd64").is_err());
 }
@@ -0,0 +1,83 @@
+use anyhow::{bail, Context, Result};
+use console::style;
+use indicatif::HumanBytes;
+use nix::sys::statvfs::statvfs;
+use std::path::Path;
+
+/// Space of a file system, in bytes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FsSpace {
+    /// Available to unprivileged users, dpkg doesn't get the reserved blocks either
+    pub available: u64,
+    pub total: u64,
+    /// Which file system it is
+    pub id: u64,
+}
+
+/// Get space of the file system holding `path`, or its closest existing parent
+pub fn fs_space(path: &Path) -> Result<FsSpace> {
+    let path = path
+        .ancestors()
+        .find(|p| p.exists())
+        .unwrap_or_else(|| Path::new("/"));
+    let stat = statvfs(path).context(format!(
+        "Failed to get free space of {}.",
+        style(path.display()).bold()
+    ))?;
+    let fragment_size = stat.fragment_size() as u64;
+    Ok(FsSpace {
+        available: stat.blocks_available() as u64 * fragment_size,
+        total: stat.blocks() as u64 * fragment_size,
+        id: stat.filesystem_id() as u64,
+    })
+}
+
+/// Free space left after using `used` more bytes of `space`, fails if it's less than `reserve`
+///
+/// `used` is negative if space is freed. Returns the projected free space.
+pub fn check_reserve(space: &FsSpace, used: i128, reserve: u64) -> Result<u64> {
+    let projected = i128::from(space.available) - used;
+    if projected < 0 {
+        bail!(
+            "Not enough free space: {} more is needed.",
+            style(HumanBytes(projected.unsigned_abs() as u64)).bold()
+        );
+    }
+    let projected = projected as u64;
+    if projected < reserve {
+        bail!(
+            "Only {} would be left free, less than {} ({}) in config.",
+            style(HumanBytes(projected)).bold(),
+            style("min_free_space").bold(),
+            HumanBytes(reserve)
+        );
+    }
+
+    Ok(projected)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_check_reserve() {
+        let space = FsSpace {
+            available: 10_000,
+            total: 100_000,
+            id: 0,
+        };
+        // Exactly the reserve is left
+        assert_eq!(check_reserve(&space, 6_000, 4_000).unwrap(), 4_000);
+        // One byte short of it
+        assert!(check_reserve(&space, 6_001, 4_000).is_err());
+        // Fits, but not with the reserve
+        assert!(check_reserve(&space, 9_000, 4_000).is_err());
+        // Doesn't fit at all
+        assert!(check_reserve(&space, 10_001, 0).is_err());
+        assert_eq!(check_reserve(&space, 10_000, 0).unwrap(), 0);
+        // Freeing space
+        assert_eq!(check_reserve(&space, -5_000, 4_000).unwrap(), 15_000);
+        assert!(fs_space(Path::new("/nonexistent/dir")).unwrap().total > 0);
+    }
+}
@@ -1,23 +1,49 @@
-use crate::{msg, types::Checksum};
+use crate::{
+    debug, msg,
+    types::{
+        config::{HttpVersion, NetworkConfig, RepoConfig},
+        Checksum, ChecksumValidator,
+    },
+    warn,
+};
 
-use anyhow::{bail, format_err, Result};
-use async_compression::tokio::write::{GzipDecoder, XzDecoder};
+use anyhow::{bail, format_err, Context, Result};
+use bytes::{Bytes, BytesMut};
 use console::style;
 use futures_util::future::select_all;
 use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
-use reqwest::Client;
+use reqwest::{
+    header::{HeaderMap, HeaderName, HeaderValue, USER_AGENT},
+    Client, ClientBuilder, RequestBuilder, Url,
+};
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
+    fs::File,
+    io::Write,
     path::{Path, PathBuf},
+    sync::{Arc, OnceLock},
+    time::Duration,
 };
-use tokio::{
-    fs::OpenOptions,
-    io::{AsyncWrite, AsyncWriteExt},
-};
+use tokio::{fs::OpenOptions, sync::mpsc};
+
+const DEFAULT_USER_AGENT: &str = concat!("omakase/", env!("CARGO_PKG_VERSION"));
+/// Downloaded data is handed over to the writer in blocks of this size
+const BLOCK_SIZE: usize = 256 * 1024;
+/// How many blocks may wait to be written for each file
+const BLOCK_QUEUE_LEN: usize = 16;
+/// Enough idle connections for all concurrent downloads to reuse them
+const DEFAULT_POOL_MAX_IDLE_PER_HOST: usize = 5;
+/// Most mirrors close idle connections after a minute, don't try to reuse them after that
+const DEFAULT_POOL_IDLE_TIMEOUT: u64 = 30;
 
 #[derive(Clone)]
 pub struct DownloadJob {
     pub url: String,
+    /// Alternative URLs of the same file, tried in turn when downloading from `url` fails
+    pub mirrors: Vec<String>,
+    /// Repositories the file comes from, their credentials and connection settings are used for
+    /// their mirrors. Empty for files that don't belong to a repository
+    pub repos: Vec<String>,
     pub description: Option<String>,
     pub filename: Option<String>,
     pub size: Option<u64>,
@@ -29,46 +55,135 @@ pub struct DownloadJob {
 pub enum Compression {
     Gzip((Option<Checksum>, Option<Checksum>)),
     Xz((Option<Checksum>, Option<Checksum>)),
+    /// Gzip compressed, but stored as is. The decompressed data is still validated
+    KeepGzip((Option<Checksum>, Option<Checksum>)),
     None(Option<Checksum>),
 }
 
+impl DownloadJob {
+    /// URL to use for the `attempt`-th try, cycling through all mirrors
+    fn url_for_attempt(&self, attempt: usize) -> &str {
+        let count = self.mirrors.len() + 1;
+        match attempt % count {
+            0 => &self.url,
+            i => &self.mirrors[i - 1],
+        }
+    }
+}
+
 impl Compression {
+    /// Checksum of the file as stored locally
     pub fn get_extracted_checksum(&self) -> Option<Checksum> {
         match self {
             Compression::Gzip((_, c)) | Compression::Xz((_, c)) | Compression::None(c) => c,
+            Compression::KeepGzip((c, _)) => c,
         }
         .clone()
     }
 
     pub fn get_download_checksum(&self) -> Option<Checksum> {
         match self {
-            Compression::Gzip((c, _)) | Compression::Xz((c, _)) | Compression::None(c) => c,
+            Compression::Gzip((c, _))
+            | Compression::Xz((c, _))
+            | Compression::KeepGzip((c, _))
+            | Compression::None(c) => c,
         }
         .clone()
     }
+
+    /// Whether the local copy at `path` is up to date, so that it doesn't need downloading
+    pub fn local_copy_valid(&self, path: &Path) -> bool {
+        match self.get_extracted_checksum() {
+            Some(checksum) => path.is_file() && checksum.cmp_file(path).unwrap_or(false),
+            None => false,
+        }
+    }
+}
+
+/// Credential for accessing private repositories
+#[derive(Clone)]
+pub enum Credential {
+    Basic {
+        username: String,
+        password: Option<String>,
+    },
+    Token(String),
+}
+
+impl std::fmt::Debug for Credential {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Credential::Basic { username, .. } => write!(f, "Basic({username}, <redacted>)"),
+            Credential::Token(_) => write!(f, "Token(<redacted>)"),
+        }
+    }
 }
 
 pub struct Downloader {
-    client: Client,
+    clients: Clients,
     max_concurrent: usize,
     max_retry: usize,
 }
 
 impl Downloader {
-    pub fn new() -> Self {
-        Downloader {
-            client: Client::new(),
+    pub fn new(config: &NetworkConfig, repos: &HashMap<String, RepoConfig>) -> Result<Self> {
+        let client = client_builder(config)?
+            .build()
+            .context("Failed to initialize HTTP client.")?;
+        let mut by_repo = HashMap::new();
+        for (name, repo) in repos {
+            let has_settings = repo.ip_family().is_some()
+                || repo.connect_timeout.is_some()
+                || repo.read_timeout.is_some();
+            let has_credential = repo.username.is_some() || repo.auth_token.is_some();
+            if has_settings || has_credential {
+                by_repo.insert(
+                    name.clone(),
+                    RepoClient::new(config, name, repo, has_settings, &client)?,
+                );
+            }
+        }
+
+        Ok(Downloader {
+            clients: Clients {
+                default: client,
+                read_timeout: config.read_timeout.map(Duration::from_secs),
+                by_repo: Arc::new(by_repo),
+            },
             max_concurrent: 5,
             max_retry: 3,
-        }
+        })
     }
 
     /// Download all required stuff in an async manner and show a progress bar
     pub async fn fetch(
+        &self,
+        to_download: Vec<DownloadJob>,
+        download_path: &Path,
+        global_progess: bool,
+    ) -> Result<HashMap<String, PathBuf>> {
+        self.fetch_jobs(to_download, download_path, global_progess, false)
+            .await
+    }
+
+    /// Like `fetch`, but files that still fail after all retries are skipped with a warning,
+    /// instead of failing the whole operation. They are not in the returned map.
+    pub async fn fetch_available(
+        &self,
+        to_download: Vec<DownloadJob>,
+        download_path: &Path,
+        global_progess: bool,
+    ) -> Result<HashMap<String, PathBuf>> {
+        self.fetch_jobs(to_download, download_path, global_progess, true)
+            .await
+    }
+
+    async fn fetch_jobs(
         &self,
         mut to_download: Vec<DownloadJob>,
         download_path: &Path,
         global_progess: bool,
+        skip_failed: bool,
     ) -> Result<HashMap<String, PathBuf>> {
         // Create download dir
         if !download_path.is_dir() {
@@ -112,13 +227,13 @@ impl Downloader {
         while !to_download.is_empty() {
             while handles.len() < self.max_concurrent && !to_download.is_empty() {
                 let job = to_download.pop().unwrap();
-                let client = self.client.clone();
+                let clients = self.clients.clone();
                 let path = download_path.to_owned();
                 let bar = multibar.insert(0, ProgressBar::new(job.size.unwrap_or(0)));
                 let global_bar = global_bar.clone();
                 bar.set_style(barsty.clone());
                 let handle = tokio::spawn(async move {
-                    try_download_file(client, path, job, 0, bar, global_bar).await
+                    try_download_file(clients, path, job, 0, bar, global_bar).await
                 });
                 handles.push(handle);
             }
@@ -135,14 +250,18 @@ impl Downloader {
                 Err(e) => {
                     // Handling download errors
                     // If have remaining reties, do it
-                    if e.retry < self.max_retry {
-                        let c = self.client.clone();
+                    // Each mirror is tried at least once
+                    if e.retry < self.max_retry.max(e.job.mirrors.len()) {
+                        let c = self.clients.clone();
                         let path = download_path.to_owned();
                         let handle = tokio::spawn(async move {
                             try_download_file(c, path, e.job, e.retry + 1, e.bar, e.global_bar)
                                 .await
                         });
                         handles.push(handle);
+                    } else if skip_failed {
+                        e.bar.finish_and_clear();
+                        warn!("Skipping {}: {:#}", e.job.url, e.error);
                     } else {
                         return Err(e.error);
                     }
@@ -162,14 +281,18 @@ impl Downloader {
                 Err(e) => {
                     // Handling download errors
                     // If have remaining reties, do it
-                    if e.retry < self.max_retry {
-                        let c = self.client.clone();
+                    // Each mirror is tried at least once
+                    if e.retry < self.max_retry.max(e.job.mirrors.len()) {
+                        let c = self.clients.clone();
                         let path = download_path.to_owned();
                         let handle = tokio::spawn(async move {
                             try_download_file(c, path, e.job, e.retry + 1, e.bar, e.global_bar)
                                 .await
                         });
                         handles.push(handle);
+                    } else if skip_failed {
+                        e.bar.finish_and_clear();
+                        warn!("Skipping {}: {:#}", e.job.url, e.error);
                     } else {
                         return Err(e.error);
                     }
@@ -180,6 +303,202 @@ impl Downloader {
     }
 }
 
+/// HTTP clients, repositories with an `ip_family`, timeouts or credentials have their own settings
+#[derive(Clone)]
+struct Clients {
+    default: Client,
+    read_timeout: Option<Duration>,
+    /// Settings of repositories, by name
+    by_repo: Arc<HashMap<String, RepoClient>>,
+}
+
+impl Clients {
+    /// Settings of the repository of `job` that `host` is a mirror of, if any
+    fn for_job(&self, job: &DownloadJob, host: Option<&str>) -> Option<&RepoClient> {
+        let host = host?;
+        job.repos
+            .iter()
+            .filter_map(|name| self.by_repo.get(name))
+            .find(|repo| repo.hosts.contains(host))
+    }
+
+    /// Client, the same client over any IP family if it's bound to one, and read timeout to use
+    /// for `host` of `repo`
+    fn for_host<'a>(
+        &'a self,
+        repo: Option<&'a RepoClient>,
+        host: Option<&str>,
+    ) -> (&'a Client, Option<&'a Client>, Option<Duration>) {
+        let (repo, client) = match repo.and_then(|repo| Some((repo, repo.client.as_ref()?))) {
+            Some(found) => found,
+            None => return (&self.default, None, self.read_timeout),
+        };
+        let read_timeout = client.read_timeout.or(self.read_timeout);
+        // Only the preferred mirror is bound to the IP family
+        if host.is_some() && host == repo.preferred_host.as_deref() {
+            (&client.client, client.any_family.as_ref(), read_timeout)
+        } else {
+            let any_family = client.any_family.as_ref().unwrap_or(&client.client);
+            (any_family, None, read_timeout)
+        }
+    }
+}
+
+/// Settings to access the mirrors of a repository
+struct RepoClient {
+    name: String,
+    /// Hosts of all mirrors of the repository
+    hosts: HashSet<String>,
+    /// Host of the preferred mirror
+    preferred_host: Option<String>,
+    /// Only set if the repository has an `ip_family` or timeouts
+    client: Option<HostClient>,
+    config: RepoConfig,
+    /// Resolved on first use, so that secrets of repositories that are not downloaded from
+    /// aren't read
+    credential: OnceLock<Result<Option<Credential>, String>>,
+}
+
+impl RepoClient {
+    fn new(
+        config: &NetworkConfig,
+        name: &str,
+        repo: &RepoConfig,
+        has_settings: bool,
+        default: &Client,
+    ) -> Result<Self> {
+        let urls = repo.get_urls()?;
+        let client = if has_settings {
+            Some(HostClient::new(config, repo, default)?)
+        } else {
+            None
+        };
+        Ok(RepoClient {
+            name: name.to_string(),
+            hosts: urls.iter().filter_map(|url| get_host(url)).collect(),
+            preferred_host: get_host(&urls[0]),
+            client,
+            config: repo.clone(),
+            credential: OnceLock::new(),
+        })
+    }
+
+    fn credential(&self) -> Result<Option<&Credential>> {
+        let credential = self.credential.get_or_init(|| {
+            self.config.get_credential().map_err(|e| {
+                format!(
+                    "Failed to load credential for repository {}: {e:#}",
+                    style(&self.name).bold()
+                )
+            })
+        });
+        match credential {
+            Ok(credential) => Ok(credential.as_ref()),
+            Err(e) => bail!("{e}"),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct HostClient {
+    /// Bound to the IP family of the preferred mirror, if any, and with the connect timeout of the repository
+    client: Client,
+    /// Same as `client`, but over any IP family. Only set if `client` is bound to one
+    any_family: Option<Client>,
+    read_timeout: Option<Duration>,
+}
+
+impl HostClient {
+    fn new(config: &NetworkConfig, repo: &RepoConfig, default: &Client) -> Result<Self> {
+        let builder = || -> Result<ClientBuilder> {
+            let builder = client_builder(config)?;
+            Ok(match repo.connect_timeout {
+                Some(secs) => builder.connect_timeout(Duration::from_secs(secs)),
+                None => builder,
+            })
+        };
+        let any_family = if repo.connect_timeout.is_some() {
+            builder()?
+                .build()
+                .context("Failed to initialize HTTP client.")?
+        } else {
+            default.clone()
+        };
+        let (client, any_family) = match repo.ip_family() {
+            Some(family) => (
+                builder()?
+                    .local_address(family.local_address())
+                    .build()
+                    .context("Failed to initialize HTTP client.")?,
+                Some(any_family),
+            ),
+            None => (any_family, None),
+        };
+        Ok(HostClient {
+            client,
+            any_family,
+            read_timeout: repo.read_timeout.map(Duration::from_secs),
+        })
+    }
+}
+
+/// Create a ClientBuilder with User-Agent, custom headers and connection settings applied
+pub fn client_builder(config: &NetworkConfig) -> Result<ClientBuilder> {
+    let headers = gen_headers(config)?;
+    let builder = ClientBuilder::new()
+        .default_headers(headers)
+        .pool_max_idle_per_host(
+            config
+                .pool_max_idle_per_host
+                .unwrap_or(DEFAULT_POOL_MAX_IDLE_PER_HOST),
+        )
+        .pool_idle_timeout(Duration::from_secs(
+            config
+                .pool_idle_timeout
+                .unwrap_or(DEFAULT_POOL_IDLE_TIMEOUT),
+        ));
+    let builder = match config.connect_timeout {
+        Some(secs) => builder.connect_timeout(Duration::from_secs(secs)),
+        None => builder,
+    };
+    let builder = match config.http_version {
+        // HTTP/2 is offered along with HTTP/1.1 during TLS handshake
+        HttpVersion::Auto => builder,
+        HttpVersion::Http1 => builder.http1_only(),
+        HttpVersion::Http2 => builder.http2_prior_knowledge(),
+    };
+    Ok(builder)
+}
+
+#[inline]
+fn get_host(url: &str) -> Option<String> {
+    let url = Url::parse(url).ok()?;
+    url.host_str().map(|host| host.to_owned())
+}
+
+fn gen_headers(config: &NetworkConfig) -> Result<HeaderMap> {
+    let mut headers = HeaderMap::new();
+    let user_agent = match &config.user_agent {
+        Some(ua) => ua.as_str(),
+        None => DEFAULT_USER_AGENT,
+    };
+    headers.insert(
+        USER_AGENT,
+        HeaderValue::from_str(user_agent).context("Invalid User-Agent in configuration.")?,
+    );
+    for (name, value) in &config.headers {
+        let header_name = HeaderName::from_bytes(name.as_bytes())
+            .context(format!("Invalid header name {} in configuration.", name))?;
+        let header_value = HeaderValue::from_str(value).context(format!(
+            "Invalid value for header {} in configuration.",
+            name
+        ))?;
+        headers.insert(header_name, header_value);
+    }
+
+    Ok(headers)
+}
+
 struct DownloadError {
     error: anyhow::Error,
     job: DownloadJob,
@@ -189,21 +508,76 @@ struct DownloadError {
 }
 
 async fn try_download_file(
-    client: Client,
+    clients: Clients,
     path: PathBuf,
     job: DownloadJob,
     retry: usize,
     bar: ProgressBar,
     global_bar: Option<ProgressBar>,
 ) -> Result<(String, PathBuf), DownloadError> {
-    match download_file(&client, &path, job.clone(), bar.clone(), global_bar.clone()).await {
+    // Fall back to other mirrors on retries
+    let url = job.url_for_attempt(retry).to_owned();
+    let host = get_host(&url);
+    let repo = clients.for_job(&job, host.as_deref());
+    let credential = match repo.map(|repo| repo.credential()).transpose() {
+        Ok(credential) => credential.flatten(),
+        Err(error) => {
+            bar.reset();
+            return Err(DownloadError {
+                error,
+                job,
+                retry,
+                bar,
+                global_bar,
+            });
+        }
+    };
+    let (client, any_family, read_timeout) = clients.for_host(repo, host.as_deref());
+    let request = |client: &Client| {
+        let req = client.get(&url);
+        match credential {
+            Some(Credential::Basic { username, password }) => {
+                req.basic_auth(username, password.as_ref())
+            }
+            Some(Credential::Token(token)) => req.bearer_auth(token),
+            None => req,
+        }
+    };
+    let mut res = download_file(
+        request(client),
+        read_timeout,
+        &path,
+        &url,
+        job.clone(),
+        bar.clone(),
+        global_bar.clone(),
+    )
+    .await;
+    // The mirror may not be reachable over the chosen IP family anymore, try any family then
+    if let (Some(any_family), Err(e)) = (any_family, &res) {
+        if is_connect_error(e) {
+            debug!("Failed to connect to {url} over the configured IP family, retrying with any family: {e}");
+            bar.reset();
+            res = download_file(
+                request(any_family),
+                read_timeout,
+                &path,
+                &url,
+                job.clone(),
+                bar.clone(),
+                global_bar.clone(),
+            )
+            .await;
+        }
+    }
+    match res {
         Ok(res) => Ok(res),
         Err(error) => Err({
             bar.reset();
             DownloadError {
                 error,
                 job,
-                retry: retry + 1,
+                retry,
                 bar,
                 global_bar,
             }
@@ -211,14 +585,35 @@ async fn try_download_file(
     }
 }
 
+/// Whether the request failed because no connection could be made
+pub fn is_connect_error(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<reqwest::Error>()
+        .is_some_and(|e| e.is_connect())
+}
+
+/// Wait for `fut`, failing if it takes longer than `timeout`
+async fn with_timeout<T>(
+    timeout: Option<Duration>,
+    fut: impl std::future::Future<Output = reqwest::Result<T>>,
+) -> Result<T> {
+    match timeout {
+        Some(timeout) => Ok(tokio::time::timeout(timeout, fut)
+            .await
+            .map_err(|_| format_err!("No data received in {}s.", timeout.as_secs()))??),
+        None => Ok(fut.await?),
+    }
+}
+
 async fn download_file(
-    client: &Client,
+    req: RequestBuilder,
+    read_timeout: Option<Duration>,
     path: &Path,
+    url: &str,
     job: DownloadJob,
     bar: ProgressBar,
     global_bar: Option<ProgressBar>,
 ) -> Result<(String, PathBuf)> {
-    let mut resp = client.get(&job.url).send().await?;
+    let mut resp = with_timeout(read_timeout, req.send()).await?;
     resp.error_for_status_ref()?;
     let filename = match job.filename {
         Some(n) => n,
@@ -227,7 +622,7 @@ async fn download_file(
             .path_segments()
             .and_then(|segments| segments.last())
             .and_then(|name| if name.is_empty() { None } else { Some(name) })
-            .ok_or_else(|| format_err!("{} doesn't contain filename.", &job.url))?
+            .ok_or_else(|| format_err!("{} doesn't contain filename.", url))?
             .to_string(),
     };
     let len = match job.size {
@@ -239,28 +634,26 @@ async fn download_file(
     let msg = job.description.as_ref().unwrap_or(&filename);
 
     let file_path = path.join(&filename);
-    let mut f = {
+    let f = {
         if file_path.is_file() {
-            if let Some(checksum) = job.compression.get_extracted_checksum() {
-                let p = file_path.clone();
-                let res = tokio::task::spawn_blocking(move || checksum.cmp_file(&p)).await?;
-                if res.is_ok() && res.unwrap() {
-                    // Checksum matched.
-                    bar.finish_and_clear();
-                    // Reduce global bar length, since we don't need to download this file
-                    if let Some(ref global_bar) = global_bar {
-                        global_bar.set_length(global_bar.length() - len);
-                    }
+            let compression = job.compression.clone();
+            let p = file_path.clone();
+            if tokio::task::spawn_blocking(move || compression.local_copy_valid(&p)).await? {
+                // Checksum matched.
+                bar.finish_and_clear();
+                // Reduce global bar length, since we don't need to download this file
+                if let Some(ref global_bar) = global_bar {
+                    global_bar.set_length(global_bar.length() - len);
+                }
 
-                    if crate::verbose() || global_bar.is_some() {
-                        bar.println(format!(
-                            "{}{} (not modified)",
-                            crate::cli::gen_prefix(&console::style("SKIP").dim().to_string()),
-                            &msg
-                        ));
-                    }
-                    return Ok((job.url, file_path));
+                if crate::verbose() || global_bar.is_some() {
+                    bar.println(format!(
+                        "{}{} (not modified)",
+                        crate::cli::gen_prefix(&console::style("SKIP").dim().to_string()),
+                        &msg
+                    ));
                 }
+                return Ok((job.url, file_path));
             }
             // If checksum DNE/mismatch, purge current content
             let f = OpenOptions::new()
@@ -293,44 +686,50 @@ async fn download_file(
 
     // Download!
     {
-        let mut validator = job
+        // Decompression and checksum validation are CPU-bound, run them on the blocking thread pool
+        // so that other downloads can proceed meanwhile
+        let validator = job
             .compression
             .get_download_checksum()
             .as_ref()
             .map(|c| c.get_validator());
-        let mut writer: Box<dyn AsyncWrite + Unpin + Send> = match job.compression {
-            Compression::Gzip(_) => Box::new(GzipDecoder::new(&mut f)),
-            Compression::Xz(_) => Box::new(XzDecoder::new(&mut f)),
-            Compression::None(_) => Box::new(&mut f),
-        };
-        while let Some(chunk) = resp.chunk().await? {
-            writer.write_all(&chunk).await?;
+        let (tx, rx) = mpsc::channel(BLOCK_QUEUE_LEN);
+        let f = f.into_std().await;
+        let compression = job.compression.clone();
+        let writer =
+            tokio::task::spawn_blocking(move || write_chunks(rx, f, compression, validator));
+        let mut block = BytesMut::with_capacity(BLOCK_SIZE);
+        while let Some(chunk) = with_timeout(read_timeout, resp.chunk()).await? {
             let len = chunk.len().try_into().unwrap();
             bar.inc(len);
             // Increase global bar, if applicable
             if let Some(ref global_bar) = global_bar {
                 global_bar.inc(len);
             }
-            if let Some(ref mut validator) = validator {
-                validator.update(&chunk);
+            block.extend_from_slice(&chunk);
+            // Sending chunks one by one wakes the writer too often
+            if block.len() >= BLOCK_SIZE && tx.send(block.split().freeze()).await.is_err() {
+                // The writer has failed, its error is returned below
+                break;
             }
         }
-        writer.shutdown().await?;
+        if !block.is_empty() {
+            tx.send(block.freeze()).await.ok();
+        }
+        drop(tx);
+        let valid = writer.await??;
 
         if let Some(len) = job.size {
             if bar.length() != len {
                 bail!(
                     "Bad file size when downloading {}: mirrors may be synchronizing, please try again later.",
-                    job.url
+                    url
                 );
             }
         }
 
-        if let Some(validator) = validator {
-            // finish() returns false if validate failed
-            if !validator.finish() {
-                bail!("Checksum mismatched for file {}.", filename);
-            }
+        if !valid {
+            bail!("Checksum mismatched for file {}.", filename);
         }
     }
 
@@ -343,6 +742,78 @@ async fn download_file(
     Ok((job.url, file_path))
 }
 
+/// Write blocks from `rx` to `f`, decompressing them according to `compression`
+///
+/// Returns false if the received data doesn't match the checksum of `validator`.
+fn write_chunks(
+    mut rx: mpsc::Receiver<Bytes>,
+    f: File,
+    compression: Compression,
+    mut validator: Option<ChecksumValidator>,
+) -> Result<bool> {
+    let mut copy = |writer: &mut dyn Write| -> Result<()> {
+        while let Some(chunk) = rx.blocking_recv() {
+            writer.write_all(&chunk)?;
+            if let Some(ref mut validator) = validator {
+                validator.update(&chunk);
+            }
+        }
+        Ok(())
+    };
+    match compression {
+        Compression::Gzip(_) => {
+            let mut writer = flate2::write::GzDecoder::new(f);
+            copy(&mut writer)?;
+            writer.try_finish()?;
+        }
+        Compression::Xz(_) => {
+            let mut writer = xz2::write::XzDecoder::new(f);
+            copy(&mut writer)?;
+            writer.finish()?;
+        }
+        Compression::KeepGzip((_, decompressed)) => {
+            let mut writer = f;
+            match decompressed {
+                Some(checksum) => {
+                    // Store the data as is, and decompress it on the side to validate it
+                    let mut decoder = flate2::write::GzDecoder::new(checksum.get_validator());
+                    copy(&mut Tee(&mut writer, &mut decoder))?;
+                    if !decoder.finish()?.finish() {
+                        return Ok(false);
+                    }
+                }
+                None => copy(&mut writer)?,
+            }
+        }
+        Compression::None(_) => {
+            let mut writer = f;
+            copy(&mut writer)?;
+        }
+    }
+
+    // finish() returns false if validate failed
+    Ok(match validator {
+        Some(validator) => validator.finish(),
+        None => true,
+    })
+}
+
+/// Writes all data to both writers
+struct Tee<A, B>(A, B);
+
+impl<A: Write, B: Write> Write for Tee<A, B> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.write_all(buf)?;
+        self.1.write_all(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.0.flush()?;
+        self.1.flush()
+    }
+}
+
 #[inline]
 fn update_global_bar(
     bar: &Option<ProgressBar>,
@@ -370,3 +841,291 @@ fn gen_global_bar_message(total: usize, finished: usize, total_text_len: usize)
         width = total_text_len
     )
 }
+
+/// Serve `files` over HTTP on localhost for tests, returns the base URL
+#[cfg(test)]
+pub fn test_server(files: HashMap<String, Vec<u8>>) -> String {
+    use std::io::{BufRead, BufReader};
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut request = String::new();
+            reader.read_line(&mut request).unwrap();
+            // Skip headers
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 2 {
+                line.clear();
+            }
+            let path = request.split(' ').nth(1).unwrap_or_default();
+            let res = match files.get(path) {
+                Some(body) => {
+                    let header = format!(
+                        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                        body.len()
+                    );
+                    [header.as_bytes(), body].concat()
+                }
+                None => b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                    .to_vec(),
+            };
+            stream.write_all(&res).ok();
+        }
+    });
+    url
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::utils::temp_dir::TempDir;
+
+    /// Job to download `url` with nothing else set
+    fn job(url: &str) -> DownloadJob {
+        DownloadJob {
+            url: url.to_string(),
+            mirrors: Vec::new(),
+            repos: Vec::new(),
+            description: None,
+            filename: None,
+            size: None,
+            compression: Compression::None(None),
+        }
+    }
+
+    #[test]
+    fn test_gen_headers() {
+        let mut config = NetworkConfig::default();
+        let headers = gen_headers(&config).unwrap();
+        assert_eq!(headers.get(USER_AGENT).unwrap(), DEFAULT_USER_AGENT);
+        assert_eq!(headers.len(), 1);
+
+        config.user_agent = Some("custom-agent/1.0".to_string());
+        config
+            .headers
+            .insert("X-Mirror-Token".to_string(), "secret".to_string());
+        let headers = gen_headers(&config).unwrap();
+        assert_eq!(headers.get(USER_AGENT).unwrap(), "custom-agent/1.0");
+        assert_eq!(headers.get("x-mirror-token").unwrap(), "secret");
+
+        config
+            .headers
+            .insert("Bad Header".to_string(), "value".to_string());
+        assert!(gen_headers(&config).is_err());
+    }
+    #[test]
+    fn test_client_builder() {
+        for version in ["auto", "http1", "http2"] {
+            let config: NetworkConfig = toml::from_str(&format!(
+                "http_version = \"{version}\"\npool_max_idle_per_host = 0\npool_idle_timeout = 10"
+            ))
+            .unwrap();
+            client_builder(&config).unwrap().build().unwrap();
+        }
+        let config: NetworkConfig = toml::from_str("").unwrap();
+        assert_eq!(config.http_version, HttpVersion::Auto);
+        assert!(toml::from_str::<NetworkConfig>("http_version = \"http3\"").is_err());
+    }
+    #[test]
+    fn test_write_chunks() {
+        use flate2::write::GzEncoder;
+        use sha2::{Digest, Sha256};
+
+        let dir = TempDir::new("chunks");
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(b"Package: bash\n").unwrap();
+        let gz = encoder.finish().unwrap();
+        let checksum = Checksum::Sha256(Sha256::digest(&gz).to_vec());
+        let write = |compression: Compression, data: &[u8]| {
+            let (tx, rx) = mpsc::channel(BLOCK_QUEUE_LEN);
+            for chunk in data.chunks(4) {
+                tx.blocking_send(Bytes::copy_from_slice(chunk)).unwrap();
+            }
+            drop(tx);
+            let f = File::create(dir.join("Packages")).unwrap();
+            let validator = compression
+                .get_download_checksum()
+                .map(|c| c.get_validator());
+            write_chunks(rx, f, compression, validator)
+        };
+
+        // Checksum is validated against compressed data
+        assert!(write(Compression::Gzip((Some(checksum.clone()), None)), &gz).unwrap());
+        assert_eq!(
+            std::fs::read(dir.join("Packages")).unwrap(),
+            b"Package: bash\n"
+        );
+        assert!(!write(
+            Compression::None(Some(checksum.clone())),
+            b"Package: dash\n"
+        )
+        .unwrap());
+        // Bad data fails the job
+        assert!(write(Compression::Gzip((None, None)), b"not gzip").is_err());
+        // Kept compressed, but validated against decompressed data as well
+        let decompressed = Checksum::Sha256(Sha256::digest(b"Package: bash\n").to_vec());
+        let keep = |decompressed| Compression::KeepGzip((Some(checksum.clone()), decompressed));
+        assert!(write(keep(Some(decompressed.clone())), &gz).unwrap());
+        assert_eq!(std::fs::read(dir.join("Packages")).unwrap(), gz);
+        let stale = Checksum::Sha256(Sha256::digest(b"Package: dash\n").to_vec());
+        assert!(!write(keep(Some(stale)), &gz).unwrap());
+        assert!(write(keep(None), &gz).unwrap());
+    }
+
+    #[test]
+    fn test_url_for_attempt() {
+        let mut job = job("https://a.example.org/foo.deb");
+        assert_eq!(job.url_for_attempt(0), "https://a.example.org/foo.deb");
+        assert_eq!(job.url_for_attempt(1), "https://a.example.org/foo.deb");
+
+        job.mirrors = vec!["https://b.example.org/foo.deb".to_string()];
+        assert_eq!(job.url_for_attempt(1), "https://b.example.org/foo.deb");
+        assert_eq!(job.url_for_attempt(2), "https://a.example.org/foo.deb");
+    }
+
+    #[test]
+    fn test_mirror_fallback() {
+        use sha2::{Digest, Sha256};
+
+        let deb = b"not really a deb".to_vec();
+        let checksum = Checksum::from_sha256_str(&hex::encode(Sha256::digest(&deb))).unwrap();
+        // The package is missing on the first mirror
+        let primary = test_server(HashMap::new());
+        let secondary = test_server(HashMap::from([(
+            "/debs/pool/tool_1.0_amd64.deb".to_string(),
+            deb.clone(),
+        )]));
+        let job = DownloadJob {
+            mirrors: vec![format!("{secondary}/debs/pool/tool_1.0_amd64.deb")],
+            size: Some(deb.len() as u64),
+            compression: Compression::None(Some(checksum)),
+            ..job(&format!("{primary}/debs/pool/tool_1.0_amd64.deb"))
+        };
+        let dir = TempDir::new("fallback");
+        let mut downloader = Downloader::new(&NetworkConfig::default(), &HashMap::new()).unwrap();
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        // Mirrors are tried even without retries
+        downloader.max_retry = 0;
+        runtime
+            .block_on(downloader.fetch(vec![job.clone()], &dir, false))
+            .unwrap();
+        assert_eq!(std::fs::read(dir.join("tool_1.0_amd64.deb")).unwrap(), deb);
+
+        let mut job = job;
+        job.mirrors.clear();
+        std::fs::remove_dir_all(&*dir).unwrap();
+        assert!(runtime
+            .block_on(downloader.fetch(vec![job], &dir, false))
+            .is_err());
+    }
+
+    #[test]
+    fn test_repo_credentials() {
+        let repo = |source: &str, token: &str| -> RepoConfig {
+            toml::from_str(&format!(
+                "source = \"{source}\"\nflat = true\nkeys = []\nauth_token = {token}"
+            ))
+            .unwrap()
+        };
+        let repos = HashMap::from([
+            (
+                "a".to_string(),
+                repo("https://repo.example.org/a", "\"token-a\""),
+            ),
+            (
+                "b".to_string(),
+                repo("https://repo.example.org/b", "\"token-b\""),
+            ),
+            // Only read when downloading from it
+            (
+                "c".to_string(),
+                repo(
+                    "https://c.example.org",
+                    "{ env = \"OMAKASE_TEST_UNSET_TOKEN\" }",
+                ),
+            ),
+        ]);
+        let downloader = Downloader::new(&NetworkConfig::default(), &repos).unwrap();
+        let token = |repos: &[&str], host: &str| {
+            let job = DownloadJob {
+                repos: repos.iter().map(|r| r.to_string()).collect(),
+                ..job(&format!("https://{host}/foo.deb"))
+            };
+            let repo = downloader.clients.for_job(&job, Some(host))?;
+            match repo.credential().unwrap() {
+                Some(Credential::Token(token)) => Some(token.clone()),
+                _ => None,
+            }
+        };
+        // Repositories on the same host keep their own credentials
+        assert_eq!(token(&["a"], "repo.example.org").unwrap(), "token-a");
+        assert_eq!(token(&["b"], "repo.example.org").unwrap(), "token-b");
+        assert_eq!(token(&["c", "b"], "repo.example.org").unwrap(), "token-b");
+        // Not sent to other hosts, or for files of other repositories
+        assert!(token(&["a"], "mirror.example.org").is_none());
+        assert!(token(&[], "repo.example.org").is_none());
+
+        let job = DownloadJob {
+            repos: vec!["c".to_string()],
+            ..job("https://c.example.org/foo.deb")
+        };
+        let repo = downloader
+            .clients
+            .for_job(&job, Some("c.example.org"))
+            .unwrap();
+        assert!(repo.credential().is_err());
+    }
+
+    #[test]
+    fn test_repo_timeout() {
+        // Accepts connections, but never responds
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let mut streams = Vec::new();
+            for stream in listener.incoming() {
+                streams.push(stream);
+            }
+        });
+        let repo: RepoConfig = toml::from_str(&format!(
+            "source = \"http://{addr}\"\nflat = true\nkeys = []\nconnect_timeout = 5\nread_timeout = 1"
+        ))
+        .unwrap();
+        let config: NetworkConfig = toml::from_str("read_timeout = 600").unwrap();
+        let mut downloader =
+            Downloader::new(&config, &HashMap::from([("slow".to_string(), repo)])).unwrap();
+        let job = DownloadJob {
+            repos: vec!["slow".to_string()],
+            ..job(&format!("http://{addr}/InRelease"))
+        };
+        let timeout = |job: &DownloadJob, host: &str| {
+            let repo = downloader.clients.for_job(job, Some(host));
+            downloader.clients.for_host(repo, Some(host)).2
+        };
+        assert_eq!(timeout(&job, "127.0.0.1"), Some(Duration::from_secs(1)));
+        // Other hosts, and files of other repositories, keep the global timeout
+        assert_eq!(timeout(&job, "localhost"), Some(Duration::from_secs(600)));
+        let mut other = job.clone();
+        other.repos = vec!["other".to_string()];
+        assert_eq!(timeout(&other, "127.0.0.1"), Some(Duration::from_secs(600)));
+
+        let dir = TempDir::new("timeout");
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        downloader.max_retry = 0;
+        let start = std::time::Instant::now();
+        let e = runtime
+            .block_on(downloader.fetch(vec![job], &dir, false))
+            .unwrap_err();
+        assert!(e.to_string().contains("No data received in 1s"));
+        assert!(start.elapsed() < Duration::from_secs(60));
+    }
+}
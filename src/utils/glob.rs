@@ -0,0 +1,47 @@
+/// Match `name` against a glob `pattern`, where `*` matches any string and `?` matches any character
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // Where to resume if the last `*` should match more characters: (pattern pos, name pos)
+    let mut backtrack = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, n));
+                p += 1;
+            }
+            Some(c) if *c == '?' || *c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                Some((star_p, star_n)) => {
+                    p = star_p + 1;
+                    n = star_n + 1;
+                    backtrack = Some((star_p, star_n + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("vim", "vim"));
+        assert!(!glob_match("vim", "vim-runtime"));
+        assert!(glob_match("linux-kernel-*", "linux-kernel-6.1.0"));
+        assert!(glob_match("*-dbg", "bash-dbg"));
+        assert!(glob_match("lib*-dev", "libfoo-bar-dev"));
+        assert!(!glob_match("lib*-dev", "libfoo-dev-doc"));
+        assert!(glob_match("python3.1?", "python3.11"));
+        assert!(!glob_match("python3.1?", "python3.1"));
+        assert!(glob_match("*", ""));
+    }
+}
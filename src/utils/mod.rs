@@ -1,4 +1,13 @@
+pub mod alt_root;
+pub mod arch;
 pub mod debcontrol;
+pub mod disk;
 pub mod downloader;
+pub mod glob;
 pub mod lock;
+pub mod name;
 pub mod pager;
+#[cfg(test)]
+pub mod temp_dir;
+pub mod time;
+pub mod timing;
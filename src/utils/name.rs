@@ -0,0 +1,18 @@
+use anyhow::{bail, Result};
+use console::style;
+
+/// Check that `name` of a `kind` of object, e.g. a profile, can be used as a file name
+pub fn check_name(kind: &str, name: &str) -> Result<()> {
+    let valid = !name.is_empty()
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.');
+    if !valid {
+        bail!(
+            "Invalid {kind} name {}: only letters, digits, '-', '_' and '.' are allowed.",
+            style(name).bold()
+        );
+    }
+    Ok(())
+}
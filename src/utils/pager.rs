@@ -1,5 +1,15 @@
+use crate::types::config::Opts;
+
 use anyhow::{format_err, Result};
-use std::{env::var, io::Write, process::Child, sync::atomic::Ordering};
+use console::Term;
+use std::{
+    env::var,
+    io::{ErrorKind, Write},
+    process::Child,
+    sync::atomic::Ordering,
+};
+
+const DEFAULT_PAGER: &str = "less";
 
 pub enum Pager {
     Plain,
@@ -7,7 +17,8 @@ pub enum Pager {
 }
 
 impl Pager {
-    pub fn new(no_pager: bool) -> Result<Self> {
+    /// Spawn a pager. `pager_cmd` is the `pager` key in config, if any
+    pub fn new(no_pager: bool, pager_cmd: Option<&str>) -> Result<Self> {
         if no_pager {
             return Ok(Pager::Plain);
         }
@@ -18,15 +29,16 @@ impl Pager {
             return Ok(Pager::Plain);
         }
 
-        let pager_cmd = var("PAGER").unwrap_or_else(|_| "less".to_owned());
+        let pager_cmd = resolve_pager_cmd(pager_cmd, var("APM_PAGER").ok(), var("PAGER").ok());
         let pager_cmd_segments: Vec<&str> = pager_cmd.split_ascii_whitespace().collect();
-        let pager_name = pager_cmd_segments.get(0).unwrap_or(&"less");
+        let pager_name = pager_cmd_segments.get(0).unwrap_or(&DEFAULT_PAGER);
         let mut p = std::process::Command::new(&pager_name);
-        if pager_name == &"less" {
+        if pager_name == &DEFAULT_PAGER {
             p.arg("-R"); // Show ANSI escape sequences correctly
             p.arg("-c"); // Start from the top of the screen
             p.env("LESSCHARSET", "UTF-8"); // Rust uses UTF-8
-        } else if pager_cmd_segments.len() > 1 {
+        }
+        if pager_cmd_segments.len() > 1 {
             p.args(&pager_cmd_segments[1..]);
         }
         let pager_process = p.stdin(std::process::Stdio::piped()).spawn()?;
@@ -37,6 +49,29 @@ impl Pager {
         Ok(res)
     }
 
+    /// Only use a pager if the content doesn't fit in the terminal (or the user-defined threshold)
+    pub fn with_threshold(opts: &Opts, pager_cmd: Option<&str>, lines: usize) -> Result<Self> {
+        if opts.no_pager {
+            return Ok(Pager::Plain);
+        }
+
+        if !opts.pager {
+            let threshold = match opts.max_pager_lines {
+                Some(max) => max,
+                None => match Term::stdout().size_checked() {
+                    Some((rows, _)) => rows.into(),
+                    // Not a terminal, don't bother
+                    None => return Ok(Pager::Plain),
+                },
+            };
+            if lines <= threshold {
+                return Ok(Pager::Plain);
+            }
+        }
+
+        Self::new(false, pager_cmd)
+    }
+
     pub fn pager_name(&self) -> Option<&str> {
         match self {
             Pager::Plain => None,
@@ -52,7 +87,7 @@ impl Pager {
                     .stdin
                     .as_ref()
                     .ok_or_else(|| format_err!("Failed to take pager's stdin"))?;
-                let res: Box<dyn Write> = Box::new(stdin);
+                let res: Box<dyn Write> = Box::new(PagerWriter(stdin));
                 res
             }
         };
@@ -68,9 +103,66 @@ impl Pager {
     }
 }
 
+/// Pick the pager command: config first, then `APM_PAGER`, then `PAGER`, and `less` at last
+fn resolve_pager_cmd(
+    config: Option<&str>,
+    apm_pager: Option<String>,
+    pager: Option<String>,
+) -> String {
+    config
+        .map(|s| s.to_owned())
+        .into_iter()
+        .chain(apm_pager)
+        .chain(pager)
+        .find(|cmd| !cmd.trim().is_empty())
+        .unwrap_or_else(|| DEFAULT_PAGER.to_owned())
+}
+
+/// Writer to the stdin of pager
+///
+/// The user may quit the pager before we finish writing, so broken pipes are ignored.
+struct PagerWriter<W: Write>(W);
+
+impl<W: Write> Write for PagerWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self.0.write(buf) {
+            Err(e) if e.kind() == ErrorKind::BrokenPipe => Ok(buf.len()),
+            res => res,
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self.0.flush() {
+            Err(e) if e.kind() == ErrorKind::BrokenPipe => Ok(()),
+            res => res,
+        }
+    }
+}
+
 impl Drop for Pager {
     fn drop(&mut self) {
         // Un-set subprocess pid
         crate::SUBPROCESS.store(-1, Ordering::SeqCst);
     }
 }
+
+/// Number of lines in `buf`, to compare with the pager threshold
+#[inline]
+pub fn count_lines(buf: &[u8]) -> usize {
+    buf.iter().filter(|c| **c == b'\n').count()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_resolve_pager_cmd() {
+        let s = |x: &str| Some(x.to_owned());
+        assert_eq!(resolve_pager_cmd(Some("bat"), s("most"), s("more")), "bat");
+        assert_eq!(resolve_pager_cmd(None, s("most"), s("more")), "most");
+        assert_eq!(resolve_pager_cmd(None, None, s("more")), "more");
+        assert_eq!(resolve_pager_cmd(None, s(""), s("more -s")), "more -s");
+        assert_eq!(resolve_pager_cmd(None, None, None), "less");
+    }
+}
@@ -0,0 +1,58 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Format time as YYYY-MM-DD in UTC
+pub fn format_date(time: SystemTime) -> String {
+    let days = match time.duration_since(UNIX_EPOCH) {
+        Ok(d) => (d.as_secs() / 86400) as i64,
+        Err(_) => return "(before 1970)".to_string(),
+    };
+    // Civil from days, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719468;
+    let era = z / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+/// Format time as YYYY-MM-DD HH:MM:SS in UTC
+pub fn format_time(time: SystemTime) -> String {
+    let secs = match time.duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_secs() % 86400,
+        Err(_) => return "(before 1970)".to_string(),
+    };
+    format!(
+        "{} {:02}:{:02}:{:02}",
+        format_date(time),
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_format_date() {
+        assert_eq!(format_date(UNIX_EPOCH), "1970-01-01");
+        assert_eq!(
+            format_date(UNIX_EPOCH + Duration::from_secs(1609372800)),
+            "2020-12-31"
+        );
+        assert_eq!(
+            format_date(UNIX_EPOCH + Duration::from_secs(951782400)),
+            "2000-02-29"
+        );
+        assert_eq!(
+            format_time(UNIX_EPOCH + Duration::from_secs(1609459199)),
+            "2020-12-31 23:59:59"
+        );
+    }
+}
@@ -0,0 +1,55 @@
+/// Record how long each phase takes, for `--timings`
+use crate::{info, msg};
+
+use anyhow::Result;
+use std::{
+    io::Write,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use tabled::{Alignment, Column, Full, Modify, Style, Table, Tabled};
+
+#[derive(Tabled)]
+struct TimingRow {
+    #[header("Phase")]
+    phase: &'static str,
+    #[header("Time")]
+    time: String,
+}
+
+#[derive(Default)]
+pub struct Timings {
+    phases: Mutex<Vec<(&'static str, Duration)>>,
+}
+
+impl Timings {
+    /// Record a phase that started at `start` and ends now
+    pub fn record(&self, phase: &'static str, start: Instant) {
+        let elapsed = start.elapsed();
+        self.phases.lock().unwrap().push((phase, elapsed));
+    }
+
+    pub fn show(&self) -> Result<()> {
+        let phases = self.phases.lock().unwrap();
+        if phases.is_empty() {
+            return Ok(());
+        }
+        let rows: Vec<TimingRow> = phases
+            .iter()
+            .map(|(phase, time)| TimingRow {
+                phase,
+                time: format!("{:.3}s", time.as_secs_f64()),
+            })
+            .collect();
+        let table = Table::new(&rows)
+            .with(Modify::new(Full).with(Alignment::left()))
+            .with(Modify::new(Column(1..2)).with(Alignment::right()))
+            .with(Modify::new(Full).with(|s: &str| format!(" {s} ")))
+            .with(Style::PSEUDO_CLEAN);
+        msg!("");
+        info!("Timings:");
+        write!(crate::WRITER.get_writer(), "{}", table)?;
+
+        Ok(())
+    }
+}
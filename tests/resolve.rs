@@ -0,0 +1,108 @@
+use omakase::{Checksum, PkgMeta, PkgSource, PkgVersion, SolverError, VersionRequirement};
+
+fn pkg(name: &str, version: &str, depends: &[(&str, &str)]) -> PkgMeta {
+    let source = PkgSource::Http((
+        format!("https://example.org/{name}_{version}.deb"),
+        0,
+        Checksum::from_sha256_str(&"0".repeat(64)).unwrap(),
+    ));
+    let mut pkg = PkgMeta::new(name, PkgVersion::try_from(version).unwrap(), source);
+    pkg.depends = depends
+        .iter()
+        .map(|(name, ver_req)| request(name, ver_req))
+        .collect();
+    pkg
+}
+
+fn request(name: &str, ver_req: &str) -> (String, VersionRequirement) {
+    let ver_req = if ver_req.is_empty() {
+        VersionRequirement::default()
+    } else {
+        VersionRequirement::try_from(ver_req).unwrap()
+    };
+    (name.to_string(), ver_req)
+}
+
+#[test]
+fn resolve_through_public_api() {
+    let pkgs = vec![
+        pkg("a", "1.0", &[("b", ">=2.0")]),
+        pkg("b", "1.0", &[]),
+        pkg("b", "2.0", &[("c", "")]),
+        pkg("b", "3.0", &[("c", "")]),
+        pkg("c", "1.0", &[]),
+    ];
+    let res = omakase::resolve(pkgs.clone(), &[request("a", "")]).unwrap();
+    let mut resolved: Vec<String> = res
+        .iter()
+        .map(|pkg| format!("{}={}", pkg.name, pkg.version))
+        .collect();
+    resolved.sort();
+    assert_eq!(resolved, vec!["a=1.0", "b=3.0", "c=1.0"]);
+
+    // Version requirements of requests are respected
+    let res = omakase::resolve(pkgs, &[request("a", ""), request("b", "<<3.0")]).unwrap();
+    let b = res.iter().find(|pkg| pkg.name == "b").unwrap();
+    assert_eq!(b.version.to_string(), "2.0");
+}
+
+#[test]
+fn resolve_errors() {
+    let pkgs = vec![
+        pkg("a", "1.0", &[("b", ">=2.0")]),
+        pkg("b", "1.0", &[]),
+        pkg("b", "2.0", &[]),
+    ];
+    match omakase::resolve(pkgs.clone(), &[request("x", "")]) {
+        Err(SolverError::PackageNotFound(name)) => assert_eq!(name, "x"),
+        _ => panic!("expected PackageNotFound"),
+    }
+    match omakase::resolve(pkgs.clone(), &[request("b", ">=3.0")]) {
+        Err(SolverError::VersionConflict { name, .. }) => assert_eq!(name, "b"),
+        _ => panic!("expected VersionConflict"),
+    }
+    assert!(matches!(
+        omakase::resolve(pkgs, &[request("a", ""), request("b", "<<2.0")]),
+        Err(SolverError::Unsatisfiable { .. })
+    ));
+}
+
+#[test]
+fn resolve_superseded() {
+    // new-pkg takes over old-pkg (e.g. a rename)
+    let mut new_pkg = pkg("new-pkg", "2.0", &[]);
+    new_pkg.conflicts = vec![request("old-pkg", "<<2.0")];
+    new_pkg.replaces = Some(vec![request("old-pkg", "<<2.0")]);
+    let pkgs = vec![
+        pkg("old-pkg", "1.0", &[]),
+        new_pkg,
+        pkg("app", "1.0", &[("new-pkg", "")]),
+    ];
+
+    // A package is not superseded unless necessary
+    let res = omakase::resolve(pkgs.clone(), &[request("old-pkg", "")]).unwrap();
+    let names: Vec<&str> = res.iter().map(|pkg| pkg.name.as_str()).collect();
+    assert_eq!(names, vec!["old-pkg"]);
+
+    // Superseded instead of reporting an unsatisfiable conflict
+    let res = omakase::resolve(pkgs, &[request("old-pkg", ""), request("app", "")]).unwrap();
+    let mut names: Vec<&str> = res.iter().map(|pkg| pkg.name.as_str()).collect();
+    names.sort();
+    assert_eq!(names, vec!["app", "new-pkg"]);
+}
+
+#[test]
+fn resolve_pre_depends() {
+    let mut a = pkg("a", "1.0", &[]);
+    a.pre_depends = vec![request("b", ">=2.0")];
+    let mut b = pkg("b", "2.0", &[]);
+    b.pre_depends = vec![request("c", "")];
+    let pkgs = vec![a, pkg("b", "1.0", &[]), b, pkg("c", "1.0", &[])];
+    let res = omakase::resolve(pkgs, &[request("a", "")]).unwrap();
+    // Pre-dependencies come first in installation order
+    let resolved: Vec<String> = res
+        .iter()
+        .map(|pkg| format!("{}={}", pkg.name, pkg.version))
+        .collect();
+    assert_eq!(resolved, vec!["c=1.0", "b=2.0", "a=1.0"]);
+}